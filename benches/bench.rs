@@ -4,7 +4,6 @@ use microchip_rs::lib::mcus::mcs51::*;
 use microchip_rs::lib::traits::component::*;
 
 fn mcs51_benchmark(c: &mut Criterion) {
-    let iterations = 1000000;
     let mut mcu = MCS51::new();
     mcu.generate_opcode_array();
 
@@ -16,16 +15,8 @@ fn mcs51_benchmark(c: &mut Criterion) {
 
     for instruction in test_data {
         mcu.reset();
-        mcu.set_program(vec![instruction.0; iterations]);
-        c.bench_function(&format!("MATCH {}", instruction.1), |b| {
-            b.iter(|| mcu.next_instruction_debug_match())
-        });
-
-        mcu.reset();
-        mcu.set_program(vec![instruction.0; iterations]);
-        c.bench_function(&format!("TABLE {}", instruction.1), |b| {
-            b.iter(|| mcu.next_instruction_debug_table())
-        });
+        mcu.set_program(vec![instruction.0; MCS51_CODE_SIZE]).unwrap();
+        c.bench_function(instruction.1, |b| b.iter(|| mcu.step()));
     }
 }
 
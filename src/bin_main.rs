@@ -1,19 +1,22 @@
 #![allow(dead_code)]
+mod cli;
 mod lib;
-use lib::decompiler::mcs51::*;
-use lib::mcus::mcs51::*;
+mod repl;
 use lib::mcus::pic16f628a::*;
-use std::fs;
-use std::fs::File;
-use std::io::Read;
 use std::time::{Instant};
-use lib::traits::component::*;
-use rustyline::error::ReadlineError;
-use rustyline::Editor;
+use cli::*;
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use lib::decompiler::mcs51::*;
+    use lib::mcus::mcs51::hex::*;
+    use lib::mcus::mcs51::serial_bus::*;
+    use lib::mcus::mcs51::timers::*;
+    use lib::mcus::mcs51::*;
+    use lib::traits::component::*;
+    use repl::*;
+    use std::time::Duration;
     #[test]
     fn register_operations_16f628a() {
         let mut mcu = PIC16F628A::new();
@@ -56,106 +59,3234 @@ mod tests {
             0x14, // Decrement Accumulator
             0x19, // Decrement Register 1
             0x09, // Increment Register 1
+        ]).unwrap();
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 1);
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 3);
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.read_register(1), 2);
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 1);
+        mcu.next_instruction();
+        assert_eq!(mcu.read_register(1), 1);
+
+        //Switch bank
+        mcu.write(0xD0, 0b00001000);
+        mcu.next_instruction();
+        assert_eq!(mcu.read_register(1), 1);
+    }
+
+    #[test]
+    fn banking_operations_mcs51() {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.write(0xD0, 0b00000000);
+        assert_eq!(mcu.get_current_register_bank(), 0);
+        mcu.write(0xD0, 0b00001000);
+        assert_eq!(mcu.get_current_register_bank(), 1);
+        mcu.write(0xD0, 0b00010000);
+        assert_eq!(mcu.get_current_register_bank(), 2);
+        mcu.write(0xD0, 0b00011000);
+        assert_eq!(mcu.get_current_register_bank(), 3);
+    }
+
+    #[test]
+    fn bit_operations_mcs51() {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        assert_eq!(mcu.read_bit(0x60), false);
+        assert_eq!(mcu.read_bit(0x61), false);
+        assert_eq!(mcu.read_bit(0x62), false);
+
+        mcu.write_bit(0x60, true);
+        assert_eq!(mcu.get_accumulator(), 0);
+        assert_eq!(*mcu.read(0x2c).unwrap(), 1);
+
+        mcu.write_bit(0x61, true);
+        assert_eq!(*mcu.read(0x2c).unwrap(), 3);
+        assert_eq!(mcu.read_bit(0x61), true);
+
+        mcu.write_bit(0x62, true);
+        assert_eq!(*mcu.read(0x2c).unwrap(), 7);
+        assert_eq!(mcu.read_bit(0x62), true);
+
+        // Clearing a bit leaves the rest of the byte untouched
+        mcu.write_bit(0x61, false);
+        assert_eq!(*mcu.read(0x2c).unwrap(), 5);
+        assert_eq!(mcu.read_bit(0x60), true);
+        assert_eq!(mcu.read_bit(0x61), false);
+
+        // Both ends of the bit addressable RAM
+        mcu.write_bit(0x00, true);
+        mcu.write_bit(0x7F, true);
+        assert_eq!(*mcu.read(0x20).unwrap(), 0x01);
+        assert_eq!(*mcu.read(0x2F).unwrap(), 0x80);
+
+        // SFR bits
+        mcu.write_bit(0xE3, true);
+        assert_eq!(mcu.get_accumulator(), 0x08);
+        mcu.write_bit(0xD7, true);
+        assert_eq!(mcu.get_carry_flag(), true);
+        mcu.write_bit(0x97, false);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::P1), 0x7F);
+        assert_eq!(mcu.read_bit(0x97), false);
+        assert_eq!(mcu.read_bit(0x96), true);
+        assert_eq!(mcu.read_bit(0xE3), true);
+        assert_eq!(mcu.read_bit(0xE2), false);
+    }
+
+    // Runs one instruction with the carry and the bit at 0x20.0 / 0x20.1 preset
+    fn run_bit_instruction(program: Vec<u8>, carry: bool, bits: u8) -> MCS51 {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(program).unwrap();
+        mcu.set_carry_flag(carry);
+        mcu.write(0x20, bits);
+        mcu.next_instruction();
+        mcu
+    }
+
+    #[test]
+    fn bit_set_clear_instructions_mcs51() {
+        // (program, carry, 0x20, expected carry, expected 0x20, expected pc)
+        let cases: Vec<(Vec<u8>, bool, u8, bool, u8, u16)> = vec![
+            (vec![0xD3], false, 0x00, true, 0x00, 1),        // SETB C
+            (vec![0xC3], true, 0x00, false, 0x00, 1),        // CLR C
+            (vec![0xB3], true, 0x00, false, 0x00, 1),        // CPL C
+            (vec![0xB3], false, 0x00, true, 0x00, 1),        // CPL C
+            (vec![0xD2, 0x01], false, 0x81, false, 0x83, 2), // SETB 20.1
+            (vec![0xC2, 0x00], false, 0x81, false, 0x80, 2), // CLR 20.0
+            (vec![0xB2, 0x00], false, 0x81, false, 0x80, 2), // CPL 20.0
+            (vec![0xB2, 0x01], false, 0x81, false, 0x83, 2), // CPL 20.1
+            (vec![0x92, 0x01], true, 0x00, true, 0x02, 2),   // MOV 20.1, C
+            (vec![0x92, 0x00], false, 0xFF, false, 0xFE, 2), // MOV 20.0, C
+        ];
+
+        for (program, carry, bits, expected_carry, expected_bits, pc) in cases {
+            let mut mcu = run_bit_instruction(program.clone(), carry, bits);
+            assert_eq!(mcu.get_carry_flag(), expected_carry, "{:02x?}", program);
+            assert_eq!(*mcu.read(0x20).unwrap(), expected_bits, "{:02x?}", program);
+            assert_eq!(mcu.pc, pc, "{:02x?}", program);
+        }
+    }
+
+    #[test]
+    fn bit_logic_instructions_mcs51() {
+        // (program, carry, 0x20, expected carry)
+        let cases: Vec<(Vec<u8>, bool, u8, bool)> = vec![
+            (vec![0x82, 0x00], true, 0x01, true),   // ANL C, 20.0
+            (vec![0x82, 0x00], true, 0x00, false),  // ANL C, 20.0
+            (vec![0x82, 0x00], false, 0x01, false), // ANL C, 20.0
+            (vec![0xB0, 0x00], true, 0x00, true),   // ANL C, /20.0
+            (vec![0xB0, 0x00], true, 0x01, false),  // ANL C, /20.0
+            (vec![0x72, 0x01], false, 0x02, true),  // ORL C, 20.1
+            (vec![0x72, 0x01], false, 0x01, false), // ORL C, 20.1
+            (vec![0x72, 0x01], true, 0x00, true),   // ORL C, 20.1
+            (vec![0xA0, 0x01], false, 0x00, true),  // ORL C, /20.1
+            (vec![0xA0, 0x01], false, 0x02, false), // ORL C, /20.1
+            (vec![0xA2, 0x01], false, 0x02, true),  // MOV C, 20.1
+            (vec![0xA2, 0x01], true, 0xFD, false),  // MOV C, 20.1
+        ];
+
+        for (program, carry, bits, expected_carry) in cases {
+            let mut mcu = run_bit_instruction(program.clone(), carry, bits);
+            assert_eq!(mcu.get_carry_flag(), expected_carry, "{:02x?} {} {:02x}", program, carry, bits);
+            assert_eq!(*mcu.read(0x20).unwrap(), bits);
+            assert_eq!(mcu.pc, 2);
+        }
+    }
+
+    #[test]
+    fn bit_jump_instructions_mcs51() {
+        // (program, carry, 0x20, expected pc, expected 0x20)
+        let cases: Vec<(Vec<u8>, bool, u8, u16, u8)> = vec![
+            (vec![0x20, 0x00, 0x10], false, 0x01, 0x13, 0x01), // JB 20.0
+            (vec![0x20, 0x00, 0x10], false, 0x00, 0x03, 0x00), // JB 20.0
+            (vec![0x30, 0x00, 0x10], false, 0x00, 0x13, 0x00), // JNB 20.0
+            (vec![0x30, 0x00, 0x10], false, 0x01, 0x03, 0x01), // JNB 20.0
+            (vec![0x10, 0x01, 0x10], false, 0x03, 0x13, 0x01), // JBC 20.1
+            (vec![0x10, 0x01, 0x10], false, 0x01, 0x03, 0x01), // JBC 20.1
+            (vec![0x20, 0x00, 0xFD], false, 0x01, 0x00, 0x01), // JB 20.0 backwards
+            (vec![0x40, 0x10], true, 0x00, 0x12, 0x00),        // JC
+            (vec![0x40, 0x10], false, 0x00, 0x02, 0x00),       // JC
+            (vec![0x50, 0x10], false, 0x00, 0x12, 0x00),       // JNC
+            (vec![0x50, 0x10], true, 0x00, 0x02, 0x00),        // JNC
+        ];
+
+        for (program, carry, bits, pc, expected_bits) in cases {
+            let mut mcu = run_bit_instruction(program.clone(), carry, bits);
+            assert_eq!(mcu.pc, pc, "{:02x?} {} {:02x}", program, carry, bits);
+            assert_eq!(*mcu.read(0x20).unwrap(), expected_bits, "{:02x?}", program);
+        }
+    }
+
+    #[test]
+    fn bit_sfr_instructions_mcs51() {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(vec![
+            0xC2, 0x94, // CLR P1.4
+            0xD2, 0xE7, // SETB ACC.7
+            0xB2, 0xD5, // CPL PSW.5 (F0)
+            0x10, 0x8D, 0x00, // JBC TCON.5 (TF0), +0
+            0xA2, 0xE7, // MOV C, ACC.7
+        ]).unwrap();
+        mcu.write_sfr(MCS51_REGISTERS::TCON, MCS51_TCON_TF0 | MCS51_TCON_TR0);
+
+        mcu.next_instruction();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::P1), 0xEF);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x80);
+        mcu.next_instruction();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::PSW) & 0x20, 0x20);
+        mcu.next_instruction();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TCON), MCS51_TCON_TR0);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_carry_flag(), true);
+        assert_eq!(mcu.pc, 11);
+    }
+
+    #[test]
+    fn parity_flag_mcs51() {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        assert_eq!(mcu.get_parity_flag(), false);
+
+        mcu.set_accumulator(0x01);
+        assert_eq!(mcu.get_parity_flag(), true);
+        mcu.set_accumulator(0x03);
+        assert_eq!(mcu.get_parity_flag(), false);
+
+        mcu.write(0xE0, 0x07);
+        assert_eq!(mcu.get_parity_flag(), true);
+        mcu.write_bit(0xE3, true);
+        assert_eq!(mcu.get_parity_flag(), false);
+
+        // Software can't override the parity flag
+        mcu.write(0xD0, 0x01);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::PSW), 0x00);
+        mcu.write_bit(0xD0, true);
+        assert_eq!(mcu.get_parity_flag(), false);
+
+        // Writes through references are seen by the PSW reads
+        *mcu.get_u8_mut(MCS51_ADDRESSING::ACCUMULATOR).unwrap() = 0x10;
+        assert_eq!(mcu.get_parity_flag(), true);
+        *mcu.get_sfr_mut(MCS51_REGISTERS::ACC).unwrap() = 0x11;
+        assert_eq!(mcu.read_raw(0xD0) & 0x01, 0);
+        *mcu.get_mut_addr(0xE0).unwrap() = 0x10;
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::PSW), 0x01);
+        mcu.update_parity_flag();
+        assert_eq!(*mcu.read(0xD0).unwrap(), 0x01);
+
+        mcu.set_program(vec![
+            0x04, // INC A
+            0x05, 0xE0, // INC ACC
+            0x74, 0xFE, // MOV A, #0FEH
+            0x14, // DEC A
+        ]).unwrap();
+        mcu.next_instruction();
+        assert_eq!(mcu.get_parity_flag(), false);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x12);
+        assert_eq!(mcu.get_parity_flag(), false);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_parity_flag(), true);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0xFD);
+        assert_eq!(mcu.get_parity_flag(), true);
+    }
+
+    // (program, A, operand in R0 and B, C, AC, result A, result B, C, AC, OV)
+    type FlagCase = (Vec<u8>, u8, u8, bool, bool, u8, u8, bool, bool, bool);
+
+    fn run_flag_cases(name: &str, cases: Vec<FlagCase>) {
+        for (program, acc, operand, carry, aux_carry, result, b, cy, ac, ov) in cases {
+            let mut mcu = MCS51::new();
+            mcu.reset();
+            mcu.set_program(program).unwrap();
+            mcu.set_accumulator(acc);
+            mcu.write_register(0, operand);
+            mcu.write_sfr(MCS51_REGISTERS::B, operand);
+            mcu.set_carry_flag(carry);
+            mcu.set_aux_carry_flag(aux_carry);
+            mcu.next_instruction();
+
+            let case = format!("{} {:02x} {:02x} {}", name, acc, operand, carry);
+            assert_eq!(mcu.get_accumulator(), result, "{}", case);
+            assert_eq!(mcu.read_sfr(MCS51_REGISTERS::B), b, "{}", case);
+            assert_eq!(mcu.get_carry_flag(), cy, "{} CY", case);
+            assert_eq!(mcu.get_aux_carry_flag(), ac, "{} AC", case);
+            assert_eq!(mcu.get_overflow_flag(), ov, "{} OV", case);
+            assert_eq!(mcu.get_parity_flag(), result.count_ones() % 2 == 1, "{} P", case);
+        }
+    }
+
+    #[test]
+    fn add_flags_mcs51() {
+        run_flag_cases("ADD", vec![
+            (vec![0x28], 0xC3, 0xAA, false, false, 0x6D, 0xAA, true, false, true),
+            (vec![0x28], 0x0F, 0x01, false, false, 0x10, 0x01, false, true, false),
+            (vec![0x28], 0x7F, 0x01, false, false, 0x80, 0x01, false, true, true),
+            (vec![0x28], 0xFF, 0x01, true, false, 0x00, 0x01, true, true, false),
+            (vec![0x28], 0x80, 0x80, false, true, 0x00, 0x80, true, false, true),
+            (vec![0x28], 0x12, 0x34, true, true, 0x46, 0x34, false, false, false),
+        ]);
+    }
+
+    #[test]
+    fn addc_flags_mcs51() {
+        run_flag_cases("ADDC", vec![
+            (vec![0x38], 0xC3, 0xAA, true, false, 0x6E, 0xAA, true, false, true),
+            (vec![0x38], 0x0E, 0x01, true, false, 0x10, 0x01, false, true, false),
+            (vec![0x38], 0x7F, 0x00, true, false, 0x80, 0x00, false, true, true),
+            (vec![0x38], 0xFF, 0x00, true, false, 0x00, 0x00, true, true, false),
+            (vec![0x38], 0x12, 0x34, false, true, 0x46, 0x34, false, false, false),
+        ]);
+    }
+
+    #[test]
+    fn subb_flags_mcs51() {
+        run_flag_cases("SUBB", vec![
+            (vec![0x98], 0xC9, 0x54, true, false, 0x74, 0x54, false, false, true),
+            (vec![0x98], 0xC9, 0x54, false, false, 0x75, 0x54, false, false, true),
+            (vec![0x98], 0x00, 0x01, false, false, 0xFF, 0x01, true, true, false),
+            (vec![0x98], 0x80, 0x01, false, false, 0x7F, 0x01, false, true, true),
+            (vec![0x98], 0x7F, 0xFF, false, false, 0x80, 0xFF, true, false, true),
+            (vec![0x98], 0x10, 0x0F, true, true, 0x00, 0x0F, false, true, false),
+            (vec![0x94, 0x05], 0x05, 0x00, false, true, 0x00, 0x00, false, false, false),
+        ]);
+    }
+
+    #[test]
+    fn da_flags_mcs51() {
+        // OV is left untouched, AC as set by the preceding addition
+        run_flag_cases("DA", vec![
+            (vec![0xD4], 0xBE, 0x00, false, false, 0x24, 0x00, true, false, false),
+            (vec![0xD4], 0xC9, 0x00, false, false, 0x29, 0x00, true, false, false),
+            (vec![0xD4], 0x45, 0x00, false, false, 0x45, 0x00, false, false, false),
+            (vec![0xD4], 0x11, 0x00, false, true, 0x17, 0x00, false, true, false),
+            (vec![0xD4], 0x11, 0x00, true, false, 0x71, 0x00, true, false, false),
+            (vec![0xD4], 0x9A, 0x00, false, false, 0x00, 0x00, true, false, false),
+        ]);
+
+        // 56 + 67 + 1 = 124 in BCD
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(vec![0x3B, 0xD4]).unwrap(); // ADDC A, R3; DA A
+        mcu.set_accumulator(0x56);
+        mcu.write_register(3, 0x67);
+        mcu.set_carry_flag(true);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0xBE);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x24);
+        assert_eq!(mcu.get_carry_flag(), true);
+    }
+
+    #[test]
+    fn mul_div_flags_mcs51() {
+        run_flag_cases("MUL", vec![
+            (vec![0xA4], 0x50, 0xA0, true, false, 0x00, 0x32, false, false, true),
+            (vec![0xA4], 0x0F, 0x11, true, false, 0xFF, 0x00, false, false, false),
+            (vec![0xA4], 0x00, 0xFF, true, false, 0x00, 0x00, false, false, false),
+        ]);
+
+        run_flag_cases("DIV", vec![
+            (vec![0x84], 0xFB, 0x12, true, false, 0x0D, 0x11, false, false, false),
+            (vec![0x84], 0x10, 0x20, true, false, 0x00, 0x10, false, false, false),
+        ]);
+
+        // Division by zero sets OV, A and B are undefined
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(vec![0x84]).unwrap();
+        mcu.set_accumulator(0x12);
+        mcu.set_carry_flag(true);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_overflow_flag(), true);
+        assert_eq!(mcu.get_carry_flag(), false);
+    }
+
+    #[test]
+    fn cjne_flags_mcs51() {
+        // (R7, immediate, expected carry, expected pc)
+        let cases: Vec<(u8, u8, bool, u16)> = vec![
+            (0x56, 0x60, true, 0x13),
+            (0x56, 0x34, false, 0x13),
+            (0x56, 0x56, false, 0x03),
+            (0x00, 0xFF, true, 0x13),
+            (0x80, 0x7F, false, 0x13),
+        ];
+
+        for (r7, data, carry, pc) in cases {
+            let mut mcu = MCS51::new();
+            mcu.reset();
+            mcu.set_program(vec![0xBF, data, 0x10]).unwrap(); // CJNE R7, #data, +10H
+            mcu.write_register(7, r7);
+            mcu.set_carry_flag(!carry);
+            mcu.next_instruction();
+            assert_eq!(mcu.get_carry_flag(), carry, "{:02x} {:02x}", r7, data);
+            assert_eq!(mcu.pc, pc, "{:02x} {:02x}", r7, data);
+        }
+    }
+
+    // Standard 8051 opcode map, one row per high nibble
+    const MCS51_OPCODE_LENGTHS: [[u8; 16]; 16] = [
+        [1, 2, 3, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+        [3, 2, 3, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+        [3, 2, 1, 1, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+        [3, 2, 1, 1, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+        [2, 2, 2, 3, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+        [2, 2, 2, 3, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+        [2, 2, 2, 3, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+        [2, 2, 2, 1, 2, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2],
+        [2, 2, 2, 1, 1, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2],
+        [3, 2, 2, 1, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+        [2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2],
+        [2, 2, 2, 1, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3],
+        [2, 2, 2, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+        [2, 2, 2, 1, 1, 3, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+        [1, 2, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+        [1, 2, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+    ];
+
+    // Runs one instruction to completion and returns the number of machine cycles it took
+    fn run_instruction(mcu: &mut MCS51) -> u8 {
+        mcu.clock();
+        let mut cycles = 1;
+        while mcu.additional_cycles > 0 {
+            mcu.clock();
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn opcode_length_and_cycles_mcs51() {
+        for opcode in 0..=0xFFu16 {
+            let opcode = opcode as u8;
+            let mut program = vec![0x00; 0x300];
+            program[0x100] = opcode;
+
+            let mut mcu = MCS51::new();
+            mcu.reset();
+            mcu.set_program(program).unwrap();
+            mcu.pc = 0x100;
+
+            let cycles = run_instruction(&mut mcu);
+            let row = (opcode >> 4) as usize;
+            let column = (opcode & 0x0F) as usize;
+
+            // Operands are all zero, so relative branches land on the next instruction
+            let pc = match opcode {
+                0x02 | 0x12 | 0x22 | 0x32 | 0x73 => 0x0000,
+                _ if opcode & 0x0F == 0x01 => ((opcode & 0xE0) as u16) << 3,
+                _ => 0x100 + MCS51_OPCODE_LENGTHS[row][column] as u16,
+            };
+
+            assert_eq!(mcu.pc, pc, "PC after {:02x}", opcode);
+            assert_eq!(cycles, MCS51::get_instruction_cycles(opcode), "cycles of {:02x}", opcode);
+        }
+    }
+
+    /*
+    Common starting point of the conformance tests: A = 5AH, B = 03H, R0/R1 point to 40H/41H,
+    R2-R7 = 12H-17H, direct address 30H = 81H
+    */
+    fn conformance_mcu(program: Vec<u8>) -> MCS51 {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(program).unwrap();
+        mcu.set_accumulator(0x5A);
+        mcu.write_sfr(MCS51_REGISTERS::B, 0x03);
+        mcu.write_register(0, 0x40);
+        mcu.write_register(1, 0x41);
+        for n in 2..8 {
+            mcu.write_register(n, 0x10 + n);
+        }
+        mcu.write(0x40, 0xC3);
+        mcu.write(0x41, 0x3C);
+        mcu.write(0x30, 0x81);
+        mcu
+    }
+
+    // Program and source value for the 4H (#data), 5H (direct 30H), 6H-7H (@Ri) and 8H-FH (Rn) columns
+    fn conformance_operand(opcode: u8) -> (Vec<u8>, u8) {
+        match opcode & 0x0F {
+            0x4 => (vec![opcode, 0x66], 0x66),
+            0x5 => (vec![opcode, 0x30], 0x81),
+            0x6 => (vec![opcode], 0xC3),
+            0x7 => (vec![opcode], 0x3C),
+            n => (vec![opcode], conformance_register(n - 8)),
+        }
+    }
+
+    fn conformance_register(n: u8) -> u8 {
+        match n {
+            0 => 0x40,
+            1 => 0x41,
+            n => 0x10 + n,
+        }
+    }
+
+    #[test]
+    fn arithmetic_logic_conformance_mcs51() {
+        // (base opcode, operation on A and the source)
+        let operations: Vec<(u8, fn(u8, u8) -> u8)> = vec![
+            (0x20, |a, b| a.wrapping_add(b)),
+            (0x30, |a, b| a.wrapping_add(b)),
+            (0x40, |a, b| a | b),
+            (0x50, |a, b| a & b),
+            (0x60, |a, b| a ^ b),
+            (0x90, |a, b| a.wrapping_sub(b)),
+        ];
+
+        for (base, operation) in operations {
+            for column in 0x4..=0xF {
+                let (program, source) = conformance_operand(base + column);
+                let length = program.len() as u16;
+                let mut mcu = conformance_mcu(program);
+                mcu.next_instruction();
+
+                assert_eq!(mcu.get_accumulator(), operation(0x5A, source), "{:02x}", base + column);
+                assert_eq!(mcu.pc, length, "{:02x}", base + column);
+            }
+        }
+
+        // ORL/ANL/XRL direct, A and direct, #data
+        let cases: Vec<(Vec<u8>, u8)> = vec![
+            (vec![0x42, 0x30], 0xDB),
+            (vec![0x43, 0x30, 0x0F], 0x8F),
+            (vec![0x52, 0x30], 0x00),
+            (vec![0x53, 0x30, 0x0F], 0x01),
+            (vec![0x62, 0x30], 0xDB),
+            (vec![0x63, 0x30, 0x0F], 0x8E),
+        ];
+        for (program, result) in cases {
+            let length = program.len() as u16;
+            let opcode = program[0];
+            let mut mcu = conformance_mcu(program);
+            mcu.next_instruction();
+            assert_eq!(*mcu.read(0x30).unwrap(), result, "{:02x}", opcode);
+            assert_eq!(mcu.get_accumulator(), 0x5A, "{:02x}", opcode);
+            assert_eq!(mcu.pc, length, "{:02x}", opcode);
+        }
+    }
+
+    #[test]
+    fn inc_dec_conformance_mcs51() {
+        for (base, delta) in [(0x00u8, 1u8), (0x10, 0xFF)].iter() {
+            let mut mcu = conformance_mcu(vec![base + 0x04]);
+            mcu.next_instruction();
+            assert_eq!(mcu.get_accumulator(), 0x5Au8.wrapping_add(*delta));
+
+            for column in 0x5..=0xF {
+                let (program, source) = conformance_operand(base + column);
+                let length = program.len() as u16;
+                let mut mcu = conformance_mcu(program);
+                mcu.next_instruction();
+
+                let value = match column {
+                    0x5 => *mcu.read(0x30).unwrap(),
+                    0x6 => *mcu.read(0x40).unwrap(),
+                    0x7 => *mcu.read(0x41).unwrap(),
+                    n => mcu.read_register(n - 8),
+                };
+                assert_eq!(value, source.wrapping_add(*delta), "{:02x}", base + column);
+                assert_eq!(mcu.pc, length, "{:02x}", base + column);
+            }
+        }
+
+        let mut mcu = conformance_mcu(vec![0xA3, 0xA3]); // INC DPTR
+        mcu.set_dptr(0x00FF);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_dptr(), 0x0100);
+        mcu.set_dptr(0xFFFF);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_dptr(), 0x0000);
+    }
+
+    #[test]
+    fn mov_conformance_mcs51() {
+        // MOV A, <src>
+        for column in 0x5..=0xF {
+            let (program, source) = conformance_operand(0xE0 + column);
+            let mut mcu = conformance_mcu(program);
+            mcu.next_instruction();
+            assert_eq!(mcu.get_accumulator(), source, "{:02x}", 0xE0 + column);
+        }
+
+        // MOV <dest>, A and MOV <dest>, #data
+        for (base, value) in [(0xF0u8, 0x5Au8), (0x70, 0x99)].iter() {
+            for column in 0x5..=0xF {
+                let mut program = vec![base + column];
+                if column == 0x5 {
+                    program.push(0x30);
+                }
+                if *base == 0x70 {
+                    program.push(0x99);
+                }
+                let length = program.len() as u16;
+                let mut mcu = conformance_mcu(program);
+                mcu.next_instruction();
+
+                let dest = match column {
+                    0x5 => 0x30,
+                    0x6 => 0x40,
+                    0x7 => 0x41,
+                    n => n - 8,
+                };
+                assert_eq!(*mcu.read(dest).unwrap(), *value, "{:02x}", base + column);
+                assert_eq!(mcu.pc, length, "{:02x}", base + column);
+            }
+        }
+
+        // MOV direct, <src> and MOV <dest>, direct
+        for column in 0x6..=0xF {
+            let (mut program, source) = conformance_operand(0x80 + column);
+            program.push(0x50);
+            let mut mcu = conformance_mcu(program);
+            mcu.next_instruction();
+            assert_eq!(*mcu.read(0x50).unwrap(), source, "{:02x}", 0x80 + column);
+
+            let mut mcu = conformance_mcu(vec![0xA0 + column, 0x30]);
+            mcu.next_instruction();
+            let dest = match column {
+                0x6 => 0x40,
+                0x7 => 0x41,
+                n => n - 8,
+            };
+            assert_eq!(*mcu.read(dest).unwrap(), 0x81, "{:02x}", 0xA0 + column);
+            assert_eq!(mcu.pc, 2);
+        }
+
+        // MOV direct, direct has the source first
+        let mut mcu = conformance_mcu(vec![0x85, 0x30, 0x50]);
+        mcu.next_instruction();
+        assert_eq!(*mcu.read(0x50).unwrap(), 0x81);
+        assert_eq!(*mcu.read(0x30).unwrap(), 0x81);
+        assert_eq!(mcu.pc, 3);
+
+        // MOV direct, #data
+        let mut mcu = conformance_mcu(vec![0x75, 0xF0, 0x42]);
+        mcu.next_instruction();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::B), 0x42);
+
+        // MOV DPTR, #data16
+        let mut mcu = conformance_mcu(vec![0x90, 0x12, 0x34]);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_dptr(), 0x1234);
+        assert_eq!(mcu.pc, 3);
+    }
+
+    #[test]
+    fn exchange_conformance_mcs51() {
+        for column in 0x5..=0xF {
+            let (program, source) = conformance_operand(0xC0 + column);
+            let mut mcu = conformance_mcu(program);
+            mcu.next_instruction();
+
+            let value = match column {
+                0x5 => *mcu.read(0x30).unwrap(),
+                0x6 => *mcu.read(0x40).unwrap(),
+                0x7 => *mcu.read(0x41).unwrap(),
+                n => mcu.read_register(n - 8),
+            };
+            assert_eq!(mcu.get_accumulator(), source, "{:02x}", 0xC0 + column);
+            assert_eq!(value, 0x5A, "{:02x}", 0xC0 + column);
+        }
+
+        // XCHD A, @R0 and XCHD A, @R1
+        let mut mcu = conformance_mcu(vec![0xD6, 0xD7]);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x53);
+        assert_eq!(*mcu.read(0x40).unwrap(), 0xCA);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x5C);
+        assert_eq!(*mcu.read(0x41).unwrap(), 0x33);
+        assert_eq!(mcu.pc, 2);
+
+        // SWAP A
+        let mut mcu = conformance_mcu(vec![0xC4]);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0xA5);
+    }
+
+    #[test]
+    fn accumulator_conformance_mcs51() {
+        // (opcode, carry, A, carry after)
+        let cases: Vec<(u8, bool, u8, bool)> = vec![
+            (0x03, false, 0x2D, false), // RR A
+            (0x13, true, 0xAD, false),  // RRC A
+            (0x23, false, 0xB4, false), // RL A
+            (0x33, true, 0xB5, false),  // RLC A
+            (0xE4, true, 0x00, true),   // CLR A
+            (0xF4, true, 0xA5, true),   // CPL A
+        ];
+        for (opcode, carry, result, carry_after) in cases {
+            let mut mcu = conformance_mcu(vec![opcode]);
+            mcu.set_carry_flag(carry);
+            mcu.next_instruction();
+            assert_eq!(mcu.get_accumulator(), result, "{:02x}", opcode);
+            assert_eq!(mcu.get_carry_flag(), carry_after, "{:02x}", opcode);
+            assert_eq!(mcu.pc, 1);
+        }
+
+        let cases: Vec<(u8, bool, u8, u8, bool)> = vec![
+            (0x03, false, 0x01, 0x80, false), // RR A
+            (0x13, false, 0x01, 0x00, true),  // RRC A
+            (0x23, false, 0x80, 0x01, false), // RL A
+            (0x33, false, 0x80, 0x00, true),  // RLC A
+        ];
+        for (opcode, carry, acc, result, carry_after) in cases {
+            let mut mcu = conformance_mcu(vec![opcode]);
+            mcu.set_accumulator(acc);
+            mcu.set_carry_flag(carry);
+            mcu.next_instruction();
+            assert_eq!(mcu.get_accumulator(), result, "{:02x}", opcode);
+            assert_eq!(mcu.get_carry_flag(), carry_after, "{:02x}", opcode);
+        }
+    }
+
+    #[test]
+    fn stack_conformance_mcs51() {
+        let mut program = vec![0x00; 0x200];
+        program[0x000..0x006].copy_from_slice(&[
+            0xC0, 0xE0, // PUSH ACC
+            0xC0, 0xF0, // PUSH B
+            0xD0, 0xE0, // POP ACC
+        ]);
+        program[0x006..0x008].copy_from_slice(&[0xD0, 0x30]); // POP 30H
+        program[0x008..0x00B].copy_from_slice(&[0x12, 0x01, 0x00]); // LCALL 0100H
+        program[0x00B..0x00D].copy_from_slice(&[0x31, 0x10]); // ACALL 0110H
+        program[0x100] = 0x22; // RET
+        program[0x110] = 0x22; // RET
+
+        let mut mcu = conformance_mcu(program);
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.get_stack_pointer(), 0x09);
+        assert_eq!(*mcu.read(0x08).unwrap(), 0x5A);
+        assert_eq!(*mcu.read(0x09).unwrap(), 0x03);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x03);
+        mcu.next_instruction();
+        assert_eq!(*mcu.read(0x30).unwrap(), 0x5A);
+        assert_eq!(mcu.get_stack_pointer(), 0x07);
+        assert_eq!(mcu.pc, 0x08);
+
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x100);
+        assert_eq!(*mcu.read(0x08).unwrap(), 0x0B);
+        assert_eq!(*mcu.read(0x09).unwrap(), 0x00);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x0B);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x110);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x0D);
+        assert_eq!(mcu.get_stack_pointer(), 0x07);
+    }
+
+    #[test]
+    fn jump_conformance_mcs51() {
+        let mut program = vec![0x00; 0x1000];
+
+        // AJMP stays within the 2K page of the next instruction
+        program[0x7FE..0x800].copy_from_slice(&[0xE1, 0x23]); // AJMP 0F23H in page 0800H
+        let mut mcu = conformance_mcu(program.clone());
+        mcu.pc = 0x7FE;
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x0F23);
+
+        // SJMP forwards and backwards
+        program[0x10..0x12].copy_from_slice(&[0x80, 0x10]);
+        program[0x12..0x14].copy_from_slice(&[0x80, 0xFE]);
+        let mut mcu = conformance_mcu(program.clone());
+        mcu.pc = 0x10;
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x22);
+        mcu.pc = 0x12;
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x12);
+
+        // LJMP and JMP @A+DPTR
+        program[0x20..0x24].copy_from_slice(&[0x02, 0x0A, 0xBC, 0x73]);
+        let mut mcu = conformance_mcu(program.clone());
+        mcu.pc = 0x20;
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x0ABC);
+        mcu.pc = 0x23;
+        mcu.set_dptr(0x0100);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x015A);
+
+        // JZ/JNZ
+        program[0x30..0x34].copy_from_slice(&[0x60, 0x10, 0x70, 0x10]);
+        let mut mcu = conformance_mcu(program.clone());
+        mcu.pc = 0x30;
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x32);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x44);
+
+        // DJNZ Rn and DJNZ direct
+        program[0x40..0x45].copy_from_slice(&[0xDA, 0xFE, 0xD5, 0x30, 0xFD]);
+        let mut mcu = conformance_mcu(program.clone());
+        mcu.pc = 0x40;
+        for _i in 0..0x12 {
+            mcu.next_instruction();
+        }
+        assert_eq!(mcu.read_register(2), 0x00);
+        assert_eq!(mcu.pc, 0x42);
+        mcu.next_instruction();
+        assert_eq!(*mcu.read(0x30).unwrap(), 0x80);
+        assert_eq!(mcu.pc, 0x42);
+    }
+
+    #[test]
+    fn cjne_conformance_mcs51() {
+        // Every form compares against the same value and jumps +10H when different
+        for opcode in 0xB4..=0xBF {
+            let (operand, value) = match opcode {
+                0xB4 => (0x5A, 0x5A),
+                0xB5 => (0x30, 0x5A),
+                0xB6 => (0xC3, 0xC3),
+                0xB7 => (0x3C, 0x3C),
+                n => (conformance_register(n - 0xB8), conformance_register(n - 0xB8)),
+            };
+
+            let mut mcu = conformance_mcu(vec![opcode, operand, 0x10]);
+            mcu.next_instruction();
+            let equal = opcode != 0xB5;
+            assert_eq!(mcu.pc, if equal { 0x03 } else { 0x13 }, "{:02x}", opcode);
+            assert_eq!(mcu.get_carry_flag(), !equal && value < 0x81, "{:02x}", opcode);
+
+            let mut mcu = conformance_mcu(vec![opcode, operand.wrapping_add(1), 0x10]);
+            mcu.next_instruction();
+            assert_eq!(mcu.pc, 0x13, "{:02x}", opcode);
+        }
+    }
+
+    #[test]
+    fn cycle_counter_mcs51() {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(vec![
+            0x7F, 0x0A, // MOV R7, #10
+            0xDF, 0xFE, // DJNZ R7, $
+            0xA4, // MUL AB
+            0x00, // NOP
+        ]).unwrap();
+
+        mcu.step();
+        assert_eq!(mcu.get_cycle_count(), 1);
+        for _i in 0..10 {
+            mcu.step();
+        }
+        assert_eq!(mcu.pc, 4);
+        assert_eq!(mcu.get_cycle_count(), 21);
+        mcu.step();
+        assert_eq!(mcu.get_cycle_count(), 25);
+        mcu.step();
+        assert_eq!(mcu.get_cycle_count(), 26);
+
+        // 12 MHz, 12 clocks per machine cycle: 1us per cycle
+        assert_eq!(mcu.get_elapsed_time(), Duration::from_micros(26));
+
+        assert_eq!(mcu.get_states_per_cycle(), 6);
+        mcu.set_clocks_per_cycle(6);
+        assert_eq!(mcu.get_elapsed_time(), Duration::from_micros(13));
+        assert_eq!(mcu.get_states_per_cycle(), 3);
+
+        mcu.set_oscillator_frequency(11_059_200);
+        mcu.set_clocks_per_cycle(12);
+        assert_eq!(mcu.get_machine_cycle_frequency(), 921_600.0);
+        assert_eq!(mcu.duration_to_cycles(Duration::from_secs(1)), 921_600);
+        assert_eq!(mcu.cycles_to_duration(921_600), Duration::from_secs(1));
+
+        mcu.power_on();
+        assert_eq!(mcu.get_cycle_count(), 0);
+    }
+
+    #[test]
+    fn derivative_profiles_mcs51() {
+        let parts: Vec<(&str, usize, usize, bool)> = vec![
+            ("8031", 0x0000, 128, false),
+            ("8051", 0x1000, 128, false),
+            ("80C52", 0x2000, 256, true),
+            ("at89c2051", 0x0800, 128, false),
+            ("AT89S52", 0x2000, 256, true),
+        ];
+
+        for (name, rom_size, ram_size, timer2) in parts {
+            let mut mcu = MCS51::from_part_name(name).unwrap();
+            mcu.reset();
+            let derivative = mcu.get_derivative();
+            assert_eq!(derivative.rom_size, rom_size, "{}", name);
+            assert_eq!(derivative.ram_size, ram_size, "{}", name);
+            assert_eq!(derivative.timer2, timer2, "{}", name);
+            assert_eq!(mcu.has_sfr(0xC8), timer2, "{}", name);
+            assert_eq!(mcu.read_sfr(MCS51_REGISTERS::SP), 0x07, "{}", name);
+            assert_eq!(mcu.read_sfr(MCS51_REGISTERS::P1), 0xFF, "{}", name);
+        }
+
+        assert!(MCS51::from_part_name("8751H").is_none());
+        assert!(MCS51_Derivative::from_name("AT89C2051").is_some());
+    }
+
+    #[test]
+    fn derivative_sfr_set_mcs51() {
+        // Timer 2 doesn't exist on the 8051
+        let mut mcu = MCS51::new_derivative(&MCS51_8051);
+        mcu.reset();
+        mcu.set_program(vec![0x00; 0x100]).unwrap();
+        mcu.write(0xC8, MCS51_T2CON_TR2);
+        assert_eq!(mcu.read(0xC8), None);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::T2CON), 0x00);
+        mcu.write_sfr(MCS51_REGISTERS::T2CON, MCS51_T2CON_TR2);
+        clock_n(&mut mcu, 10);
+        assert_eq!(mcu.get_timer2(), 0);
+
+        // The 8052 counts
+        let mut mcu = MCS51::new_derivative(&MCS51_8052);
+        mcu.reset();
+        mcu.set_program(vec![0x00; 0x100]).unwrap();
+        mcu.write(0xC8, MCS51_T2CON_TR2);
+        clock_n(&mut mcu, 10);
+        assert_eq!(mcu.get_timer2(), 10);
+
+        // The AT89C2051 has no P0/P2 and no external data memory
+        let mut mcu = MCS51::new_derivative(&MCS51_AT89C2051);
+        mcu.reset();
+        assert_eq!(mcu.has_sfr(0x80), false);
+        assert_eq!(mcu.has_sfr(0xA0), false);
+        assert_eq!(mcu.has_sfr(0x90), true);
+        mcu.write(0x80, 0x12);
+        assert_eq!(mcu.read(0x80), None);
+        assert_eq!(mcu.read_bit(0x87), false);
+        mcu.write_xdata(0x0000, 0x12);
+        assert_eq!(mcu.read_xdata(0x0000), 0xFF);
+
+        // Selecting a part keeps the regions mapped by the board, only the default RAM changes
+        let mut mcu = MCS51::new();
+        mcu.xdata.map_rom(0xF000, &[0x5A]).unwrap();
+        mcu.set_derivative(&MCS51_AT89C2051);
+        assert_eq!(mcu.read_xdata(0xF000), 0x5A);
+        mcu.write_xdata(0x0010, 0x12);
+        assert_eq!(mcu.read_xdata(0x0010), 0xFF);
+        mcu.set_derivative(&MCS51_8031);
+        mcu.set_derivative(&MCS51_8052);
+        assert_eq!(mcu.xdata.mappings.len(), 2);
+        assert_eq!(mcu.read_xdata(0xF000), 0x5A);
+        mcu.write_xdata(0x0010, 0x12);
+        assert_eq!(mcu.read_xdata(0x0010), 0x12);
+    }
+
+    #[test]
+    fn upper_ram_indirect_mcs51() {
+        let mut mcu = MCS51::new_derivative(&MCS51_8052);
+        mcu.reset();
+        mcu.set_program(vec![
+            0x78, 0x90, // MOV R0, #90H
+            0x74, 0x5A, // MOV A, #5AH
+            0xF6, // MOV @R0, A
+            0x06, // INC @R0
+            0xE6, // MOV A, @R0
+            0xF5, 0x90, // MOV 90H, A
+            0x86, 0xF0, // MOV B, @R0
+        ]).unwrap();
+
+        for _i in 0..4 {
+            mcu.next_instruction();
+        }
+        assert_eq!(mcu.ram[0x90], 0x5B);
+        assert_eq!(*mcu.read_indirect(0x90).unwrap(), 0x5B);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::P1), 0xFF);
+
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x5B);
+
+        mcu.next_instruction();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::P1), 0x5B);
+        assert_eq!(mcu.ram[0x90], 0x5B);
+
+        mcu.set_accumulator(0x00);
+        mcu.ram[0x90] = 0x77;
+        mcu.next_instruction();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::B), 0x77);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::P1), 0x5B);
+    }
+
+    #[test]
+    fn upper_ram_stack_mcs51() {
+        let mut program = vec![0x00; 0x200];
+        program[0x00..0x03].copy_from_slice(&[0x75, 0x81, 0xE0]); // MOV SP, #0E0H
+        program[0x03..0x05].copy_from_slice(&[0xC0, 0x90]); // PUSH P1
+        program[0x05..0x08].copy_from_slice(&[0x12, 0x01, 0x00]); // LCALL 0100H
+        program[0x08..0x0A].copy_from_slice(&[0xD0, 0xF0]); // POP B
+        program[0x100] = 0x22; // RET
+
+        let mut mcu = MCS51::new_derivative(&MCS51_8052);
+        mcu.reset();
+        mcu.set_program(program).unwrap();
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.ram[0xE1], 0xFF);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x100);
+        assert_eq!(mcu.get_stack_pointer(), 0xE3);
+        assert_eq!(mcu.ram[0xE2], 0x08);
+        assert_eq!(mcu.ram[0xE3], 0x00);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::ACC), 0x00);
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::B), 0xFF);
+        assert_eq!(mcu.get_stack_pointer(), 0xE0);
+    }
+
+    #[test]
+    fn upper_ram_missing_mcs51() {
+        // The 8051 has no RAM above 7FH, indirect accesses there don't reach the SFRs either
+        let mut mcu = MCS51::new_derivative(&MCS51_8051);
+        mcu.reset();
+        mcu.set_program(vec![
+            0x78, 0x90, // MOV R0, #90H
+            0x74, 0x5A, // MOV A, #5AH
+            0xF6, // MOV @R0, A
+        ]).unwrap();
+        for _i in 0..3 {
+            mcu.next_instruction();
+        }
+        assert_eq!(mcu.read_indirect(0x90), None);
+        assert_eq!(mcu.ram[0x90], 0x00);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::P1), 0xFF);
+    }
+
+    #[test]
+    fn upper_ram_read_missing_mcs51() {
+        let program = vec![
+            0x78, 0x90, // MOV R0, #90H
+            0xE6, // MOV A, @R0
+            0x06, // INC @R0
+            0x00, // NOP
+        ];
+
+        // Reads of the missing upper RAM return the floating bus
+        for part in ["8051", "8031", "AT89C2051"] {
+            let mut mcu = MCS51::from_part_name(part).unwrap();
+            mcu.set_program(program.clone()).unwrap();
+            mcu.step();
+            mcu.step();
+            assert_eq!(mcu.get_accumulator(), 0xFF, "{}", part);
+            mcu.step();
+            assert_eq!(mcu.pc, 4, "{}", part);
+        }
+
+        let mut mcu = MCS51::from_part_name("8051").unwrap();
+        mcu.set_program(program.clone()).unwrap();
+        mcu.set_sfr_policy(MCS51_SFR_POLICY::LOG);
+        mcu.run();
+        let log: Vec<(u16, MCS51_MEMORY_SPACE, u8, MCS51_SFR_ACCESS)> = mcu
+            .get_sfr_log()
+            .iter()
+            .map(|error| (error.pc, error.space, error.address, error.access))
+            .collect();
+        assert_eq!(
+            log,
+            vec![
+                (2, MCS51_MEMORY_SPACE::INDIRECT, 0x90, MCS51_SFR_ACCESS::READ),
+                (3, MCS51_MEMORY_SPACE::INDIRECT, 0x90, MCS51_SFR_ACCESS::WRITE),
+            ]
+        );
+
+        mcu.reset();
+        mcu.set_sfr_policy(MCS51_SFR_POLICY::STOP);
+        mcu.run();
+        assert_eq!(mcu.pc, 2);
+        assert_eq!(
+            mcu.sfr_error.unwrap().to_string(),
+            "0002: opcode E6 read from unimplemented RAM 90"
+        );
+
+        // The 8052 has the upper RAM
+        let mut mcu = MCS51::from_part_name("8052").unwrap();
+        mcu.set_program(program).unwrap();
+        mcu.set_sfr_policy(MCS51_SFR_POLICY::STOP);
+        mcu.run();
+        assert_eq!(mcu.sfr_error, None);
+        assert_eq!(mcu.read_indirect(0x90), Some(&0x01));
+    }
+
+    #[test]
+    fn port_pins_mcs51() {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(vec![
+            0xE5, 0x90, // MOV A, P1
+            0x63, 0x90, 0x80, // XRL P1, #80H
+            0xA2, 0x90, // MOV C, P1.0
+            0xB2, 0x91, // CPL P1.1
+            0x75, 0x90, 0x0F, // MOV P1, #0FH
+        ]).unwrap();
+
+        // A button pulls P1.0 low
+        mcu.set_pin(8, false);
+        assert_eq!(mcu.get_port_u8(1), 0xFE);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::P1), 0xFF);
+
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0xFE);
+
+        // Read-modify-write uses the latch, P1.0 stays released in the latch
+        mcu.next_instruction();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::P1), 0x7F);
+        assert_eq!(mcu.get_port_u8(1), 0x7E);
+
+        mcu.next_instruction();
+        assert_eq!(mcu.get_carry_flag(), false);
+
+        mcu.next_instruction();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::P1), 0x7D);
+
+        mcu.set_pin(8, true);
+        assert_eq!(mcu.get_port_u8(1), 0x7D);
+
+        // LEDs driven by the latch
+        mcu.next_instruction();
+        assert_eq!(mcu.get_pin(8), true);
+        assert_eq!(mcu.get_pin(12), false);
+        assert_eq!(mcu.get_port_u8(1), 0x0F);
+
+        // Wired-AND with the external drivers
+        mcu.set_port_u8(1, 0x3C);
+        assert_eq!(mcu.get_port_u8(1), 0x0C);
+        mcu.set_port_u16(1, 0xF0FF);
+        assert_eq!(mcu.get_port_u16(1), 0xF0FF);
+        assert_eq!(mcu.get_port_u8(3), 0xF0);
+    }
+
+    #[test]
+    fn port_pins_peripherals_mcs51() {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(vec![0x00; 0x100]).unwrap();
+
+        // INT0 on P3.2, edge triggered
+        mcu.write_sfr(MCS51_REGISTERS::TCON, 0x01);
+        mcu.set_pin(26, false);
+        mcu.step();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TCON) & 0x02, 0x02);
+        mcu.set_pin(26, true);
+
+        // T0 on P3.4 as counter
+        mcu.write_sfr(MCS51_REGISTERS::TMOD, 0x05);
+        mcu.write_sfr(MCS51_REGISTERS::TCON, MCS51_TCON_TR0);
+        for _i in 0..3 {
+            mcu.set_pin(28, false);
+            mcu.step();
+            mcu.set_pin(28, true);
+            mcu.step();
+        }
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), 3);
+    }
+
+    #[test]
+    fn movc_conformance_mcs51() {
+        let mut program = vec![0x00; 0x200];
+        program[0x00] = 0x83; // MOVC A, @A+PC
+        program[0x01] = 0x93; // MOVC A, @A+DPTR
+        program[0x5B] = 0x11;
+        program[0x15A] = 0x22;
+
+        let mut mcu = conformance_mcu(program);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x11);
+        assert_eq!(mcu.pc, 1);
+
+        mcu.set_accumulator(0x5A);
+        mcu.set_dptr(0x0100);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x22);
+        assert_eq!(mcu.pc, 2);
+    }
+
+    #[test]
+    fn code_memory_ea_mcs51() {
+        // Internal ROM holds MOV A, #11H, the external ROM behind it MOV A, #22H
+        let mut mcu = MCS51::new_derivative(&MCS51_8051);
+        mcu.reset();
+        mcu.load_program(0x0000, &[0x74, 0x11]).unwrap();
+        mcu.load_external_program(0x0000, &[0x74, 0x22]).unwrap();
+        mcu.load_program(0x1000, &[0x74, 0x33]).unwrap();
+
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x11);
+
+        mcu.reset();
+        mcu.set_ea_pin(false);
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x22);
+
+        // Above the internal ROM fetches go external whatever the EA level
+        mcu.pc = 0x1000;
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x33);
+
+        // The ROMless 8031 always fetches externally
+        let mut mcu = MCS51::new_derivative(&MCS51_8031);
+        mcu.reset();
+        mcu.load_external_program(0x0000, &[0x74, 0x44]).unwrap();
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x44);
+    }
+
+    #[test]
+    fn code_memory_unprogrammed_mcs51() {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.load_program(0x8000, &[0x74, 0x5A, 0x83]).unwrap(); // MOV A, #5AH / MOVC A, @A+PC
+        assert_eq!(mcu.read_code_byte(0x0000), 0xFF);
+
+        mcu.set_code_fill(0x00);
+        assert_eq!(mcu.read_code_byte(0x0000), 0x00);
+
+        // Unprogrammed locations run as the fill value, NOP here
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 1);
+
+        mcu.pc = 0x8000;
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x00);
+
+        mcu.set_code_trap(true);
+        mcu.next_instruction();
+        assert_eq!(mcu.code_trap, Some(0x8003));
+        assert_eq!(mcu.pc, 0x8003);
+        mcu.step();
+        assert_eq!(mcu.pc, 0x8003);
+
+        mcu.reset();
+        assert_eq!(mcu.code_trap, None);
+    }
+
+    #[test]
+    fn code_memory_wrap_mcs51() {
+        // An instruction at the top of code memory takes its operands from 0000H
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.load_program(0xFFFF, &[0x02]).unwrap(); // LJMP 1234H
+        mcu.load_program(0x0000, &[0x12, 0x34]).unwrap();
+        mcu.pc = 0xFFFF;
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x1234);
+
+        mcu.load_program(0xFFFF, &[0x00]).unwrap(); // NOP
+        mcu.pc = 0xFFFF;
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x0000);
+
+        mcu.set_program(vec![0x04, 0x04]).unwrap();
+        mcu.reset();
+        mcu.run();
+        assert_eq!(mcu.pc, 2);
+        assert_eq!(mcu.get_accumulator(), 2);
+
+        // Images past the top of code memory are refused and leave the code as it was
+        assert!(mcu.set_program(vec![0x00; MCS51_CODE_SIZE + 1]).unwrap_err().contains("do not fit"));
+        assert!(mcu.load_program(0xFFFF, &[0x00, 0x00]).is_err());
+        assert!(mcu.load_external_program(0xFFFF, &[0x00, 0x00]).is_err());
+        assert_eq!(mcu.read_code_byte(0x0000), 0x04);
+        assert!(!mcu.is_code_programmed(0xFFFF));
+    }
+
+    // Common area 0000H-7FFFH followed by the 32K window of 3 banks, each starting with MOV A, #bank
+    fn banked_image(common: &[u8]) -> Vec<u8> {
+        let mut image = vec![0x00; 0x20000];
+        image[..common.len()].copy_from_slice(common);
+        for bank in 0..3 {
+            let window = 0x8000 + bank * 0x8000;
+            image[window] = 0x74;
+            image[window + 1] = 0x10 + bank as u8;
+        }
+        image
+    }
+
+    #[test]
+    fn code_banking_port1_mcs51() {
+        let mut mcu = MCS51::new_derivative(&MCS51_8031);
+        mcu.set_code_banking(MCS51_BANK_SELECT::PORT1, 0x03, 0x8000).unwrap();
+        mcu.load_banked_program(&banked_image(&[
+            0x75, 0x90, 0xF9, // MOV P1, #F9H
+            0x02, 0x80, 0x00, // LJMP 8000H
+        ]))
+        .unwrap();
+        mcu.reset();
+        assert_eq!(mcu.get_code_bank(), 3);
+
+        for _i in 0..3 {
+            mcu.next_instruction();
+        }
+        assert_eq!(mcu.get_code_bank(), 1);
+        assert_eq!(mcu.get_accumulator(), 0x11);
+
+        // MOVC and the common area see the same mapping
+        mcu.write_sfr(MCS51_REGISTERS::P1, 0xFA);
+        assert_eq!(mcu.read_code_byte(0x8001), 0x12);
+        assert_eq!(mcu.read_code_byte(0x0001), 0x90);
+
+        // Bank 3 is past the end of the image
+        mcu.write_sfr(MCS51_REGISTERS::P1, 0xFF);
+        assert!(!mcu.is_code_programmed(0x8000));
+
+        // The 4 banks of the mask reach 28000H bytes, the common area at most 64K
+        assert!(mcu.load_banked_program(&vec![0x00; 0x28001]).is_err());
+        assert!(mcu.load_banked_program(&vec![0x00; 0x28000]).is_ok());
+        assert!(mcu
+            .set_code_banking(MCS51_BANK_SELECT::PORT1, 0x03, MCS51_CODE_SIZE + 1)
+            .unwrap_err()
+            .contains("larger than code memory"));
+        mcu.clear_code_banking();
+        assert!(mcu.load_banked_program(&vec![0x00; MCS51_CODE_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn code_banking_xdata_mcs51() {
+        let mut mcu = MCS51::new_derivative(&MCS51_8031);
+        mcu.set_code_banking(MCS51_BANK_SELECT::XDATA(0xFFFF), 0x0F, 0x8000).unwrap();
+        mcu.load_banked_program(&banked_image(&[
+            0x90, 0xFF, 0xFF, // MOV DPTR, #FFFFH
+            0x74, 0x02, // MOV A, #02H
+            0xF0, // MOVX @DPTR, A
+            0x02, 0x80, 0x00, // LJMP 8000H
+        ]))
+        .unwrap();
+        mcu.reset();
+        assert_eq!(mcu.get_code_bank(), 0);
+
+        for _i in 0..5 {
+            mcu.next_instruction();
+        }
+        assert_eq!(mcu.get_code_bank(), 2);
+        assert_eq!(mcu.get_accumulator(), 0x12);
+        assert_eq!(mcu.read_xdata(0xFFFF), 0x02);
+
+        mcu.reset();
+        assert_eq!(mcu.get_code_bank(), 0);
+    }
+
+    #[test]
+    fn decompile_banked_mcs51() {
+        let mut image = banked_image(&[
+            0x12, 0x00, 0x10, // LCALL 0010H
+            0x80, 0xFE, // SJMP $
+        ]);
+        // Trampoline to FUN_8000 in bank 2
+        image[0x10..0x16].copy_from_slice(&[0x90, 0x80, 0x00, 0x02, 0x00, 0x20]);
+        // Switch routine for bank 2
+        image[0x20..0x29].copy_from_slice(&[0x53, 0x90, 0xFC, 0x43, 0x90, 0x02, 0xE4, 0x73, 0x00]);
+        image[0x8002] = 0x22; // RET in bank 0
+        image[0x18002] = 0x22; // RET in bank 2
+
+        let mut dec = MCS51_Decompiler::new();
+        dec.program = image;
+        dec.banking = Some(MCS51_Decompiler_Banking::new(0x8000, 0x03));
+        assert_eq!(dec.get_bank_switch(0x0020), Some(2));
+        assert_eq!(dec.get_bank_switch(0x0000), None);
+        assert_eq!(dec.get_trampoline(0x0010), Some((2, 0x8000)));
+        assert_eq!(dec.get_trampoline(0x0000), None);
+
+        // Decoding the trampoline only annotates it, the calls are collected by decompile_banked
+        assert!(dec.get_instruction(0x0010).to_string().contains("BANK 2"));
+        assert!(dec.banked_calls.is_empty());
+
+        dec.decompile_banked(0);
+        assert!(dec.banked_calls.contains(&(2, 0x8000)));
+        assert!(dec.instructions[&0x0010].to_string().contains("BANK 2"));
+
+        let bank2 = &dec.banked_instructions[&2];
+        assert!(bank2[&0x8000].to_string().contains("MOV A, #12"));
+        assert!(!dec.instructions.contains_key(&0x8000));
+    }
+
+    #[test]
+    fn decompiler_instruction_length_mcs51() {
+        for opcode in 0..=0xFFu8 {
+            let expected = MCS51_OPCODE_LENGTHS[(opcode >> 4) as usize][(opcode & 0x0F) as usize];
+            assert_eq!(
+                MCS51_Decompiler::instruction_length(opcode),
+                expected as u16,
+                "length of {:02x}",
+                opcode
+            );
+        }
+    }
+
+    #[test]
+    fn von_neumann_mcs51() {
+        let mut mcu = MCS51::new_derivative(&MCS51_8031);
+        mcu.set_von_neumann(0x8000, 0xFFFF);
+        mcu.reset();
+        mcu.set_program(vec![
+            0x90, 0x80, 0x00, // MOV DPTR, #8000H
+            0x74, 0x04, // MOV A, #04H (INC A)
+            0xF0, // MOVX @DPTR, A
+            0xA3, // INC DPTR
+            0x74, 0x22, // MOV A, #22H (RET)
+            0xF0, // MOVX @DPTR, A
+            0xE4, // CLR A
+            0x12, 0x80, 0x00, // LCALL 8000H
+            0x90, 0x80, 0x00, // MOV DPTR, #8000H
+            0x93, // MOVC A, @A+DPTR
+        ]).unwrap();
+        assert_eq!(mcu.read_code_byte(0x8000), 0x00);
+
+        for _i in 0..10 {
+            mcu.next_instruction();
+        }
+        assert_eq!(mcu.pc, 0x000E);
+        assert_eq!(mcu.get_accumulator(), 0x01);
+
+        // MOVC reads the code written with MOVX
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0x22);
+
+        // Patched code runs on the next call
+        mcu.write_xdata(0x8000, 0x14); // DEC A
+        mcu.pc = 0x000B;
+        for _i in 0..3 {
+            mcu.next_instruction();
+        }
+        assert_eq!(mcu.get_accumulator(), 0x21);
+
+        // Outside the window code still comes from the ROM
+        mcu.clear_von_neumann();
+        assert_eq!(mcu.read_code_byte(0x8000), 0xFF);
+    }
+
+    #[test]
+    fn idle_mode_mcs51() {
+        let mut program = vec![0x00; 0x10];
+        program[0x00] = 0x43; // ORL PCON, #01H
+        program[0x01] = 0x87;
+        program[0x02] = 0x01;
+        program[0x0B] = 0x32; // RETI
+
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(program).unwrap();
+        mcu.write_sfr(MCS51_REGISTERS::TMOD, 0x02);
+        mcu.write_sfr(MCS51_REGISTERS::TL0, 0xF0);
+        mcu.write_sfr(MCS51_REGISTERS::TCON, MCS51_TCON_TR0);
+        mcu.write_sfr(MCS51_REGISTERS::IE, MCS51_IE_EA | MCS51_INTERRUPT::TIMER0.mask());
+
+        mcu.step();
+        assert_eq!(mcu.get_power_state(), MCS51_POWER_STATE::IDLE);
+        assert_eq!(mcu.pc, 3);
+
+        // The timer keeps running while the CPU is stopped
+        clock_n(&mut mcu, 10);
+        assert_eq!(mcu.pc, 3);
+        assert_eq!(mcu.get_power_state(), MCS51_POWER_STATE::IDLE);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), 0xFC);
+        assert_eq!(mcu.get_idle_cycles(), 10);
+
+        // The overflow interrupt ends the idle mode
+        clock_n(&mut mcu, 4);
+        assert_eq!(mcu.get_power_state(), MCS51_POWER_STATE::ACTIVE);
+        assert_eq!(mcu.pc, 0x0B);
+        assert_eq!(mcu.get_idle_cycles(), 14);
+        assert_eq!(mcu.get_active_cycles(), mcu.get_cycle_count() - 14);
+
+        // Second cycle of the interrupt call, then RETI back after the ORL
+        mcu.step();
+        mcu.step();
+        assert_eq!(mcu.pc, 3);
+        assert_eq!(mcu.get_power_state(), MCS51_POWER_STATE::ACTIVE);
+    }
+
+    #[test]
+    fn power_down_mode_mcs51() {
+        let mut program = vec![0x00; 0x10];
+        program[0x00] = 0x75; // MOV PCON, #02H
+        program[0x01] = 0x87;
+        program[0x02] = 0x02;
+
+        for derivative in [&MCS51_8052, &MCS51_AT89S52].iter() {
+            let mut mcu = MCS51::new_derivative(derivative);
+            mcu.reset();
+            mcu.set_program(program.clone()).unwrap();
+            mcu.write_sfr(MCS51_REGISTERS::TCON, MCS51_TCON_TR0);
+            mcu.write_sfr(MCS51_REGISTERS::IE, MCS51_IE_EA | MCS51_INTERRUPT::EXTERNAL0.mask());
+
+            mcu.step();
+            let timer = mcu.read_sfr(MCS51_REGISTERS::TL0);
+            clock_n(&mut mcu, 20);
+            assert_eq!(mcu.get_power_state(), MCS51_POWER_STATE::POWER_DOWN);
+            assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), timer);
+            assert_eq!(mcu.get_power_down_cycles(), 20);
+            assert_eq!(mcu.pc, 3);
+
+            // Only the AT89S52 wakes up on a low level on INT0
+            mcu.set_pin(3 * 8 + 2, false);
+            clock_n(&mut mcu, 2);
+            if derivative.power_down_wakeup {
+                assert_eq!(mcu.get_power_state(), MCS51_POWER_STATE::ACTIVE);
+                assert_eq!(mcu.pc, MCS51_INTERRUPT::EXTERNAL0.vector());
+            } else {
+                assert_eq!(mcu.get_power_state(), MCS51_POWER_STATE::POWER_DOWN);
+                assert_eq!(mcu.pc, 3);
+
+                // A reset leaves power down, the counters only restart on power up
+                mcu.reset();
+                assert_eq!(mcu.get_power_state(), MCS51_POWER_STATE::ACTIVE);
+                assert_eq!(mcu.get_power_down_cycles(), 22);
+                mcu.power_on();
+                assert_eq!(mcu.get_power_down_cycles(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn dual_dptr_at89s52() {
+        let mut program = vec![0x00; 0x20];
+        program[..18].copy_from_slice(&[
+            0x90, 0x12, 0x34, // MOV DPTR, #1234H
+            0x75, 0xA2, 0x01, // MOV AUXR1, #01H
+            0x90, 0x00, 0x1E, // MOV DPTR, #001EH
+            0xA3, // INC DPTR
+            0xE4, // CLR A
+            0x93, // MOVC A, @A+DPTR
+            0xF0, // MOVX @DPTR, A
+            0x75, 0xA2, 0x00, // MOV AUXR1, #00H
+            0xE4, // CLR A
+            0xF0, // MOVX @DPTR, A
+        ]);
+        program[0x1F] = 0x5A;
+
+        let mut mcu = MCS51::new_derivative(&MCS51_AT89S52);
+        mcu.reset();
+        mcu.set_program(program.clone()).unwrap();
+        for _i in 0..8 {
+            mcu.next_instruction();
+        }
+        assert_eq!(mcu.read(0x82), Some(&0x34));
+        assert_eq!(mcu.read(0x83), Some(&0x12));
+        assert_eq!(mcu.read(0x84), Some(&0x1F));
+        assert_eq!(mcu.read(0x85), Some(&0x00));
+        assert_eq!(mcu.read_xdata(0x001F), 0x5A);
+
+        mcu.next_instruction();
+        assert_eq!(mcu.get_dptr(), 0x1234);
+        for _i in 0..2 {
+            mcu.next_instruction();
+        }
+        assert_eq!(mcu.read_xdata(0x1234), 0x00);
+        assert_eq!(mcu.read_xdata(0x001F), 0x5A);
+
+        // Without AUXR1 there is a single data pointer
+        let mut mcu = MCS51::new_derivative(&MCS51_8052);
+        mcu.reset();
+        mcu.set_program(program).unwrap();
+        for _i in 0..4 {
+            mcu.next_instruction();
+        }
+        assert_eq!(mcu.get_dptr(), 0x001F);
+        assert_eq!(mcu.read(0xA2), None);
+    }
+
+    #[test]
+    fn watchdog_at89s52() {
+        let program = vec![
+            0x75, 0xA6, 0x1E, // MOV WDTRST, #1EH
+            0x75, 0xA6, 0xE1, // MOV WDTRST, #0E1H
+            0x80, 0xFE, // SJMP $
+        ];
+
+        let mut mcu = MCS51::new_derivative(&MCS51_AT89S52);
+        mcu.reset();
+        mcu.set_program(program).unwrap();
+        assert_eq!(mcu.read(0xA6), Some(&0xFF));
+
+        mcu.step();
+        assert!(!mcu.is_watchdog_enabled());
+        mcu.step();
+        assert!(mcu.is_watchdog_enabled());
+
+        // Enabled in the first cycle of the MOV, its second cycle already counts
+        clock_n(&mut mcu, MCS51_WDT_PERIOD as usize - 2);
+        assert_eq!(mcu.pc, 6);
+        assert_eq!(mcu.get_watchdog_timeouts(), 0);
+
+        // The overflow resets the chip and disables the watchdog
+        mcu.clock();
+        assert_eq!(mcu.pc, 0);
+        assert_eq!(mcu.get_watchdog_timeouts(), 1);
+        assert_eq!(mcu.get_reset_source(), MCS51_RESET_SOURCE::WATCHDOG);
+        assert!(!mcu.is_watchdog_enabled());
+
+        // Servicing it in time avoids the reset
+        mcu.watchdog_write(0x1E);
+        mcu.watchdog_write(0xE1);
+        for _i in 0..4 {
+            clock_n(&mut mcu, MCS51_WDT_PERIOD as usize - 10);
+            mcu.watchdog_write(0x1E);
+            mcu.watchdog_write(0xE1);
+        }
+        assert_eq!(mcu.get_watchdog_timeouts(), 1);
+
+        // A broken sequence does not clear the counter
+        mcu.watchdog_write(0x1E);
+        mcu.watchdog_write(0x00);
+        mcu.watchdog_write(0xE1);
+        clock_n(&mut mcu, MCS51_WDT_PERIOD as usize);
+        assert_eq!(mcu.get_watchdog_timeouts(), 2);
+    }
+
+    #[test]
+    fn warm_reset_mcs51() {
+        let mut mcu = MCS51::new();
+        mcu.power_on();
+        mcu.set_program(vec![0x75, 0x30, 0xA5, 0x75, 0x81, 0x40, 0x80, 0xFE]).unwrap();
+        for _i in 0..3 {
+            mcu.step();
+        }
+        assert_eq!(mcu.get_reset_source(), MCS51_RESET_SOURCE::POWER_ON);
+
+        mcu.reset();
+        assert_eq!(mcu.get_reset_source(), MCS51_RESET_SOURCE::PIN);
+        assert_eq!(mcu.ram[0x30], 0xA5);
+        assert_eq!(mcu.get_stack_pointer(), 0x07);
+        assert_eq!(mcu.pc, 0);
+        assert_eq!(mcu.get_cycle_count(), 6);
+
+        mcu.power_on();
+        assert_eq!(mcu.get_reset_source(), MCS51_RESET_SOURCE::POWER_ON);
+        assert_eq!(mcu.ram[0x30], 0x00);
+        assert_eq!(mcu.get_cycle_count(), 0);
+    }
+
+    #[test]
+    fn power_up_seed_mcs51() {
+        let power_up = |seed: u64| {
+            let mut mcu = MCS51::new();
+            mcu.xdata.map_rom(0xF000, &[0x12, 0x34]).unwrap();
+            mcu.set_power_up_seed(Some(seed));
+            mcu.power_on();
+            mcu
+        };
+
+        let mut first = power_up(1);
+        let mut second = power_up(1);
+        let mut other = power_up(2);
+        assert_eq!(first.ram[..], second.ram[..]);
+        assert_ne!(first.ram[..], other.ram[..]);
+        assert!(first.ram.iter().any(|byte| *byte != first.ram[0]));
+
+        let xdata: Vec<u8> = (0..0x100).map(|a| first.read_xdata(a)).collect();
+        assert_eq!(xdata, (0..0x100).map(|a| second.read_xdata(a)).collect::<Vec<u8>>());
+        assert_ne!(xdata, (0..0x100).map(|a| other.read_xdata(a)).collect::<Vec<u8>>());
+
+        // ROM regions keep their image, SFRs get their reset values
+        assert_eq!(first.read_xdata(0xF001), 0x34);
+        assert_eq!(first.get_stack_pointer(), 0x07);
+
+        first.set_power_up_seed(None);
+        first.power_on();
+        assert!(first.ram.iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn reset_pin_mcs51() {
+        let mut mcu = MCS51::new();
+        mcu.power_on();
+        mcu.set_program(vec![0x04, 0x04, 0x04, 0x04, 0x80, 0xFE]).unwrap(); // INC A x4, SJMP $
+        clock_n(&mut mcu, 3);
+        assert_eq!(mcu.pc, 3);
+
+        // A single cycle pulse only stalls the CPU
+        mcu.set_rst_pin(true);
+        mcu.clock();
+        mcu.set_rst_pin(false);
+        mcu.clock();
+        assert_eq!(mcu.pc, 4);
+        assert_eq!(mcu.get_accumulator(), 4);
+
+        // Two machine cycles reset the chip, which restarts when RST is released
+        mcu.set_rst_pin(true);
+        clock_n(&mut mcu, 2);
+        assert_eq!(mcu.pc, 0);
+        assert_eq!(mcu.get_accumulator(), 0);
+        assert_eq!(mcu.get_reset_source(), MCS51_RESET_SOURCE::PIN);
+        clock_n(&mut mcu, 5);
+        assert_eq!(mcu.pc, 0);
+
+        mcu.set_rst_pin(false);
+        mcu.clock();
+        assert_eq!(mcu.pc, 1);
+        assert_eq!(mcu.get_cycle_count(), 13);
+    }
+
+    #[test]
+    fn unimplemented_sfr_policy() {
+        let program = vec![
+            0x75, 0xC8, 0x12, // MOV T2CON, #12H
+            0x05, 0xC9, // INC T2MOD
+            0xD2, 0xCA, // SETB T2CON.2
+            0xE5, 0xCD, // MOV A, TH2
+            0x85, 0xC8, 0x30, // MOV 30H, T2CON
+            0x00, // NOP
+        ];
+
+        // Timer 2 does not exist on the 8051, the accesses read 0 and the writes are lost
+        let mut mcu = MCS51::new_derivative(&MCS51_8051);
+        mcu.reset();
+        mcu.set_program(program.clone()).unwrap();
+        mcu.write_sfr(MCS51_REGISTERS::ACC, 0x55);
+        mcu.run();
+        assert_eq!(mcu.pc, 13);
+        assert_eq!(mcu.get_accumulator(), 0x00);
+        assert_eq!(mcu.read(0x30), Some(&0x00));
+        assert!(mcu.get_sfr_log().is_empty());
+
+        mcu.reset();
+        mcu.set_sfr_policy(MCS51_SFR_POLICY::LOG);
+        mcu.run();
+        assert_eq!(mcu.pc, 13);
+        let log: Vec<(u16, u8, u8, MCS51_SFR_ACCESS)> = mcu
+            .get_sfr_log()
+            .iter()
+            .map(|error| (error.pc, error.opcode, error.address, error.access))
+            .collect();
+        assert_eq!(
+            log,
+            vec![
+                (0, 0x75, 0xC8, MCS51_SFR_ACCESS::WRITE),
+                (3, 0x05, 0xC9, MCS51_SFR_ACCESS::WRITE),
+                (5, 0xD2, 0xC8, MCS51_SFR_ACCESS::WRITE),
+                (7, 0xE5, 0xCD, MCS51_SFR_ACCESS::READ),
+                (9, 0x85, 0xC8, MCS51_SFR_ACCESS::READ),
+            ]
+        );
+
+        // Stops before the instruction executes
+        mcu.reset();
+        mcu.set_sfr_policy(MCS51_SFR_POLICY::STOP);
+        mcu.set_program(program[3..].to_vec()).unwrap();
+        mcu.write(0x30, 0x99);
+        mcu.run();
+        let error = mcu.sfr_error.unwrap();
+        assert_eq!(error.pc, 0);
+        assert_eq!(error.opcode, 0x05);
+        assert_eq!(error.address, 0xC9);
+        assert_eq!(error.to_string(), "0000: opcode 05 write to unimplemented SFR C9");
+        assert_eq!(mcu.pc, 0);
+        mcu.step();
+        assert_eq!(mcu.pc, 0);
+
+        // The same program is fine on the 8052
+        let mut mcu = MCS51::new_derivative(&MCS51_8052);
+        mcu.reset();
+        mcu.set_sfr_policy(MCS51_SFR_POLICY::STOP);
+        mcu.set_program(program).unwrap();
+        mcu.run();
+        assert_eq!(mcu.sfr_error, None);
+        assert_eq!(mcu.read(0x30), Some(&0x16));
+    }
+
+    fn run_control_mcu(program: Vec<u8>) -> MCS51 {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(program).unwrap();
+        mcu
+    }
+
+    #[test]
+    fn run_sleep_mcs51() {
+        // ORL PCON, #02H / NOP, run returns once the core is in Power Down
+        let mut mcu = run_control_mcu(vec![0x43, 0x87, 0x02, 0x00]);
+        mcu.run();
+        assert_eq!(mcu.pc, 0x03);
+        assert_eq!(mcu.get_power_state(), MCS51_POWER_STATE::POWER_DOWN);
+
+        // ORL PCON, #01H enters Idle
+        let mut mcu = run_control_mcu(vec![0x43, 0x87, 0x01, 0x00]);
+        mcu.run();
+        assert_eq!(mcu.get_power_state(), MCS51_POWER_STATE::IDLE);
+
+        // Nothing runs while RST is held
+        let mut mcu = run_control_mcu(vec![0x00, 0x00]);
+        mcu.set_rst_pin(true);
+        mcu.run();
+        assert_eq!(mcu.pc, 0x00);
+    }
+
+    #[test]
+    fn run_control_mcs51() {
+        let program = vec![
+            0x74, 0x01, // MOV A, #01H
+            0x04, // INC A
+            0x80, 0xFD, // SJMP 02H
+        ];
+
+        let mut mcu = run_control_mcu(program.clone());
+        assert_eq!(mcu.run_for(10), StopReason::BUDGET);
+        assert_eq!(mcu.get_cycle_count(), 10);
+
+        // A run continues from the breakpoint it stopped on
+        mcu.set_breakpoint(0x03);
+        assert_eq!(mcu.run_for(100), StopReason::BREAKPOINT(0x03));
+        assert_eq!(mcu.pc, 0x03);
+        let acc = mcu.get_accumulator();
+        assert_eq!(mcu.run_for(100), StopReason::BREAKPOINT(0x03));
+        assert_eq!(mcu.get_accumulator(), acc.wrapping_add(1));
+        mcu.clear_breakpoint(0x03);
+
+        // step halts on a breakpoint too, once the event is taken it runs the instruction
+        let mut stepped = run_control_mcu(program.clone());
+        stepped.set_breakpoint(0x02);
+        stepped.step();
+        assert_eq!(stepped.pc, 0x02);
+        let cycles = stepped.get_cycle_count();
+        stepped.step();
+        assert_eq!((stepped.pc, stepped.get_cycle_count()), (0x02, cycles));
+        assert!(matches!(
+            stepped.take_debug_event(),
+            Some(MCS51_DEBUG_EVENT::BREAKPOINT { pc: 0x02, .. })
+        ));
+        stepped.step();
+        assert_eq!((stepped.pc, stepped.get_accumulator()), (0x03, 0x02));
+        assert_eq!(stepped.run_for(100), StopReason::BREAKPOINT(0x02));
+
+        assert_eq!(mcu.run_until(|mcu| mcu.get_accumulator() == 0x40), StopReason::CONDITION);
+        assert_eq!(mcu.get_accumulator(), 0x40);
+
+        // Requests are consumed by the run they stop
+        mcu.request_stop();
+        let cycles = mcu.get_cycle_count();
+        assert_eq!(mcu.run_for(100), StopReason::EXTERNAL_REQUEST);
+        assert_eq!(mcu.get_cycle_count(), cycles);
+        mcu.get_stop_handle().store(true, std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(mcu.run_for(100), StopReason::EXTERNAL_REQUEST);
+        assert_eq!(mcu.run_for(100), StopReason::BUDGET);
+
+        let mut mcu = run_control_mcu(vec![0x00, 0xA5]);
+        assert_eq!(
+            mcu.run_for(100),
+            StopReason::INVALID_OPCODE {
+                pc: 1,
+                opcode: 0xA5
+            }
+        );
+
+        let mut mcu = run_control_mcu(vec![0x00, 0x00]);
+        assert_eq!(mcu.run_for(100), StopReason::PC_OUT_OF_CODE(2));
+
+        // A run started in Idle keeps clocking the idle CPU
+        let mut mcu = run_control_mcu(vec![0x75, 0x87, 0x01, 0x00]);
+        assert_eq!(mcu.run_for(100), StopReason::SLEEP);
+        assert_eq!(mcu.pc, 3);
+        assert_eq!(mcu.run_for(50), StopReason::BUDGET);
+        assert_eq!(mcu.get_idle_cycles(), 50);
+
+        let mut mcu = MCS51::new_derivative(&MCS51_AT89S52);
+        mcu.reset();
+        mcu.set_program(vec![
+            0x75, 0xA6, 0x1E, // MOV WDTRST, #1EH
+            0x75, 0xA6, 0xE1, // MOV WDTRST, #0E1H
+            0x80, 0xFE, // SJMP $
+        ]).unwrap();
+        assert_eq!(mcu.run_for(0x10000), StopReason::WATCHDOG_RESET);
+        assert_eq!(mcu.pc, 0);
+        assert_eq!(mcu.get_reset_source(), MCS51_RESET_SOURCE::WATCHDOG);
+
+        let mut mcu = MCS51::new_derivative(&MCS51_8051);
+        mcu.reset();
+        mcu.set_sfr_policy(MCS51_SFR_POLICY::STOP);
+        mcu.set_program(vec![0x00, 0xE5, 0xC8]).unwrap();
+        assert_eq!(
+            mcu.run_for(100),
+            StopReason::UNIMPLEMENTED_ACCESS {
+                pc: 1,
+                address: 0xC8
+            }
+        );
+    }
+
+    fn debugger_test_mcu() -> MCS51 {
+        run_control_mcu(vec![
+            0x78, 0x30, // MOV R0, #30H
+            0x76, 0x00, // MOV @R0, #00H
+            0x06, // INC @R0
+            0xE6, // MOV A, @R0
+            0xD2, 0x90, // SETB P1.0
+            0x90, 0x12, 0x34, // MOV DPTR, #1234H
+            0xF0, // MOVX @DPTR, A
+            0x80, 0xF6, // SJMP 04H
+        ])
+    }
+
+    #[test]
+    fn breakpoints_mcs51() {
+        let mut mcu = debugger_test_mcu();
+        mcu.xdata.map_ram(0x0000, 0xFFFF);
+
+        // Hit counts, the first two hits are ignored
+        let id = mcu.add_breakpoint(0x05);
+        mcu.debugger.set_ignore_count(id, 2);
+        assert_eq!(mcu.run_for(1000), StopReason::BREAKPOINT(0x05));
+        assert_eq!(mcu.read(0x30), Some(&3));
+        assert_eq!(mcu.debugger.breakpoints[0].hits, 3);
+        assert_eq!(
+            mcu.debugger.last_event,
+            Some(MCS51_DEBUG_EVENT::BREAKPOINT { id, pc: 0x05 })
+        );
+
+        // Conditions see registers and memory
+        assert_eq!(mcu.set_condition(id, "I:0x30 == 6 && A == 5"), Ok(()));
+        assert_eq!(mcu.run_for(1000), StopReason::BREAKPOINT(0x05));
+        assert_eq!(mcu.read(0x30), Some(&6));
+        assert!(mcu.set_condition(id, "I:0x30 ==").is_err());
+        assert!(mcu.set_condition(99, "A").is_err());
+        assert!(mcu.debugger.delete(id));
+        assert!(!mcu.debugger.delete(id));
+
+        // Temporary breakpoints go away when they stop the core
+        mcu.add_temporary_breakpoint(0x0B);
+        assert_eq!(mcu.run_for(1000), StopReason::BREAKPOINT(0x0B));
+        assert!(mcu.debugger.breakpoints.is_empty());
+        assert_eq!(mcu.run_for(50), StopReason::BUDGET);
+
+        // A disabled breakpoint does not stop
+        let id = mcu.add_breakpoint(0x04);
+        mcu.debugger.set_enabled(id, false);
+        assert_eq!(mcu.run_for(50), StopReason::BUDGET);
+        mcu.debugger.set_enabled(id, true);
+        assert_eq!(mcu.run_for(50), StopReason::BREAKPOINT(0x04));
+    }
+
+    #[test]
+    fn watchpoints_mcs51() {
+        let mut mcu = debugger_test_mcu();
+        mcu.xdata.map_ram(0x0000, 0xFFFF);
+
+        let id = mcu.add_watchpoint(MCS51_WATCH_SPACE::XDATA, 0x1234, MCS51_WATCH_KIND::WRITE);
+        assert_eq!(mcu.run_for(1000), StopReason::WATCHPOINT { pc: 0x0B, address: 0x1234 });
+        assert_eq!(mcu.pc, 0x0C);
+        assert_eq!(
+            mcu.debugger.last_event,
+            Some(MCS51_DEBUG_EVENT::WATCHPOINT {
+                id,
+                pc: 0x0B,
+                space: MCS51_WATCH_SPACE::XDATA,
+                address: 0x1234,
+                kind: MCS51_WATCH_KIND::WRITE,
+                old: Some(0x00),
+                new: Some(0x01),
+            })
+        );
+        mcu.debugger.delete(id);
+
+        // The read of INC @R0, R0 itself is read by every @R0 instruction
+        let id = mcu.add_watchpoint(MCS51_WATCH_SPACE::IRAM, 0x30, MCS51_WATCH_KIND::READ);
+        assert_eq!(mcu.run_for(1000), StopReason::WATCHPOINT { pc: 0x04, address: 0x30 });
+        mcu.debugger.delete(id);
+        let id = mcu.add_watchpoint(MCS51_WATCH_SPACE::IRAM, 0x00, MCS51_WATCH_KIND::READ);
+        assert_eq!(mcu.run_for(1000), StopReason::WATCHPOINT { pc: 0x05, address: 0x00 });
+        mcu.debugger.delete(id);
+
+        // Bits and the bytes holding them see each other
+        let id = mcu.add_watchpoint(MCS51_WATCH_SPACE::SFR, 0x90, MCS51_WATCH_KIND::WRITE);
+        assert_eq!(mcu.run_for(1000), StopReason::WATCHPOINT { pc: 0x06, address: 0x90 });
+        mcu.debugger.delete(id);
+        let id = mcu.add_watchpoint(MCS51_WATCH_SPACE::BIT, 0xE0, MCS51_WATCH_KIND::WRITE);
+        assert_eq!(mcu.run_for(1000), StopReason::WATCHPOINT { pc: 0x05, address: 0xE0 });
+        mcu.debugger.delete(id);
+
+        // Value changes, with a condition on the new value
+        let id = mcu.add_watchpoint(MCS51_WATCH_SPACE::IRAM, 0x30, MCS51_WATCH_KIND::CHANGE);
+        mcu.set_condition(id, "D:0x30 == 0x10").unwrap();
+        assert_eq!(mcu.run_for(1000), StopReason::WATCHPOINT { pc: 0x04, address: 0x30 });
+        match mcu.debugger.last_event {
+            Some(MCS51_DEBUG_EVENT::WATCHPOINT { old, new, .. }) => {
+                assert_eq!((old, new), (Some(0x0F), Some(0x10)));
+            }
+            _ => panic!("watchpoint expected"),
+        }
+
+        // Without run control the core halts until the event is taken
+        mcu.set_condition(id, "").unwrap();
+        clock_n(&mut mcu, 20);
+        let pc = mcu.pc;
+        clock_n(&mut mcu, 20);
+        assert_eq!(mcu.pc, pc);
+        assert!(mcu.take_debug_event().is_some());
+        clock_n(&mut mcu, 2);
+        assert_ne!(mcu.pc, pc);
+    }
+
+    #[test]
+    fn debugger_expressions_mcs51() {
+        let mut mcu = debugger_test_mcu();
+        mcu.xdata.map_ram(0x0000, 0xFFFF);
+        mcu.write_xdata(0x1234, 0x5A);
+        mcu.write(0x30, 0x21);
+        mcu.write_register(0, 0x30);
+        mcu.set_dptr(0x1234);
+        mcu.set_accumulator(0x0F);
+        mcu.set_carry_flag(true);
+        mcu.write_sfr(MCS51_REGISTERS::P1, 0xFE);
+
+        let evaluate = |text: &str| MCS51_Expression::parse(text).unwrap().evaluate(&mcu);
+        assert_eq!(evaluate("1FH + 0x10 - 1"), 0x2E);
+        assert_eq!(evaluate("A == 15 && C && !OV"), 1);
+        assert_eq!(evaluate("ACC.0 + P1.0 + PSW.7"), 2);
+        assert_eq!(evaluate("I:R0"), 0x21);
+        assert_eq!(evaluate("D:(R0 + 0)"), 0x21);
+        assert_eq!(evaluate("X:DPTR"), 0x5A);
+        assert_eq!(evaluate("C:0 == 0x78 || 0"), 1);
+        assert_eq!(evaluate("BIT:0x90 | 2"), 2);
+        assert_eq!(evaluate("~0 & 0xFF ^ 0x0F"), 0xF0);
+        assert_eq!(evaluate("SP <= 7 && PC >= 0 && B != 1 && TMOD < 1 && P1 > 0"), 1);
+        assert_eq!(evaluate("-1"), -1);
+
+        for text in ["", "A ==", "(A", "Q", "R8", "SP.1", "P1.8", "Z:1", "A B", "0xZZ"] {
+            assert!(MCS51_Expression::parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn repl_mcs51_commands() {
+        let mut repl = MCS51_Repl::new(run_control_mcu(vec![
+            0x75, 0x81, 0x30, // MOV SP, #30H
+            0x12, 0x00, 0x0A, // LCALL 000AH
+            0x04, // INC A
+            0x80, 0xFE, // SJMP $
+            0x00, // NOP
+            0x78, 0x40, // MOV R0, #40H
+            0x11, 0x10, // ACALL 0010H
+            0x22, // RET
+            0x00, // NOP
+            0x76, 0x55, // MOV @R0, #55H
+            0x22, // RET
+        ]));
+        repl.mcu.xdata.map_ram(0x0000, 0xFFFF);
+
+        let output = repl.execute("step").unwrap();
+        assert!(output.contains("=> 0003: 12 00 0A  LCALL"), "{}", output);
+        repl.execute("next").unwrap();
+        assert_eq!(repl.mcu.pc, 0x06);
+        assert_eq!(repl.mcu.read_indirect(0x40), Some(&0x55));
+        repl.execute("s 2").unwrap();
+        assert_eq!(repl.mcu.pc, 0x07);
+        assert_eq!(repl.mcu.get_accumulator(), 1);
+
+        repl.mcu.reset();
+        assert_eq!(repl.execute("break 0x10").unwrap(), "Breakpoint 1 at 0010");
+        let output = repl.execute("continue").unwrap();
+        assert!(output.starts_with("Breakpoint 1 at 0010"), "{}", output);
+        assert_eq!(
+            repl.execute("bt").unwrap(),
+            "#0 0010\n#1 000E called from 000C\n#2 0006 called from 0003"
+        );
+        repl.execute("finish").unwrap();
+        assert_eq!(repl.mcu.pc, 0x0E);
+        assert!(repl.execute("regs").unwrap().starts_with("PC=000E A=00 B=00 SP=32"));
+        assert!(repl.execute("list").unwrap().starts_with("1 break 0010 hits 1 ignore 0"));
+        assert!(repl.execute("delete 1").is_ok());
+        assert!(repl.execute("delete 1").is_err());
+        assert_eq!(repl.execute("list").unwrap(), "No breakpoints or watchpoints");
+
+        assert_eq!(repl.execute("x/4 I:0x40").unwrap(), "I:0040: 55 00 00 00");
+        assert_eq!(repl.execute("x/2 C:3").unwrap(), "C:0003: 12 00");
+        repl.execute("set A = 0x12").unwrap();
+        repl.execute("set R1 = A + 1").unwrap();
+        repl.execute("set X:0x100 = 7").unwrap();
+        repl.execute("set PSW.7 1").unwrap();
+        repl.execute("set DPTR = 0x1234").unwrap();
+        assert_eq!(repl.mcu.get_accumulator(), 0x12);
+        assert!(!repl.mcu.get_parity_flag());
+        assert_eq!(repl.mcu.read_register(1), 0x13);
+        assert_eq!(repl.mcu.read_xdata(0x100), 7);
+        assert!(repl.mcu.get_carry_flag());
+        assert_eq!(repl.mcu.get_dptr(), 0x1234);
+        assert!(repl.execute("set 3 = 4").is_err());
+
+        assert_eq!(
+            repl.execute("disas 0 6").unwrap(),
+            "   0000: 75 81 30  MOV SP, #30\n   0003: 12 00 0A  LCALL FUN_000a\n   0006: 04        INC A"
+        );
+
+        // Temporary conditional breakpoint and watchpoint
+        repl.mcu.reset();
+        repl.execute("tbreak 0x07 if A == 1").unwrap();
+        let output = repl.execute("c").unwrap();
+        assert!(output.starts_with("Breakpoint 2 at 0007"), "{}", output);
+        assert!(repl.mcu.debugger.breakpoints.is_empty());
+        repl.mcu.reset();
+        assert_eq!(repl.execute("watch c I:0x40").unwrap(), "Watchpoint 3 CHANGE IRAM:0040");
+        repl.execute("set I:0x40 = 0").unwrap();
+        let output = repl.execute("c").unwrap();
+        assert!(output.starts_with("Watchpoint 3 CHANGE IRAM:0040 at 0010, Some(00) -> Some(55)"), "{}", output);
+        assert!(repl.execute("break 1 if A ==").is_err());
+        assert!(repl.execute("watch C:0").is_err());
+
+        let output = repl.execute("help setb").unwrap();
+        assert!(output.starts_with("SETB: Set Bit"), "{}", output);
+        assert!(repl.execute("help").unwrap().contains("CPL A"));
+        assert!(repl.execute("help foo").unwrap().starts_with("No description"));
+        assert!(repl.execute("frobnicate").is_err());
+        assert!(repl.execute("load /nonexistent/image.bin").is_err());
+    }
+
+    fn cli_args(line: &str) -> Vec<String> {
+        return line.split_whitespace().map(|arg| arg.to_owned()).collect();
+    }
+
+    #[test]
+    fn cli_options() {
+        let options = CLI_Options::parse(&cli_args(
+            "run --mcu at89s52 --base 0x100 --start 100H --clock 11.0592M --max-cycles 5000 \
+             --break 0x10 --break 20 -o out.txt firmware.HEX",
+        ))
+        .unwrap();
+        assert_eq!(options.command, CLI_COMMAND::RUN);
+        assert_eq!(options.file, "firmware.HEX");
+        assert_eq!(options.mcu, "at89s52");
+        assert_eq!(options.format, CLI_FORMAT::HEX);
+        assert_eq!(options.base, 0x100);
+        assert_eq!(options.start, 0x100);
+        assert_eq!(options.clock, Some(11_059_200));
+        assert_eq!(options.max_cycles, Some(5000));
+        assert_eq!(options.breakpoints, vec![0x10, 20]);
+        assert_eq!(options.output, Some("out.txt".to_owned()));
+
+        let options = CLI_Options::parse(&cli_args("disasm --format bin dump.hex")).unwrap();
+        assert_eq!(options.command, CLI_COMMAND::DISASM);
+        assert_eq!(options.format, CLI_FORMAT::BIN);
+        assert_eq!(options.mcu, "8051");
+        assert_eq!(options.clock, None);
+        assert_eq!(CLI_Options::parse(&cli_args("trace dump.bin")).unwrap().format, CLI_FORMAT::BIN);
+
+        assert_eq!(parse_frequency("12MHz"), Some(12_000_000));
+        assert_eq!(parse_frequency("32.768k"), Some(32_768));
+        assert_eq!(parse_frequency("0"), None);
+
+        for line in [
+            "",
+            "flash dump.bin",
+            "run",
+            "run a.bin b.bin",
+            "run --clock fast a.bin",
+            "run --start 0x10000 a.bin",
+            "run --max-cycles 0 a.bin",
+            "run --format elf a.bin",
+            "run --verbose a.bin",
+            "run a.bin --mcu",
+        ] {
+            assert!(CLI_Options::parse(&cli_args(line)).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn intel_hex_mcs51() {
+        let text = "\
+:0300000002003BC0
+:02003B00800043
+:020000040000FA
+:01003D0000C2
+:00000001FF
+";
+        let segments = parse_intel_hex(text).unwrap();
+        assert_eq!(
+            segments,
+            vec![
+                MCS51_Hex_Segment {
+                    address: 0x0000,
+                    data: vec![0x02, 0x00, 0x3B]
+                },
+                MCS51_Hex_Segment {
+                    address: 0x003B,
+                    data: vec![0x80, 0x00, 0x00]
+                },
+            ]
+        );
+
+        let mut mcu = MCS51::new();
+        mcu.load_program(0x1000, &[0xFF]).unwrap();
+        assert_eq!(mcu.load_intel_hex(text), Ok(6));
+        assert_eq!(mcu.fetch_code(0x0001), Some(0x00));
+        assert_eq!(mcu.fetch_code(0x003C), Some(0x00));
+        assert_eq!(mcu.fetch_code(0x0003), None);
+        assert_eq!(mcu.fetch_code(0x1000), None);
+
+        assert!(parse_intel_hex(":0300000002003BC1\n:00000001FF").unwrap_err().contains("Checksum"));
+        assert!(parse_intel_hex(":0300000002003BC0").unwrap_err().contains("end of file"));
+        assert!(parse_intel_hex("0300000002003BC0\n").is_err());
+        assert!(parse_intel_hex(":0400000002003BC0\n").is_err());
+        assert!(parse_intel_hex(":0300000602003BBA\n").unwrap_err().contains("record type"));
+        assert!(mcu
+            .load_intel_hex(":020000040001F9\n:0100000000FF\n:00000001FF")
+            .unwrap_err()
+            .contains("does not fit"));
+    }
+
+    #[test]
+    fn cli_commands_mcs51() {
+        let directory = std::env::temp_dir().join(format!("microchip_rs_cli_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_owned();
+
+        // MOV A, #3 / INC A / SJMP $, loaded at 100H
+        std::fs::write(path("image.bin"), [0x74, 0x03, 0x04, 0x80, 0xFE]).unwrap();
+
+        let options = CLI_Options::parse(&cli_args(&format!(
+            "run --mcu 80C52 --base 0x100 --start 0x100 --clock 6M --max-cycles 3 {}",
+            path("image.bin")
+        )))
+        .unwrap();
+        let mcu = create_mcu(&options).unwrap();
+        assert_eq!(mcu.get_derivative().name, "8052");
+        assert_eq!(mcu.get_oscillator_frequency(), 6_000_000);
+        assert_eq!(mcu.pc, 0x100);
+        assert!(!mcu.is_code_programmed(0));
+
+        let output = path("run.txt");
+        run_cli(&cli_args(&format!(
+            "run --base 0x100 --start 0x100 --max-cycles 6 -o {} {}",
+            output,
+            path("image.bin")
+        )))
+        .unwrap();
+        let text = std::fs::read_to_string(&output).unwrap();
+        assert!(text.starts_with("Stopped: BUDGET\n=> 0103: 80 FE"), "{}", text);
+        assert!(text.contains("PC=0103 A=04"), "{}", text);
+
+        run_cli(&cli_args(&format!(
+            "run --base 0x100 --start 0x100 --break 0x102 -o {} {}",
+            output,
+            path("image.bin")
+        )))
+        .unwrap();
+        let text = std::fs::read_to_string(&output).unwrap();
+        assert!(text.starts_with("Breakpoint 1 at 0102"), "{}", text);
+
+        run_cli(&cli_args(&format!(
+            "trace --base 0x100 --start 0x100 --max-cycles 4 -o {} {}",
+            output,
+            path("image.bin")
+        )))
+        .unwrap();
+        let text = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(
+            text,
+            "0100: MOV A, #03\n0102: INC A\n0103: SJMP LAB_0103\nStopped: BUDGET\n"
+        );
+
+        // Without a budget the lines are written as the core runs, until it stops
+        run_cli(&cli_args(&format!(
+            "trace --base 0x100 --start 0x100 --break 0x103 -o {} {}",
+            output,
+            path("image.bin")
+        )))
+        .unwrap();
+        let text = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(text, "0100: MOV A, #03\n0102: INC A\nStopped: BREAKPOINT(259)\n");
+
+        let csv = path("trace.csv");
+        run_cli(&cli_args(&format!(
+            "trace --base 0x100 --start 0x100 --max-cycles 4 --trace-format csv --range 0x102-0x1FF -o {} {}",
+            csv,
+            path("image.bin")
+        )))
+        .unwrap();
+        let text = std::fs::read_to_string(&csv).unwrap();
+        assert_eq!(
+            text,
+            "pc,bytes,disassembly,cycle,cycles,registers,flags,writes\n\
+             0102,04,\"INC A\",1,1,A:03->04,P:0->1,\n\
+             0103,80 FE,\"SJMP LAB_0103\",2,2,,,\n"
+        );
+        let options = CLI_Options::parse(&cli_args("trace --writes --trace-format jsonl --range 10H-20H a.bin")).unwrap();
+        assert_eq!(options.trace_format, Some(MCS51_TRACE_FORMAT::JSONL));
+        assert_eq!(options.range, (0x10, 0x20));
+        assert!(options.memory_writes);
+        assert!(CLI_Options::parse(&cli_args("trace --trace-format xml a.bin")).is_err());
+        assert!(CLI_Options::parse(&cli_args("trace --range 0x10 a.bin")).is_err());
+
+        let asm = path("image.asm");
+        run_cli(&cli_args(&format!("disasm --base 0x100 --start 0x100 -o {} {}", asm, path("image.bin")))).unwrap();
+        assert!(std::fs::read_to_string(&asm).unwrap().contains("INC A"));
+
+        // A tiny image is disassembled up to its end, not through the fill after it
+        std::fs::write(path("tiny.bin"), [0x00, 0x00, 0x00]).unwrap();
+        run_cli(&cli_args(&format!("disasm -o {} {}", asm, path("tiny.bin")))).unwrap();
+        let text = std::fs::read_to_string(&asm).unwrap();
+        assert_eq!(text.matches("NOP").count(), 3, "{}", text);
+        run_cli(&cli_args(&format!("disasm --base 0xFFFD --start 0xFFFD -o {} {}", asm, path("tiny.bin")))).unwrap();
+
+        std::fs::write(path("image.hex"), ":0300000074030482\n:0200030080FE7D\n:00000001FF\n").unwrap();
+        run_cli(&cli_args(&format!("bench --max-cycles 1000 -o {} {}", output, path("image.hex")))).unwrap();
+        let text = std::fs::read_to_string(&output).unwrap();
+        assert!(text.starts_with("1000 machine cycles"), "{}", text);
+
+        assert!(run_cli(&cli_args(&format!("run --mcu 8086 {}", path("image.bin")))).is_err());
+        assert!(run_cli(&cli_args(&format!("run {}", path("missing.bin")))).is_err());
+        assert!(run_cli(&cli_args(&format!("run --base 0xFFFE {}", path("image.bin")))).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    fn trace_test_mcu(start: u16, end: u16, memory_writes: bool) -> (MCS51, Vec<MCS51_Trace_Record>) {
+        let mut mcu = run_control_mcu(vec![
+            0x75, 0x81, 0x30, // MOV SP, #30H
+            0x74, 0x7F, // MOV A, #7FH
+            0x24, 0x01, // ADD A, #01H
+            0xF5, 0x40, // MOV 40H, A
+            0x90, 0x10, 0x00, // MOV DPTR, #1000H
+            0xF0, // MOVX @DPTR, A
+            0x12, 0x00, 0x12, // LCALL 0012H
+            0x80, 0xFE, // SJMP $
+            0xD3, // SETB C
+            0x22, // RET
         ]);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut tracer = MCS51_Tracer::new(Box::new(sender));
+        tracer.set_range(start, end);
+        tracer.set_memory_writes(memory_writes);
+        mcu.attach_tracer(tracer);
+        mcu.run_until(|mcu| mcu.pc == 0x10);
+        let tracer = mcu.detach_tracer().unwrap();
+        assert!(tracer.error.is_none());
+        return (mcu, receiver.try_iter().collect());
+    }
+
+    #[test]
+    fn trace_records_mcs51() {
+        let (_, records) = trace_test_mcu(0x0000, 0xFFFF, true);
+        let lines: Vec<String> = records
+            .iter()
+            .map(|record| {
+                let deltas: Vec<String> = record
+                    .registers
+                    .iter()
+                    .chain(record.flags.iter())
+                    .map(|delta| format!("{}:{:X}->{:X}", delta.get_name(), delta.old, delta.new))
+                    .collect();
+                format!(
+                    "{:04X} {:02X?} {} @{}+{} [{}] {:X?}",
+                    record.pc,
+                    record.bytes,
+                    record.disassembly,
+                    record.cycle,
+                    record.cycles,
+                    deltas.join(" "),
+                    record.writes.iter().map(|write| (write.space, write.address, write.value)).collect::<Vec<_>>()
+                )
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "0000 [75, 81, 30] MOV SP, #30 @0+2 [SP:7->30] []",
+                "0003 [74, 7F] MOV A, #7f @2+1 [A:0->7F P:0->1] []",
+                "0005 [24, 01] ADD A, #01 @3+1 [A:7F->80 AC:0->1 OV:0->1] []",
+                "0007 [F5, 40] MOV 40, A @4+1 [] [(IRAM, 40, 80)]",
+                "0009 [90, 10, 00] MOV DPTR, #1000 @5+2 [DPTR:0->1000] []",
+                "000C [F0] MOVX @DPTR, A @7+2 [] [(XDATA, 1000, 80)]",
+                "000D [12, 00, 12] LCALL FUN_0012 @9+2 [SP:30->32] [(IRAM, 31, 10), (IRAM, 32, 0)]",
+                "0012 [D3] SETB C @11+1 [CY:0->1] []",
+                "0013 [22] RET @12+2 [SP:32->30] []",
+            ]
+        );
+
+        // Without memory writes, and only in the called function
+        let (mcu, records) = trace_test_mcu(0x0012, 0x001F, false);
+        assert_eq!(records.iter().map(|record| record.pc).collect::<Vec<u16>>(), vec![0x12, 0x13]);
+        assert!(records.iter().all(|record| record.writes.is_empty()));
+        assert_eq!(records[0].cycle, 11);
+        assert_eq!(mcu.pc, 0x10);
+
+        // A sink error stops the recording, the core keeps running
+        let (sender, receiver) = std::sync::mpsc::channel();
+        drop(receiver);
+        let mut mcu = run_control_mcu(vec![0x00, 0x00, 0x80, 0xFE]);
+        mcu.attach_tracer(MCS51_Tracer::new(Box::new(sender)));
+        mcu.run_for(4);
+        let tracer = mcu.detach_tracer().unwrap();
+        assert!(tracer.error.is_some());
+        assert_eq!(tracer.records, 0);
+        assert!(mcu.detach_tracer().is_none());
+
+        // With the second data pointer selected, the first one is a plain SFR write
+        let mut mcu = MCS51::new_derivative(&MCS51_AT89S52);
+        mcu.reset();
+        mcu.set_program(vec![
+            0x75, 0xA2, 0x01, // MOV AUXR1, #01H
+            0x75, 0x82, 0x34, // MOV DPL, #34H
+            0x75, 0x84, 0x56, // MOV 84H, #56H
+        ])
+        .unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut tracer = MCS51_Tracer::new(Box::new(sender));
+        tracer.set_memory_writes(true);
+        mcu.attach_tracer(tracer);
+        mcu.run_for(6);
+        let records: Vec<MCS51_Trace_Record> = receiver.try_iter().collect();
+        let writes: Vec<Vec<(u16, u8)>> = records
+            .iter()
+            .map(|record| record.writes.iter().map(|write| (write.address, write.value)).collect())
+            .collect();
+        assert_eq!(writes, vec![vec![(0xA2, 0x01)], vec![(0x82, 0x34)], vec![]]);
+        assert_eq!(records[2].registers[0].get_name(), "DPTR");
+        assert_eq!(records[2].registers[0].new, 0x0056);
+    }
+
+    #[test]
+    fn trace_formats_mcs51() {
+        let record = MCS51_Trace_Record {
+            pc: 0x0105,
+            bytes: vec![0x24, 0x01],
+            disassembly: "ADD A, #01".to_owned(),
+            cycle: 300,
+            cycles: 1,
+            registers: vec![MCS51_Trace_Delta {
+                register: 0,
+                old: 0x7F,
+                new: 0x80,
+            }],
+            flags: vec![MCS51_Trace_Delta {
+                register: 17,
+                old: 0,
+                new: 1,
+            }],
+            writes: vec![MCS51_Trace_Write {
+                space: MCS51_WATCH_SPACE::XDATA,
+                address: 0x1000,
+                value: 0x80,
+            }],
+        };
+        let write = |format: MCS51_TRACE_FORMAT| {
+            let mut writer = MCS51_Trace_Writer::new(Vec::new(), format);
+            writer.record(&record).unwrap();
+            writer.record(&record).unwrap();
+            writer.flush().unwrap();
+            writer.into_inner()
+        };
+
+        assert_eq!(
+            String::from_utf8(write(MCS51_TRACE_FORMAT::CSV)).unwrap(),
+            "pc,bytes,disassembly,cycle,cycles,registers,flags,writes\n\
+             0105,24 01,\"ADD A, #01\",300,1,A:7F->80,OV:0->1,X:1000=80\n\
+             0105,24 01,\"ADD A, #01\",300,1,A:7F->80,OV:0->1,X:1000=80\n"
+        );
+        let jsonl = String::from_utf8(write(MCS51_TRACE_FORMAT::JSONL)).unwrap();
+        assert_eq!(
+            jsonl.lines().next().unwrap(),
+            "{\"pc\":261,\"bytes\":\"24 01\",\"disassembly\":\"ADD A, #01\",\"cycle\":300,\"cycles\":1,\
+             \"registers\":{\"A\":[127,128]},\"flags\":{\"OV\":[0,1]},\"writes\":[[\"X\",4096,128]]}"
+        );
+        assert_eq!(jsonl.lines().count(), 2);
+
+        let binary = write(MCS51_TRACE_FORMAT::BINARY);
+        let mut expected = b"MCS51TR\x01".to_vec();
+        for _ in 0..2 {
+            expected.extend_from_slice(&[
+                0x05, 0x01, // pc
+                0x2C, 0x01, 0, 0, 0, 0, 0, 0, // cycle
+                1, 2, 0x24, 0x01, // cycles, bytes
+                2, 0, 0x7F, 0, 0x80, 0, 17, 0, 0, 1, 0, // deltas
+                1, 3, 0x00, 0x10, 0x80, // writes
+            ]);
+        }
+        assert_eq!(binary, expected);
+
+        assert_eq!(MCS51_TRACE_FORMAT::from_name("JSONL"), Some(MCS51_TRACE_FORMAT::JSONL));
+        assert_eq!(MCS51_TRACE_FORMAT::from_name("xml"), None);
+    }
+
+    #[test]
+    fn bit_mov_operations_mcs51() {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(vec![
+            0x04, // Increment Accumulator
+            0x04, // Increment Accumulator
+            0x04, // Increment Accumulator
+            0x09, // Increment Register 1
+            0x09, // Increment Register 1
+            0x09, // Increment Register 1
+            0x74, 0xFE, // Store 0xFE in accumulator
+            0x79, 0xFD, // Store 0xFD in R1
+        ]).unwrap();
+        for _i in 0..6 {
+            mcu.next_instruction();
+        }
+
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0xFE);
+
+        mcu.next_instruction();
+        assert_eq!(mcu.read_register(1), 0xFD);
+    }
+
+    #[test]
+    fn add_operations_mcs51() {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(vec![
+            0x74, 0xC3, // Store 0xC3 in Accumulator
+            0x79, 0xAA, // Store 0xAA in R1
+            0x29, //Add R1 to accumulator
+        ]).unwrap();
+        mcu.next_instruction();
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.read_register(1), 0xAA);
+        assert_eq!(mcu.get_accumulator(), 0x6D);
+        assert_eq!(mcu.get_aux_carry_flag(), false);
+        assert_eq!(mcu.get_carry_flag(), true);
+        assert_eq!(mcu.get_overflow_flag(), true);
+    }
+
+    struct LatchDevice {
+        writes: std::rc::Rc<std::cell::RefCell<Vec<(u16, u8)>>>,
+    }
+
+    impl BusComponent for LatchDevice {
+        fn read(&mut self, address: u16) -> u8 {
+            address as u8 | 0x40
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.writes.borrow_mut().push((address, value));
+        }
+    }
+
+    #[test]
+    fn movx_dptr_operations_mcs51() {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(vec![
+            0x90, 0x80, 0x04, // MOV DPTR, #8004
+            0x74, 0xFF, // MOV A, #FF
+            0xF0, // MOVX @DPTR, A
+            0x74, 0x00, // MOV A, #00
+            0xE0, // MOVX A, @DPTR
+        ]).unwrap();
+        for _i in 0..4 {
+            mcu.next_instruction();
+        }
+        assert_eq!(mcu.xdata.memory[0x8004], 0xFF);
+        assert_eq!(mcu.get_accumulator(), 0);
+
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0xFF);
+        assert_eq!(mcu.pc, 9);
+    }
+
+    #[test]
+    fn movx_ri_operations_mcs51() {
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(vec![
+            0x75, 0xA0, 0x12, // MOV P2, #12
+            0x78, 0x34, // MOV R0, #34
+            0x79, 0x35, // MOV R1, #35
+            0x74, 0x5A, // MOV A, #5A
+            0xF2, // MOVX @R0, A
+            0xE3, // MOVX A, @R1
+        ]).unwrap();
+        for _i in 0..5 {
+            mcu.next_instruction();
+        }
+        assert_eq!(mcu.xdata.memory[0x1234], 0x5A);
+
+        mcu.xdata.memory[0x1235] = 0xA5;
+        mcu.next_instruction();
+        assert_eq!(mcu.get_accumulator(), 0xA5);
+    }
+
+    #[test]
+    fn xdata_mapping_mcs51() {
+        let writes = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.xdata.map_rom(0x0000, &[0x11, 0x22, 0x33]).unwrap();
+        mcu.xdata.map_device(0x8000, 0x800F, Box::new(LatchDevice { writes: writes.clone() }));
+
+        mcu.write_xdata(0x0001, 0x99);
+        assert_eq!(mcu.read_xdata(0x0001), 0x22);
+
+        mcu.write_xdata(0x0003, 0x99);
+        assert_eq!(mcu.read_xdata(0x0003), 0x99);
+
+        mcu.write_xdata(0x800C, 0xFF);
+        assert_eq!(*writes.borrow(), vec![(0x000C, 0xFF)]);
+        assert_eq!(mcu.read_xdata(0x8004), 0x44);
+
+        // Images past FFFFH are refused without mapping anything
+        let mappings = mcu.xdata.mappings.len();
+        assert!(mcu.xdata.map_rom(0xFFFF, &[0x01, 0x02]).unwrap_err().contains("do not fit"));
+        assert!(mcu.xdata.load(0xFFFE, &[0x01, 0x02, 0x03]).is_err());
+        assert_eq!(mcu.xdata.mappings.len(), mappings);
+        assert_eq!(mcu.xdata.memory[0xFFFF], 0x00);
+
+        mcu.xdata.clear_mappings();
+        assert_eq!(mcu.read_xdata(0x0003), 0xFF);
+    }
+
+    // NOPs everywhere, RETI on every interrupt vector except the ones given as ISR bodies
+    fn interrupt_test_mcu(isr: &[(u16, Vec<u8>)]) -> MCS51 {
+        let mut program = vec![0x00; 0x100];
+        for vector in (0x03..0x33).step_by(8) {
+            program[vector] = 0x32;
+        }
+        for (addr, code) in isr {
+            program[*addr as usize..*addr as usize + code.len()].copy_from_slice(code);
+        }
+
+        let mut mcu = MCS51::new();
+        mcu.reset();
+        mcu.set_program(program).unwrap();
+        mcu.pc = 0x40;
+        mcu
+    }
+
+    #[test]
+    fn interrupt_vectoring_mcs51() {
+        let mut mcu = interrupt_test_mcu(&[]);
+
+        // Flag set but interrupt disabled
+        mcu.write_sfr(MCS51_REGISTERS::TCON, 0x20);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x41);
+
+        // Source enabled but EA cleared
+        mcu.write(0xA8, 0x02);
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x43);
+
+        // Writing IE delays the interrupt by one instruction
+        mcu.write(0xA8, 0x82);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x44);
+
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x0B);
+        assert_eq!(mcu.get_stack_pointer(), 0x09);
+        assert_eq!(*mcu.read(0x08).unwrap(), 0x44);
+        assert_eq!(*mcu.read(0x09).unwrap(), 0x00);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TCON) & 0x20, 0);
+
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x44);
+        assert_eq!(mcu.get_stack_pointer(), 0x07);
+        assert_eq!(mcu.interrupt_in_progress, [false, false]);
+
+        // Serial and Timer 2 flags are not cleared by the hardware
+        mcu.write(0xA8, 0xB0);
+        mcu.write_sfr(MCS51_REGISTERS::SCON, 0x02);
+        mcu.write_sfr(MCS51_REGISTERS::T2CON, 0x40);
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x23);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::SCON), 0x02);
+        mcu.write_sfr(MCS51_REGISTERS::SCON, 0x00);
+        mcu.next_instruction();
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x2B);
+    }
+
+    #[test]
+    fn interrupt_rmw_ie_mcs51() {
+        // ORL IE, #80H / NOP
+        let mut mcu = interrupt_test_mcu(&[(0x40, vec![0x43, 0xA8, 0x80])]);
+        mcu.write_sfr(MCS51_REGISTERS::IE, 0x02);
+        mcu.write_sfr(MCS51_REGISTERS::TCON, 0x20);
+
+        // Read-modify-write instructions delay the interrupt like MOV IE
         mcu.next_instruction();
-        assert_eq!(mcu.get_accumulator(), 1);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::IE), 0x82);
         mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x44);
         mcu.next_instruction();
-        assert_eq!(mcu.get_accumulator(), 3);
+        assert_eq!(mcu.pc, 0x0B);
+
+        // INC IE
+        let mut mcu = interrupt_test_mcu(&[(0x40, vec![0x05, 0xA8])]);
+        mcu.write_sfr(MCS51_REGISTERS::IE, 0x81);
+        mcu.write_sfr(MCS51_REGISTERS::TCON, 0x20);
         mcu.next_instruction();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::IE), 0x82);
         mcu.next_instruction();
-        assert_eq!(mcu.read_register(1), 2);
+        assert_eq!(mcu.pc, 0x43);
         mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x0B);
+    }
+
+    #[test]
+    fn interrupt_priority_mcs51() {
+        let mut mcu = interrupt_test_mcu(&[(0x0B, vec![0x00, 0x00, 0x00, 0x32])]);
+        mcu.write(0xA8, 0x8F); // EA, ET1, EX1, ET0, EX0
+        mcu.write(0xB8, 0x01); // PX0
+        mcu.write_sfr(MCS51_REGISTERS::TCON, 0x05); // Edge triggered INT0 and INT1
         mcu.next_instruction();
-        assert_eq!(mcu.get_accumulator(), 1);
+
+        // Low priority Timer 0
+        mcu.write_sfr(MCS51_REGISTERS::TCON, 0x25);
         mcu.next_instruction();
-        assert_eq!(mcu.read_register(1), 1);
+        assert_eq!(mcu.pc, 0x0B);
+        assert_eq!(mcu.interrupt_in_progress, [true, false]);
 
-        //Switch bank
-        mcu.write(0xD0, 0b00001000);
+        // Timer 1 has the same level and can't interrupt
+        mcu.write_sfr(MCS51_REGISTERS::TCON, 0x85);
         mcu.next_instruction();
-        assert_eq!(mcu.read_register(1), 1);
+        assert_eq!(mcu.pc, 0x0C);
+
+        // External 0 is high priority and nests
+        mcu.write_sfr(MCS51_REGISTERS::TCON, 0x87);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x03);
+        assert_eq!(mcu.interrupt_in_progress, [true, true]);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TCON), 0x85);
+
+        // Nothing can interrupt a high priority routine
+        mcu.write_sfr(MCS51_REGISTERS::TCON, 0x8F);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x0C);
+        assert_eq!(mcu.interrupt_in_progress, [true, false]);
+
+        // One instruction always runs after RETI, then INT0 is taken again
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x0D);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x03);
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x0E);
+
+        // INT1 and Timer 1 are pending, the Timer 0 routine returns first
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x41);
+        assert_eq!(mcu.interrupt_in_progress, [false, false]);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x42);
+
+        // Same level requests follow the polling sequence
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x13);
+        mcu.next_instruction();
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x1B);
+
+        // Higher level wins over the polling sequence
+        mcu.next_instruction();
+        mcu.next_instruction();
+        mcu.write(0xB8, 0x08); // PT1
+        mcu.write_sfr(MCS51_REGISTERS::TCON, 0xA5);
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x1B);
+        assert_eq!(mcu.interrupt_in_progress, [false, true]);
     }
 
     #[test]
-    fn banking_operations_mcs51() {
+    fn external_interrupt_mcs51() {
+        let mut mcu = interrupt_test_mcu(&[]);
+        mcu.write(0xA8, 0x81); // EA, EX0
+        mcu.write_sfr(MCS51_REGISTERS::TCON, 0x01); // Edge triggered
+        mcu.next_instruction();
+
+        // Falling edge on INT0 latches IE0
+        mcu.write_sfr(MCS51_REGISTERS::P3, 0xFB);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x03);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TCON) & 0x02, 0);
+        mcu.next_instruction();
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x43);
+
+        // Level triggered, the routine is entered again as long as the pin is low
+        mcu.write_sfr(MCS51_REGISTERS::TCON, 0x00);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x03);
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x44);
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x03);
+
+        mcu.write_sfr(MCS51_REGISTERS::P3, 0xFF);
+        mcu.next_instruction();
+        mcu.next_instruction();
+        mcu.next_instruction();
+        assert_eq!(mcu.pc, 0x46);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TCON) & 0x02, 0);
+    }
+
+    fn timer_test_mcu(tmod: u8, tcon: u8) -> MCS51 {
         let mut mcu = MCS51::new();
         mcu.reset();
-        mcu.write(0xD0, 0b00000000);
-        assert_eq!(mcu.get_current_register_bank(), 0);
-        mcu.write(0xD0, 0b00001000);
-        assert_eq!(mcu.get_current_register_bank(), 1);
-        mcu.write(0xD0, 0b00010000);
-        assert_eq!(mcu.get_current_register_bank(), 2);
-        mcu.write(0xD0, 0b00011000);
-        assert_eq!(mcu.get_current_register_bank(), 3);
+        mcu.write_sfr(MCS51_REGISTERS::TMOD, tmod);
+        mcu.write_sfr(MCS51_REGISTERS::TCON, tcon);
+        mcu
     }
 
     #[test]
-    fn bit_operations_mcs51() {
-        let mut mcu = MCS51::new();
-        mcu.reset();
-        assert_eq!(mcu.read_bit(0x60), false);
-        assert_eq!(mcu.read_bit(0x61), false);
-        assert_eq!(mcu.read_bit(0x62), false);
+    fn timer_mode2_operations_mcs51() {
+        let mut mcu = timer_test_mcu(0x22, 0x50);
+        mcu.write_sfr(MCS51_REGISTERS::TH0, 0xFE);
+        mcu.write_sfr(MCS51_REGISTERS::TL0, 0xFD);
+        mcu.write_sfr(MCS51_REGISTERS::TH1, 0x80);
+        mcu.write_sfr(MCS51_REGISTERS::TL1, 0xFF);
 
-        mcu.write_bit(0x60, true);
-        assert_eq!(mcu.get_accumulator(), 1);
-        assert_eq!(*mcu.read(0x2c).unwrap(), 1);
+        mcu.tick_timers();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), 0xFE);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL1), 0x80);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TCON), 0xD0);
 
-        mcu.write_bit(0x61, true);
-        assert_eq!(mcu.get_accumulator(), 3);
-        assert_eq!(*mcu.read(0x2c).unwrap(), 3);
-        assert_eq!(mcu.read_bit(0x61), true);
+        mcu.tick_timers();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), 0xFF);
+        mcu.tick_timers();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), 0xFE);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TH0), 0xFE);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TCON), 0xF0);
 
-        mcu.write_bit(0x62, true);
-        assert_eq!(mcu.get_accumulator(), 7);
-        assert_eq!(*mcu.read(0x2c).unwrap(), 7);
-        assert_eq!(mcu.read_bit(0x62), true);
+        // Stopped timers hold their value
+        mcu.write_sfr(MCS51_REGISTERS::TCON, 0x00);
+        mcu.tick_timers();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), 0xFE);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL1), 0x82);
     }
 
     #[test]
-    fn bit_mov_operations_mcs51() {
-        let mut mcu = MCS51::new();
-        mcu.reset();
-        mcu.set_program(vec![
-            0x04, // Increment Accumulator
-            0x04, // Increment Accumulator
-            0x04, // Increment Accumulator
-            0x09, // Increment Register 1
-            0x09, // Increment Register 1
-            0x09, // Increment Register 1
-            0x74, 0xFE, // Store 0xFE in accumulator
-            0x79, 0xFD, // Store 0xFD in R1
-        ]);
-        for _i in 0..6 {
-            mcu.next_instruction();
+    fn timer_mode0_mode1_operations_mcs51() {
+        let mut mcu = timer_test_mcu(0x10, 0x50);
+        mcu.write_sfr(MCS51_REGISTERS::TL0, 0xFF);
+        mcu.write_sfr(MCS51_REGISTERS::TH0, 0x00);
+        mcu.write_sfr(MCS51_REGISTERS::TL1, 0xFF);
+        mcu.write_sfr(MCS51_REGISTERS::TH1, 0xFE);
+
+        // 13 bit timer, the upper 3 bits of TL0 are not part of the count
+        mcu.tick_timers();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), 0xE0);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TH0), 0x01);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL1), 0x00);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TH1), 0xFF);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TCON), 0x50);
+
+        mcu.write_sfr(MCS51_REGISTERS::TL0, 0x1F);
+        mcu.write_sfr(MCS51_REGISTERS::TH0, 0xFF);
+        mcu.write_sfr(MCS51_REGISTERS::TL1, 0xFF);
+        mcu.tick_timers();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), 0x00);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TH0), 0x00);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL1), 0x00);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TH1), 0x00);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TCON), 0xF0);
+    }
+
+    #[test]
+    fn timer_gate_counter_operations_mcs51() {
+        // Timer 0 gated by INT0, Timer 1 counting T1 falling edges
+        let mut mcu = timer_test_mcu(0x59, 0x50);
+
+        mcu.tick_timers();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), 0x01);
+        mcu.write_sfr(MCS51_REGISTERS::P3, 0xFB);
+        mcu.tick_timers();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), 0x01);
+        mcu.write_sfr(MCS51_REGISTERS::P3, 0xFF);
+        mcu.tick_timers();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), 0x02);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL1), 0x00);
+
+        for level in [0xDF, 0xDF, 0xFF, 0xDF, 0xFF, 0xFF].iter() {
+            mcu.write_sfr(MCS51_REGISTERS::P3, *level);
+            mcu.tick_timers();
         }
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL1), 0x02);
+    }
 
-        mcu.next_instruction();
-        assert_eq!(mcu.get_accumulator(), 0xFE);
+    #[test]
+    fn timer_mode3_operations_mcs51() {
+        let mut mcu = timer_test_mcu(0x23, 0x10);
+        mcu.write_sfr(MCS51_REGISTERS::TL0, 0xFF);
+        mcu.write_sfr(MCS51_REGISTERS::TH0, 0xFE);
+        mcu.write_sfr(MCS51_REGISTERS::TH1, 0x10);
+        mcu.write_sfr(MCS51_REGISTERS::TL1, 0xFF);
 
-        mcu.next_instruction();
-        assert_eq!(mcu.read_register(1), 0xFD);
+        // TL0 runs from TR0, TH0 is stopped as TR1 is cleared, Timer 1 runs without TR1
+        mcu.tick_timers();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), 0x00);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TH0), 0xFE);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL1), 0x10);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TCON), 0x30);
+
+        // TH0 uses TR1 and TF1
+        mcu.write_sfr(MCS51_REGISTERS::TCON, 0x40);
+        mcu.tick_timers();
+        mcu.tick_timers();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), 0x00);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TH0), 0x00);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TCON), 0xC0);
+
+        // Timer 1 in mode 3 holds its count
+        mcu.write_sfr(MCS51_REGISTERS::TMOD, 0x33);
+        mcu.tick_timers();
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL1), 0x12);
     }
 
     #[test]
-    fn add_operations_mcs51() {
+    fn timer_interrupt_mcs51() {
+        let mut mcu = interrupt_test_mcu(&[]);
+        mcu.write_sfr(MCS51_REGISTERS::TMOD, 0x02);
+        mcu.write_sfr(MCS51_REGISTERS::TH0, 0xFC);
+        mcu.write_sfr(MCS51_REGISTERS::TL0, 0xFC);
+        mcu.write_sfr(MCS51_REGISTERS::IE, 0x82);
+        mcu.write_sfr(MCS51_REGISTERS::TCON, MCS51_TCON_TR0);
+
+        for _i in 0..4 {
+            mcu.step();
+        }
+        assert_eq!(mcu.pc, 0x0B);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TCON), MCS51_TCON_TR0);
+
+        // Reloaded, then counted during the second cycle of the interrupt LCALL
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::TL0), 0xFD);
+    }
+
+    #[test]
+    fn timer2_auto_reload_operations_mcs51() {
+        let mut mcu = timer_test_mcu(0x00, 0x00);
+        mcu.set_rcap2(0x1234);
+        mcu.set_timer2(0xFFFE);
+        mcu.write_sfr(MCS51_REGISTERS::T2CON, MCS51_T2CON_TR2);
+
+        mcu.tick_timer2();
+        assert_eq!(mcu.get_timer2(), 0xFFFF);
+        mcu.tick_timer2();
+        assert_eq!(mcu.get_timer2(), 0x1234);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::T2CON), MCS51_T2CON_TR2 | MCS51_T2CON_TF2);
+
+        // T2EX falling edge forces a reload when EXEN2 is set
+        mcu.write_sfr(MCS51_REGISTERS::T2CON, MCS51_T2CON_TR2 | MCS51_T2CON_EXEN2);
+        mcu.tick_timer2();
+        assert_eq!(mcu.get_timer2(), 0x1235);
+        mcu.write_sfr(MCS51_REGISTERS::P1, 0xFD);
+        mcu.tick_timer2();
+        assert_eq!(mcu.get_timer2(), 0x1235);
+        assert_eq!(
+            mcu.read_sfr(MCS51_REGISTERS::T2CON),
+            MCS51_T2CON_TR2 | MCS51_T2CON_EXEN2 | MCS51_T2CON_EXF2
+        );
+    }
+
+    #[test]
+    fn timer2_capture_operations_mcs51() {
+        let mut mcu = timer_test_mcu(0x00, 0x00);
+        mcu.set_rcap2(0x1234);
+        mcu.set_timer2(0xFFFF);
+        mcu.write_sfr(MCS51_REGISTERS::T2CON, MCS51_T2CON_TR2 | MCS51_T2CON_CPRL2);
+
+        // Plain 16 bit overflow, RCAP2 is untouched
+        mcu.tick_timer2();
+        assert_eq!(mcu.get_timer2(), 0x0000);
+        assert_eq!(mcu.get_rcap2(), 0x1234);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::T2CON) & MCS51_T2CON_TF2, MCS51_T2CON_TF2);
+
+        // No capture without EXEN2
+        mcu.write_sfr(MCS51_REGISTERS::P1, 0xFD);
+        mcu.tick_timer2();
+        assert_eq!(mcu.get_rcap2(), 0x1234);
+
+        mcu.write_sfr(MCS51_REGISTERS::T2CON, MCS51_T2CON_TR2 | MCS51_T2CON_CPRL2 | MCS51_T2CON_EXEN2);
+        mcu.write_sfr(MCS51_REGISTERS::P1, 0xFF);
+        mcu.tick_timer2();
+        mcu.write_sfr(MCS51_REGISTERS::P1, 0xFD);
+        mcu.tick_timer2();
+        assert_eq!(mcu.get_rcap2(), 0x0002);
+        assert_eq!(mcu.get_timer2(), 0x0003);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::T2CON) & MCS51_T2CON_EXF2, MCS51_T2CON_EXF2);
+    }
+
+    #[test]
+    fn timer2_up_down_operations_mcs51() {
+        let mut mcu = timer_test_mcu(0x00, 0x00);
+        mcu.write_sfr(MCS51_REGISTERS::T2MOD, MCS51_T2MOD_DCEN);
+        mcu.set_rcap2(0xFF00);
+        mcu.set_timer2(0xFFFF);
+        mcu.write_sfr(MCS51_REGISTERS::T2CON, MCS51_T2CON_TR2);
+
+        // T2EX high counts up
+        mcu.tick_timer2();
+        assert_eq!(mcu.get_timer2(), 0xFF00);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::T2CON), MCS51_T2CON_TR2 | MCS51_T2CON_TF2 | MCS51_T2CON_EXF2);
+
+        // T2EX low counts down and underflows once RCAP2 is reached
+        mcu.write_sfr(MCS51_REGISTERS::T2CON, MCS51_T2CON_TR2 | MCS51_T2CON_EXF2);
+        mcu.set_timer2(0xFF01);
+        mcu.write_sfr(MCS51_REGISTERS::P1, 0xFD);
+        mcu.tick_timer2();
+        assert_eq!(mcu.get_timer2(), 0xFF00);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::T2CON), MCS51_T2CON_TR2 | MCS51_T2CON_EXF2);
+        mcu.tick_timer2();
+        assert_eq!(mcu.get_timer2(), 0xFFFF);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::T2CON), MCS51_T2CON_TR2 | MCS51_T2CON_TF2);
+    }
+
+    #[test]
+    fn timer2_baud_rate_operations_mcs51() {
+        let mut mcu = timer_test_mcu(0x00, 0x00);
+        mcu.set_rcap2(0xFFFB);
+        mcu.set_timer2(0xFFFB);
+        mcu.write_sfr(MCS51_REGISTERS::T2CON, MCS51_T2CON_TR2 | MCS51_T2CON_TCLK | MCS51_T2CON_EXEN2);
+        assert!(mcu.is_timer2_baud_rate_generator());
+
+        // Counts once per state, reloads without setting TF2
+        assert_eq!(mcu.tick_timer2(), 1);
+        assert_eq!(mcu.get_timer2(), 0xFFFC);
+        assert_eq!(mcu.tick_timer2(), 1);
+        assert_eq!(mcu.get_timer2(), 0xFFFD);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::T2CON) & MCS51_T2CON_TF2, 0);
+
+        // T2EX only sets EXF2
+        mcu.write_sfr(MCS51_REGISTERS::P1, 0xFD);
+        mcu.tick_timer2();
+        assert_eq!(mcu.get_timer2(), 0xFFFE);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::T2CON) & MCS51_T2CON_EXF2, MCS51_T2CON_EXF2);
+    }
+
+    #[test]
+    fn timer2_counter_interrupt_mcs51() {
+        let mut mcu = interrupt_test_mcu(&[]);
+        mcu.set_timer2(0xFFFE);
+        mcu.write_sfr(MCS51_REGISTERS::IE, 0xA0);
+        mcu.write_sfr(MCS51_REGISTERS::T2CON, MCS51_T2CON_TR2 | MCS51_T2CON_CT2);
+
+        // Counts falling edges on T2
+        for level in [0xFE, 0xFF, 0xFE].iter() {
+            mcu.write_sfr(MCS51_REGISTERS::P1, *level);
+            mcu.step();
+        }
+        assert_eq!(mcu.get_timer2(), 0x0000);
+        assert_eq!(mcu.pc, 0x2B);
+    }
+
+    fn serial_test_mcu(scon: u8) -> MCS51 {
         let mut mcu = MCS51::new();
         mcu.reset();
-        mcu.set_program(vec![
-            0x74, 0xC3, // Store 0xC3 in Accumulator
-            0x79, 0xAA, // Store 0xAA in R1
-            0x29, //Add R1 to accumulator
-        ]);
-        mcu.next_instruction();
-        mcu.next_instruction();
-        mcu.next_instruction();
-        assert_eq!(mcu.read_register(1), 0xAA);
-        assert_eq!(mcu.get_accumulator(), 0x6D);
-        assert_eq!(mcu.get_aux_carry_flag(), false);
-        assert_eq!(mcu.get_carry_flag(), true);
-        assert_eq!(mcu.get_overflow_flag(), true);
+        mcu.set_program(vec![0x00; 0x1000]).unwrap();
+        mcu.write_sfr(MCS51_REGISTERS::SCON, scon);
+        mcu
+    }
+
+    fn clock_n(mcu: &mut MCS51, cycles: usize) {
+        for _i in 0..cycles {
+            mcu.clock();
+        }
+    }
+
+    fn scon_flags(mcu: &MCS51) -> u8 {
+        mcu.read_sfr(MCS51_REGISTERS::SCON) & (MCS51_SCON_TI | MCS51_SCON_RI)
+    }
+
+    #[test]
+    fn serial_mode1_timer1_mcs51() {
+        // Timer 1 overflowing every machine cycle, SMOD set: 16 cycles per bit
+        let mut mcu = serial_test_mcu(0x50);
+        mcu.write_sfr(MCS51_REGISTERS::TMOD, 0x20);
+        mcu.write_sfr(MCS51_REGISTERS::TH1, 0xFF);
+        mcu.write_sfr(MCS51_REGISTERS::TL1, 0xFF);
+        mcu.write_sfr(MCS51_REGISTERS::TCON, MCS51_TCON_TR1);
+        mcu.write_sfr(MCS51_REGISTERS::PCON, MCS51_PCON_SMOD);
+
+        // TI is set at the beginning of the stop bit
+        mcu.write(0x99, 0x41);
+        clock_n(&mut mcu, 143);
+        assert_eq!(scon_flags(&mcu), 0);
+        assert!(mcu.serial_output().is_empty());
+        mcu.clock();
+        assert_eq!(scon_flags(&mcu), MCS51_SCON_TI);
+        assert_eq!(mcu.serial_output(), vec![0x41]);
+
+        // Writing SBUF does not change the receive buffer
+        assert_eq!(*mcu.read(0x99).unwrap(), 0x00);
+
+        // RI is set halfway through the stop bit, bytes are lost while RI is set
+        mcu.serial_input(0x55);
+        mcu.serial_input(0xAA);
+        clock_n(&mut mcu, 151);
+        assert_eq!(scon_flags(&mcu), MCS51_SCON_TI);
+        mcu.clock();
+        assert_eq!(scon_flags(&mcu), MCS51_SCON_TI | MCS51_SCON_RI);
+        assert_eq!(*mcu.read(0x99).unwrap(), 0x55);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::SCON) & MCS51_SCON_RB8, MCS51_SCON_RB8);
+
+        clock_n(&mut mcu, 160);
+        assert_eq!(*mcu.read(0x99).unwrap(), 0x55);
+
+        mcu.write_sfr(MCS51_REGISTERS::SCON, 0x50);
+        mcu.serial_input(0x66);
+        clock_n(&mut mcu, 160);
+        assert_eq!(*mcu.read(0x99).unwrap(), 0x66);
+
+        // SMOD cleared halves the baud rate
+        mcu.write_sfr(MCS51_REGISTERS::PCON, 0x00);
+        mcu.write(0x99, 0x42);
+        clock_n(&mut mcu, 287);
+        assert!(mcu.serial_output().is_empty());
+        mcu.clock();
+        assert_eq!(mcu.serial_output(), vec![0x42]);
+    }
+
+    #[test]
+    fn serial_mode1_timer2_mcs51() {
+        // Timer 2 overflowing every 3 states: 8 cycles per bit
+        let mut mcu = serial_test_mcu(0x50);
+        mcu.set_rcap2(0xFFFD);
+        mcu.set_timer2(0xFFFD);
+        mcu.write_sfr(
+            MCS51_REGISTERS::T2CON,
+            MCS51_T2CON_TR2 | MCS51_T2CON_RCLK | MCS51_T2CON_TCLK,
+        );
+
+        mcu.write(0x99, 0x99);
+        mcu.serial_input(0x24);
+        clock_n(&mut mcu, 71);
+        assert_eq!(scon_flags(&mcu), 0);
+        mcu.clock();
+        assert_eq!(scon_flags(&mcu), MCS51_SCON_TI);
+        clock_n(&mut mcu, 3);
+        assert_eq!(scon_flags(&mcu), MCS51_SCON_TI);
+        mcu.clock();
+        assert_eq!(scon_flags(&mcu), MCS51_SCON_TI | MCS51_SCON_RI);
+        assert_eq!(mcu.serial_output(), vec![0x99]);
+        assert_eq!(*mcu.read(0x99).unwrap(), 0x24);
+
+        // The receiver is busy until the end of the stop bit
+        clock_n(&mut mcu, 3);
+        assert!(mcu.serial.is_receiving());
+        mcu.clock();
+        assert!(!mcu.serial.is_receiving());
+    }
+
+    #[test]
+    fn serial_mode2_operations_mcs51() {
+        // 9 bit mode with SM2 set, only address bytes (9th bit set) raise RI
+        let mut mcu = serial_test_mcu(0xB0);
+        mcu.serial_input_9bit(0x12, false);
+        mcu.serial_input_9bit(0x34, true);
+
+        clock_n(&mut mcu, 59);
+        assert_eq!(scon_flags(&mcu), 0);
+        clock_n(&mut mcu, 59);
+        assert_eq!(scon_flags(&mcu), MCS51_SCON_RI);
+        assert_eq!(*mcu.read(0x99).unwrap(), 0x34);
+        assert_eq!(mcu.read_sfr(MCS51_REGISTERS::SCON) & MCS51_SCON_RB8, MCS51_SCON_RB8);
+
+        // TB8 is sent as the 9th bit
+        mcu.write_sfr(MCS51_REGISTERS::SCON, 0x88);
+        mcu.write(0x99, 0x5A);
+        clock_n(&mut mcu, 53);
+        assert_eq!(scon_flags(&mcu), 0);
+        mcu.clock();
+        assert_eq!(scon_flags(&mcu), MCS51_SCON_TI);
+        assert_eq!(mcu.serial.output.pop_front(), Some(0x15A));
+    }
+
+    #[test]
+    fn serial_bus_mcs51() {
+        // Mode 2 master and slaves with SM2 set, the third slave runs at half the clock
+        let mut bus = MCS51_Serial_Bus::new();
+        let master = bus.add_node(serial_test_mcu(0x88));
+        let slave1 = bus.add_node(serial_test_mcu(0xB0));
+        let slave2 = bus.add_node(serial_test_mcu(0xB0));
+        let slave3 = bus.add_node(serial_test_mcu(0xB0));
+        bus.node_mut(slave3).set_oscillator_frequency(6_000_000);
+
+        // Address byte, every slave is interrupted
+        bus.node_mut(master).write(0x99, 0x02);
+        bus.run_for(Duration::from_micros(100));
+        assert_eq!(scon_flags(bus.node(master)), MCS51_SCON_TI);
+        for slave in [slave1, slave2] {
+            assert_eq!(scon_flags(bus.node(slave)), MCS51_SCON_RI);
+            assert_eq!(*bus.node(slave).read(0x99).unwrap(), 0x02);
+            assert_ne!(bus.node(slave).read_sfr(MCS51_REGISTERS::SCON) & MCS51_SCON_RB8, 0);
+        }
+        assert_eq!(scon_flags(bus.node(slave3)), 0);
+        bus.run_for(Duration::from_micros(50));
+        assert_eq!(scon_flags(bus.node(slave3)), MCS51_SCON_RI);
+        assert_eq!(*bus.node(slave3).read(0x99).unwrap(), 0x02);
+
+        // Only the addressed slave clears SM2 and gets the data byte
+        bus.node_mut(slave1).write_sfr(MCS51_REGISTERS::SCON, 0xB0);
+        bus.node_mut(slave2).write_sfr(MCS51_REGISTERS::SCON, 0x90);
+        bus.node_mut(slave3).write_sfr(MCS51_REGISTERS::SCON, 0xB0);
+        bus.node_mut(master).write_sfr(MCS51_REGISTERS::SCON, 0x80);
+        bus.node_mut(master).write(0x99, 0x77);
+        bus.run_for(Duration::from_micros(200));
+        assert_eq!(scon_flags(bus.node(master)), MCS51_SCON_TI);
+        assert_eq!(scon_flags(bus.node(slave1)), 0);
+        assert_eq!(scon_flags(bus.node(slave2)), MCS51_SCON_RI);
+        assert_eq!(*bus.node(slave2).read(0x99).unwrap(), 0x77);
+        assert_eq!(bus.node(slave2).read_sfr(MCS51_REGISTERS::SCON) & MCS51_SCON_RB8, 0);
+        assert_eq!(scon_flags(bus.node(slave3)), 0);
+        assert_eq!(bus.frames, 2);
+        assert_eq!(bus.lost_frames, 0);
+
+        // A disabled receiver ignores the frame, the receiver that did not read SBUF overruns
+        bus.node_mut(slave1).write_sfr(MCS51_REGISTERS::SCON, 0xA0);
+        bus.node_mut(master).write_sfr(MCS51_REGISTERS::SCON, 0x88);
+        bus.node_mut(master).write(0x99, 0x01);
+        bus.run_for(Duration::from_micros(200));
+        assert_eq!(scon_flags(bus.node(slave1)), 0);
+        assert_eq!(scon_flags(bus.node(slave2)), MCS51_SCON_RI);
+        assert_eq!(*bus.node(slave2).read(0x99).unwrap(), 0x77);
+        assert_eq!(scon_flags(bus.node(slave3)), MCS51_SCON_RI);
+        assert_eq!(*bus.node(slave3).read(0x99).unwrap(), 0x01);
+        assert_eq!(bus.node(slave1).serial.overruns, 0);
+        assert_eq!(bus.node(slave2).serial.overruns, 1);
+        assert_eq!(bus.lost_frames, 1);
+    }
+
+    #[test]
+    fn serial_mode0_operations_mcs51() {
+        let mut mcu = serial_test_mcu(0x00);
+        mcu.serial_input(0xC3);
+
+        // Reception needs REN
+        mcu.write(0x99, 0x3C);
+        clock_n(&mut mcu, 8);
+        assert_eq!(scon_flags(&mcu), MCS51_SCON_TI);
+        assert_eq!(mcu.serial_output(), vec![0x3C]);
+
+        mcu.write_sfr(MCS51_REGISTERS::SCON, MCS51_SCON_REN);
+        clock_n(&mut mcu, 7);
+        assert_eq!(scon_flags(&mcu), 0);
+        mcu.clock();
+        assert_eq!(scon_flags(&mcu), MCS51_SCON_RI);
+        assert_eq!(*mcu.read(0x99).unwrap(), 0xC3);
+    }
+
+    #[test]
+    fn serial_interrupt_mcs51() {
+        let mut mcu = interrupt_test_mcu(&[]);
+        mcu.write_sfr(MCS51_REGISTERS::IE, 0x90);
+        mcu.write(0x99, 0x00);
+        clock_n(&mut mcu, 8);
+        mcu.step();
+        assert_eq!(mcu.pc, 0x23);
     }
 }
 
@@ -250,204 +3381,15 @@ fn test_emulator_16f628a() {
     
 }
 
-fn test_emulator_mcs51() {
-    let mut mcu = MCS51::new();
-    
-    test_emulator(&mut mcu, vec![
-        0x08, 0x08, 0x08, 0x08, 0x08,
-        0x08, 0x08, 0x08, 0x08, 0x08,
-        0x02, 0x00, 0x00, // Jump to beginning
-    ]);
-    
-    /*
-    test_emulator(&mut mcu, vec![
-        0x00, 0x00, 0x00, 0x00, 0x00,
-        0x00, 0x00, 0x00, 0x00, 0x00,
-        0x02, 0x00, 0x00, // Jump to beginning
-    ]);
-    */
-}
-
-fn test_emulator(mcu: &mut dyn MCU<u8>, program: Vec<u8>) {
-    mcu.setup();
-    mcu.set_program(program);
-
-    for _j in 0..10 {
-        mcu.reset();
-
-        let iterations = 1000000000;
-        let now = Instant::now();
-        for _i in 0..iterations {
-            mcu.next_instruction();
-        }
-
-        let time_us = now.elapsed().as_micros();
-        let time_ns = now.elapsed().as_nanos();
-        let time_us_inst = time_us as f64 / iterations as f64;
-        let time_ns_inst = time_ns as f64 / iterations as f64;
-        println!(
-            "({:.3}ns/inst) ({:.3} GHz) ({:.3} MHz)",
-            time_ns_inst,
-            1.0 / time_ns_inst,
-            1.0 / time_us_inst
-        );
-    }
-}
-
-fn run_mcs51(program: Vec<u8>) {
-    let mut mcu = MCS51::new();
-    mcu.setup();
-    mcu.set_program(program);
-    mcu.run();
-    println!("{}", mcu.read_register(2));
-}
-
-fn get_file_as_byte_vec(filename: &str) -> Vec<u8> {
-    let mut f = File::open(filename).expect("no file found");
-    let metadata = std::fs::metadata(filename).expect("unable to read metadata");
-    let mut buffer = vec![0; metadata.len() as usize];
-    f.read(&mut buffer).expect("buffer overflow");
-    buffer
-}
-
-fn test_decompile_mcs51_2(program: Vec<u8>, out_file: &str) {
-    let mut dec = MCS51_Decompiler::new();
-    dec.program = program;
-
-    /*
-    let mut next: u16 = 0;
-    let mut code = String::new();
-    for _i in 0..512 {
-        let v = dec.get_instruction(next);
-        code.push_str(format!("{}", v).as_str());
-        code.push('\n');
-        next = v.next[0];
-    }
-
-    fs::write("data/code.asm", code).expect("Unable to write file");
-    */
-
-    dec.decompile(0);
-    dec.write_to_file(out_file);
-}
-
-fn test_decompile_mcs51() {
-    test_decompile_mcs51_2(get_file_as_byte_vec(r#"D:\Perso\Prog\rust\microchip-rs\data\V2-10_raw.bin"#), "data/code_2_10.asm")
-}
-
-fn repl_mcs51(filename: &str) {
-    let mut f = File::open(filename).expect("no file found");
-    let metadata = fs::metadata(filename).expect("unable to read metadata");
-    let mut buffer = vec![0; metadata.len() as usize];
-    f.read(&mut buffer).expect("buffer overflow");
-
-    let mut mcu = MCS51::new();
-    mcu.generate_opcode_array();
-    mcu.set_program(buffer.clone());
-    //mcu.debug = true;
-
-    let mut decomp = MCS51_Decompiler::new();
-    decomp.program = buffer;
-    decomp.decompile(0);
-
-    let mut prev_pc: u16 = 0;
-
-    let breakpoints: Vec<u16> = vec![0x5DD6];
-
-    let mut rl = Editor::<()>::new();
-
-    loop {
-        let readline = rl.readline(">> ");
-
-        match readline {
-            Ok(line) => {
-                rl.add_history_entry(line.as_str());
-
-                match line.as_str() {
-                    "exit" => {
-                        break;
-                    }
-
-                    "" => {}
-
-                    _ => {
-                        if line.starts_with("wait ") {
-                            let pattern = line.replace("wait ", "");
-
-                            loop {
-                                let pc = &mcu.pc;
-                                let inst = decomp.get_instruction(*pc);
-                                mcu.next_instruction();
-                                println!("{}", inst);
-
-                                if inst.to_string().contains(&pattern) {
-                                    println!("{}", inst);
-                                    break;
-                                }
-                            }
-                            
-                            
-                        } else {
-                            let pc = &mcu.pc;
-                            let inst = decomp.get_instruction(*pc);
-                            println!("{}", inst);
-
-                            mcu.next_instruction();
-                        }
-                    }
-                }
-            }
-            Err(err) => {
-                println!("Error: {:?}", err);
-                break;
-            }
-        }
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", CLI_USAGE);
+        return;
     }
-}
 
-fn run_mcs51_file(filename: &str) {
-    let mut f = File::open(filename).expect("no file found");
-    let metadata = fs::metadata(filename).expect("unable to read metadata");
-    let mut buffer = vec![0; metadata.len() as usize];
-    f.read(&mut buffer).expect("buffer overflow");
-
-    let mut mcu = MCS51::new();
-    mcu.generate_opcode_array();
-    mcu.set_program(buffer.clone());
-    //mcu.debug = true;
-
-    let mut decomp = MCS51_Decompiler::new();
-    decomp.program = buffer;
-    decomp.decompile(0);
-
-    let mut prev_pc: u16 = 0;
-
-    let breakpoints: Vec<u16> = vec![0x5DD6];
-
-    loop {
-        let pc = &mcu.pc;
-        //println!("{:0x} {:0x}", pc, decomp.program[*pc as usize]);
-        //let inst = decomp.instructions[pc].clone();
-        let inst = decomp.get_instruction(*pc);
-        println!("{}", inst);
-
-        /*
-        if *pc != 0 && prev_pc == *pc {
-            break;
-        } else {
-            prev_pc = *pc;
-            mcu.next_instruction();
-        }
-        */
-        mcu.next_instruction();
+    if let Err(err) = run_cli(&args) {
+        eprintln!("Error: {}, see --help", err);
+        std::process::exit(1);
     }
 }
-
-fn main() {
-    test_emulator_mcs51();
-    //repl_mcs51("data/1594462804_raw.bin");
-    //test_decompile_mcs51();
-
-    //run_mcs51(vec![0x78, 0x39, 0x79, 0x05, 0x7B, 0x10, 0xE8, 0x13, 0x50, 0x01, 0x0A, 0xBB, 0x08, 0x01, 0xE9, 0xDB, 0xF6]);
-    //test_decompile_mcs51_2(vec![0x78, 0x39, 0x79, 0x05, 0x7B, 0x10, 0xE8, 0x13, 0x50, 0x01, 0x0A, 0xBB, 0x08, 0x01, 0xE9, 0xDB, 0xF6], "R:\\out.asm")
-}
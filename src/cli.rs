@@ -0,0 +1,396 @@
+use crate::lib::decompiler::mcs51::*;
+use crate::lib::mcus::mcs51::*;
+use crate::lib::traits::component::*;
+use crate::repl::*;
+use std::cell::RefCell;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::rc::Rc;
+use std::time::Instant;
+
+/*
+Command line
+
+microchip_rs_bin <command> [options] <file>
+
+Numbers take the same forms as in the debugger, 4096, 0x1000 or 1000H. Without --format, files
+ending in .hex or .ihx are read as Intel HEX and the others as raw binaries.
+*/
+
+pub const CLI_USAGE: &str = "\
+Usage: microchip_rs_bin <command> [options] <file>
+
+Commands:
+  run        run the image and print the stop reason and the registers
+  disasm     decompile the image from the start address
+  repl       debug the image interactively
+  bench      measure the emulation speed on the image
+  trace      record every instruction executed
+
+Options:
+  --mcu <part>          8031, 8051 (default), 8052, AT89C2051, AT89S52
+  --format <bin|hex>    image format, guessed from the file extension by default
+  --base <addr>         load address of a binary image (default 0)
+  --start <addr>        PC after reset (default 0)
+  --clock <Hz>          oscillator frequency, e.g. 12000000, 11.0592M or 32768
+  --max-cycles <n>      stop after n machine cycles (bench: 10000000 by default)
+  --break <addr>        stop at an address, can be repeated
+  --trace-format <fmt>  trace as csv, jsonl or bin instead of text
+  --range <start-end>   trace only the instructions in this address range
+  --writes              trace the memory writes too
+  -o, --output <path>   write the output to a file (disasm: <file>.asm by default)
+  -h, --help            this text";
+
+const CLI_BENCH_CYCLES: u64 = 10_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CLI_COMMAND {
+    RUN,
+    DISASM,
+    REPL,
+    BENCH,
+    TRACE,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CLI_FORMAT {
+    BIN,
+    HEX,
+}
+
+impl CLI_FORMAT {
+    pub fn from_path(path: &str) -> CLI_FORMAT {
+        let path = path.to_lowercase();
+        if path.ends_with(".hex") || path.ends_with(".ihx") {
+            return CLI_FORMAT::HEX;
+        }
+        return CLI_FORMAT::BIN;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CLI_Options {
+    pub command: CLI_COMMAND,
+    pub file: String,
+    pub mcu: String,
+    pub format: CLI_FORMAT,
+    pub base: u16,
+    pub start: u16,
+    pub clock: Option<u32>,
+    pub max_cycles: Option<u64>,
+    pub breakpoints: Vec<u16>,
+    pub output: Option<String>,
+    pub trace_format: Option<MCS51_TRACE_FORMAT>,
+    pub range: (u16, u16),
+    pub memory_writes: bool,
+}
+
+fn parse_address(option: &str, text: &str) -> Result<u16, String> {
+    return match parse_number(text) {
+        Some(value) if (0..=0xFFFF).contains(&value) => Ok(value as u16),
+        _ => Err(format!("Invalid address for {}: {}", option, text)),
+    };
+}
+
+// Hz, with an optional k or M multiplier and Hz suffix: 11.0592M, 12MHz, 32768
+pub fn parse_frequency(text: &str) -> Option<u32> {
+    let upper = text.to_uppercase();
+    let number = upper.strip_suffix("HZ").unwrap_or(&upper);
+    let (number, multiplier) = match number.strip_suffix('M') {
+        Some(number) => (number, 1e6),
+        None => match number.strip_suffix('K') {
+            Some(number) => (number, 1e3),
+            None => (number, 1.0),
+        },
+    };
+
+    let frequency = number.parse::<f64>().ok()? * multiplier;
+    if frequency < 1.0 || frequency > u32::MAX as f64 {
+        return None;
+    }
+    return Some(frequency.round() as u32);
+}
+
+impl CLI_Options {
+    pub fn parse(args: &[String]) -> Result<CLI_Options, String> {
+        let mut args = args.iter();
+        let command = match args.next().map(|arg| arg.as_str()) {
+            Some("run") => CLI_COMMAND::RUN,
+            Some("disasm") => CLI_COMMAND::DISASM,
+            Some("repl") => CLI_COMMAND::REPL,
+            Some("bench") => CLI_COMMAND::BENCH,
+            Some("trace") => CLI_COMMAND::TRACE,
+            Some(command) => return Err(format!("Unknown command {}", command)),
+            None => return Err("Missing command".to_owned()),
+        };
+
+        let mut file: Option<String> = None;
+        let mut mcu = "8051".to_owned();
+        let mut format: Option<CLI_FORMAT> = None;
+        let mut base = 0;
+        let mut start = 0;
+        let mut clock = None;
+        let mut max_cycles = None;
+        let mut breakpoints: Vec<u16> = Vec::new();
+        let mut output = None;
+        let mut trace_format = None;
+        let mut range = (0x0000, 0xFFFF);
+        let mut memory_writes = false;
+
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') {
+                if file.is_some() {
+                    return Err(format!("Unexpected argument {}", arg));
+                }
+                file = Some(arg.clone());
+                continue;
+            }
+
+            let option = arg.as_str();
+            if option == "--writes" {
+                memory_writes = true;
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", option))?;
+            match option {
+                "--mcu" => mcu = value.clone(),
+                "--format" => {
+                    format = match value.to_lowercase().as_str() {
+                        "bin" => Some(CLI_FORMAT::BIN),
+                        "hex" | "ihx" => Some(CLI_FORMAT::HEX),
+                        _ => return Err(format!("Unknown format {}", value)),
+                    }
+                }
+                "--base" => base = parse_address(option, value)?,
+                "--start" => start = parse_address(option, value)?,
+                "--clock" => {
+                    clock = Some(
+                        parse_frequency(value).ok_or_else(|| format!("Invalid clock {}", value))?,
+                    )
+                }
+                "--max-cycles" => {
+                    max_cycles = match parse_number(value) {
+                        Some(cycles) if cycles > 0 => Some(cycles as u64),
+                        _ => return Err(format!("Invalid cycle count {}", value)),
+                    }
+                }
+                "--break" => breakpoints.push(parse_address(option, value)?),
+                "-o" | "--output" => output = Some(value.clone()),
+                "--trace-format" => {
+                    trace_format = match value.to_lowercase().as_str() {
+                        "text" => None,
+                        name => Some(
+                            MCS51_TRACE_FORMAT::from_name(name)
+                                .ok_or_else(|| format!("Unknown trace format {}", value))?,
+                        ),
+                    }
+                }
+                "--range" => {
+                    let (start, end) = value
+                        .split_once('-')
+                        .ok_or_else(|| format!("Invalid range {}, expected <start>-<end>", value))?;
+                    range = (parse_address(option, start)?, parse_address(option, end)?);
+                }
+                _ => return Err(format!("Unknown option {}", option)),
+            }
+        }
+
+        let file = file.ok_or_else(|| "Missing image file".to_owned())?;
+        return Ok(CLI_Options {
+            command,
+            format: format.unwrap_or_else(|| CLI_FORMAT::from_path(&file)),
+            file,
+            mcu,
+            base,
+            start,
+            clock,
+            max_cycles,
+            breakpoints,
+            output,
+            trace_format,
+            range,
+            memory_writes,
+        });
+    }
+}
+
+// Replaces the code memory with the image in path, returns the number of bytes loaded
+pub fn load_image(mcu: &mut MCS51, path: &str, format: CLI_FORMAT, base: u16) -> Result<usize, String> {
+    match format {
+        CLI_FORMAT::BIN => {
+            let data = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+            MCS51_Code::check_image(base, data.len()).map_err(|err| format!("{}: {}", path, err))?;
+            mcu.code.clear();
+            mcu.load_program(base, &data).map_err(|err| format!("{}: {}", path, err))?;
+            return Ok(data.len());
+        }
+        CLI_FORMAT::HEX => {
+            let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+            return mcu.load_intel_hex(&text).map_err(|err| format!("{}: {}", path, err));
+        }
+    }
+}
+
+// A powered up core of the part, with the image loaded and the options applied
+pub fn create_mcu(options: &CLI_Options) -> Result<MCS51, String> {
+    let mut mcu = match MCS51::from_part_name(&options.mcu) {
+        Some(mcu) => mcu,
+        None => {
+            let parts: Vec<&str> = MCS51_DERIVATIVES.iter().map(|derivative| derivative.name).collect();
+            return Err(format!("Unknown MCU {}, expected one of {}", options.mcu, parts.join(", ")));
+        }
+    };
+
+    mcu.setup();
+    if let Some(clock) = options.clock {
+        mcu.set_oscillator_frequency(clock);
+    }
+    load_image(&mut mcu, &options.file, options.format, options.base)?;
+    mcu.reset();
+    mcu.pc = options.start;
+    for address in &options.breakpoints {
+        mcu.add_breakpoint(*address);
+    }
+    return Ok(mcu);
+}
+
+fn run(options: &CLI_Options, mcu: MCS51) -> Result<String, String> {
+    let mut repl = MCS51_Repl::new(mcu);
+    let stop = match options.max_cycles {
+        Some(cycles) => repl.execute(&format!("continue {}", cycles))?,
+        None => repl.execute("continue")?,
+    };
+    let time = repl.mcu.get_elapsed_time();
+    return Ok(format!("{}\n{}\ntime={:?}", stop, repl.execute("regs")?, time));
+}
+
+fn disasm(options: &CLI_Options, mcu: MCS51) -> String {
+    let mut decomp = MCS51_Decompiler::new();
+    // Only the loaded image is disassembled, not the fill past its end
+    let end = (0..=0xFFFF)
+        .rev()
+        .find(|address| mcu.is_code_programmed(*address))
+        .map_or(0, |address| address as usize + 1);
+    decomp.program = (0..end).map(|address| mcu.read_code_byte(address as u16)).collect();
+    decomp.decompile(options.start);
+
+    let output = match &options.output {
+        Some(output) => output.clone(),
+        None => format!("{}.asm", options.file),
+    };
+    decomp.write_to_file(&output);
+    return format!("Wrote {} instructions to {}", decomp.instructions.len(), output);
+}
+
+fn bench(options: &CLI_Options, mut mcu: MCS51) -> String {
+    let cycles = options.max_cycles.unwrap_or(CLI_BENCH_CYCLES);
+    let start = mcu.get_cycle_count();
+
+    let now = Instant::now();
+    let reason = mcu.run_for(cycles);
+    let elapsed = now.elapsed();
+
+    let executed = mcu.get_cycle_count() - start;
+    let emulated = mcu.cycles_to_duration(executed);
+    let ns_per_cycle = elapsed.as_nanos() as f64 / executed.max(1) as f64;
+    return format!(
+        "{} machine cycles in {:?} ({:.3}ns/cycle), {:?} of {} time ({:.1}x real time)\nStopped: {:?}",
+        executed,
+        elapsed,
+        ns_per_cycle,
+        emulated,
+        mcu.get_derivative().name,
+        emulated.as_secs_f64() / elapsed.as_secs_f64().max(1e-9),
+        reason
+    );
+}
+
+// Text trace, one line per instruction written as it executes
+struct CLI_Text_Trace {
+    writer: Rc<RefCell<Box<dyn Write>>>,
+}
+
+impl MCS51_Trace_Sink for CLI_Text_Trace {
+    fn record(&mut self, record: &MCS51_Trace_Record) -> io::Result<()> {
+        return writeln!(self.writer.borrow_mut(), "{:04X}: {}", record.pc, record.disassembly);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.writer.borrow_mut().flush();
+    }
+}
+
+/*
+The records go to the output file or stdout while the core runs. Without --trace-format they are
+the address and disassembly of the instructions followed by the stop reason, with it the stop
+reason goes to stderr.
+*/
+fn trace(options: &CLI_Options, mut mcu: MCS51) -> Result<(), String> {
+    let writer: Box<dyn Write> = match &options.output {
+        Some(path) => {
+            let file = File::create(path).map_err(|err| format!("{}: {}", path, err))?;
+            Box::new(BufWriter::new(file))
+        }
+        None => Box::new(io::stdout()),
+    };
+    let (sink, text): (Box<dyn MCS51_Trace_Sink>, _) = match options.trace_format {
+        Some(format) => (Box::new(MCS51_Trace_Writer::new(writer, format)), None),
+        None => {
+            let writer = Rc::new(RefCell::new(writer));
+            (Box::new(CLI_Text_Trace { writer: writer.clone() }), Some(writer))
+        }
+    };
+
+    let mut tracer = MCS51_Tracer::new(sink);
+    tracer.set_range(options.range.0, options.range.1);
+    tracer.set_memory_writes(options.memory_writes);
+    mcu.attach_tracer(tracer);
+    let reason = mcu.run_control(options.max_cycles, |_| false);
+    if let Some(err) = mcu.detach_tracer().and_then(|tracer| tracer.error) {
+        return Err(format!("Trace: {}", err));
+    }
+
+    let stopped = format!("Stopped: {:?}", reason);
+    match text {
+        Some(writer) => {
+            let mut writer = writer.borrow_mut();
+            writeln!(writer, "{}", stopped)
+                .and_then(|_| writer.flush())
+                .map_err(|err| format!("Trace: {}", err))?;
+        }
+        None => eprintln!("{}", stopped),
+    }
+    return Ok(());
+}
+
+pub fn run_cli(args: &[String]) -> Result<(), String> {
+    let options = CLI_Options::parse(args)?;
+    let mcu = create_mcu(&options)?;
+
+    let output = match options.command {
+        CLI_COMMAND::RUN => run(&options, mcu)?,
+        CLI_COMMAND::DISASM => {
+            println!("{}", disasm(&options, mcu));
+            return Ok(());
+        }
+        CLI_COMMAND::REPL => {
+            repl_mcs51(mcu);
+            return Ok(());
+        }
+        CLI_COMMAND::BENCH => bench(&options, mcu),
+        CLI_COMMAND::TRACE => {
+            trace(&options, mcu)?;
+            return Ok(());
+        }
+    };
+
+    match &options.output {
+        Some(path) => fs::write(path, output + "\n").map_err(|err| format!("{}: {}", path, err))?,
+        None => println!("{}", output),
+    }
+    return Ok(());
+}
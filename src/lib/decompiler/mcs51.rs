@@ -1,4 +1,5 @@
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::VecDeque;
 use std::fmt;
 use std::fs;
@@ -20,6 +21,15 @@ impl MCS51_Decompiler_Instruction {
             next: Vec::new(),
         }
     }
+
+    pub fn get_address(&self) -> u16 {
+        return self.address;
+    }
+
+    // Assembly text without the address and the successors
+    pub fn get_code(&self) -> &str {
+        return &self.code;
+    }
 }
 
 impl fmt::Display for MCS51_Decompiler_Instruction {
@@ -33,9 +43,42 @@ impl fmt::Display for MCS51_Decompiler_Instruction {
     }
 }
 
+/*
+Banked firmware
+
+The program is laid out as the common area followed by the window of each bank, like the code
+memory of the emulator. Banked functions are called through trampolines in the common area:
+
+    ?B_BANKn_FUNC:  MOV DPTR, #FUNC
+                    LJMP ?B_SWITCHn
+
+The switch routine selects bank n through P1 or a latch in XDATA and jumps to the function
+with JMP @A+DPTR (or pushes it and returns). Switch routines are recognized by the constant
+they write to P1 or with MOVX, they can also be registered by hand when they compute the bank.
+*/
+pub struct MCS51_Decompiler_Banking {
+    pub common_size: usize,
+    pub mask: u8,
+    pub switches: BTreeMap<u16, usize>,
+}
+
+impl MCS51_Decompiler_Banking {
+    pub fn new(common_size: usize, mask: u8) -> MCS51_Decompiler_Banking {
+        MCS51_Decompiler_Banking {
+            common_size,
+            mask,
+            switches: BTreeMap::new(),
+        }
+    }
+}
+
 pub struct MCS51_Decompiler {
     pub program: Vec<u8>,
     pub instructions: BTreeMap<u16, MCS51_Decompiler_Instruction>,
+    pub banking: Option<MCS51_Decompiler_Banking>,
+    pub bank: usize,
+    pub banked_calls: BTreeSet<(usize, u16)>,
+    pub banked_instructions: BTreeMap<usize, BTreeMap<u16, MCS51_Decompiler_Instruction>>,
 }
 
 impl MCS51_Decompiler {
@@ -43,13 +86,23 @@ impl MCS51_Decompiler {
         MCS51_Decompiler {
             program: Vec::new(),
             instructions: BTreeMap::new(),
+            banking: None,
+            bank: 0,
+            banked_calls: BTreeSet::new(),
+            banked_instructions: BTreeMap::new(),
         }
     }
 
     pub fn label_list(&self) -> BTreeMap<u16, bool> {
+        return MCS51_Decompiler::get_labels(&self.instructions);
+    }
+
+    pub fn get_labels(
+        instructions: &BTreeMap<u16, MCS51_Decompiler_Instruction>,
+    ) -> BTreeMap<u16, bool> {
         let mut labels: BTreeMap<u16, bool> = BTreeMap::new();
 
-        for inst in &self.instructions {
+        for inst in instructions {
             if (inst.1).next.len() > 1 {
                 let addr = inst.1.next[1];
                 let t = inst.1.code.contains("CALL");
@@ -65,10 +118,32 @@ impl MCS51_Decompiler {
     }
 
     pub fn write_to_file(&self, path: &str) {
+        let mut code = MCS51_Decompiler::format_instructions(&self.instructions, 0);
+
+        // Only the window of the other banks, their common area is the one listed above
+        let common_size = self.banking.as_ref().map_or(0, |b| b.common_size);
+        for (bank, instructions) in &self.banked_instructions {
+            code.push_str(&format!("\n;================"));
+            code.push_str(&format!("\n;BANK {}", bank));
+            code.push_str(&format!("\n;================\n"));
+            code.push_str(&MCS51_Decompiler::format_instructions(instructions, common_size));
+        }
+
+        fs::write(path, code).expect("Unable to write file");
+    }
+
+    pub fn format_instructions(
+        instructions: &BTreeMap<u16, MCS51_Decompiler_Instruction>,
+        start: usize,
+    ) -> String {
         let mut code = String::new();
-        let labels = self.label_list();
+        let labels = MCS51_Decompiler::get_labels(instructions);
+
+        for inst in instructions {
+            if (*inst.0 as usize) < start {
+                continue;
+            }
 
-        for inst in &self.instructions {
             if labels.contains_key(inst.0) {
                 if labels[inst.0] {
                     code.push_str(&format!("\n;----------------"));
@@ -85,7 +160,7 @@ impl MCS51_Decompiler {
             code.push('\n');
         }
 
-        fs::write(path, code).expect("Unable to write file");
+        return code;
     }
 
     pub fn decompile(&mut self, start: u16) {
@@ -94,23 +169,169 @@ impl MCS51_Decompiler {
 
         while !next_addresses.is_empty() {
             let addr = next_addresses.pop_front().unwrap();
-            if !self.instructions.contains_key(&addr) {
-                if (addr as usize) < self.program.len() {
-                    println!("PC address {} ", addr);
-                    let v = self.get_instruction(addr);
-                    //println!("{}", v);
-                    for new_addr in &v.next {
-                        next_addresses.push_front(*new_addr);
+            // Flow that leaves the image, e.g. running off its end, is not followed
+            if !self.instructions.contains_key(&addr)
+                && self.get_physical_address(addr) < self.program.len()
+            {
+                let v = self.get_instruction(addr);
+                for new_addr in &v.next {
+                    next_addresses.push_front(*new_addr);
+                }
+                self.instructions.insert(addr, v);
+            }
+        }
+    }
+
+    /*
+    Decompiles the common area and bank 0 from start, then every banked function reached through
+    a trampoline in the bank it lives in, until no new banked function is found
+    */
+    pub fn decompile_banked(&mut self, start: u16) {
+        self.bank = 0;
+        self.decompile(start);
+        self.find_banked_calls();
+
+        let mut done: BTreeSet<(usize, u16)> = BTreeSet::new();
+        loop {
+            let pending: Vec<(usize, u16)> = self.banked_calls.difference(&done).cloned().collect();
+            if pending.is_empty() {
+                break;
+            }
+
+            for (bank, address) in pending {
+                done.insert((bank, address));
+
+                if bank == 0 {
+                    self.decompile(address);
+                    self.find_banked_calls();
+                    continue;
+                }
+
+                let common = std::mem::take(&mut self.instructions);
+                self.instructions = self.banked_instructions.remove(&bank).unwrap_or_default();
+                self.bank = bank;
+                self.decompile(address);
+                self.find_banked_calls();
+                self.bank = 0;
+
+                let instructions = std::mem::replace(&mut self.instructions, common);
+                self.banked_instructions.insert(bank, instructions);
+            }
+        }
+    }
+
+    /*
+    Returns the bank and address of the function called by a bank switching trampoline at address:
+    MOV DPTR, #function followed by LJMP switch
+    */
+    pub fn get_trampoline(&self, address: u16) -> Option<(usize, u16)> {
+        if self.get_opcode(address) != 0x90
+            || self.get_physical_address(address.wrapping_add(5)) >= self.program.len()
+            || self.get_opcode(address.wrapping_add(3)) != 0x02
+        {
+            return None;
+        }
+
+        let bank = self.get_bank_switch(self.get_u16(address, 4))?;
+        return Some((bank, self.get_u16(address, 1)));
+    }
+
+    // Records the banked functions called through the trampolines decompiled in the current bank
+    fn find_banked_calls(&mut self) {
+        let calls: Vec<(usize, u16)> = self
+            .instructions
+            .keys()
+            .filter_map(|address| self.get_trampoline(*address))
+            .collect();
+        self.banked_calls.extend(calls);
+    }
+
+    // Offset in the program of a code address in the bank being decompiled
+    pub fn get_physical_address(&self, address: u16) -> usize {
+        return match &self.banking {
+            Some(banking) if address as usize >= banking.common_size => {
+                address as usize + self.bank * (0x10000 - banking.common_size)
+            }
+            _ => address as usize,
+        };
+    }
+
+    pub fn instruction_length(opcode: u8) -> u16 {
+        return match opcode {
+            0x02 | 0x10 | 0x12 | 0x20 | 0x30 | 0x43 | 0x53 | 0x63 | 0x75 | 0x85 | 0x90 => 3,
+            0xB4..=0xBF | 0xD5 => 3,
+            _ if opcode & 0x1F == 0x01 || opcode & 0x1F == 0x11 => 2,
+            0x05 | 0x15 | 0x24 | 0x25 | 0x34 | 0x35 | 0x40 | 0x42 | 0x44 | 0x45 | 0x50 | 0x52 => 2,
+            0x54 | 0x55 | 0x60 | 0x62 | 0x64 | 0x65 | 0x70 | 0x72 | 0x74 | 0x76 | 0x77 => 2,
+            0x78..=0x7F | 0x80 | 0x82 | 0x86 | 0x87 | 0x88..=0x8F | 0x92 | 0x94 | 0x95 => 2,
+            0xA0 | 0xA2 | 0xA6 | 0xA7 | 0xA8..=0xAF | 0xB0 | 0xB2 | 0xC0 | 0xC2 | 0xC5 => 2,
+            0xD0 | 0xD2 | 0xD8..=0xDF | 0xE5 | 0xF5 => 2,
+            _ => 1,
+        };
+    }
+
+    /*
+    Returns the bank selected by the switch routine at address, either registered or found by
+    following the constants written to P1 (MOV, ANL, ORL) or to XDATA (MOVX after MOV A or CLR A)
+    up to the JMP @A+DPTR or RET ending the routine
+    */
+    pub fn get_bank_switch(&self, address: u16) -> Option<usize> {
+        let banking = self.banking.as_ref()?;
+        if let Some(bank) = banking.switches.get(&address) {
+            return Some(*bank);
+        }
+
+        let mut value: u8 = 0;
+        let mut known: u8 = 0;
+        let mut acc: Option<u8> = None;
+        let mut pc = address;
+
+        for _i in 0..16 {
+            if self.get_physical_address(pc) + 2 >= self.program.len() {
+                return None;
+            }
+
+            let opcode = self.get_opcode(pc);
+            let operand = self.get_u8(pc, 1);
+            let data = self.get_u8(pc, 2);
+
+            match opcode {
+                0x75 if operand == 0x90 => {
+                    value = data;
+                    known = 0xFF;
+                }
+                0x53 if operand == 0x90 => {
+                    value &= data;
+                    known |= !data;
+                }
+                0x43 if operand == 0x90 => {
+                    value |= data;
+                    known |= data;
+                }
+                0x74 => acc = Some(operand),
+                0xE4 => acc = Some(0),
+                0xF0 | 0xF2 | 0xF3 => {
+                    if let Some(a) = acc {
+                        value = a;
+                        known = 0xFF;
                     }
-                    self.instructions.insert(addr, v);
-                } else {
-                    println!("PC address {} out of bounds {}", addr, self.program.len());
                 }
-                
+                0x73 | 0x22 => {
+                    if known & banking.mask != banking.mask || banking.mask == 0 {
+                        return None;
+                    }
+                    let bank = (value & banking.mask) >> banking.mask.trailing_zeros();
+                    return Some(bank as usize);
+                }
+                0x02 | 0x12 | 0x80 | 0x32 => return None,
+                _ if opcode & 0x0F == 0x01 => return None,
+                _ => (),
             }
+
+            pc = pc.wrapping_add(MCS51_Decompiler::instruction_length(opcode));
         }
 
-        println!("Decompiled {} instructions", self.instructions.len());
+        return None;
     }
 
     pub fn sfr_name(address: u8) -> String {
@@ -119,6 +340,8 @@ impl MCS51_Decompiler {
             0x81 => "SP".to_owned(),
             0x82 => "DPL".to_owned(),
             0x83 => "DPH".to_owned(),
+            0x84 => "DP1L".to_owned(),
+            0x85 => "DP1H".to_owned(),
             0x87 => "PCON".to_owned(),
             0x88 => "TCON".to_owned(),
             0x89 => "TMOD".to_owned(),
@@ -126,14 +349,18 @@ impl MCS51_Decompiler {
             0x8B => "TL1".to_owned(),
             0x8C => "TH0".to_owned(),
             0x8D => "TH1".to_owned(),
+            0x8E => "AUXR".to_owned(),
             0x90 => "P1".to_owned(),
             0x98 => "SCON".to_owned(),
             0x99 => "SBUF".to_owned(),
             0xA0 => "P2".to_owned(),
+            0xA2 => "AUXR1".to_owned(),
+            0xA6 => "WDTRST".to_owned(),
             0xA8 => "IE".to_owned(),
             0xB0 => "P3".to_owned(),
             0xB8 => "IP".to_owned(),
             0xC8 => "T2CON".to_owned(),
+            0xC9 => "T2MOD".to_owned(),
             0xCA => "RCAP2L".to_owned(),
             0xCB => "RCAP2H".to_owned(),
             0xCC => "TL2".to_owned(),
@@ -206,18 +433,19 @@ impl MCS51_Decompiler {
     }
 
     pub fn get_u16(&self, address: u16, offset: u16) -> u16 {
-        let hi_byte = self.program[address as usize + offset as usize] as u16;
-        let lo_byte = self.program[address as usize + offset as usize + 1] as u16;
+        let hi_byte = self.get_u8(address, offset) as u16;
+        let lo_byte = self.get_u8(address, offset.wrapping_add(1)) as u16;
         return (hi_byte << 8) + lo_byte;
     }
 
     pub fn get_u8(&self, address: u16, offset: u16) -> u8 {
-        let addr = (address + offset) as usize;
-        return *self.program.get(addr).unwrap();
+        // Operands past the end of the image read as erased memory
+        let addr = self.get_physical_address(address.wrapping_add(offset));
+        return *self.program.get(addr).unwrap_or(&0xFF);
     }
 
     pub fn get_opcode(&self, address: u16) -> u8 {
-        return self.program[address as usize];
+        return self.get_u8(address, 0);
     }
 
     pub fn one_byte_instruction(
@@ -230,7 +458,7 @@ impl MCS51_Decompiler {
             address: address,
             instruction: vec![opcode as u16],
             code: label.to_owned(),
-            next: vec![address + 1],
+            next: vec![address.wrapping_add(1)],
         };
     }
 
@@ -259,7 +487,7 @@ impl MCS51_Decompiler {
             address: address,
             instruction: vec![opcode as u16, val],
             code: code,
-            next: vec![address + 2],
+            next: vec![address.wrapping_add(2)],
         };
     }
 
@@ -272,31 +500,31 @@ impl MCS51_Decompiler {
         let code_addr = self.get_u8(address, 1) as u16;
 
         let new_address: u16 = if code_addr & 0x80 > 0 {
-            address.wrapping_sub((code_addr as i8 * -1) as u16) + 2
+            address.wrapping_sub((code_addr as i8 * -1) as u16).wrapping_add(2)
         } else {
-            address.wrapping_add(code_addr as u16) + 2
+            address.wrapping_add(code_addr as u16).wrapping_add(2)
         };
 
         return MCS51_Decompiler_Instruction {
             address: address,
             instruction: vec![opcode as u16, code_addr],
             code: format!("{} LAB_{:04x}", label, new_address),
-            next: vec![address + 2, new_address],
+            next: vec![address.wrapping_add(2), new_address],
         };
     }
 
     pub fn get_rel_address(address: u16, val_i8: u16, instruction_length: u16) -> u16 {
         let new_address: u16 = if val_i8 & 0x80 > 0 {
-            address.wrapping_sub((val_i8 as i8 * -1) as u16) + instruction_length
+            address.wrapping_sub((val_i8 as i8 * -1) as u16).wrapping_add(instruction_length)
         } else {
-            address.wrapping_add(val_i8) + instruction_length
+            address.wrapping_add(val_i8).wrapping_add(instruction_length)
         };
 
         return new_address;
     }
 
-    pub fn get_instruction(&mut self, address: u16) -> MCS51_Decompiler_Instruction {
-        let opcode = self.program[address as usize];
+    pub fn get_instruction(&self, address: u16) -> MCS51_Decompiler_Instruction {
+        let opcode = self.get_opcode(address);
 
         match opcode {
             0x00 => {
@@ -329,7 +557,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, dest],
                     code: format!("INC {:02x}", dest),
-                    next: vec![address + 2],
+                    next: vec![address.wrapping_add(2)],
                 };
             }
 
@@ -360,7 +588,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, bit_addr as u16, code_addr as u16],
                     code: format!("JBC {}, LAB_{:04x}", dest_name, new_address), //TODO Store as negative number
-                    next: vec![address + 3, new_address],
+                    next: vec![address.wrapping_add(3), new_address],
                 };
             }
 
@@ -371,7 +599,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, dest],
                     code: format!("LCALL FUN_{:04x}", dest),
-                    next: vec![address + 3, dest],
+                    next: vec![address.wrapping_add(3), dest],
                 };
             }
 
@@ -390,7 +618,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, data],
                     code: format!("DEC {}", MCS51_Decompiler::sfr_name(data as u8)),
-                    next: vec![address + 2],
+                    next: vec![address.wrapping_add(2)],
                 };
             }
 
@@ -421,7 +649,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, bit_addr as u16, code_addr as u16],
                     code: format!("JB {}, LAB_{:04x}", dest_name, new_address),
-                    next: vec![address + 3, new_address],
+                    next: vec![address.wrapping_add(3), new_address],
                 };
             }
 
@@ -469,7 +697,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, bit_addr as u16, code_addr as u16],
                     code: format!("JNB {}, LAB_{:04x}", dest_name, new_address), //TODO Store as negative number
-                    next: vec![address + 3, new_address],
+                    next: vec![address.wrapping_add(3), new_address],
                 };
             }
 
@@ -529,7 +757,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, dest, src],
                     code: format!("ANL {:02x}, #{:02x}", dest, src), //TODO Store as negative number
-                    next: vec![address + 3],
+                    next: vec![address.wrapping_add(3)],
                 };
             }
 
@@ -576,7 +804,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, data1, data2],
                     code: format!("MOV {}, #{:02x}", address_label, data2),
-                    next: vec![address + 3],
+                    next: vec![address.wrapping_add(3)],
                 };
             }
 
@@ -588,7 +816,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, data],
                     code: format!("MOV R{}, #{:02x}", register, data),
-                    next: vec![address + 2],
+                    next: vec![address.wrapping_add(2)],
                 };
             }
 
@@ -615,7 +843,7 @@ impl MCS51_Decompiler {
                         MCS51_Decompiler::sfr_name(dest as u8),
                         MCS51_Decompiler::sfr_name(src as u8)
                     ),
-                    next: vec![address + 3],
+                    next: vec![address.wrapping_add(3)],
                 };
             }
 
@@ -629,7 +857,7 @@ impl MCS51_Decompiler {
                         MCS51_Decompiler::sfr_name(src as u8),
                         opcode & 0x01
                     ),
-                    next: vec![address + 2],
+                    next: vec![address.wrapping_add(2)],
                 };
             }
 
@@ -643,18 +871,25 @@ impl MCS51_Decompiler {
                         MCS51_Decompiler::sfr_name(src as u8),
                         opcode & 0x07
                     ),
-                    next: vec![address + 2],
+                    next: vec![address.wrapping_add(2)],
                 };
             }
 
             0x90 => {
                 let data = self.get_u16(address, 1) as u16;
 
+                let code = match self.get_trampoline(address) {
+                    Some((bank, _)) => {
+                        format!("MOV DPTR, #{:04x} ; BANK {} FUN_{:04x}", data, bank, data)
+                    }
+                    None => format!("MOV DPTR, #{:04x}", data),
+                };
+
                 return MCS51_Decompiler_Instruction {
                     address: address,
                     instruction: vec![opcode as u16, data],
-                    code: format!("MOV DPTR, #{:04x}", data),
-                    next: vec![address + 3],
+                    code: code,
+                    next: vec![address.wrapping_add(3)],
                 };
             }
 
@@ -694,7 +929,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, data],
                     code: format!("MOV R{}, {:02x}", register, data),
-                    next: vec![address + 2],
+                    next: vec![address.wrapping_add(2)],
                 };
             }
 
@@ -706,7 +941,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, data_addr],
                     code: format!("CPL {}", bit_dest),
-                    next: vec![address + 2],
+                    next: vec![address.wrapping_add(2)],
                 };
             }
 
@@ -719,7 +954,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, data, destu as u16],
                     code: format!("CJNE A, #{:02x}, LAB_{:04x}", data, new_address),
-                    next: vec![address + 3, new_address],
+                    next: vec![address.wrapping_add(3), new_address],
                 };
             }
 
@@ -732,7 +967,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, data_addr, destu],
                     code: format!("CJNE A, {:02x}, LAB_{:04x}", data_addr, new_address),
-                    next: vec![address + 3, new_address],
+                    next: vec![address.wrapping_add(3), new_address],
                 };
             }
 
@@ -746,7 +981,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, data, destu],
                     code: format!("CJNE R{}, #{:02x}, LAB_{:04x}", register, data, new_address),
-                    next: vec![address + 3, new_address],
+                    next: vec![address.wrapping_add(3), new_address],
                 };
             }
 
@@ -761,7 +996,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, dest],
                     code: format!("CLR {}", dest_name),
-                    next: vec![address + 2],
+                    next: vec![address.wrapping_add(2)],
                 };
             }
 
@@ -796,7 +1031,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, dest],
                     code: format!("SETB {}", dest_name),
-                    next: vec![address + 2],
+                    next: vec![address.wrapping_add(2)],
                 };
             }
 
@@ -817,7 +1052,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, data_addr as u16, destu as u16],
                     code: format!("DJNZ {:02x}, LAB_{:04x}", data_addr, new_address),
-                    next: vec![address + 3, new_address],
+                    next: vec![address.wrapping_add(3), new_address],
                 };
             }
 
@@ -830,7 +1065,7 @@ impl MCS51_Decompiler {
                     address: address,
                     instruction: vec![opcode as u16, destu as u16],
                     code: format!("DJNZ R{}, LAB_{:04x}", register, new_address),
-                    next: vec![address + 2, new_address],
+                    next: vec![address.wrapping_add(2), new_address],
                 };
             }
 
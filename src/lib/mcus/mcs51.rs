@@ -1,5 +1,43 @@
 use crate::lib::decompiler::mcs51;
 use crate::lib::traits::component::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+mod code;
+mod debugger;
+mod derivative;
+mod expression;
+pub mod hex;
+mod interrupts;
+mod ports;
+mod power;
+mod reset;
+mod run_control;
+mod serial;
+pub mod serial_bus;
+mod strict;
+mod timer2;
+pub mod timers;
+mod timing;
+mod trace;
+mod watchdog;
+mod xdata;
+pub use self::code::*;
+pub use self::debugger::*;
+pub use self::derivative::*;
+pub use self::expression::*;
+pub use self::interrupts::*;
+pub use self::ports::*;
+pub use self::power::*;
+pub use self::reset::*;
+pub use self::run_control::*;
+pub use self::serial::*;
+pub use self::strict::*;
+pub use self::timer2::*;
+pub use self::timing::*;
+pub use self::trace::*;
+pub use self::watchdog::*;
+pub use self::xdata::*;
 
 #[derive(Debug, Clone, Copy)]
 pub enum MCS51_REGISTERS {
@@ -7,6 +45,8 @@ pub enum MCS51_REGISTERS {
     SP,
     DPL,
     DPH,
+    DP1L,
+    DP1H,
     PCON,
     TCON,
     TMOD,
@@ -14,14 +54,17 @@ pub enum MCS51_REGISTERS {
     TL1,
     TH0,
     TH1,
+    AUXR,
     P1,
     SCON,
     SBUF,
     P2,
+    AUXR1,
     IE,
     P3,
     IP,
     T2CON,
+    T2MOD,
     RCAP2L,
     RCAP2H,
     TL2,
@@ -47,39 +90,102 @@ pub enum MCS51_ADDRESSING {
 pub struct MCS51 {
     pub pc: u16,
     pub op_pc: u16,
-    program: Vec<u8>,
+    pub code: MCS51_Code,
+    pub code_trap: Option<u16>,
+    pub sfr_policy: MCS51_SFR_POLICY,
+    pub sfr_log: Vec<MCS51_SFR_Error>,
+    pub sfr_error: Option<MCS51_SFR_Error>,
+    pub debugger: MCS51_Debugger,
+    pub stop_request: Arc<AtomicBool>,
+    pub tracer: Option<MCS51_Tracer>,
+    pub open_bus: u8,
     pub special_function_registers: [u8; MCS51_REGISTERS::REGISTER_COUNT as usize],
-    pub ram: [u8; 255],
+    pub ram: [u8; 256],
+    pub derivative: &'static MCS51_Derivative,
+    pub sfr_mask: u128,
+    pub xdata: MCS51_Xdata,
+    pub port_pins: [u8; MCS51_PORT_COUNT],
+    pub port_drivers: [u8; MCS51_PORT_COUNT],
+    pub interrupt_in_progress: [bool; 2],
+    pub interrupt_blocked: bool,
+    pub interrupt_pins: [bool; 2],
+    pub timer_pins: [bool; 2],
+    pub timer2_pins: [bool; 2],
+    pub serial: MCS51_Serial,
+    pub watchdog: MCS51_Watchdog,
+    pub power_up_seed: Option<u64>,
+    pub rst_pin: bool,
+    pub rst_cycles: u8,
+    pub reset_source: MCS51_RESET_SOURCE,
     pub additional_cycles: u8,
+    pub cycle_count: u64,
+    pub idle_cycles: u64,
+    pub power_down_cycles: u64,
+    pub oscillator_frequency: u32,
+    pub clocks_per_cycle: u32,
     pub dispatch: [fn(&mut MCS51); 256],
     pub debug: bool,
 }
 
 impl MCS51 {
     pub fn new() -> MCS51 {
-        let mcs51 = MCS51 {
+        let mut mcs51 = MCS51 {
             pc: 0,
             op_pc: 0,
-            ram: [0; 255],
-            program: vec![],
+            ram: [0; 256],
+            derivative: &MCS51_8052,
+            sfr_mask: MCS51_8052.get_sfr_mask(),
+            code: MCS51_Code::new(MCS51_8052.rom_size),
+            code_trap: None,
+            sfr_policy: MCS51_SFR_POLICY::IGNORE,
+            sfr_log: Vec::new(),
+            sfr_error: None,
+            debugger: MCS51_Debugger::new(),
+            stop_request: Arc::new(AtomicBool::new(false)),
+            tracer: None,
+            open_bus: 0,
             special_function_registers: [0; MCS51_REGISTERS::REGISTER_COUNT as usize],
+            xdata: MCS51_Xdata::new(),
+            port_pins: [0xFF; MCS51_PORT_COUNT],
+            port_drivers: [0xFF; MCS51_PORT_COUNT],
+            interrupt_in_progress: [false; 2],
+            interrupt_blocked: false,
+            interrupt_pins: [true; 2],
+            timer_pins: [true; 2],
+            timer2_pins: [true; 2],
+            serial: MCS51_Serial::new(),
+            watchdog: MCS51_Watchdog::new(),
+            power_up_seed: None,
+            rst_pin: false,
+            rst_cycles: 0,
+            reset_source: MCS51_RESET_SOURCE::POWER_ON,
             additional_cycles: 0,
+            cycle_count: 0,
+            idle_cycles: 0,
+            power_down_cycles: 0,
+            oscillator_frequency: MCS51_DEFAULT_OSCILLATOR_FREQUENCY,
+            clocks_per_cycle: MCS51_DEFAULT_CLOCKS_PER_CYCLE,
             dispatch: [|cpu| {}; 256],
             debug: false,
         };
 
+        mcs51.generate_opcode_array();
         mcs51
     }
 
+    // The stack lives in the indirectly addressed RAM, it can use the upper 128 bytes on 8052 parts
     pub fn push_stack(&mut self, value: u8) {
         self.write_sfr_rel(MCS51_REGISTERS::SP, 1, false);
         let sp = self.get_stack_pointer();
-        self.write(sp, value);
+        self.write_indirect(sp, value);
     }
 
     pub fn pop_stack(&mut self) -> u8 {
         let sp = self.get_stack_pointer();
-        let val = *self.read(sp).unwrap();
+        let val = self
+            .read_indirect(sp)
+            .cloned()
+            .unwrap_or(MCS51_UNIMPLEMENTED_RAM_VALUE);
         self.write_sfr_rel(MCS51_REGISTERS::SP, 1, true);
         return val;
     }
@@ -97,11 +203,22 @@ impl MCS51 {
     }
 
     pub fn read_sfr(&self, register: MCS51_REGISTERS) -> u8 {
-        return self.special_function_registers[register as usize];
+        return match register {
+            MCS51_REGISTERS::PSW => self.get_psw(),
+            _ => self.special_function_registers[register as usize],
+        };
     }
 
     pub fn write_sfr(&mut self, register: MCS51_REGISTERS, value: u8) {
         self.special_function_registers[register as usize] = value;
+
+        match register {
+            MCS51_REGISTERS::ACC | MCS51_REGISTERS::PSW => self.update_parity_flag(),
+            MCS51_REGISTERS::P0 | MCS51_REGISTERS::P1 | MCS51_REGISTERS::P2 | MCS51_REGISTERS::P3 => {
+                self.update_port_pins()
+            }
+            _ => (),
+        }
     }
 
     pub fn write_sfr_rel(&mut self, register: MCS51_REGISTERS, value: u8, sub: bool) {
@@ -151,10 +268,6 @@ impl MCS51 {
         self.ram[register as usize + bank as usize] = value;
     }
 
-    pub fn read_code_byte(&mut self, addr: usize) -> u8 {
-        return self.program[addr];
-    }
-
     /*
     Bit Addressable Area: 16 bytes have been assigned for this segment, 20H-2FH. Each one of the 128 bits of this
     segment can be directly addressed (0-7FH).
@@ -162,41 +275,74 @@ impl MCS51 {
     addresses, ie. 0 to 7FH. The other way is with reference to bytes 20H to 2FH. Thus, bits 0–7 can also be referred to
     as bits 20.0–20.7, and bits 8-FH are the same as 21.0–21.7 and so on.
     Each of the 16 bytes in this segment can also be addressed as a byte.
-    */
 
-    pub fn read_bit(&self, address: u8) -> bool {
-        let register = address >> 3;
-        let bit = address & 0x7;
+    Bit addresses 80H-FFH refer to the SFRs whose address ends in 0H or 8H (P0, TCON, P1, SCON...),
+    bit 0 of the register having the same address as the register itself.
+    */
 
-        let value = self.read((register * 0x08) + 0x80);
-        let val = *value.unwrap();
-        return (val & bit) != 0;
+    // Returns the byte address holding a bit and the bit mask within it
+    pub fn get_bit_location(address: u8) -> (u8, u8) {
+        if address < 0x80 {
+            return (0x20 + (address >> 3), 1 << (address & 0x7));
+        }
+        return (address & 0xF8, 1 << (address & 0x7));
     }
 
-    pub fn write_bit(&mut self, address: u8, value: bool) {        
-        let addr = address & 0xF8;
+    pub fn read_bit(&self, address: u8) -> bool {
+        let (byte, mask) = MCS51::get_bit_location(address);
+        return self.read_raw(byte) & mask != 0;
+    }
 
-        //println!("{:0x} {:0x}", address, addr);
+    // Read-modify-write instructions (JBC, CPL, CLR, SETB, MOV bit,C) use the port latches
+    pub fn read_bit_latch(&self, address: u8) -> bool {
+        let (byte, mask) = MCS51::get_bit_location(address);
+        return self.read_latch(byte) & mask != 0;
+    }
 
-        let bit = address & 0x7;
-        let mut src = *self.read(addr).unwrap();
+    pub fn write_bit(&mut self, address: u8, value: bool) {
+        let (byte, mask) = MCS51::get_bit_location(address);
+        let src = self.read_latch(byte);
 
         if value {
-            src |= (value as u8) << bit;
+            self.write(byte, src | mask);
         } else {
-            src &= ((!value) as u8) << bit;
+            self.write(byte, src & !mask);
+        }
+    }
+
+    /*
+    Port latches
+
+    Instructions that read-modify-write a port read the port latch rather than the pins, so that
+    a pin pulled low externally is not written back as 0 into its latch.
+    */
+    pub fn read_latch(&self, address: u8) -> u8 {
+        if !self.has_sfr(address) {
+            return self.read_raw(address);
         }
 
-        self.write(addr, src);
+        match address {
+            0x80 => self.special_function_registers[MCS51_REGISTERS::P0 as usize],
+            0x90 => self.special_function_registers[MCS51_REGISTERS::P1 as usize],
+            0xA0 => self.special_function_registers[MCS51_REGISTERS::P2 as usize],
+            0xB0 => self.special_function_registers[MCS51_REGISTERS::P3 as usize],
+            _ => self.read_raw(address),
+        }
     }
 
     pub fn read_raw(&self, address: u8) -> u8 {
+        if address >= 0x80 && !self.has_sfr(address) {
+            return 0;
+        }
+
         match address {
             0x00..=0x7F => self.ram[address as usize],
-            0x80 => self.special_function_registers[MCS51_REGISTERS::P0 as usize],
+            0x80 => self.port_pins[0],
             0x81 => self.special_function_registers[MCS51_REGISTERS::SP as usize],
             0x82 => self.special_function_registers[MCS51_REGISTERS::DPL as usize],
             0x83 => self.special_function_registers[MCS51_REGISTERS::DPH as usize],
+            0x84 => self.special_function_registers[MCS51_REGISTERS::DP1L as usize],
+            0x85 => self.special_function_registers[MCS51_REGISTERS::DP1H as usize],
             0x87 => self.special_function_registers[MCS51_REGISTERS::PCON as usize],
             0x88 => self.special_function_registers[MCS51_REGISTERS::TCON as usize],
             0x89 => self.special_function_registers[MCS51_REGISTERS::TMOD as usize],
@@ -204,19 +350,23 @@ impl MCS51 {
             0x8B => self.special_function_registers[MCS51_REGISTERS::TL1 as usize],
             0x8C => self.special_function_registers[MCS51_REGISTERS::TH0 as usize],
             0x8D => self.special_function_registers[MCS51_REGISTERS::TH1 as usize],
-            0x90 => self.special_function_registers[MCS51_REGISTERS::P1 as usize],
+            0x8E => self.special_function_registers[MCS51_REGISTERS::AUXR as usize],
+            0x90 => self.port_pins[1],
             0x98 => self.special_function_registers[MCS51_REGISTERS::SCON as usize],
             0x99 => self.special_function_registers[MCS51_REGISTERS::SBUF as usize],
-            0xA0 => self.special_function_registers[MCS51_REGISTERS::P2 as usize],
+            0xA0 => self.port_pins[2],
+            0xA2 => self.special_function_registers[MCS51_REGISTERS::AUXR1 as usize],
+            0xA6 => 0xFF,
             0xA8 => self.special_function_registers[MCS51_REGISTERS::IE as usize],
-            0xB0 => self.special_function_registers[MCS51_REGISTERS::P3 as usize],
+            0xB0 => self.port_pins[3],
             0xB8 => self.special_function_registers[MCS51_REGISTERS::IP as usize],
             0xC8 => self.special_function_registers[MCS51_REGISTERS::T2CON as usize],
+            0xC9 => self.special_function_registers[MCS51_REGISTERS::T2MOD as usize],
             0xCA => self.special_function_registers[MCS51_REGISTERS::RCAP2L as usize],
             0xCB => self.special_function_registers[MCS51_REGISTERS::RCAP2H as usize],
             0xCC => self.special_function_registers[MCS51_REGISTERS::TL2 as usize],
             0xCD => self.special_function_registers[MCS51_REGISTERS::TH2 as usize],
-            0xD0 => self.special_function_registers[MCS51_REGISTERS::PSW as usize],
+            0xD0 => self.get_psw(),
             0xE0 => self.special_function_registers[MCS51_REGISTERS::ACC as usize],
             0xF0 => self.special_function_registers[MCS51_REGISTERS::B as usize],
             _ => 0,
@@ -224,6 +374,10 @@ impl MCS51 {
     }
 
     pub fn get_mut_addr(&mut self, address: u8) -> Option<&mut u8> {
+        if address >= 0x80 && !self.has_sfr(address) {
+            return None;
+        }
+
         match address {
             0x00..=0x7F => self.ram.get_mut(address as usize),
             0x80 => self
@@ -238,6 +392,12 @@ impl MCS51 {
             0x83 => self
                 .special_function_registers
                 .get_mut(MCS51_REGISTERS::DPH as usize),
+            0x84 => self
+                .special_function_registers
+                .get_mut(MCS51_REGISTERS::DP1L as usize),
+            0x85 => self
+                .special_function_registers
+                .get_mut(MCS51_REGISTERS::DP1H as usize),
             0x87 => self
                 .special_function_registers
                 .get_mut(MCS51_REGISTERS::PCON as usize),
@@ -259,6 +419,9 @@ impl MCS51 {
             0x8D => self
                 .special_function_registers
                 .get_mut(MCS51_REGISTERS::TH1 as usize),
+            0x8E => self
+                .special_function_registers
+                .get_mut(MCS51_REGISTERS::AUXR as usize),
             0x90 => self
                 .special_function_registers
                 .get_mut(MCS51_REGISTERS::P1 as usize),
@@ -271,6 +434,9 @@ impl MCS51 {
             0xA0 => self
                 .special_function_registers
                 .get_mut(MCS51_REGISTERS::P2 as usize),
+            0xA2 => self
+                .special_function_registers
+                .get_mut(MCS51_REGISTERS::AUXR1 as usize),
             0xA8 => self
                 .special_function_registers
                 .get_mut(MCS51_REGISTERS::IE as usize),
@@ -283,6 +449,9 @@ impl MCS51 {
             0xC8 => self
                 .special_function_registers
                 .get_mut(MCS51_REGISTERS::T2CON as usize),
+            0xC9 => self
+                .special_function_registers
+                .get_mut(MCS51_REGISTERS::T2MOD as usize),
             0xCA => self
                 .special_function_registers
                 .get_mut(MCS51_REGISTERS::RCAP2L as usize),
@@ -309,11 +478,13 @@ impl MCS51 {
     }
 
     pub fn read(&self, address: u8) -> Option<&u8> {
+        if address >= 0x80 && !self.has_sfr(address) {
+            return None;
+        }
+
         match address {
             0x00..=0x7F => self.ram.get(address as usize),
-            0x80 => self
-                .special_function_registers
-                .get(MCS51_REGISTERS::P0 as usize),
+            0x80 => self.port_pins.get(0),
             0x81 => self
                 .special_function_registers
                 .get(MCS51_REGISTERS::SP as usize),
@@ -323,6 +494,12 @@ impl MCS51 {
             0x83 => self
                 .special_function_registers
                 .get(MCS51_REGISTERS::DPH as usize),
+            0x84 => self
+                .special_function_registers
+                .get(MCS51_REGISTERS::DP1L as usize),
+            0x85 => self
+                .special_function_registers
+                .get(MCS51_REGISTERS::DP1H as usize),
             0x87 => self
                 .special_function_registers
                 .get(MCS51_REGISTERS::PCON as usize),
@@ -344,30 +521,35 @@ impl MCS51 {
             0x8D => self
                 .special_function_registers
                 .get(MCS51_REGISTERS::TH1 as usize),
-            0x90 => self
+            0x8E => self
                 .special_function_registers
-                .get(MCS51_REGISTERS::P1 as usize),
+                .get(MCS51_REGISTERS::AUXR as usize),
+            0x90 => self.port_pins.get(1),
             0x98 => self
                 .special_function_registers
                 .get(MCS51_REGISTERS::SCON as usize),
             0x99 => self
                 .special_function_registers
                 .get(MCS51_REGISTERS::SBUF as usize),
-            0xA0 => self
+            0xA0 => self.port_pins.get(2),
+            0xA2 => self
                 .special_function_registers
-                .get(MCS51_REGISTERS::P2 as usize),
+                .get(MCS51_REGISTERS::AUXR1 as usize),
+            // WDTRST is write only
+            0xA6 => Some(&0xFF),
             0xA8 => self
                 .special_function_registers
                 .get(MCS51_REGISTERS::IE as usize),
-            0xB0 => self
-                .special_function_registers
-                .get(MCS51_REGISTERS::P3 as usize),
+            0xB0 => self.port_pins.get(3),
             0xB8 => self
                 .special_function_registers
                 .get(MCS51_REGISTERS::IP as usize),
             0xC8 => self
                 .special_function_registers
                 .get(MCS51_REGISTERS::T2CON as usize),
+            0xC9 => self
+                .special_function_registers
+                .get(MCS51_REGISTERS::T2MOD as usize),
             0xCA => self
                 .special_function_registers
                 .get(MCS51_REGISTERS::RCAP2L as usize),
@@ -394,12 +576,21 @@ impl MCS51 {
     }
 
     pub fn write(&mut self, address: u8, value: u8) {
+        if address >= 0x80 && !self.has_sfr(address) {
+            return;
+        }
+
         match address {
             0x00..=0x7F => self.ram[address as usize] = value,
-            0x80 => self.special_function_registers[MCS51_REGISTERS::P0 as usize] = value,
+            0x80 => {
+                self.special_function_registers[MCS51_REGISTERS::P0 as usize] = value;
+                self.update_port_pins();
+            }
             0x81 => self.special_function_registers[MCS51_REGISTERS::SP as usize] = value,
             0x82 => self.special_function_registers[MCS51_REGISTERS::DPL as usize] = value,
             0x83 => self.special_function_registers[MCS51_REGISTERS::DPH as usize] = value,
+            0x84 => self.special_function_registers[MCS51_REGISTERS::DP1L as usize] = value,
+            0x85 => self.special_function_registers[MCS51_REGISTERS::DP1H as usize] = value,
             0x87 => self.special_function_registers[MCS51_REGISTERS::PCON as usize] = value,
             0x88 => self.special_function_registers[MCS51_REGISTERS::TCON as usize] = value,
             0x89 => self.special_function_registers[MCS51_REGISTERS::TMOD as usize] = value,
@@ -407,33 +598,109 @@ impl MCS51 {
             0x8B => self.special_function_registers[MCS51_REGISTERS::TL1 as usize] = value,
             0x8C => self.special_function_registers[MCS51_REGISTERS::TH0 as usize] = value,
             0x8D => self.special_function_registers[MCS51_REGISTERS::TH1 as usize] = value,
-            0x90 => self.special_function_registers[MCS51_REGISTERS::P1 as usize] = value,
+            0x8E => self.special_function_registers[MCS51_REGISTERS::AUXR as usize] = value,
+            0x90 => {
+                self.special_function_registers[MCS51_REGISTERS::P1 as usize] = value;
+                self.update_port_pins();
+            }
             0x98 => self.special_function_registers[MCS51_REGISTERS::SCON as usize] = value,
-            0x99 => self.special_function_registers[MCS51_REGISTERS::SBUF as usize] = value,
-            0xA0 => self.special_function_registers[MCS51_REGISTERS::P2 as usize] = value,
-            0xA8 => self.special_function_registers[MCS51_REGISTERS::IE as usize] = value,
-            0xB0 => self.special_function_registers[MCS51_REGISTERS::P3 as usize] = value,
-            0xB8 => self.special_function_registers[MCS51_REGISTERS::IP as usize] = value,
+            0x99 => self.serial_transmit(value),
+            0xA0 => {
+                self.special_function_registers[MCS51_REGISTERS::P2 as usize] = value;
+                self.update_port_pins();
+            }
+            0xA2 => self.special_function_registers[MCS51_REGISTERS::AUXR1 as usize] = value,
+            0xA6 => self.watchdog_write(value),
+            0xA8 => {
+                self.special_function_registers[MCS51_REGISTERS::IE as usize] = value;
+                self.interrupt_blocked = true;
+            }
+            0xB0 => {
+                self.special_function_registers[MCS51_REGISTERS::P3 as usize] = value;
+                self.update_port_pins();
+            }
+            0xB8 => {
+                self.special_function_registers[MCS51_REGISTERS::IP as usize] = value;
+                self.interrupt_blocked = true;
+            }
             0xC8 => self.special_function_registers[MCS51_REGISTERS::T2CON as usize] = value,
+            0xC9 => self.special_function_registers[MCS51_REGISTERS::T2MOD as usize] = value,
             0xCA => self.special_function_registers[MCS51_REGISTERS::RCAP2L as usize] = value,
             0xCB => self.special_function_registers[MCS51_REGISTERS::RCAP2H as usize] = value,
             0xCC => self.special_function_registers[MCS51_REGISTERS::TL2 as usize] = value,
             0xCD => self.special_function_registers[MCS51_REGISTERS::TH2 as usize] = value,
-            0xD0 => self.special_function_registers[MCS51_REGISTERS::PSW as usize] = value,
-            0xE0 => self.special_function_registers[MCS51_REGISTERS::ACC as usize] = value,
+            0xD0 => self.write_sfr(MCS51_REGISTERS::PSW, value),
+            0xE0 => self.write_sfr(MCS51_REGISTERS::ACC, value),
             0xF0 => self.special_function_registers[MCS51_REGISTERS::B as usize] = value,
             _ => (),
         }
     }
 
+    /*
+    Indirect addressing (@R0, @R1 and the stack) always refers to the internal RAM. On parts with
+    256 bytes of RAM the upper 128 bytes share the addresses 80H-FFH with the SFRs but can only be
+    reached indirectly, while direct addresses in that range always refer to the SFRs. On parts
+    with 128 bytes, indirect reads above 7FH return the floating bus (FFH) and writes are lost,
+    the SFR policy can log or stop on these accesses.
+    */
+
+    pub fn read_indirect(&self, address: u8) -> Option<&u8> {
+        if address as usize >= self.derivative.ram_size {
+            return None;
+        }
+        return self.ram.get(address as usize);
+    }
+
+    pub fn get_mut_indirect(&mut self, address: u8) -> Option<&mut u8> {
+        if address as usize >= self.derivative.ram_size {
+            return None;
+        }
+        return self.ram.get_mut(address as usize);
+    }
+
+    pub fn write_indirect(&mut self, address: u8, value: u8) {
+        if let Some(byte) = self.get_mut_indirect(address) {
+            *byte = value;
+        }
+    }
+
+    pub fn read_xdata(&mut self, address: u16) -> u8 {
+        return self.xdata.read(address);
+    }
+
+    pub fn write_xdata(&mut self, address: u16, value: u8) {
+        self.write_bank_latch(address, value);
+        self.xdata.write(address, value);
+    }
+
+    // MOVX @Ri addresses the external page selected by the P2 latch
+    pub fn get_xdata_ri_address(&self, reg: u8) -> u16 {
+        let page = self.read_sfr(MCS51_REGISTERS::P2) as u16;
+        return (page << 8) + self.read_register(reg) as u16;
+    }
+
+    /*
+    Dual Data Pointer: the AT89S52 has two 16-bit data pointers, DP0 at 82H-83H and DP1 at
+    84H-85H. AUXR1 bit 0, DPS, selects the one used by the instructions referring to DPTR. On
+    the other parts AUXR1 is not implemented and always reads 0.
+    */
+    fn get_dptr_registers(&self) -> (MCS51_REGISTERS, MCS51_REGISTERS) {
+        if self.read_sfr(MCS51_REGISTERS::AUXR1) & MCS51_AUXR1_DPS != 0 {
+            return (MCS51_REGISTERS::DP1H, MCS51_REGISTERS::DP1L);
+        }
+        return (MCS51_REGISTERS::DPH, MCS51_REGISTERS::DPL);
+    }
+
     pub fn set_dptr(&mut self, value: u16) {
-        self.write_sfr(MCS51_REGISTERS::DPH, (value >> 8) as u8);
-        self.write_sfr(MCS51_REGISTERS::DPL, value as u8 & 0xFF);
+        let (high, low) = self.get_dptr_registers();
+        self.write_sfr(high, (value >> 8) as u8);
+        self.write_sfr(low, value as u8 & 0xFF);
     }
 
-    pub fn get_dptr(&mut self) -> u16 {
-        let dph = self.read_sfr(MCS51_REGISTERS::DPH);
-        let dpl = self.read_sfr(MCS51_REGISTERS::DPL);
+    pub fn get_dptr(&self) -> u16 {
+        let (high, low) = self.get_dptr_registers();
+        let dph = self.read_sfr(high);
+        let dpl = self.read_sfr(low);
 
         return ((dph as u16) << 8) + dpl as u16;
     }
@@ -477,90 +744,103 @@ impl MCS51 {
         return self.read_sfr(MCS51_REGISTERS::PSW) & 0x04 != 0;
     }
 
+    /*
+    PSW.P is set/cleared by hardware each instruction cycle to indicate an odd/even number of
+    "one" bits in the Accumulator, i.e., even parity. The stored flag is recomputed on every write
+    to ACC or PSW and around every instruction. ACC can also be changed through references
+    (get_u8_mut, get_mut_addr, get_sfr_mut), so the PSW reads by value (read_sfr, read_raw)
+    compute the flag from ACC.
+    */
+
+    pub fn get_psw(&self) -> u8 {
+        let acc = self.special_function_registers[MCS51_REGISTERS::ACC as usize];
+        let psw = self.special_function_registers[MCS51_REGISTERS::PSW as usize];
+        return (psw & !0x01) | (acc.count_ones() & 1) as u8;
+    }
+
+    pub fn update_parity_flag(&mut self) {
+        self.special_function_registers[MCS51_REGISTERS::PSW as usize] = self.get_psw();
+    }
+
+    pub fn get_parity_flag(&self) -> bool {
+        return self.read_sfr(MCS51_REGISTERS::PSW) & 0x01 != 0;
+    }
+
     pub fn get_accumulator(&self) -> u8 {
         return self.special_function_registers[MCS51_REGISTERS::ACC as usize];
     }
 
     pub fn set_accumulator(&mut self, value: u8) {
-        self.special_function_registers[MCS51_REGISTERS::ACC as usize] = value;
+        self.write_sfr(MCS51_REGISTERS::ACC, value);
     }
 
+    // Loads the reset value of every SFR implemented by the derivative
     pub fn reset_registers(&mut self) {
-        self.special_function_registers[MCS51_REGISTERS::P0 as usize] = 0xFF;
-        self.special_function_registers[MCS51_REGISTERS::SP as usize] = 0x07;
-        self.special_function_registers[MCS51_REGISTERS::DPL as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::DPH as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::PCON as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::TCON as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::TMOD as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::TL0 as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::TL1 as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::TH0 as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::TH1 as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::P1 as usize] = 0xFF;
-        self.special_function_registers[MCS51_REGISTERS::SCON as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::SBUF as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::P2 as usize] = 0xFF;
-        self.special_function_registers[MCS51_REGISTERS::IE as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::P3 as usize] = 0xFF;
-        self.special_function_registers[MCS51_REGISTERS::IP as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::T2CON as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::RCAP2L as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::RCAP2H as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::TL2 as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::TH2 as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::PSW as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::ACC as usize] = 0x00;
-        self.special_function_registers[MCS51_REGISTERS::B as usize] = 0x00;
-    }
-
-    pub fn next_instruction_debug_match(&mut self) {
-        if self.pc as usize >= self.program.len() {
-            self.pc = 0;
+        for (address, value) in self.derivative.sfrs.iter() {
+            if let Some(register) = self.get_mut_addr(*address) {
+                *register = *value;
+            }
         }
-
-        let opcode = self.program[self.pc as usize];
-        //self.opcode_dispatch_match(opcode);
+        self.update_parity_flag();
+        self.update_port_pins();
     }
 
-    pub fn next_instruction_debug_table(&mut self) {
-        if self.pc as usize >= self.program.len() {
-            self.pc = 0;
+    // Clocks the core until the current instruction, including its extra machine cycles, is done
+    pub fn step(&mut self) {
+        self.clock();
+        while self.additional_cycles > 0 {
+            self.clock();
         }
+    }
 
-        let opcode = self.program[self.pc as usize];
-        self.opcode_dispatch_table(opcode);
+    // Byte of the current instruction, the address wraps around the top of code memory
+    pub fn read_operand(&self, offset: u8) -> u8 {
+        return self.read_code_byte(self.op_pc.wrapping_add(offset as u16));
     }
 
     pub fn set_u8(&mut self, addressing: MCS51_ADDRESSING, value: u8) {
         match addressing {
             MCS51_ADDRESSING::ACCUMULATOR => self.write_sfr(MCS51_REGISTERS::ACC, value),
             MCS51_ADDRESSING::REGISTER(reg) => self.write_register(reg, value),
-            MCS51_ADDRESSING::DIRECT(offset) => self.write(
-                self.program[self.op_pc as usize + offset as usize],
-                value,
-            ),
-            MCS51_ADDRESSING::INDIRECT_Ri(reg) => self.write(self.read_register(reg), value),
-            _ => {
-                println!("Unsupported addressing mode");
+            MCS51_ADDRESSING::DIRECT(offset) => self.write(self.read_operand(offset), value),
+            MCS51_ADDRESSING::INDIRECT_Ri(reg) => {
+                self.write_indirect(self.read_register(reg), value)
             }
+            _ => unreachable!("Unsupported addressing mode {:?}", addressing),
         }
     }
 
+    /*
+    Reference to an operand for host code, it bypasses the side effects of write(). Unimplemented
+    locations are backed by open_bus, they read what a plain read would (0 for SFRs, FFH for upper
+    RAM) and what is written to them is discarded.
+    */
     pub fn get_u8_mut(&mut self, addressing: MCS51_ADDRESSING) -> Option<&mut u8> {
+        let open_bus = match addressing {
+            MCS51_ADDRESSING::DIRECT(offset) if self.get_mut_addr(self.read_operand(offset)).is_none() => {
+                Some(0)
+            }
+            MCS51_ADDRESSING::INDIRECT_Ri(reg) if self.get_mut_indirect(self.read_register(reg)).is_none() => {
+                Some(MCS51_UNIMPLEMENTED_RAM_VALUE)
+            }
+            _ => None,
+        };
+
+        if let Some(value) = open_bus {
+            self.open_bus = value;
+            return Some(&mut self.open_bus);
+        }
+
         match addressing {
             MCS51_ADDRESSING::ACCUMULATOR => self
                 .special_function_registers
                 .get_mut(MCS51_REGISTERS::ACC as usize),
             MCS51_ADDRESSING::REGISTER(reg) => self.get_register_mut(reg),
             MCS51_ADDRESSING::DIRECT(offset) => {
-                self.get_mut_addr(self.program[self.op_pc as usize + offset as usize])
-            }
-            MCS51_ADDRESSING::INDIRECT_Ri(reg) => self.get_mut_addr(self.read_register(reg)),
-            _ => {
-                println!("Unsupported addressing mode");
-                return None;
+                self.get_mut_addr(self.read_operand(offset))
             }
+            MCS51_ADDRESSING::INDIRECT_Ri(reg) => self.get_mut_indirect(self.read_register(reg)),
+            _ => unreachable!("Unsupported addressing mode {:?}", addressing),
         }
     }
 
@@ -568,21 +848,30 @@ impl MCS51 {
         match addressing {
             MCS51_ADDRESSING::ACCUMULATOR => Some(self.read_sfr(MCS51_REGISTERS::ACC)),
             MCS51_ADDRESSING::REGISTER(reg) => Some(self.read_register(reg)),
-            MCS51_ADDRESSING::DIRECT(offset) => Some(
-                *self
-                    .read(self.program[self.op_pc as usize + offset as usize])
-                    .unwrap(),
-            ),
-            MCS51_ADDRESSING::INDIRECT_Ri(reg) => {
-                self.read(self.read_register(reg)).cloned()
-            }
-            MCS51_ADDRESSING::DATA(offset) => {
-                Some(self.program[self.op_pc as usize + offset as usize])
+            MCS51_ADDRESSING::DIRECT(offset) => {
+                Some(self.read(self.read_operand(offset)).cloned().unwrap_or(0))
             }
-            _ => {
-                println!("Unsupported addressing mode");
-                return None;
+            MCS51_ADDRESSING::INDIRECT_Ri(reg) => Some(
+                self.read_indirect(self.read_register(reg))
+                    .cloned()
+                    .unwrap_or(MCS51_UNIMPLEMENTED_RAM_VALUE),
+            ),
+            MCS51_ADDRESSING::DATA(offset) => Some(self.read_operand(offset)),
+            _ => unreachable!("Unsupported addressing mode {:?}", addressing),
+        }
+    }
+
+    // Operand read of the read-modify-write instructions, ports return their latch
+    pub fn get_u8_latch(&self, addressing: MCS51_ADDRESSING) -> Option<u8> {
+        match addressing {
+            MCS51_ADDRESSING::DIRECT(offset) => {
+                let address = self.read_operand(offset);
+                if address >= 0x80 && !self.has_sfr(address) {
+                    return None;
+                }
+                Some(self.read_latch(address))
             }
+            _ => self.get_u8(addressing),
         }
     }
 
@@ -590,43 +879,33 @@ impl MCS51 {
         match addressing {
             MCS51_ADDRESSING::DATA(offset) => {
                 //Some(i8::from_be_bytes([*self.read(offset).unwrap()]))
-                Some(i8::from_be_bytes([self.program[self.op_pc as usize + offset as usize]]))
-            }
-            _ => {
-                println!("Unsupported addressing mode");
-                return None;
+                Some(i8::from_be_bytes([self.read_operand(offset)]))
             }
+            _ => unreachable!("Unsupported addressing mode {:?}", addressing),
         }
     }
 
     pub fn get_u16(&self, addressing: MCS51_ADDRESSING) -> Option<u16> {
         match addressing {
             MCS51_ADDRESSING::ADDR_16 => {
-                let offset: usize = self.op_pc as usize + 1;
-                let mut data: [u8; 2] = [0; 2];
-                data.copy_from_slice(&self.program[offset..offset + 2]);
+                let data = [self.read_operand(1), self.read_operand(2)];
                 let addr = u16::from_be_bytes(data);
 
                 return Some(addr);
             }
             MCS51_ADDRESSING::DATA(off) => {
-                let offset: usize = self.op_pc as usize + off as usize;
-
-                let mut data: [u8; 2] = [0; 2];
-                data.copy_from_slice(&self.program[offset..offset + 2]);
+                let data = [self.read_operand(off), self.read_operand(off.wrapping_add(1))];
                 let dat = u16::from_be_bytes(data);
                 return Some(dat);
             }
-            _ => {
-                println!("Unsupported addressing mode");
-                return None;
-            }
+            _ => unreachable!("Unsupported addressing mode {:?}", addressing),
         }
     }
 
+    // A10-A8 are the top three bits of the opcode, A7-A0 the second byte
     pub fn get_u11(&self) -> u16 {
-        let hi_byte = (self.program[self.op_pc as usize] as u16) << 3;
-        let lo_byte = self.program[self.op_pc as usize + 1];
+        let hi_byte = ((self.read_operand(0) & 0xE0) as u16) << 3;
+        let lo_byte = self.read_operand(1);
         let addr: u16 = hi_byte + lo_byte as u16;
         return addr;
     }
@@ -637,1014 +916,1116 @@ impl MCS51 {
     pub fn generate_opcode_array(&mut self) {
         self.dispatch[0x00] = |cpu: &mut MCS51| {
             cpu.op_nop();
-            cpu.opcode_additional_work("NOP", 0, 1);
+            cpu.opcode_additional_work("NOP", 1);
         };
         self.dispatch[0x01] = |cpu: &mut MCS51| {
             cpu.op_ajmp();
-            cpu.opcode_additional_work("AJMP", 1, 0);
+            cpu.opcode_additional_work("AJMP", 0);
         };
         self.dispatch[0x02] = |cpu: &mut MCS51| {
             cpu.op_ljmp(MCS51_ADDRESSING::ADDR_16);
-            cpu.opcode_additional_work("LJMP", 1, 0);
+            cpu.opcode_additional_work("LJMP", 0);
         };
         self.dispatch[0x03] = |cpu: &mut MCS51| {
             cpu.op_rr();
-            cpu.opcode_additional_work("RR", 0, 1);
+            cpu.opcode_additional_work("RR", 1);
         };
         self.dispatch[0x04] = |cpu: &mut MCS51| {
             cpu.op_inc(MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("INC", 0, 1);
+            cpu.opcode_additional_work("INC", 1);
         };
         self.dispatch[0x05] = |cpu: &mut MCS51| {
             cpu.op_inc(MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("INC", 0, 2);
+            cpu.opcode_additional_work("INC", 2);
         };
         self.dispatch[0x06] = |cpu: &mut MCS51| {
             cpu.op_inc(MCS51_ADDRESSING::INDIRECT_Ri(0));
-            cpu.opcode_additional_work("INC", 0, 1);
+            cpu.opcode_additional_work("INC", 1);
         };
         self.dispatch[0x07] = |cpu: &mut MCS51| {
             cpu.op_inc(MCS51_ADDRESSING::INDIRECT_Ri(1));
-            cpu.opcode_additional_work("INC", 0, 1);
+            cpu.opcode_additional_work("INC", 1);
         };
         self.dispatch[0x08] = |cpu: &mut MCS51| {
             cpu.op_inc(MCS51_ADDRESSING::REGISTER(0));
-            cpu.opcode_additional_work("INC", 0, 1);
+            cpu.opcode_additional_work("INC", 1);
         };
         self.dispatch[0x09] = |cpu: &mut MCS51| {
             cpu.op_inc(MCS51_ADDRESSING::REGISTER(1));
-            cpu.opcode_additional_work("INC", 0, 1);
+            cpu.opcode_additional_work("INC", 1);
         };
         self.dispatch[0x0A] = |cpu: &mut MCS51| {
             cpu.op_inc(MCS51_ADDRESSING::REGISTER(2));
-            cpu.opcode_additional_work("INC", 0, 1);
+            cpu.opcode_additional_work("INC", 1);
         };
         self.dispatch[0x0B] = |cpu: &mut MCS51| {
             cpu.op_inc(MCS51_ADDRESSING::REGISTER(3));
-            cpu.opcode_additional_work("INC", 0, 1);
+            cpu.opcode_additional_work("INC", 1);
         };
         self.dispatch[0x0C] = |cpu: &mut MCS51| {
             cpu.op_inc(MCS51_ADDRESSING::REGISTER(4));
-            cpu.opcode_additional_work("INC", 0, 1);
+            cpu.opcode_additional_work("INC", 1);
         };
         self.dispatch[0x0D] = |cpu: &mut MCS51| {
             cpu.op_inc(MCS51_ADDRESSING::REGISTER(5));
-            cpu.opcode_additional_work("INC", 0, 1);
+            cpu.opcode_additional_work("INC", 1);
         };
         self.dispatch[0x0E] = |cpu: &mut MCS51| {
             cpu.op_inc(MCS51_ADDRESSING::REGISTER(6));
-            cpu.opcode_additional_work("INC", 0, 1);
+            cpu.opcode_additional_work("INC", 1);
         };
         self.dispatch[0x0F] = |cpu: &mut MCS51| {
             cpu.op_inc(MCS51_ADDRESSING::REGISTER(7));
-            cpu.opcode_additional_work("INC", 0, 1);
+            cpu.opcode_additional_work("INC", 1);
         };
         self.dispatch[0x10] = |cpu: &mut MCS51| {
             cpu.op_jbc(MCS51_ADDRESSING::DATA(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("JBC", 1, 0);
+            cpu.opcode_additional_work("JBC", 0);
         };
         self.dispatch[0x11] = |cpu: &mut MCS51| {
             cpu.op_acall();
-            cpu.opcode_additional_work("ACALL", 1, 0);
+            cpu.opcode_additional_work("ACALL", 0);
         };
         self.dispatch[0x12] = |cpu: &mut MCS51| {
             cpu.op_lcall(MCS51_ADDRESSING::ADDR_16);
-            cpu.opcode_additional_work("LCALL", 1, 0);
+            cpu.opcode_additional_work("LCALL", 0);
         };
         self.dispatch[0x13] = |cpu: &mut MCS51| {
             cpu.op_rrc();
-            cpu.opcode_additional_work("RRC", 0, 1);
+            cpu.opcode_additional_work("RRC", 1);
         };
         self.dispatch[0x14] = |cpu: &mut MCS51| {
             cpu.op_dec(MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("DEC", 0, 1);
+            cpu.opcode_additional_work("DEC", 1);
         };
         self.dispatch[0x15] = |cpu: &mut MCS51| {
             cpu.op_dec(MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("DEC", 0, 2);
+            cpu.opcode_additional_work("DEC", 2);
         };
         self.dispatch[0x16] = |cpu: &mut MCS51| {
             cpu.op_dec(MCS51_ADDRESSING::INDIRECT_Ri(0));
-            cpu.opcode_additional_work("DEC", 0, 1);
+            cpu.opcode_additional_work("DEC", 1);
         };
         self.dispatch[0x17] = |cpu: &mut MCS51| {
             cpu.op_dec(MCS51_ADDRESSING::INDIRECT_Ri(1));
-            cpu.opcode_additional_work("DEC", 0, 1);
+            cpu.opcode_additional_work("DEC", 1);
         };
         self.dispatch[0x18] = |cpu: &mut MCS51| {
             cpu.op_dec(MCS51_ADDRESSING::REGISTER(0));
-            cpu.opcode_additional_work("DEC", 0, 1);
+            cpu.opcode_additional_work("DEC", 1);
         };
         self.dispatch[0x19] = |cpu: &mut MCS51| {
             cpu.op_dec(MCS51_ADDRESSING::REGISTER(1));
-            cpu.opcode_additional_work("DEC", 0, 1);
+            cpu.opcode_additional_work("DEC", 1);
         };
         self.dispatch[0x1A] = |cpu: &mut MCS51| {
             cpu.op_dec(MCS51_ADDRESSING::REGISTER(2));
-            cpu.opcode_additional_work("DEC", 0, 1);
+            cpu.opcode_additional_work("DEC", 1);
         };
         self.dispatch[0x1B] = |cpu: &mut MCS51| {
             cpu.op_dec(MCS51_ADDRESSING::REGISTER(3));
-            cpu.opcode_additional_work("DEC", 0, 1);
+            cpu.opcode_additional_work("DEC", 1);
         };
         self.dispatch[0x1C] = |cpu: &mut MCS51| {
             cpu.op_dec(MCS51_ADDRESSING::REGISTER(4));
-            cpu.opcode_additional_work("DEC", 0, 1);
+            cpu.opcode_additional_work("DEC", 1);
         };
         self.dispatch[0x1D] = |cpu: &mut MCS51| {
             cpu.op_dec(MCS51_ADDRESSING::REGISTER(5));
-            cpu.opcode_additional_work("DEC", 0, 1);
+            cpu.opcode_additional_work("DEC", 1);
         };
         self.dispatch[0x1E] = |cpu: &mut MCS51| {
             cpu.op_dec(MCS51_ADDRESSING::REGISTER(6));
-            cpu.opcode_additional_work("DEC", 0, 1);
+            cpu.opcode_additional_work("DEC", 1);
         };
         self.dispatch[0x1F] = |cpu: &mut MCS51| {
             cpu.op_dec(MCS51_ADDRESSING::REGISTER(7));
-            cpu.opcode_additional_work("DEC", 0, 1);
+            cpu.opcode_additional_work("DEC", 1);
         };
         self.dispatch[0x20] = |cpu: &mut MCS51| {
             cpu.op_jb(MCS51_ADDRESSING::DATA(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("JB", 1, 0);
+            cpu.opcode_additional_work("JB", 0);
         };
         self.dispatch[0x21] = |cpu: &mut MCS51| {
             cpu.op_ajmp();
-            cpu.opcode_additional_work("AJMP", 1, 0);
+            cpu.opcode_additional_work("AJMP", 0);
         };
         self.dispatch[0x22] = |cpu: &mut MCS51| {
             cpu.op_ret();
-            cpu.opcode_additional_work("RET", 1, 0);
+            cpu.opcode_additional_work("RET", 0);
         };
         self.dispatch[0x23] = |cpu: &mut MCS51| {
             cpu.op_rl();
-            cpu.opcode_additional_work("RL", 0, 1);
+            cpu.opcode_additional_work("RL", 1);
         };
         self.dispatch[0x24] = |cpu: &mut MCS51| {
             cpu.op_add(MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("ADD", 0, 2);
+            cpu.opcode_additional_work("ADD", 2);
         };
         self.dispatch[0x25] = |cpu: &mut MCS51| {
             cpu.op_add(MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("ADD", 0, 2);
+            cpu.opcode_additional_work("ADD", 2);
         };
         self.dispatch[0x26] = |cpu: &mut MCS51| {
             cpu.op_add(MCS51_ADDRESSING::INDIRECT_Ri(0));
-            cpu.opcode_additional_work("ADD", 0, 1);
+            cpu.opcode_additional_work("ADD", 1);
         };
         self.dispatch[0x27] = |cpu: &mut MCS51| {
             cpu.op_add(MCS51_ADDRESSING::INDIRECT_Ri(1));
-            cpu.opcode_additional_work("ADD", 0, 1);
+            cpu.opcode_additional_work("ADD", 1);
         };
         self.dispatch[0x28] = |cpu: &mut MCS51| {
             cpu.op_add(MCS51_ADDRESSING::REGISTER(0));
-            cpu.opcode_additional_work("ADD", 0, 1);
+            cpu.opcode_additional_work("ADD", 1);
         };
         self.dispatch[0x29] = |cpu: &mut MCS51| {
             cpu.op_add(MCS51_ADDRESSING::REGISTER(1));
-            cpu.opcode_additional_work("ADD", 0, 1);
+            cpu.opcode_additional_work("ADD", 1);
         };
         self.dispatch[0x2A] = |cpu: &mut MCS51| {
             cpu.op_add(MCS51_ADDRESSING::REGISTER(2));
-            cpu.opcode_additional_work("ADD", 0, 1);
+            cpu.opcode_additional_work("ADD", 1);
         };
         self.dispatch[0x2B] = |cpu: &mut MCS51| {
             cpu.op_add(MCS51_ADDRESSING::REGISTER(3));
-            cpu.opcode_additional_work("ADD", 0, 1);
+            cpu.opcode_additional_work("ADD", 1);
         };
         self.dispatch[0x2C] = |cpu: &mut MCS51| {
             cpu.op_add(MCS51_ADDRESSING::REGISTER(4));
-            cpu.opcode_additional_work("ADD", 0, 1);
+            cpu.opcode_additional_work("ADD", 1);
         };
         self.dispatch[0x2D] = |cpu: &mut MCS51| {
             cpu.op_add(MCS51_ADDRESSING::REGISTER(5));
-            cpu.opcode_additional_work("ADD", 0, 1);
+            cpu.opcode_additional_work("ADD", 1);
         };
         self.dispatch[0x2E] = |cpu: &mut MCS51| {
             cpu.op_add(MCS51_ADDRESSING::REGISTER(6));
-            cpu.opcode_additional_work("ADD", 0, 1);
+            cpu.opcode_additional_work("ADD", 1);
         };
         self.dispatch[0x2F] = |cpu: &mut MCS51| {
             cpu.op_add(MCS51_ADDRESSING::REGISTER(7));
-            cpu.opcode_additional_work("ADD", 0, 1);
+            cpu.opcode_additional_work("ADD", 1);
         };
         self.dispatch[0x30] = |cpu: &mut MCS51| {
             cpu.op_jnb(MCS51_ADDRESSING::DATA(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("JNB", 1, 0);
+            cpu.opcode_additional_work("JNB", 0);
         };
         self.dispatch[0x31] = |cpu: &mut MCS51| {
             cpu.op_acall();
-            cpu.opcode_additional_work("ACALL", 1, 0);
+            cpu.opcode_additional_work("ACALL", 0);
         };
         self.dispatch[0x32] = |cpu: &mut MCS51| {
             cpu.op_reti();
-            cpu.opcode_additional_work("RETI", 1, 0);
+            cpu.opcode_additional_work("RETI", 0);
         };
         self.dispatch[0x33] = |cpu: &mut MCS51| {
             cpu.op_rlc();
-            cpu.opcode_additional_work("RLC", 1, 1);
+            cpu.opcode_additional_work("RLC", 1);
         };
         self.dispatch[0x34] = |cpu: &mut MCS51| {
             cpu.op_addc(MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("ADDC", 0, 2);
+            cpu.opcode_additional_work("ADDC", 2);
         };
         self.dispatch[0x35] = |cpu: &mut MCS51| {
             cpu.op_addc(MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("ADDC", 0, 2);
+            cpu.opcode_additional_work("ADDC", 2);
         };
         self.dispatch[0x36] = |cpu: &mut MCS51| {
             cpu.op_addc(MCS51_ADDRESSING::INDIRECT_Ri(0));
-            cpu.opcode_additional_work("ADDC", 0, 1);
+            cpu.opcode_additional_work("ADDC", 1);
         };
         self.dispatch[0x37] = |cpu: &mut MCS51| {
             cpu.op_addc(MCS51_ADDRESSING::INDIRECT_Ri(1));
-            cpu.opcode_additional_work("ADDC", 0, 1);
+            cpu.opcode_additional_work("ADDC", 1);
         };
         self.dispatch[0x38] = |cpu: &mut MCS51| {
             cpu.op_addc(MCS51_ADDRESSING::REGISTER(0));
-            cpu.opcode_additional_work("ADDC", 0, 1);
+            cpu.opcode_additional_work("ADDC", 1);
         };
         self.dispatch[0x39] = |cpu: &mut MCS51| {
             cpu.op_addc(MCS51_ADDRESSING::REGISTER(1));
-            cpu.opcode_additional_work("ADDC", 0, 1);
+            cpu.opcode_additional_work("ADDC", 1);
         };
         self.dispatch[0x3A] = |cpu: &mut MCS51| {
             cpu.op_addc(MCS51_ADDRESSING::REGISTER(2));
-            cpu.opcode_additional_work("ADDC", 0, 1);
+            cpu.opcode_additional_work("ADDC", 1);
         };
         self.dispatch[0x3B] = |cpu: &mut MCS51| {
             cpu.op_addc(MCS51_ADDRESSING::REGISTER(3));
-            cpu.opcode_additional_work("ADDC", 0, 1);
+            cpu.opcode_additional_work("ADDC", 1);
         };
         self.dispatch[0x3C] = |cpu: &mut MCS51| {
             cpu.op_addc(MCS51_ADDRESSING::REGISTER(4));
-            cpu.opcode_additional_work("ADDC", 0, 1);
+            cpu.opcode_additional_work("ADDC", 1);
         };
         self.dispatch[0x3D] = |cpu: &mut MCS51| {
             cpu.op_addc(MCS51_ADDRESSING::REGISTER(5));
-            cpu.opcode_additional_work("ADDC", 0, 1);
+            cpu.opcode_additional_work("ADDC", 1);
         };
         self.dispatch[0x3E] = |cpu: &mut MCS51| {
             cpu.op_addc(MCS51_ADDRESSING::REGISTER(6));
-            cpu.opcode_additional_work("ADDC", 0, 1);
+            cpu.opcode_additional_work("ADDC", 1);
         };
         self.dispatch[0x3F] = |cpu: &mut MCS51| {
             cpu.op_addc(MCS51_ADDRESSING::REGISTER(7));
-            cpu.opcode_additional_work("ADDC", 0, 1);
+            cpu.opcode_additional_work("ADDC", 1);
         };
         self.dispatch[0x40] = |cpu: &mut MCS51| {
             cpu.op_jc(MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("JC", 1, 0);
+            cpu.opcode_additional_work("JC", 0);
         };
         self.dispatch[0x41] = |cpu: &mut MCS51| {
             cpu.op_ajmp();
-            cpu.opcode_additional_work("AJMP", 1, 0);
+            cpu.opcode_additional_work("AJMP", 0);
         };
         self.dispatch[0x42] = |cpu: &mut MCS51| {
             cpu.op_orl(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("ORL", 0, 2);
+            cpu.opcode_additional_work("ORL", 2);
         };
         self.dispatch[0x43] = |cpu: &mut MCS51| {
             cpu.op_orl(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("ORL", 1, 3);
+            cpu.opcode_additional_work("ORL", 3);
         };
         self.dispatch[0x44] = |cpu: &mut MCS51| {
             cpu.op_orl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("ORL", 0, 2);
+            cpu.opcode_additional_work("ORL", 2);
         };
         self.dispatch[0x45] = |cpu: &mut MCS51| {
             cpu.op_orl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("ORL", 0, 2);
+            cpu.opcode_additional_work("ORL", 2);
         };
         self.dispatch[0x46] = |cpu: &mut MCS51| {
             cpu.op_orl(
                 MCS51_ADDRESSING::ACCUMULATOR,
                 MCS51_ADDRESSING::INDIRECT_Ri(0),
             );
-            cpu.opcode_additional_work("ORL", 0, 1);
+            cpu.opcode_additional_work("ORL", 1);
         };
         self.dispatch[0x47] = |cpu: &mut MCS51| {
             cpu.op_orl(
                 MCS51_ADDRESSING::ACCUMULATOR,
                 MCS51_ADDRESSING::INDIRECT_Ri(1),
             );
-            cpu.opcode_additional_work("ORL", 0, 1);
+            cpu.opcode_additional_work("ORL", 1);
         };
         self.dispatch[0x48] = |cpu: &mut MCS51| {
             cpu.op_orl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(0));
-            cpu.opcode_additional_work("ORL", 0, 1);
+            cpu.opcode_additional_work("ORL", 1);
         };
         self.dispatch[0x49] = |cpu: &mut MCS51| {
             cpu.op_orl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(1));
-            cpu.opcode_additional_work("ORL", 0, 1);
+            cpu.opcode_additional_work("ORL", 1);
         };
         self.dispatch[0x4A] = |cpu: &mut MCS51| {
             cpu.op_orl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(2));
-            cpu.opcode_additional_work("ORL", 0, 1);
+            cpu.opcode_additional_work("ORL", 1);
         };
         self.dispatch[0x4B] = |cpu: &mut MCS51| {
             cpu.op_orl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(3));
-            cpu.opcode_additional_work("ORL", 0, 1);
+            cpu.opcode_additional_work("ORL", 1);
         };
         self.dispatch[0x4C] = |cpu: &mut MCS51| {
             cpu.op_orl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(4));
-            cpu.opcode_additional_work("ORL", 0, 1);
+            cpu.opcode_additional_work("ORL", 1);
         };
         self.dispatch[0x4D] = |cpu: &mut MCS51| {
             cpu.op_orl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(5));
-            cpu.opcode_additional_work("ORL", 0, 1);
+            cpu.opcode_additional_work("ORL", 1);
         };
         self.dispatch[0x4E] = |cpu: &mut MCS51| {
             cpu.op_orl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(6));
-            cpu.opcode_additional_work("ORL", 0, 1);
+            cpu.opcode_additional_work("ORL", 1);
         };
         self.dispatch[0x4F] = |cpu: &mut MCS51| {
             cpu.op_orl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(7));
-            cpu.opcode_additional_work("ORL", 0, 1);
+            cpu.opcode_additional_work("ORL", 1);
         };
         self.dispatch[0x50] = |cpu: &mut MCS51| {
             cpu.op_jnc(MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("JNC", 1, 0);
+            cpu.opcode_additional_work("JNC", 0);
         };
         self.dispatch[0x51] = |cpu: &mut MCS51| {
             cpu.op_acall();
-            cpu.opcode_additional_work("ACALL", 1, 0);
+            cpu.opcode_additional_work("ACALL", 0);
         };
         self.dispatch[0x52] = |cpu: &mut MCS51| {
             cpu.op_anl(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("ANL", 0, 2);
+            cpu.opcode_additional_work("ANL", 2);
         };
         self.dispatch[0x53] = |cpu: &mut MCS51| {
             cpu.op_anl(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("ANL", 1, 3);
+            cpu.opcode_additional_work("ANL", 3);
         };
         self.dispatch[0x54] = |cpu: &mut MCS51| {
             cpu.op_anl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("ANL", 0, 2);
+            cpu.opcode_additional_work("ANL", 2);
         };
         self.dispatch[0x55] = |cpu: &mut MCS51| {
             cpu.op_anl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("ANL", 0, 2);
+            cpu.opcode_additional_work("ANL", 2);
         };
         self.dispatch[0x56] = |cpu: &mut MCS51| {
             cpu.op_anl(
                 MCS51_ADDRESSING::ACCUMULATOR,
                 MCS51_ADDRESSING::INDIRECT_Ri(0),
             );
-            cpu.opcode_additional_work("ANL", 0, 1);
+            cpu.opcode_additional_work("ANL", 1);
         };
         self.dispatch[0x57] = |cpu: &mut MCS51| {
             cpu.op_anl(
                 MCS51_ADDRESSING::ACCUMULATOR,
                 MCS51_ADDRESSING::INDIRECT_Ri(1),
             );
-            cpu.opcode_additional_work("ANL", 0, 1);
+            cpu.opcode_additional_work("ANL", 1);
         };
         self.dispatch[0x58] = |cpu: &mut MCS51| {
             cpu.op_anl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(0));
-            cpu.opcode_additional_work("ANL", 0, 1);
+            cpu.opcode_additional_work("ANL", 1);
         };
         self.dispatch[0x59] = |cpu: &mut MCS51| {
             cpu.op_anl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(1));
-            cpu.opcode_additional_work("ANL", 0, 1);
+            cpu.opcode_additional_work("ANL", 1);
         };
         self.dispatch[0x5A] = |cpu: &mut MCS51| {
             cpu.op_anl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(2));
-            cpu.opcode_additional_work("ANL", 0, 1);
+            cpu.opcode_additional_work("ANL", 1);
         };
         self.dispatch[0x5B] = |cpu: &mut MCS51| {
             cpu.op_anl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(3));
-            cpu.opcode_additional_work("ANL", 0, 1);
+            cpu.opcode_additional_work("ANL", 1);
         };
         self.dispatch[0x5C] = |cpu: &mut MCS51| {
             cpu.op_anl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(4));
-            cpu.opcode_additional_work("ANL", 0, 1);
+            cpu.opcode_additional_work("ANL", 1);
         };
         self.dispatch[0x5D] = |cpu: &mut MCS51| {
             cpu.op_anl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(5));
-            cpu.opcode_additional_work("ANL", 0, 1);
+            cpu.opcode_additional_work("ANL", 1);
         };
         self.dispatch[0x5E] = |cpu: &mut MCS51| {
             cpu.op_anl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(6));
-            cpu.opcode_additional_work("ANL", 0, 1);
+            cpu.opcode_additional_work("ANL", 1);
         };
         self.dispatch[0x5F] = |cpu: &mut MCS51| {
             cpu.op_anl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(7));
-            cpu.opcode_additional_work("ANL", 0, 1);
+            cpu.opcode_additional_work("ANL", 1);
         };
         self.dispatch[0x60] = |cpu: &mut MCS51| {
             cpu.op_jz(MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("JZ", 1, 0);
+            cpu.opcode_additional_work("JZ", 0);
         };
         self.dispatch[0x61] = |cpu: &mut MCS51| {
             cpu.op_ajmp();
-            cpu.opcode_additional_work("AJMP", 1, 0);
+            cpu.opcode_additional_work("AJMP", 0);
         };
         self.dispatch[0x62] = |cpu: &mut MCS51| {
             cpu.op_xrl(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("XRL", 0, 2);
+            cpu.opcode_additional_work("XRL", 2);
         };
         self.dispatch[0x63] = |cpu: &mut MCS51| {
-            cpu.op_orl(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("XRL", 1, 3);
+            cpu.op_xrl(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::DATA(2));
+            cpu.opcode_additional_work("XRL", 3);
         };
         self.dispatch[0x64] = |cpu: &mut MCS51| {
             cpu.op_xrl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("XRL", 0, 2);
+            cpu.opcode_additional_work("XRL", 2);
         };
         self.dispatch[0x65] = |cpu: &mut MCS51| {
             cpu.op_xrl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("XRL", 0, 2);
+            cpu.opcode_additional_work("XRL", 2);
         };
         self.dispatch[0x66] = |cpu: &mut MCS51| {
             cpu.op_xrl(
                 MCS51_ADDRESSING::ACCUMULATOR,
                 MCS51_ADDRESSING::INDIRECT_Ri(0),
             );
-            cpu.opcode_additional_work("XRL", 0, 1);
+            cpu.opcode_additional_work("XRL", 1);
         };
         self.dispatch[0x67] = |cpu: &mut MCS51| {
             cpu.op_xrl(
                 MCS51_ADDRESSING::ACCUMULATOR,
                 MCS51_ADDRESSING::INDIRECT_Ri(1),
             );
-            cpu.opcode_additional_work("XRL", 0, 1);
+            cpu.opcode_additional_work("XRL", 1);
         };
         self.dispatch[0x68] = |cpu: &mut MCS51| {
             cpu.op_xrl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(0));
-            cpu.opcode_additional_work("XRL", 0, 1);
+            cpu.opcode_additional_work("XRL", 1);
         };
         self.dispatch[0x69] = |cpu: &mut MCS51| {
             cpu.op_xrl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(1));
-            cpu.opcode_additional_work("XRL", 0, 1);
+            cpu.opcode_additional_work("XRL", 1);
         };
         self.dispatch[0x6A] = |cpu: &mut MCS51| {
             cpu.op_xrl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(2));
-            cpu.opcode_additional_work("XRL", 0, 1);
+            cpu.opcode_additional_work("XRL", 1);
         };
         self.dispatch[0x6B] = |cpu: &mut MCS51| {
             cpu.op_xrl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(3));
-            cpu.opcode_additional_work("XRL", 0, 1);
+            cpu.opcode_additional_work("XRL", 1);
         };
         self.dispatch[0x6C] = |cpu: &mut MCS51| {
             cpu.op_xrl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(4));
-            cpu.opcode_additional_work("XRL", 0, 1);
+            cpu.opcode_additional_work("XRL", 1);
         };
         self.dispatch[0x6D] = |cpu: &mut MCS51| {
             cpu.op_xrl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(5));
-            cpu.opcode_additional_work("XRL", 0, 1);
+            cpu.opcode_additional_work("XRL", 1);
         };
         self.dispatch[0x6E] = |cpu: &mut MCS51| {
             cpu.op_xrl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(6));
-            cpu.opcode_additional_work("XRL", 0, 1);
+            cpu.opcode_additional_work("XRL", 1);
         };
         self.dispatch[0x6F] = |cpu: &mut MCS51| {
             cpu.op_xrl(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(7));
-            cpu.opcode_additional_work("XRL", 0, 1);
+            cpu.opcode_additional_work("XRL", 1);
         };
         self.dispatch[0x70] = |cpu: &mut MCS51| {
             cpu.op_jnz(MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("JNZ", 1, 0);
+            cpu.opcode_additional_work("JNZ", 0);
         };
         self.dispatch[0x71] = |cpu: &mut MCS51| {
             cpu.op_acall();
-            cpu.opcode_additional_work("ACALL", 1, 0);
+            cpu.opcode_additional_work("ACALL", 0);
         };
         self.dispatch[0x72] = |cpu: &mut MCS51| {
             cpu.op_orl_c(MCS51_ADDRESSING::DATA(1), false);
-            cpu.opcode_additional_work("ORL", 1, 2);
+            cpu.opcode_additional_work("ORL", 2);
         };
         self.dispatch[0x73] = |cpu: &mut MCS51| {
             cpu.op_jmp();
-            cpu.opcode_additional_work("JMP", 1, 1);
+            cpu.opcode_additional_work("JMP", 0);
         };
         self.dispatch[0x74] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("MOV", 0, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x75] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("MOV", 0, 3);
+            cpu.opcode_additional_work("MOV", 3);
         };
         self.dispatch[0x76] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::INDIRECT_Ri(0), MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("MOV", 0, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x77] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::INDIRECT_Ri(1), MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("MOV", 0, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x78] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(0), MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("MOV", 0, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x79] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(1), MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("MOV", 0, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x7A] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(2), MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("MOV", 0, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x7B] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(3), MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("MOV", 0, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x7C] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(4), MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("MOV", 0, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x7D] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(5), MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("MOV", 0, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x7E] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(6), MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("MOV", 0, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x7F] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(7), MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("MOV", 0, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x80] = |cpu: &mut MCS51| {
             cpu.op_sjmp(MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("SJMP", 1, 1);
+            cpu.opcode_additional_work("SJMP", 0);
         };
         self.dispatch[0x81] = |cpu: &mut MCS51| {
             cpu.op_ajmp();
-            cpu.opcode_additional_work("AJMP", 1, 1);
+            cpu.opcode_additional_work("AJMP", 0);
         };
         self.dispatch[0x82] = |cpu: &mut MCS51| {
             cpu.op_anl_c(MCS51_ADDRESSING::DATA(1), false);
-            cpu.opcode_additional_work("ANL", 1, 2);
+            cpu.opcode_additional_work("ANL", 2);
         };
         self.dispatch[0x83] = |cpu: &mut MCS51| {
             cpu.op_movc_pc();
-            cpu.opcode_additional_work("MOVC", 1, 1);
+            cpu.opcode_additional_work("MOVC", 1);
         };
         self.dispatch[0x84] = |cpu: &mut MCS51| {
             cpu.op_div();
-            cpu.opcode_additional_work("DIV", 3, 1);
+            cpu.opcode_additional_work("DIV", 1);
         };
         self.dispatch[0x85] = |cpu: &mut MCS51| {
-            cpu.op_mov(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::DIRECT(2));
-            cpu.opcode_additional_work("MOV", 1, 3);
+            cpu.op_mov(MCS51_ADDRESSING::DIRECT(2), MCS51_ADDRESSING::DIRECT(1));
+            cpu.opcode_additional_work("MOV", 3);
         };
         self.dispatch[0x86] = |cpu: &mut MCS51| {
             cpu.op_mov(
                 MCS51_ADDRESSING::DIRECT(1),
                 MCS51_ADDRESSING::INDIRECT_Ri(0),
             );
-            cpu.opcode_additional_work("MOV", 1, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x87] = |cpu: &mut MCS51| {
             cpu.op_mov(
                 MCS51_ADDRESSING::DIRECT(1),
                 MCS51_ADDRESSING::INDIRECT_Ri(1),
             );
-            cpu.opcode_additional_work("MOV", 1, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x88] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::REGISTER(0));
-            cpu.opcode_additional_work("MOV", 1, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x89] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::REGISTER(1));
-            cpu.opcode_additional_work("MOV", 1, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x8A] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::REGISTER(2));
-            cpu.opcode_additional_work("MOV", 1, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x8B] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::REGISTER(3));
-            cpu.opcode_additional_work("MOV", 1, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x8C] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::REGISTER(4));
-            cpu.opcode_additional_work("MOV", 1, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x8D] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::REGISTER(5));
-            cpu.opcode_additional_work("MOV", 1, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x8E] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::REGISTER(6));
-            cpu.opcode_additional_work("MOV", 1, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x8F] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::REGISTER(7));
-            cpu.opcode_additional_work("MOV", 1, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x90] = |cpu: &mut MCS51| {
             cpu.op_mov_dptr(MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("MOV", 1, 3);
+            cpu.opcode_additional_work("MOV", 3);
         };
         self.dispatch[0x91] = |cpu: &mut MCS51| {
             cpu.op_acall();
-            cpu.opcode_additional_work("ACALL", 1, 1);
+            cpu.opcode_additional_work("ACALL", 0);
         };
         self.dispatch[0x92] = |cpu: &mut MCS51| {
             cpu.op_mov_bit_c(MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("MOV", 1, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0x93] = |cpu: &mut MCS51| {
             cpu.op_movc_dptr();
-            cpu.opcode_additional_work("MOVC", 1, 1);
+            cpu.opcode_additional_work("MOVC", 1);
         };
         self.dispatch[0x94] = |cpu: &mut MCS51| {
             cpu.op_subb(MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("SUBB", 0, 2);
+            cpu.opcode_additional_work("SUBB", 2);
         };
         self.dispatch[0x95] = |cpu: &mut MCS51| {
             cpu.op_subb(MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("SUBB", 0, 2);
+            cpu.opcode_additional_work("SUBB", 2);
         };
         self.dispatch[0x96] = |cpu: &mut MCS51| {
             cpu.op_subb(MCS51_ADDRESSING::INDIRECT_Ri(0));
-            cpu.opcode_additional_work("SUBB", 0, 1);
+            cpu.opcode_additional_work("SUBB", 1);
         };
         self.dispatch[0x97] = |cpu: &mut MCS51| {
             cpu.op_subb(MCS51_ADDRESSING::INDIRECT_Ri(1));
-            cpu.opcode_additional_work("SUBB", 0, 1);
+            cpu.opcode_additional_work("SUBB", 1);
         };
         self.dispatch[0x98] = |cpu: &mut MCS51| {
             cpu.op_subb(MCS51_ADDRESSING::REGISTER(0));
-            cpu.opcode_additional_work("SUBB", 0, 1);
+            cpu.opcode_additional_work("SUBB", 1);
         };
         self.dispatch[0x99] = |cpu: &mut MCS51| {
             cpu.op_subb(MCS51_ADDRESSING::REGISTER(1));
-            cpu.opcode_additional_work("SUBB", 0, 1);
+            cpu.opcode_additional_work("SUBB", 1);
         };
         self.dispatch[0x9A] = |cpu: &mut MCS51| {
             cpu.op_subb(MCS51_ADDRESSING::REGISTER(2));
-            cpu.opcode_additional_work("SUBB", 0, 1);
+            cpu.opcode_additional_work("SUBB", 1);
         };
         self.dispatch[0x9B] = |cpu: &mut MCS51| {
             cpu.op_subb(MCS51_ADDRESSING::REGISTER(3));
-            cpu.opcode_additional_work("SUBB", 0, 1);
+            cpu.opcode_additional_work("SUBB", 1);
         };
         self.dispatch[0x9C] = |cpu: &mut MCS51| {
             cpu.op_subb(MCS51_ADDRESSING::REGISTER(4));
-            cpu.opcode_additional_work("SUBB", 0, 1);
+            cpu.opcode_additional_work("SUBB", 1);
         };
         self.dispatch[0x9D] = |cpu: &mut MCS51| {
             cpu.op_subb(MCS51_ADDRESSING::REGISTER(5));
-            cpu.opcode_additional_work("SUBB", 0, 1);
+            cpu.opcode_additional_work("SUBB", 1);
         };
         self.dispatch[0x9E] = |cpu: &mut MCS51| {
             cpu.op_subb(MCS51_ADDRESSING::REGISTER(6));
-            cpu.opcode_additional_work("SUBB", 0, 1);
+            cpu.opcode_additional_work("SUBB", 1);
         };
         self.dispatch[0x9F] = |cpu: &mut MCS51| {
             cpu.op_subb(MCS51_ADDRESSING::REGISTER(7));
-            cpu.opcode_additional_work("SUBB", 0, 1);
+            cpu.opcode_additional_work("SUBB", 1);
         };
         self.dispatch[0xA0] = |cpu: &mut MCS51| {
             cpu.op_orl_c(MCS51_ADDRESSING::DATA(1), true);
-            cpu.opcode_additional_work("ORLC", 1, 2);
+            cpu.opcode_additional_work("ORL", 2);
         };
         self.dispatch[0xA1] = |cpu: &mut MCS51| {
             cpu.op_ajmp();
-            cpu.opcode_additional_work("AJMP", 1, 1);
+            cpu.opcode_additional_work("AJMP", 0);
         };
         self.dispatch[0xA2] = |cpu: &mut MCS51| {
             cpu.op_mov_c_bit(MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("MOV", 0, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0xA3] = |cpu: &mut MCS51| {
             cpu.op_inc_dptr();
-            cpu.opcode_additional_work("INC DPTR", 1, 1);
+            cpu.opcode_additional_work("INC DPTR", 1);
         };
         self.dispatch[0xA4] = |cpu: &mut MCS51| {
             cpu.op_mul();
-            cpu.opcode_additional_work("MUL", 3, 1)
+            cpu.opcode_additional_work("MUL", 1);
+        };
+        self.dispatch[0xA5] = |cpu: &mut MCS51| {
+            cpu.opcode_additional_work("RESERVED", 1);
         };
-        self.dispatch[0xA5] = |cpu: &mut MCS51| { cpu.opcode_additional_work("RESERVED", 0, 0); };
         self.dispatch[0xA6] = |cpu: &mut MCS51| {
             cpu.op_mov(
                 MCS51_ADDRESSING::INDIRECT_Ri(0),
                 MCS51_ADDRESSING::DIRECT(1),
             );
-            cpu.opcode_additional_work("MOV", 1, 2)
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0xA7] = |cpu: &mut MCS51| {
             cpu.op_mov(
                 MCS51_ADDRESSING::INDIRECT_Ri(1),
                 MCS51_ADDRESSING::DIRECT(1),
             );
-            cpu.opcode_additional_work("MOV", 1, 2)
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0xA8] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(0), MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("MOV", 1, 2)
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0xA9] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(1), MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("MOV", 1, 2)
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0xAA] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(2), MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("MOV", 1, 2)
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0xAB] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(3), MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("MOV", 1, 2)
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0xAC] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(4), MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("MOV", 1, 2)
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0xAD] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(5), MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("MOV", 1, 2)
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0xAE] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(6), MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("MOV", 1, 2)
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0xAF] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(7), MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("MOV", 1, 2)
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0xB0] = |cpu: &mut MCS51| {
-            cpu.op_anl_c(MCS51_ADDRESSING::DIRECT(1), true);
-            cpu.opcode_additional_work("ANL", 1, 2)
+            cpu.op_anl_c(MCS51_ADDRESSING::DATA(1), true);
+            cpu.opcode_additional_work("ANL", 2);
+        };
+        self.dispatch[0xB1] = |cpu: &mut MCS51| {
+            cpu.op_acall();
+            cpu.opcode_additional_work("ACALL", 0);
+        };
+        self.dispatch[0xB2] = |cpu: &mut MCS51| {
+            cpu.op_cpl_bit(MCS51_ADDRESSING::DATA(1));
+            cpu.opcode_additional_work("CPL", 2);
+        };
+        self.dispatch[0xB3] = |cpu: &mut MCS51| {
+            cpu.op_cpl_c();
+            cpu.opcode_additional_work("CPL C", 1);
         };
-        self.dispatch[0xB1] = |cpu: &mut MCS51| {};
-        self.dispatch[0xB2] = |cpu: &mut MCS51| {};
-        self.dispatch[0xB3] = |cpu: &mut MCS51| {};
         self.dispatch[0xB4] = |cpu: &mut MCS51| {
-            cpu.op_cjne(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::DATA(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("CJNE", 2, 0)
+            cpu.op_cjne(
+                MCS51_ADDRESSING::ACCUMULATOR,
+                MCS51_ADDRESSING::DATA(1),
+                MCS51_ADDRESSING::DATA(2),
+            );
+            cpu.opcode_additional_work("CJNE", 0);
         };
         self.dispatch[0xB5] = |cpu: &mut MCS51| {
-            cpu.op_cjne(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("CJNE", 2, 0)
+            cpu.op_cjne(
+                MCS51_ADDRESSING::ACCUMULATOR,
+                MCS51_ADDRESSING::DIRECT(1),
+                MCS51_ADDRESSING::DATA(2),
+            );
+            cpu.opcode_additional_work("CJNE", 0);
         };
         self.dispatch[0xB6] = |cpu: &mut MCS51| {
-            cpu.op_cjne(MCS51_ADDRESSING::INDIRECT_Ri(0), MCS51_ADDRESSING::DATA(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("CJNE", 2, 0)
+            cpu.op_cjne(
+                MCS51_ADDRESSING::INDIRECT_Ri(0),
+                MCS51_ADDRESSING::DATA(1),
+                MCS51_ADDRESSING::DATA(2),
+            );
+            cpu.opcode_additional_work("CJNE", 0);
         };
         self.dispatch[0xB7] = |cpu: &mut MCS51| {
-            cpu.op_cjne(MCS51_ADDRESSING::INDIRECT_Ri(1), MCS51_ADDRESSING::DATA(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("CJNE", 2, 0)
+            cpu.op_cjne(
+                MCS51_ADDRESSING::INDIRECT_Ri(1),
+                MCS51_ADDRESSING::DATA(1),
+                MCS51_ADDRESSING::DATA(2),
+            );
+            cpu.opcode_additional_work("CJNE", 0);
         };
         self.dispatch[0xB8] = |cpu: &mut MCS51| {
-            cpu.op_cjne(MCS51_ADDRESSING::REGISTER(0), MCS51_ADDRESSING::DATA(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("CJNE", 2, 0)
+            cpu.op_cjne(
+                MCS51_ADDRESSING::REGISTER(0),
+                MCS51_ADDRESSING::DATA(1),
+                MCS51_ADDRESSING::DATA(2),
+            );
+            cpu.opcode_additional_work("CJNE", 0);
         };
         self.dispatch[0xB9] = |cpu: &mut MCS51| {
-            cpu.op_cjne(MCS51_ADDRESSING::REGISTER(1), MCS51_ADDRESSING::DATA(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("CJNE", 2, 0)
+            cpu.op_cjne(
+                MCS51_ADDRESSING::REGISTER(1),
+                MCS51_ADDRESSING::DATA(1),
+                MCS51_ADDRESSING::DATA(2),
+            );
+            cpu.opcode_additional_work("CJNE", 0);
         };
         self.dispatch[0xBA] = |cpu: &mut MCS51| {
-            cpu.op_cjne(MCS51_ADDRESSING::REGISTER(2), MCS51_ADDRESSING::DATA(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("CJNE", 2, 0)
+            cpu.op_cjne(
+                MCS51_ADDRESSING::REGISTER(2),
+                MCS51_ADDRESSING::DATA(1),
+                MCS51_ADDRESSING::DATA(2),
+            );
+            cpu.opcode_additional_work("CJNE", 0);
         };
         self.dispatch[0xBB] = |cpu: &mut MCS51| {
-            cpu.op_cjne(MCS51_ADDRESSING::REGISTER(3), MCS51_ADDRESSING::DATA(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("CJNE", 2, 0)
+            cpu.op_cjne(
+                MCS51_ADDRESSING::REGISTER(3),
+                MCS51_ADDRESSING::DATA(1),
+                MCS51_ADDRESSING::DATA(2),
+            );
+            cpu.opcode_additional_work("CJNE", 0);
         };
         self.dispatch[0xBC] = |cpu: &mut MCS51| {
-            cpu.op_cjne(MCS51_ADDRESSING::REGISTER(4), MCS51_ADDRESSING::DATA(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("CJNE", 2, 0)
+            cpu.op_cjne(
+                MCS51_ADDRESSING::REGISTER(4),
+                MCS51_ADDRESSING::DATA(1),
+                MCS51_ADDRESSING::DATA(2),
+            );
+            cpu.opcode_additional_work("CJNE", 0);
         };
         self.dispatch[0xBD] = |cpu: &mut MCS51| {
-            cpu.op_cjne(MCS51_ADDRESSING::REGISTER(5), MCS51_ADDRESSING::DATA(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("CJNE", 2, 0)
+            cpu.op_cjne(
+                MCS51_ADDRESSING::REGISTER(5),
+                MCS51_ADDRESSING::DATA(1),
+                MCS51_ADDRESSING::DATA(2),
+            );
+            cpu.opcode_additional_work("CJNE", 0);
         };
         self.dispatch[0xBE] = |cpu: &mut MCS51| {
-            cpu.op_cjne(MCS51_ADDRESSING::REGISTER(6), MCS51_ADDRESSING::DATA(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("CJNE", 2, 0)
+            cpu.op_cjne(
+                MCS51_ADDRESSING::REGISTER(6),
+                MCS51_ADDRESSING::DATA(1),
+                MCS51_ADDRESSING::DATA(2),
+            );
+            cpu.opcode_additional_work("CJNE", 0);
         };
         self.dispatch[0xBF] = |cpu: &mut MCS51| {
-            cpu.op_cjne(MCS51_ADDRESSING::REGISTER(7), MCS51_ADDRESSING::DATA(1), MCS51_ADDRESSING::DATA(2));
-            cpu.opcode_additional_work("CJNE", 2, 0)
+            cpu.op_cjne(
+                MCS51_ADDRESSING::REGISTER(7),
+                MCS51_ADDRESSING::DATA(1),
+                MCS51_ADDRESSING::DATA(2),
+            );
+            cpu.opcode_additional_work("CJNE", 0);
+        };
+        self.dispatch[0xC0] = |cpu: &mut MCS51| {
+            cpu.op_push(MCS51_ADDRESSING::DIRECT(1));
+            cpu.opcode_additional_work("PUSH", 2);
+        };
+        self.dispatch[0xC1] = |cpu: &mut MCS51| {
+            cpu.op_ajmp();
+            cpu.opcode_additional_work("AJMP", 0);
         };
-        self.dispatch[0xC0] = |cpu: &mut MCS51| {};
-        self.dispatch[0xC1] = |cpu: &mut MCS51| {};
         self.dispatch[0xC2] = |cpu: &mut MCS51| {
             cpu.op_clr(MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("CLR", 2, 2)
+            cpu.opcode_additional_work("CLR", 2);
         };
         self.dispatch[0xC3] = |cpu: &mut MCS51| {
             cpu.set_carry_flag(false);
-            cpu.opcode_additional_work("CLR C", 1, 1)
+            cpu.opcode_additional_work("CLR C", 1);
         };
         self.dispatch[0xC4] = |cpu: &mut MCS51| {
             cpu.op_swap();
-            cpu.opcode_additional_work("SWAP", 1, 1)
+            cpu.opcode_additional_work("SWAP", 1);
         };
         self.dispatch[0xC5] = |cpu: &mut MCS51| {
             cpu.op_xch(MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("XCH", 1, 2)
+            cpu.opcode_additional_work("XCH", 2);
         };
         self.dispatch[0xC6] = |cpu: &mut MCS51| {
             cpu.op_xch(MCS51_ADDRESSING::INDIRECT_Ri(0));
-            cpu.opcode_additional_work("XCH", 1, 1)
+            cpu.opcode_additional_work("XCH", 1);
         };
         self.dispatch[0xC7] = |cpu: &mut MCS51| {
             cpu.op_xch(MCS51_ADDRESSING::INDIRECT_Ri(1));
-            cpu.opcode_additional_work("XCH", 1, 1)
+            cpu.opcode_additional_work("XCH", 1);
         };
         self.dispatch[0xC8] = |cpu: &mut MCS51| {
             cpu.op_xch(MCS51_ADDRESSING::REGISTER(0));
-            cpu.opcode_additional_work("XCH", 1, 1)
+            cpu.opcode_additional_work("XCH", 1);
         };
         self.dispatch[0xC9] = |cpu: &mut MCS51| {
             cpu.op_xch(MCS51_ADDRESSING::REGISTER(1));
-            cpu.opcode_additional_work("XCH", 1, 1)
+            cpu.opcode_additional_work("XCH", 1);
         };
         self.dispatch[0xCA] = |cpu: &mut MCS51| {
             cpu.op_xch(MCS51_ADDRESSING::REGISTER(2));
-            cpu.opcode_additional_work("XCH", 1, 1)
+            cpu.opcode_additional_work("XCH", 1);
         };
         self.dispatch[0xCB] = |cpu: &mut MCS51| {
             cpu.op_xch(MCS51_ADDRESSING::REGISTER(3));
-            cpu.opcode_additional_work("XCH", 1, 1)
+            cpu.opcode_additional_work("XCH", 1);
         };
         self.dispatch[0xCC] = |cpu: &mut MCS51| {
             cpu.op_xch(MCS51_ADDRESSING::REGISTER(4));
-            cpu.opcode_additional_work("XCH", 1, 1)
+            cpu.opcode_additional_work("XCH", 1);
         };
         self.dispatch[0xCD] = |cpu: &mut MCS51| {
             cpu.op_xch(MCS51_ADDRESSING::REGISTER(5));
-            cpu.opcode_additional_work("XCH", 1, 1)
+            cpu.opcode_additional_work("XCH", 1);
         };
         self.dispatch[0xCE] = |cpu: &mut MCS51| {
             cpu.op_xch(MCS51_ADDRESSING::REGISTER(6));
-            cpu.opcode_additional_work("XCH", 1, 1)
+            cpu.opcode_additional_work("XCH", 1);
         };
         self.dispatch[0xCF] = |cpu: &mut MCS51| {
             cpu.op_xch(MCS51_ADDRESSING::REGISTER(7));
-            cpu.opcode_additional_work("XCH", 1, 1)
+            cpu.opcode_additional_work("XCH", 1);
+        };
+        self.dispatch[0xD0] = |cpu: &mut MCS51| {
+            cpu.op_pop(MCS51_ADDRESSING::DIRECT(1));
+            cpu.opcode_additional_work("POP", 2);
+        };
+        self.dispatch[0xD1] = |cpu: &mut MCS51| {
+            cpu.op_acall();
+            cpu.opcode_additional_work("ACALL", 0);
         };
-        self.dispatch[0xD0] = |cpu: &mut MCS51| {};
-        self.dispatch[0xD1] = |cpu: &mut MCS51| {};
         self.dispatch[0xD2] = |cpu: &mut MCS51| {
             cpu.op_setb(MCS51_ADDRESSING::DATA(1));
-            cpu.opcode_additional_work("SETB", 2, 1)
+            cpu.opcode_additional_work("SETB", 2);
         };
         self.dispatch[0xD3] = |cpu: &mut MCS51| {
             cpu.set_carry_flag(true);
-            cpu.opcode_additional_work("SETB", 1, 1)
+            cpu.opcode_additional_work("SETB C", 1);
+        };
+        self.dispatch[0xD4] = |cpu: &mut MCS51| {
+            cpu.op_da();
+            cpu.opcode_additional_work("DA", 1);
         };
-        self.dispatch[0xD4] = |cpu: &mut MCS51| {};
         self.dispatch[0xD5] = |cpu: &mut MCS51| {
             cpu.op_djnz(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::DATA(2), 3);
-            cpu.opcode_additional_work("DJNZ", 2, 0)
+            cpu.opcode_additional_work("DJNZ", 0);
+        };
+        self.dispatch[0xD6] = |cpu: &mut MCS51| {
+            cpu.op_xchd(MCS51_ADDRESSING::INDIRECT_Ri(0));
+            cpu.opcode_additional_work("XCHD", 1);
+        };
+        self.dispatch[0xD7] = |cpu: &mut MCS51| {
+            cpu.op_xchd(MCS51_ADDRESSING::INDIRECT_Ri(1));
+            cpu.opcode_additional_work("XCHD", 1);
         };
-        self.dispatch[0xD6] = |cpu: &mut MCS51| {};
-        self.dispatch[0xD7] = |cpu: &mut MCS51| {};
         self.dispatch[0xD8] = |cpu: &mut MCS51| {
             cpu.op_djnz(MCS51_ADDRESSING::REGISTER(0), MCS51_ADDRESSING::DATA(1), 2);
-            cpu.opcode_additional_work("DJNZ", 2, 0)
+            cpu.opcode_additional_work("DJNZ", 0);
         };
         self.dispatch[0xD9] = |cpu: &mut MCS51| {
             cpu.op_djnz(MCS51_ADDRESSING::REGISTER(1), MCS51_ADDRESSING::DATA(1), 2);
-            cpu.opcode_additional_work("DJNZ", 2, 0)
+            cpu.opcode_additional_work("DJNZ", 0);
         };
         self.dispatch[0xDA] = |cpu: &mut MCS51| {
             cpu.op_djnz(MCS51_ADDRESSING::REGISTER(2), MCS51_ADDRESSING::DATA(1), 2);
-            cpu.opcode_additional_work("DJNZ", 2, 0)
+            cpu.opcode_additional_work("DJNZ", 0);
         };
         self.dispatch[0xDB] = |cpu: &mut MCS51| {
             cpu.op_djnz(MCS51_ADDRESSING::REGISTER(3), MCS51_ADDRESSING::DATA(1), 2);
-            cpu.opcode_additional_work("DJNZ", 2, 0)
+            cpu.opcode_additional_work("DJNZ", 0);
         };
         self.dispatch[0xDC] = |cpu: &mut MCS51| {
             cpu.op_djnz(MCS51_ADDRESSING::REGISTER(4), MCS51_ADDRESSING::DATA(1), 2);
-            cpu.opcode_additional_work("DJNZ", 2, 0)
+            cpu.opcode_additional_work("DJNZ", 0);
         };
         self.dispatch[0xDD] = |cpu: &mut MCS51| {
             cpu.op_djnz(MCS51_ADDRESSING::REGISTER(5), MCS51_ADDRESSING::DATA(1), 2);
-            cpu.opcode_additional_work("DJNZ", 2, 0)
+            cpu.opcode_additional_work("DJNZ", 0);
         };
         self.dispatch[0xDE] = |cpu: &mut MCS51| {
             cpu.op_djnz(MCS51_ADDRESSING::REGISTER(6), MCS51_ADDRESSING::DATA(1), 2);
-            cpu.opcode_additional_work("DJNZ", 2, 0)
+            cpu.opcode_additional_work("DJNZ", 0);
         };
         self.dispatch[0xDF] = |cpu: &mut MCS51| {
             cpu.op_djnz(MCS51_ADDRESSING::REGISTER(7), MCS51_ADDRESSING::DATA(1), 2);
-            cpu.opcode_additional_work("DJNZ", 2, 0)
+            cpu.opcode_additional_work("DJNZ", 0);
         };
         self.dispatch[0xE0] = |cpu: &mut MCS51| {
-            cpu.opcode_additional_work("MOVX A, @DPTR", 2, 1);
+            cpu.op_movx_a_dptr();
+            cpu.opcode_additional_work("MOVX A, @DPTR", 1);
+        };
+        self.dispatch[0xE1] = |cpu: &mut MCS51| {
+            cpu.op_ajmp();
+            cpu.opcode_additional_work("AJMP", 0);
         };
-        self.dispatch[0xE1] = |cpu: &mut MCS51| {};
         self.dispatch[0xE2] = |cpu: &mut MCS51| {
-            cpu.opcode_additional_work("MOVX A, @R0", 2, 1);
+            cpu.op_movx_a_ri(0);
+            cpu.opcode_additional_work("MOVX A, @R0", 1);
         };
         self.dispatch[0xE3] = |cpu: &mut MCS51| {
-            cpu.opcode_additional_work("MOVX A, @R1", 2, 1);
+            cpu.op_movx_a_ri(1);
+            cpu.opcode_additional_work("MOVX A, @R1", 1);
         };
         self.dispatch[0xE4] = |cpu: &mut MCS51| {
-            cpu.op_clr(MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("CLR A", 1, 1)
+            cpu.op_clr_a();
+            cpu.opcode_additional_work("CLR A", 1);
         };
         self.dispatch[0xE5] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::DIRECT(1));
-            cpu.opcode_additional_work("MOV", 1, 2)
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0xE6] = |cpu: &mut MCS51| {
-            cpu.op_mov(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::INDIRECT_Ri(0));
-            cpu.opcode_additional_work("MOV", 1, 1)
+            cpu.op_mov(
+                MCS51_ADDRESSING::ACCUMULATOR,
+                MCS51_ADDRESSING::INDIRECT_Ri(0),
+            );
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xE7] = |cpu: &mut MCS51| {
-            cpu.op_mov(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::INDIRECT_Ri(1));
-            cpu.opcode_additional_work("MOV", 1, 1)
+            cpu.op_mov(
+                MCS51_ADDRESSING::ACCUMULATOR,
+                MCS51_ADDRESSING::INDIRECT_Ri(1),
+            );
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xE8] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(0));
-            cpu.opcode_additional_work("MOV", 1, 1)
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xE9] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(1));
-            cpu.opcode_additional_work("MOV", 1, 1)
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xEA] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(2));
-            cpu.opcode_additional_work("MOV", 1, 1)
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xEB] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(3));
-            cpu.opcode_additional_work("MOV", 1, 1)
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xEC] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(4));
-            cpu.opcode_additional_work("MOV", 1, 1)
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xED] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(5));
-            cpu.opcode_additional_work("MOV", 1, 1)
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xEE] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(6));
-            cpu.opcode_additional_work("MOV", 1, 1)
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xEF] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::ACCUMULATOR, MCS51_ADDRESSING::REGISTER(7));
-            cpu.opcode_additional_work("MOV", 1, 1)
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xF0] = |cpu: &mut MCS51| {
-            cpu.opcode_additional_work("MOVX @DPTR, A", 2, 1);
+            cpu.op_movx_dptr_a();
+            cpu.opcode_additional_work("MOVX @DPTR, A", 1);
         };
         self.dispatch[0xF1] = |cpu: &mut MCS51| {
-            
+            cpu.op_acall();
+            cpu.opcode_additional_work("ACALL", 0);
         };
         self.dispatch[0xF2] = |cpu: &mut MCS51| {
-            cpu.opcode_additional_work("MOVX @R0, A", 2, 1);
+            cpu.op_movx_ri_a(0);
+            cpu.opcode_additional_work("MOVX @R0, A", 1);
         };
         self.dispatch[0xF3] = |cpu: &mut MCS51| {
-            cpu.opcode_additional_work("MOVX @R1, A", 2, 1);
+            cpu.op_movx_ri_a(1);
+            cpu.opcode_additional_work("MOVX @R1, A", 1);
         };
         self.dispatch[0xF4] = |cpu: &mut MCS51| {
             cpu.op_cpl_a();
-            cpu.opcode_additional_work("CPL A", 1, 1);
+            cpu.opcode_additional_work("CPL A", 1);
         };
         self.dispatch[0xF5] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::DIRECT(1), MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("MOV", 0, 2);
+            cpu.opcode_additional_work("MOV", 2);
         };
         self.dispatch[0xF6] = |cpu: &mut MCS51| {
-            cpu.op_mov(MCS51_ADDRESSING::INDIRECT_Ri(0), MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("MOV", 0, 1);
+            cpu.op_mov(
+                MCS51_ADDRESSING::INDIRECT_Ri(0),
+                MCS51_ADDRESSING::ACCUMULATOR,
+            );
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xF7] = |cpu: &mut MCS51| {
-            cpu.op_mov(MCS51_ADDRESSING::INDIRECT_Ri(1), MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("MOV", 0, 1);
+            cpu.op_mov(
+                MCS51_ADDRESSING::INDIRECT_Ri(1),
+                MCS51_ADDRESSING::ACCUMULATOR,
+            );
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xF8] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(0), MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("MOV", 0, 1);
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xF9] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(1), MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("MOV", 0, 1);
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xFA] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(2), MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("MOV", 0, 1);
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xFB] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(3), MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("MOV", 0, 1);
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xFC] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(4), MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("MOV", 0, 1);
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xFD] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(5), MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("MOV", 0, 1);
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xFE] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(6), MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("MOV", 0, 1);
+            cpu.opcode_additional_work("MOV", 1);
         };
         self.dispatch[0xFF] = |cpu: &mut MCS51| {
             cpu.op_mov(MCS51_ADDRESSING::REGISTER(7), MCS51_ADDRESSING::ACCUMULATOR);
-            cpu.opcode_additional_work("MOV", 0, 1);
+            cpu.opcode_additional_work("MOV", 1);
         };
     }
 
@@ -1652,12 +2033,10 @@ impl MCS51 {
         (self.dispatch[opcode as usize])(self);
     }
 
-    pub fn opcode_additional_work(&mut self, _label: &str, cycles: u8, pc: u16) {
+    // The extra machine cycles come from MCS51_INSTRUCTION_CYCLES, see run_opcode
+    pub fn opcode_additional_work(&mut self, _label: &str, pc: u16) {
         if pc != 0 {
-            self.pc += pc
-        }
-        if cycles != 0 {
-            self.additional_cycles = cycles
+            self.pc = self.pc.wrapping_add(pc)
         }
         if self.debug {
             println!("{:0x} : {}", self.pc, _label);
@@ -1704,6 +2083,28 @@ impl MCS51 {
         self.set_accumulator(!acc);
     }
 
+    /*
+    Complement bit
+
+    The bit variable specified is complemented. A bit which had been a one is changed to zero and
+    vice-versa. No other flags are affected. CPL can operate on the carry or any directly
+    addressable bit.
+
+    Note: When this instruction is used to modify an output pin, the value used as the original
+    data will be read from the output data latch, not the input pin.
+    */
+
+    pub fn op_cpl_bit(&mut self, bit_addr: MCS51_ADDRESSING) {
+        let bit_address = self.get_u8(bit_addr).unwrap();
+        let bit = self.read_bit_latch(bit_address);
+        self.write_bit(bit_address, !bit);
+    }
+
+    pub fn op_cpl_c(&mut self) {
+        let cf = self.get_carry_flag();
+        self.set_carry_flag(!cf);
+    }
+
     /*
     Swap nibbles within the Accumulator
 
@@ -1719,12 +2120,46 @@ impl MCS51 {
         self.set_accumulator((lo << 4) + hi);
     }
 
+    /*
+    Move External
+
+    The MOVX instructions transfer data between the Accumulator and a byte of external data
+    memory, hence the "X" appended to MOV. There are two types of instructions, differing in
+    whether they provide an eight-bit or sixteen-bit indirect address to the external data RAM.
+
+    In the first type, the contents of R0 or R1 in the current register bank provide an eight-bit
+    address multiplexed with data on P0. Eight bits are sufficient for external I/O expansion
+    decoding or for a relatively small RAM array. For somewhat larger arrays, any output port pins
+    can be used to output higher-order address bits. These pins are controlled by an output
+    instruction preceding the MOVX.
+
+    In the second type of MOVX instruction, the Data Pointer generates a sixteen-bit address. P2
+    outputs the high-order eight address bits (the contents of DPH), while P0 multiplexes the
+    low-order eight bits (DPL) with data.
+    */
+
     pub fn op_movx_a_ri(&mut self, reg: u8) {
-        let src_addr = self.get_u8(MCS51_ADDRESSING::REGISTER(reg));
+        let src_addr = self.get_xdata_ri_address(reg);
+        let value = self.read_xdata(src_addr);
+        self.set_accumulator(value);
     }
 
     pub fn op_movx_ri_a(&mut self, reg: u8) {
-        let dest_addr = self.get_u8(MCS51_ADDRESSING::REGISTER(reg));
+        let dest_addr = self.get_xdata_ri_address(reg);
+        let acc = self.get_accumulator();
+        self.write_xdata(dest_addr, acc);
+    }
+
+    pub fn op_movx_a_dptr(&mut self) {
+        let src_addr = self.get_dptr();
+        let value = self.read_xdata(src_addr);
+        self.set_accumulator(value);
+    }
+
+    pub fn op_movx_dptr_a(&mut self) {
+        let dest_addr = self.get_dptr();
+        let acc = self.get_accumulator();
+        self.write_xdata(dest_addr, acc);
     }
 
     /*
@@ -1743,9 +2178,9 @@ impl MCS51 {
     */
 
     pub fn op_djnz(&mut self, addr: MCS51_ADDRESSING, rel: MCS51_ADDRESSING, pc_offset: u16) {
-        let val = self.get_u8(addr).unwrap().wrapping_sub(1);
+        let val = self.get_u8_latch(addr).unwrap().wrapping_sub(1);
         self.set_u8(addr, val);
-        self.pc = self.pc + pc_offset;
+        self.pc = self.pc.wrapping_add(pc_offset);
         
         
         if val != 0 {
@@ -1758,10 +2193,66 @@ impl MCS51 {
         self.write_bit(self.get_u8(bit_addr).unwrap(), false);
     }
 
+    pub fn op_clr_a(&mut self) {
+        self.set_accumulator(0);
+    }
+
+    /*
+    Push onto stack
+
+    The Stack Pointer is incremented by one. The contents of the indicated variable is then copied
+    into the internal RAM location addressed by the Stack Pointer. Otherwise no flags are
+    affected.
+    */
+
+    pub fn op_push(&mut self, addr: MCS51_ADDRESSING) {
+        let value = self.get_u8(addr).unwrap();
+        self.push_stack(value);
+    }
+
+    /*
+    Pop from stack
+
+    The contents of the internal RAM location addressed by the Stack Pointer is read, and the
+    Stack Pointer is decremented by one. The value read is then transferred to the directly
+    addressed byte indicated. No flags are affected.
+    */
+
+    pub fn op_pop(&mut self, addr: MCS51_ADDRESSING) {
+        let value = self.pop_stack();
+        self.set_u8(addr, value);
+    }
+
+    /*
+    Exchange Digit
+
+    XCHD exchanges the low-order nibble of the Accumulator (bits 3-0), generally representing a
+    hexadecimal or BCD digit, with that of the internal RAM location indirectly addressed by the
+    specified register. The high-order nibbles (bits 7-4) of each register are not affected. No
+    flags are affected.
+    */
+
+    pub fn op_xchd(&mut self, addr: MCS51_ADDRESSING) {
+        let value = self.get_u8(addr).unwrap();
+        let acc = self.get_accumulator();
+
+        self.set_accumulator((acc & 0xF0) | (value & 0x0F));
+        self.set_u8(addr, (value & 0xF0) | (acc & 0x0F));
+    }
+
+    /*
+    Compare and Jump if Not Equal
+
+    CJNE compares the magnitudes of the first two operands, and branches if their values are not
+    equal. The carry flag is set if the unsigned integer value of the first operand is less than the
+    unsigned integer value of the second operand; otherwise, the carry is cleared. Neither operand
+    is affected.
+    */
+
     pub fn op_cjne(&mut self, dest: MCS51_ADDRESSING, src: MCS51_ADDRESSING, rel: MCS51_ADDRESSING) {
         let dest_data = self.get_u8(dest).unwrap();
         let src_data = self.get_u8(src).unwrap();
-        self.pc = self.pc + 3;
+        self.pc = self.pc.wrapping_add(3);
 
         if dest_data != src_data {
             let code = self.get_i8(rel).unwrap();
@@ -1776,6 +2267,15 @@ impl MCS51 {
         self.set_dptr(dptr.wrapping_add(1));
     }
 
+    /*
+    Multiply
+
+    MUL AB multiplies the unsigned eight-bit integers in the Accumulator and register B. The
+    low-order byte of the sixteen-bit product is left in the Accumulator, and the high-order byte
+    in B. If the product is greater than 255 (0FFH) the overflow flag is set; otherwise it is
+    cleared. The carry flag is always cleared.
+    */
+
     pub fn op_mul(&mut self) {
         let a = self.get_accumulator() as u16;
         let b = self.read_sfr(MCS51_REGISTERS::B) as u16;
@@ -1813,17 +2313,61 @@ impl MCS51 {
     */
 
     pub fn op_subb(&mut self, src_addr: MCS51_ADDRESSING) {
-        let src = self.get_u8(src_addr).unwrap();
+        let data = self.get_u8(src_addr).unwrap();
         let acc = self.get_accumulator();
+        let c = self.get_carry_flag() as u8;
+
+        // Bit 3 borrow
+        let aux_borrow = (acc & 0xF) < (data & 0xF) + c;
+        self.set_aux_carry_flag(aux_borrow);
+
+        // Bit 6 borrow
+        let ov = (acc & 0x7F) < (data & 0x7F) + c;
+
+        let borrow = (acc as u16) < data as u16 + c as u16;
 
-        let mut result = acc - src;
+        self.set_overflow_flag(borrow ^ ov);
+        self.set_carry_flag(borrow);
 
-        if self.get_carry_flag() {
-            result -= 1;
+        self.set_accumulator(acc.wrapping_sub(data).wrapping_sub(c));
+    }
+
+    /*
+    Decimal-adjust Accumulator for Addition
+
+    DA A adjusts the eight-bit value in the Accumulator resulting from the earlier addition of two
+    variables (each in packed-BCD format), producing two four-bit digits. Any ADD or ADDC
+    instruction may have been used to perform the addition.
+
+    If Accumulator bits 3-0 are greater than nine (xxxx1010-xxxx1111), or if the AC flag is one,
+    six is added to the Accumulator producing the proper BCD digit in the low-order nibble. This
+    internal addition would set the carry flag if a carry-out of the low-order four-bit field
+    propagated through all high-order bits, but it would not clear the carry flag otherwise.
+
+    If the carry flag is now set, or if the four high-order bits now exceed nine
+    (1010xxxx-1111xxxx), these high-order bits are incremented by six, producing the proper BCD
+    digit in the high-order bits. Again, this would set the carry flag if there was a carry-out of
+    the high-order bits, but wouldn't clear the carry. The carry flag thus indicates if the sum of
+    the original two BCD variables is greater than 100, allowing multiple precision decimal
+    addition. OV is not affected.
+    */
+
+    pub fn op_da(&mut self) {
+        let mut acc = self.get_accumulator() as u16;
+        let mut carry = self.get_carry_flag();
+
+        if acc & 0x0F > 0x09 || self.get_aux_carry_flag() {
+            acc += 0x06;
+            carry |= acc > 0xFF;
+        }
+
+        if carry || acc & 0x1F0 > 0x90 {
+            acc += 0x60;
+            carry |= acc > 0xFF;
         }
 
-        self.set_accumulator(result);
-        todo!();
+        self.set_carry_flag(carry);
+        self.set_accumulator(acc as u8);
     }
 
     pub fn op_mov_c_bit(&mut self, bit_addr: MCS51_ADDRESSING) {
@@ -1841,6 +2385,18 @@ impl MCS51 {
         self.set_dptr(data);
     }
 
+    /*
+    Divide
+
+    DIV AB divides the unsigned eight-bit integer in the Accumulator by the unsigned eight-bit
+    integer in register B. The Accumulator receives the integer part of the quotient; register B
+    receives the integer remainder. The carry and OV flags will be cleared.
+
+    Exception: if B had originally contained 00H, the values returned in the Accumulator and
+    B-register will be undefined and the overflow flag will be set. The carry flag is cleared in
+    any case.
+    */
+
     pub fn op_div(&mut self) {
         let b = self.read_sfr(MCS51_REGISTERS::B);
         self.set_carry_flag(false);
@@ -1859,16 +2415,16 @@ impl MCS51 {
     }
 
     pub fn op_movc_pc(&mut self) {
-        let pc = self.pc + 1;
+        let pc = self.pc.wrapping_add(1);
         let acc = self.get_accumulator() as u16;
-        let value = self.read_code_byte((pc + acc) as usize);
+        let value = self.read_code_byte(pc.wrapping_add(acc));
         self.set_accumulator(value);
     }
 
     pub fn op_movc_dptr(&mut self) {
         let acc = self.get_accumulator() as u16;
         let dptr = self.get_dptr();
-        let value = self.read_code_byte((dptr + acc) as usize);
+        let value = self.read_code_byte(dptr.wrapping_add(acc));
         self.set_accumulator(value);
     }
 
@@ -1881,7 +2437,7 @@ impl MCS51 {
     pub fn op_jnz(&mut self, code_addr: MCS51_ADDRESSING) {
         let acc = self.get_accumulator();
         let code = self.get_i8(code_addr).unwrap();
-        self.pc = self.pc + 2;
+        self.pc = self.pc.wrapping_add(2);
 
         if acc != 0 {
             self.write_pc_reli(code as i16);
@@ -1891,7 +2447,7 @@ impl MCS51 {
     pub fn op_jz(&mut self, code_addr: MCS51_ADDRESSING) {
         let acc = self.get_accumulator();
         let code = self.get_i8(code_addr).unwrap();
-        self.pc = self.pc + 2;
+        self.pc = self.pc.wrapping_add(2);
 
         if acc == 0 {
             self.write_pc_reli(code as i16);
@@ -1901,7 +2457,7 @@ impl MCS51 {
     pub fn op_jnc(&mut self, code_addr: MCS51_ADDRESSING) {
         let cf = self.get_carry_flag();
         let code = self.get_i8(code_addr).unwrap();
-        self.pc = self.pc + 2;
+        self.pc = self.pc.wrapping_add(2);
 
         if !cf {
             self.write_pc_reli(code as i16);
@@ -1923,7 +2479,7 @@ impl MCS51 {
 
     pub fn op_anl(&mut self, dest: MCS51_ADDRESSING, src: MCS51_ADDRESSING) {
         let op1 = self.get_u8(src).unwrap();
-        let op2 = self.get_u8(dest).unwrap();
+        let op2 = self.get_u8_latch(dest).unwrap();
 
         let result = op1 & op2;
 
@@ -1932,7 +2488,7 @@ impl MCS51 {
 
     pub fn op_xrl(&mut self, dest: MCS51_ADDRESSING, src: MCS51_ADDRESSING) {
         let op1 = self.get_u8(src).unwrap();
-        let op2 = self.get_u8(dest).unwrap();
+        let op2 = self.get_u8_latch(dest).unwrap();
 
         let result = op1 ^ op2;
 
@@ -1954,7 +2510,7 @@ impl MCS51 {
 
     pub fn op_orl(&mut self, dest: MCS51_ADDRESSING, src: MCS51_ADDRESSING) {
         let op1 = self.get_u8(src).unwrap();
-        let op2 = self.get_u8(dest).unwrap();
+        let op2 = self.get_u8_latch(dest).unwrap();
 
         let result = op1 | op2;
 
@@ -1964,17 +2520,31 @@ impl MCS51 {
     pub fn op_jc(&mut self, code_addr: MCS51_ADDRESSING) {
         let cf = self.get_carry_flag();
         let code = self.get_i8(code_addr).unwrap();
-        self.pc = self.pc + 2;
+        self.pc = self.pc.wrapping_add(2);
 
         if cf {
             self.write_pc_reli(code as i16);
         }
     }
 
+    /*
+    Return from interrupt
+
+    RETI pops the high- and low-order bytes of the PC successively from the stack, and restores
+    the interrupt logic to accept additional interrupts at the same priority level as the one just
+    processed. The Stack Pointer is left decremented by two. No other registers are affected; the
+    PSW is not automatically restored to its pre-interrupt status. Program execution continues at
+    the resulting address, which is generally the instruction immediately after the point at which
+    the interrupt request was detected. If a lower- or same-level interrupt was pending when the
+    RETI instruction is executed, that one instruction is executed before the pending interrupt is
+    processed.
+    */
+
     pub fn op_reti(&mut self) {
         let pc_hi = self.pop_stack() as u16;
         let pc_lo = self.pop_stack() as u16;
         self.pc = (pc_hi << 8) + pc_lo;
+        self.end_interrupt();
     }
 
     pub fn op_mov(&mut self, dest: MCS51_ADDRESSING, src: MCS51_ADDRESSING) {
@@ -1984,14 +2554,14 @@ impl MCS51 {
 
     pub fn op_ajmp(&mut self) {
         let offset = self.get_u11();
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         self.pc &= 0xF800;
         self.pc += offset;
     }
 
     pub fn op_acall(&mut self) {
         let offset = self.get_u11();
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         self.push_stack((self.pc & 0xFF) as u8);
         self.push_stack(((self.pc >> 8) & 0xFF) as u8);
         self.pc &= 0xF800;
@@ -2043,18 +2613,18 @@ impl MCS51 {
 
     pub fn op_lcall(&mut self, addr16: MCS51_ADDRESSING) {
         let new_pc = self.get_u16(addr16).unwrap();
-        self.pc = self.pc + 3;
+        self.pc = self.pc.wrapping_add(3);
         self.push_stack((self.pc & 0xFF) as u8);
         self.push_stack(((self.pc >> 8) & 0xFF) as u8);
         self.pc = new_pc;
     }
 
     pub fn op_jbc(&mut self, bit_addr: MCS51_ADDRESSING, code_addr: MCS51_ADDRESSING) {
-        self.pc = self.pc + 3;
+        self.pc = self.pc.wrapping_add(3);
         let bit_address = self.get_u8(bit_addr).unwrap();
 
-        let bit: bool = self.read_bit(bit_address);
-            
+        let bit: bool = self.read_bit_latch(bit_address);
+
         if bit {
             self.write_bit(bit_address, false);
             let rel = self.get_i8(code_addr).unwrap();
@@ -2063,7 +2633,7 @@ impl MCS51 {
     }
 
     pub fn op_jnb(&mut self, bit_addr: MCS51_ADDRESSING, code_addr: MCS51_ADDRESSING) {
-        self.pc = self.pc + 3;
+        self.pc = self.pc.wrapping_add(3);
         let bit_address = self.get_u8(bit_addr).unwrap();
 
         let bit: bool = self.read_bit(bit_address);
@@ -2075,7 +2645,7 @@ impl MCS51 {
     }
 
     pub fn op_jb(&mut self, bit_addr: MCS51_ADDRESSING, code_addr: MCS51_ADDRESSING) {
-        self.pc = self.pc + 3;
+        self.pc = self.pc.wrapping_add(3);
         let bit_address = self.get_u8(bit_addr).unwrap();
 
         let bit: bool = self.read_bit(bit_address);
@@ -2092,22 +2662,21 @@ impl MCS51 {
         self.pc = (pc_hi << 8) + pc_lo;
     }
 
-    // Decrement
+    // Decrement, a read-modify-write: ports read their latch and the write has its side effects
     pub fn op_dec(&mut self, operand: MCS51_ADDRESSING) {
-        let op = self.get_u8_mut(operand).unwrap();
-        *op = op.wrapping_sub(1);
+        let value = self.get_u8_latch(operand).unwrap_or(0).wrapping_sub(1);
+        self.set_u8(operand, value);
     }
 
-    // Increment
+    // Increment, a read-modify-write like DEC
     pub fn op_inc(&mut self, operand: MCS51_ADDRESSING) {
-        let op = self.get_u8_mut(operand).unwrap();
-        *op = op.wrapping_add(1);
+        let value = self.get_u8_latch(operand).unwrap_or(0).wrapping_add(1);
+        self.set_u8(operand, value);
     }
 
     pub fn op_rr(&mut self) {
-        let mut acc = self.get_accumulator();
-        acc = acc.wrapping_shr(1);
-        self.set_accumulator(acc);
+        let acc = self.get_accumulator();
+        self.set_accumulator(acc.rotate_right(1));
     }
 
     pub fn op_rrc(&mut self) {
@@ -2119,16 +2688,15 @@ impl MCS51 {
     }
 
     pub fn op_rl(&mut self) {
-        let mut acc = self.get_accumulator();
-        acc = acc.wrapping_shl(1);
-        self.set_accumulator(acc);
+        let acc = self.get_accumulator();
+        self.set_accumulator(acc.rotate_left(1));
     }
 
     pub fn op_rlc(&mut self) {
         let acc = self.get_accumulator();
         let overflow = acc & 0x80 != 0;
         let carry = self.get_carry_flag();
-        self.set_accumulator(acc << 1 + carry as u8);
+        self.set_accumulator((acc << 1) + carry as u8);
         self.set_carry_flag(overflow);
     }
 
@@ -2139,65 +2707,142 @@ impl MCS51 {
 
     pub fn op_sjmp(&mut self, addr: MCS51_ADDRESSING) {
         let addr_rel = self.get_i8(addr).unwrap();
-        self.pc = self.pc + 2;
+        self.pc = self.pc.wrapping_add(2);
         self.write_pc_reli(addr_rel as i16);
     }
 
     pub fn op_nop(&mut self) {}
 }
 
-/*
-trait IOComponent {
-    fn get_pin(&self, pin: usize) -> bool;
-    fn set_pin(&mut self, pin: usize, val: bool);
+impl MCU<u8> for MCS51 {
+    // One machine cycle
+    fn clock(&mut self) {
+        if self.tick_breakpoints() {
+            return;
+        }
+        self.cycle_count += 1;
 
-    fn get_port_u8(&self, port: usize) -> u8;
-    fn set_port_u8(&mut self, port: usize, val: u8);
+        if self.tick_reset_pin() || self.tick_power_down() || self.tick_watchdog() {
+            return;
+        }
 
-    fn get_port_u16(&self, port: usize) -> u16;
-    fn set_port_u16(&mut self, port: usize, val: u16);
-}*/
+        let timer1_overflow = self.tick_timers();
+        let timer2_overflows = if self.derivative.timer2 {
+            self.tick_timer2()
+        } else {
+            0
+        };
+        self.tick_serial(timer1_overflow, timer2_overflows);
 
-impl MCU<u8> for MCS51 {
-    fn clock(&mut self) {
         if self.additional_cycles > 0 {
             self.additional_cycles -= 1;
+        } else if self.get_power_state() == MCS51_POWER_STATE::IDLE {
+            self.tick_idle();
         } else {
             self.next_instruction();
         }
     }
 
     fn next_instruction(&mut self) {
-        let opcode = self.program[self.pc as usize];
+        if self.poll_interrupts() {
+            return;
+        }
+
+        if self.code_trap.is_some() || self.sfr_error.is_some() || self.debugger.event.is_some() {
+            return;
+        }
+
+        let opcode = match self.fetch_code(self.pc) {
+            Some(opcode) => opcode,
+            None if self.code.trap => {
+                self.code_trap = Some(self.pc);
+                return;
+            }
+            None => self.code.fill,
+        };
         self.op_pc = self.pc;
         self.run_opcode(opcode);
     }
 
     fn run_opcode(&mut self, opcode: u8) {
-        self.opcode_dispatch_table(opcode as u8)
+        if !self.apply_sfr_policy(opcode) {
+            return;
+        }
+
+        self.update_parity_flag();
+        let trace = self.begin_trace(opcode);
+        self.execute_instruction(opcode);
+        self.additional_cycles = MCS51::get_instruction_cycles(opcode) - 1;
+        self.update_parity_flag();
+        self.update_port_pins();
+        if let Some(trace) = trace {
+            self.end_trace(trace);
+        }
     }
 
-    fn set_program(&mut self, program: Vec<u8>) {
-        self.program = program;
+    // Replaces the whole code memory with an image loaded at address 0
+    fn set_program(&mut self, program: Vec<u8>) -> Result<(), String> {
+        MCS51_Code::check_image(0, program.len())?;
+        self.code.clear();
+        return self.code.load(0, &program);
     }
 
     fn setup(&mut self) {
-        self.reset();
-        self.reset_registers();
+        self.power_on();
         self.generate_opcode_array();
     }
 
+    // Warm reset, as by the RST pin: the RAM and the cycle counters are kept, see power_on
     fn reset(&mut self) {
         self.pc = 0;
-        self.ram = [0; 255];
+        self.code_trap = None;
+        self.sfr_error = None;
+        self.reset_code_banking();
         self.additional_cycles = 0;
+        self.reset_source = MCS51_RESET_SOURCE::PIN;
         self.reset_registers();
+        self.reset_interrupts();
+        self.reset_timers();
+        self.serial.reset();
+        self.watchdog.reset();
     }
 
+    /*
+    Runs until the PC leaves the programmed code, hits a trap, a debugger stop or an unimplemented
+    SFR stops it, the core goes to Idle or Power Down, or RST is held
+    */
     fn run(&mut self) {
-        let program_len = self.program.len() as u16;
-        while self.pc < program_len {
-            self.next_instruction();
+        while self.code_trap.is_none()
+            && self.sfr_error.is_none()
+            && self.debugger.event.is_none()
+            && !self.rst_pin
+            && self.get_power_state() == MCS51_POWER_STATE::ACTIVE
+            && self.is_code_programmed(self.pc)
+        {
+            self.step();
         }
     }
+
+    fn run_for(&mut self, cycles: u64) -> StopReason<u8> {
+        return self.run_control(Some(cycles), |_| false);
+    }
+
+    fn run_until<F: FnMut(&Self) -> bool>(&mut self, predicate: F) -> StopReason<u8> {
+        return self.run_control(None, predicate);
+    }
+
+    fn request_stop(&mut self) {
+        self.stop_request.store(true, Ordering::Relaxed);
+    }
+
+    fn set_breakpoint(&mut self, address: u32) {
+        self.add_breakpoint(address as u16);
+    }
+
+    fn clear_breakpoint(&mut self, address: u32) {
+        let address = address as u16;
+        self.debugger
+            .breakpoints
+            .retain(|breakpoint| breakpoint.address != address);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,307 @@
+use super::*;
+
+/*
+Program Memory
+
+The MCS-51 has separate address spaces for Program and Data Memory. The Program Memory can be
+up to 64K bytes long. The lower 4K (8K for the 8052) may reside on-chip.
+
+If the EA pin is strapped to VCC, program fetches to addresses 0000H through 0FFFH (1FFFH for
+the 8052) are directed to the internal ROM. Fetches from addresses 1000H (2000H) through FFFFH
+are directed to external ROM. If the EA pin is strapped to VSS, all program fetches are directed
+to external ROM. The ROMless parts (8031, 8032) must have this pin externally strapped to VSS to
+enable them to execute properly.
+
+Locations that were never loaded are unprogrammed. They read as the fill value, 0xFF like an
+erased EPROM by default, or stop the core when it tries to execute them and trapping is enabled.
+
+Code banking
+
+Firmware larger than 64K is run by swapping banks of external ROM into the top of code space.
+The addresses below the common area size always reach the common code, the window above it shows
+the selected bank. The bank number is taken from the bits of the P1 latch or of a write only
+latch decoded in XDATA, selected by the bank mask. A banked image is laid out as the common area
+followed by the window of each bank, so bank 0 is the plain 64K image.
+
+Von Neumann mode
+
+Boards that combine PSEN and RD with an AND gate fetch code from the external data RAM, so code
+downloaded with MOVX can be executed and MOVC reads what MOVX wrote. Fetches in the XDATA window
+(above the internal ROM when EA is high) read the RAM and ROM regions of the XDATA space, devices
+read as unprogrammed. Instructions are decoded from memory on every fetch, modified code takes
+effect on its next execution.
+*/
+
+pub const MCS51_CODE_SIZE: usize = 0x10000;
+
+pub enum MCS51_BANK_SELECT {
+    PORT1,
+    XDATA(u16),
+}
+
+pub struct MCS51_Code_Banking {
+    pub select: MCS51_BANK_SELECT,
+    pub mask: u8,
+    pub common_size: usize,
+    pub latch: u8,
+}
+
+impl MCS51_Code_Banking {
+    pub fn get_window_size(&self) -> usize {
+        return MCS51_CODE_SIZE - self.common_size;
+    }
+
+    pub fn get_bank(&self, value: u8) -> usize {
+        let bank = (value & self.mask).checked_shr(self.mask.trailing_zeros());
+        return bank.unwrap_or(0) as usize;
+    }
+
+    // The common area followed by the window of every bank the mask can select
+    pub fn get_image_size(&self) -> usize {
+        let banks = self.get_bank(self.mask) + 1;
+        return self.common_size + banks * self.get_window_size();
+    }
+
+    // Offset of a code address in the banked image
+    pub fn get_physical_address(&self, address: u16, bank: usize) -> usize {
+        if (address as usize) < self.common_size {
+            return address as usize;
+        }
+        return address as usize + bank * self.get_window_size();
+    }
+}
+
+pub struct MCS51_Code {
+    pub internal: Vec<Option<u8>>,
+    pub external: Vec<Option<u8>>,
+    pub ea: bool,
+    pub external_bus: bool,
+    pub fill: u8,
+    pub trap: bool,
+    pub banking: Option<MCS51_Code_Banking>,
+    pub xdata_window: Option<(u16, u16)>,
+}
+
+impl MCS51_Code {
+    pub fn new(internal_size: usize) -> MCS51_Code {
+        MCS51_Code {
+            internal: vec![None; internal_size],
+            external: vec![None; MCS51_CODE_SIZE],
+            ea: true,
+            external_bus: true,
+            fill: 0xFF,
+            trap: false,
+            banking: None,
+            xdata_window: None,
+        }
+    }
+
+    // Contents already loaded below the new size are kept
+    pub fn set_internal_size(&mut self, size: usize) {
+        self.internal.resize(size, None);
+    }
+
+    pub fn clear(&mut self) {
+        self.internal.iter_mut().for_each(|byte| *byte = None);
+        self.external.iter_mut().for_each(|byte| *byte = None);
+    }
+
+    pub fn is_internal(&self, address: u16) -> bool {
+        return self.ea && (address as usize) < self.internal.len();
+    }
+
+    // Images loaded without banking must end within the 64K code space
+    pub fn check_image(base: u16, length: usize) -> Result<(), String> {
+        if base as usize + length > MCS51_CODE_SIZE {
+            return Err(format!("{} bytes at {:04X} do not fit in code memory", length, base));
+        }
+        return Ok(());
+    }
+
+    /*
+    Loads an image as seen with EA high: the part below the internal ROM size is programmed into
+    the chip, the rest into the external ROM
+    */
+    pub fn load(&mut self, base: u16, data: &[u8]) -> Result<(), String> {
+        MCS51_Code::check_image(base, data.len())?;
+
+        for (offset, value) in data.iter().enumerate() {
+            let address = base as usize + offset;
+            if address < self.internal.len() {
+                self.internal[address] = Some(*value);
+            } else {
+                self.external[address] = Some(*value);
+            }
+        }
+        return Ok(());
+    }
+
+    // External ROM only, this is what a ROMless part or EA low fetches from below the internal size
+    pub fn load_external(&mut self, base: u16, data: &[u8]) -> Result<(), String> {
+        MCS51_Code::check_image(base, data.len())?;
+
+        for (offset, value) in data.iter().enumerate() {
+            self.external[base as usize + offset] = Some(*value);
+        }
+        return Ok(());
+    }
+
+    /*
+    The whole banked image goes to the external ROM, which grows past 64K to hold every bank. The
+    internal ROM still answers below its size unless EA is low, banked boards use ROMless parts.
+    */
+    pub fn load_banked(&mut self, data: &[u8]) -> Result<(), String> {
+        let size = self
+            .banking
+            .as_ref()
+            .map_or(MCS51_CODE_SIZE, |banking| banking.get_image_size());
+        if data.len() > size {
+            return Err(format!(
+                "Banked image of {} bytes is larger than the {} bytes the banks can reach",
+                data.len(),
+                size
+            ));
+        }
+
+        if data.len() > self.external.len() {
+            self.external.resize(data.len(), None);
+        }
+
+        for (offset, value) in data.iter().enumerate() {
+            self.external[offset] = Some(*value);
+        }
+        return Ok(());
+    }
+
+    // Returns None for unprogrammed locations
+    pub fn fetch(&self, address: u16, bank: usize) -> Option<u8> {
+        if self.is_internal(address) {
+            return self.internal[address as usize];
+        }
+
+        if !self.external_bus {
+            return None;
+        }
+
+        let offset = match &self.banking {
+            Some(banking) => banking.get_physical_address(address, bank),
+            None => address as usize,
+        };
+
+        return self.external.get(offset).cloned().flatten();
+    }
+}
+
+impl MCS51 {
+    pub fn load_program(&mut self, base: u16, program: &[u8]) -> Result<(), String> {
+        return self.code.load(base, program);
+    }
+
+    pub fn load_external_program(&mut self, base: u16, program: &[u8]) -> Result<(), String> {
+        return self.code.load_external(base, program);
+    }
+
+    // Set up the banking first, the image must fit in the banks it can select
+    pub fn load_banked_program(&mut self, program: &[u8]) -> Result<(), String> {
+        return self.code.load_banked(program);
+    }
+
+    pub fn set_code_banking(
+        &mut self,
+        select: MCS51_BANK_SELECT,
+        mask: u8,
+        common_size: usize,
+    ) -> Result<(), String> {
+        if common_size > MCS51_CODE_SIZE {
+            return Err(format!("Common area of {:X} bytes is larger than code memory", common_size));
+        }
+
+        self.code.banking = Some(MCS51_Code_Banking {
+            select,
+            mask,
+            common_size,
+            latch: 0,
+        });
+        return Ok(());
+    }
+
+    pub fn clear_code_banking(&mut self) {
+        self.code.banking = None;
+    }
+
+    // Bank currently shown in the banked window, 0 when banking is disabled
+    pub fn get_code_bank(&self) -> usize {
+        let banking = match &self.code.banking {
+            Some(banking) => banking,
+            None => return 0,
+        };
+
+        let value = match banking.select {
+            MCS51_BANK_SELECT::PORT1 => self.read_sfr(MCS51_REGISTERS::P1),
+            MCS51_BANK_SELECT::XDATA(_) => banking.latch,
+        };
+
+        return banking.get_bank(value);
+    }
+
+    // MOVX writes to the bank latch address load the latch, the XDATA memory behind it sees them too
+    pub fn write_bank_latch(&mut self, address: u16, value: u8) {
+        if let Some(banking) = &mut self.code.banking {
+            match banking.select {
+                MCS51_BANK_SELECT::XDATA(latch) if latch == address => banking.latch = value,
+                _ => (),
+            }
+        }
+    }
+
+    pub fn reset_code_banking(&mut self) {
+        if let Some(banking) = &mut self.code.banking {
+            banking.latch = 0;
+        }
+    }
+
+    // Code fetches from start to end (inclusive) read the XDATA space
+    pub fn set_von_neumann(&mut self, start: u16, end: u16) {
+        self.code.xdata_window = Some((start, end));
+    }
+
+    pub fn clear_von_neumann(&mut self) {
+        self.code.xdata_window = None;
+    }
+
+    // Returns None for unprogrammed locations
+    pub fn fetch_code(&self, address: u16) -> Option<u8> {
+        if let Some((start, end)) = self.code.xdata_window {
+            if address >= start && address <= end && !self.code.is_internal(address) {
+                return self.xdata.peek(address);
+            }
+        }
+
+        return self.code.fetch(address, self.get_code_bank());
+    }
+
+    pub fn is_code_programmed(&self, address: u16) -> bool {
+        return self.fetch_code(address).is_some();
+    }
+
+    pub fn set_ea_pin(&mut self, value: bool) {
+        self.code.ea = value;
+    }
+
+    pub fn get_ea_pin(&self) -> bool {
+        return self.code.ea;
+    }
+
+    pub fn set_code_fill(&mut self, value: u8) {
+        self.code.fill = value;
+    }
+
+    // Executing an unprogrammed location stops the core, see code_trap
+    pub fn set_code_trap(&mut self, enabled: bool) {
+        self.code.trap = enabled;
+    }
+
+    pub fn read_code_byte(&self, address: u16) -> u8 {
+        return self.fetch_code(address).unwrap_or(self.code.fill);
+    }
+}
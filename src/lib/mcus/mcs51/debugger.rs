@@ -0,0 +1,627 @@
+use super::*;
+
+/*
+Debugger
+
+Breakpoints halt the core before the instruction at their address executes, whether it is driven
+by clock, step or run_for / run_until. A breakpoint counts a hit each time it is reached with its
+condition true, and stops once the hits exceed its ignore count. Temporary breakpoints are deleted
+by their first stop. The instruction the core continues from is not checked again, so taking the
+event and stepping runs the instruction under the breakpoint; run_for / run_until also start past
+a breakpoint on the current PC.
+
+Watchpoints observe a location of internal RAM, SFR, bit or external data space. The READ and
+WRITE kinds are matched against the accesses an instruction makes, decoded from its operands and
+from the registers it uses implicitly (A, B, PSW, DPTR, Rn, @Ri, the stack and MOVX). A watch on a
+byte also sees the accesses to its bits and the other way round. The CHANGE kind compares the
+value before and after every instruction, so it also sees the timers, the serial port and the
+interrupt calls. The instruction completes, then the core halts until the event is taken.
+run_for / run_until take the event and keep the last stop in last_event.
+
+Nothing of this runs while no breakpoint or watchpoint is enabled.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MCS51_WATCH_SPACE {
+    IRAM,
+    SFR,
+    BIT,
+    XDATA,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MCS51_WATCH_KIND {
+    READ,
+    WRITE,
+    CHANGE,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MCS51_Access {
+    pub space: MCS51_WATCH_SPACE,
+    pub address: u16,
+    pub kind: MCS51_WATCH_KIND,
+}
+
+pub struct MCS51_Breakpoint {
+    pub id: usize,
+    pub address: u16,
+    pub enabled: bool,
+    pub temporary: bool,
+    pub hits: u64,
+    pub ignore_count: u64,
+    pub condition: Option<MCS51_Expression>,
+}
+
+pub struct MCS51_Watchpoint {
+    pub id: usize,
+    pub space: MCS51_WATCH_SPACE,
+    pub address: u16,
+    pub kind: MCS51_WATCH_KIND,
+    pub enabled: bool,
+    pub hits: u64,
+    pub ignore_count: u64,
+    pub condition: Option<MCS51_Expression>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MCS51_DEBUG_EVENT {
+    BREAKPOINT {
+        id: usize,
+        pc: u16,
+    },
+    WATCHPOINT {
+        id: usize,
+        pc: u16,
+        space: MCS51_WATCH_SPACE,
+        address: u16,
+        kind: MCS51_WATCH_KIND,
+        old: Option<u8>,
+        new: Option<u8>,
+    },
+}
+
+pub struct MCS51_Debugger {
+    pub breakpoints: Vec<MCS51_Breakpoint>,
+    pub watchpoints: Vec<MCS51_Watchpoint>,
+    pub event: Option<MCS51_DEBUG_EVENT>,
+    pub last_event: Option<MCS51_DEBUG_EVENT>,
+    // Address whose breakpoints are passed by the next instruction
+    pub resume: Option<u16>,
+    next_id: usize,
+}
+
+impl MCS51_Debugger {
+    pub fn new() -> MCS51_Debugger {
+        MCS51_Debugger {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            event: None,
+            last_event: None,
+            resume: None,
+            next_id: 1,
+        }
+    }
+
+    fn allocate_id(&mut self) -> usize {
+        self.next_id += 1;
+        return self.next_id - 1;
+    }
+
+    pub fn has_breakpoints(&self) -> bool {
+        return self.breakpoints.iter().any(|breakpoint| breakpoint.enabled);
+    }
+
+    pub fn has_watchpoints(&self) -> bool {
+        return self.watchpoints.iter().any(|watchpoint| watchpoint.enabled);
+    }
+
+    fn needs_accesses(&self) -> bool {
+        return self
+            .watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.enabled && watchpoint.kind != MCS51_WATCH_KIND::CHANGE);
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16, temporary: bool) -> usize {
+        let id = self.allocate_id();
+        self.breakpoints.push(MCS51_Breakpoint {
+            id,
+            address,
+            enabled: true,
+            temporary,
+            hits: 0,
+            ignore_count: 0,
+            condition: None,
+        });
+        return id;
+    }
+
+    pub fn add_watchpoint(&mut self, space: MCS51_WATCH_SPACE, address: u16, kind: MCS51_WATCH_KIND) -> usize {
+        let id = self.allocate_id();
+        self.watchpoints.push(MCS51_Watchpoint {
+            id,
+            space,
+            address,
+            kind,
+            enabled: true,
+            hits: 0,
+            ignore_count: 0,
+            condition: None,
+        });
+        return id;
+    }
+
+    // Removes the breakpoint or watchpoint with this id, returns false when there is none
+    pub fn delete(&mut self, id: usize) -> bool {
+        let count = self.breakpoints.len() + self.watchpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+        return self.breakpoints.len() + self.watchpoints.len() != count;
+    }
+
+    pub fn get_breakpoint_mut(&mut self, id: usize) -> Option<&mut MCS51_Breakpoint> {
+        return self.breakpoints.iter_mut().find(|breakpoint| breakpoint.id == id);
+    }
+
+    pub fn get_watchpoint_mut(&mut self, id: usize) -> Option<&mut MCS51_Watchpoint> {
+        return self.watchpoints.iter_mut().find(|watchpoint| watchpoint.id == id);
+    }
+
+    pub fn set_condition(&mut self, id: usize, condition: Option<MCS51_Expression>) -> bool {
+        if let Some(breakpoint) = self.get_breakpoint_mut(id) {
+            breakpoint.condition = condition;
+            return true;
+        }
+        if let Some(watchpoint) = self.get_watchpoint_mut(id) {
+            watchpoint.condition = condition;
+            return true;
+        }
+        return false;
+    }
+
+    pub fn set_ignore_count(&mut self, id: usize, count: u64) -> bool {
+        if let Some(breakpoint) = self.get_breakpoint_mut(id) {
+            breakpoint.ignore_count = count;
+            return true;
+        }
+        if let Some(watchpoint) = self.get_watchpoint_mut(id) {
+            watchpoint.ignore_count = count;
+            return true;
+        }
+        return false;
+    }
+
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        if let Some(breakpoint) = self.get_breakpoint_mut(id) {
+            breakpoint.enabled = enabled;
+            return true;
+        }
+        if let Some(watchpoint) = self.get_watchpoint_mut(id) {
+            watchpoint.enabled = enabled;
+            return true;
+        }
+        return false;
+    }
+}
+
+// Byte holding a bit, as a location of the watch spaces
+fn get_bit_byte(bit: u16) -> (MCS51_WATCH_SPACE, u16) {
+    let (byte, _) = MCS51::get_bit_location(bit as u8);
+    if byte < 0x80 {
+        return (MCS51_WATCH_SPACE::IRAM, byte as u16);
+    }
+    return (MCS51_WATCH_SPACE::SFR, byte as u16);
+}
+
+impl MCS51_Watchpoint {
+    pub fn matches(&self, space: MCS51_WATCH_SPACE, address: u16) -> bool {
+        if self.space == space {
+            return self.address == address;
+        }
+        if self.space == MCS51_WATCH_SPACE::BIT {
+            return get_bit_byte(self.address) == (space, address);
+        }
+        if space == MCS51_WATCH_SPACE::BIT {
+            return get_bit_byte(address) == (self.space, self.address);
+        }
+        return false;
+    }
+}
+
+impl MCS51 {
+    pub fn add_breakpoint(&mut self, address: u16) -> usize {
+        return self.debugger.add_breakpoint(address, false);
+    }
+
+    pub fn add_temporary_breakpoint(&mut self, address: u16) -> usize {
+        return self.debugger.add_breakpoint(address, true);
+    }
+
+    pub fn add_watchpoint(&mut self, space: MCS51_WATCH_SPACE, address: u16, kind: MCS51_WATCH_KIND) -> usize {
+        return self.debugger.add_watchpoint(space, address, kind);
+    }
+
+    // Parses the expression, an empty text removes the condition
+    pub fn set_condition(&mut self, id: usize, condition: &str) -> Result<(), String> {
+        let condition = match condition.trim() {
+            "" => None,
+            text => Some(MCS51_Expression::parse(text)?),
+        };
+        if !self.debugger.set_condition(id, condition) {
+            return Err(format!("No breakpoint or watchpoint {}", id));
+        }
+        return Ok(());
+    }
+
+    // The pending breakpoint or watchpoint stop, taking it lets the core run again
+    pub fn take_debug_event(&mut self) -> Option<MCS51_DEBUG_EVENT> {
+        return self.debugger.event.take();
+    }
+
+    fn is_condition_true(&self, condition: &Option<MCS51_Expression>) -> bool {
+        return match condition {
+            Some(condition) => condition.evaluate(self) != 0,
+            None => true,
+        };
+    }
+
+    // Returns true when a breakpoint at the PC stops the core, last_event tells which one
+    pub fn check_breakpoints(&mut self) -> bool {
+        let pc = self.pc;
+        for index in 0..self.debugger.breakpoints.len() {
+            let breakpoint = &self.debugger.breakpoints[index];
+            if !breakpoint.enabled
+                || breakpoint.address != pc
+                || !self.is_condition_true(&breakpoint.condition)
+            {
+                continue;
+            }
+
+            let breakpoint = &mut self.debugger.breakpoints[index];
+            breakpoint.hits += 1;
+            if breakpoint.hits <= breakpoint.ignore_count {
+                continue;
+            }
+
+            let id = breakpoint.id;
+            if breakpoint.temporary {
+                self.debugger.breakpoints.remove(index);
+            }
+            self.debugger.last_event = Some(MCS51_DEBUG_EVENT::BREAKPOINT { id, pc });
+            return true;
+        }
+        return false;
+    }
+
+    /*
+    Called at the start of every machine cycle, returns true when an instruction is about to start
+    on a breakpoint. The core then halts before that cycle, as for a watchpoint until the event is
+    taken.
+    */
+    pub fn tick_breakpoints(&mut self) -> bool {
+        if self.debugger.event.is_some()
+            || self.additional_cycles > 0
+            || self.code_trap.is_some()
+            || self.sfr_error.is_some()
+            || self.get_power_state() != MCS51_POWER_STATE::ACTIVE
+        {
+            return false;
+        }
+
+        let resume = self.debugger.resume.take();
+        if !self.debugger.has_breakpoints() || resume == Some(self.pc) || !self.check_breakpoints() {
+            return false;
+        }
+        self.debugger.event = self.debugger.last_event;
+        self.debugger.resume = Some(self.pc);
+        return true;
+    }
+
+    pub fn read_watch_value(&self, space: MCS51_WATCH_SPACE, address: u16) -> Option<u8> {
+        return match space {
+            MCS51_WATCH_SPACE::IRAM => self.read_indirect(address as u8).cloned(),
+            MCS51_WATCH_SPACE::SFR => Some(self.read_latch(address as u8)),
+            MCS51_WATCH_SPACE::BIT => Some(self.read_bit_latch(address as u8) as u8),
+            MCS51_WATCH_SPACE::XDATA => self.xdata.peek(address),
+        };
+    }
+
+    /*
+    Runs one instruction through the dispatch table. There is a single execution path, clock →
+    next_instruction → run_opcode → here, so the watchpoints see every instruction.
+    */
+    pub fn execute_instruction(&mut self, opcode: u8) {
+        if !self.debugger.has_watchpoints() {
+            self.opcode_dispatch_table(opcode);
+            return;
+        }
+
+        let accesses = if self.debugger.needs_accesses() {
+            self.get_instruction_accesses(opcode)
+        } else {
+            Vec::new()
+        };
+        let before: Vec<Option<u8>> = self
+            .debugger
+            .watchpoints
+            .iter()
+            .map(|watchpoint| self.read_watch_value(watchpoint.space, watchpoint.address))
+            .collect();
+
+        self.opcode_dispatch_table(opcode);
+
+        for index in 0..self.debugger.watchpoints.len() {
+            let watchpoint = &self.debugger.watchpoints[index];
+            if !watchpoint.enabled {
+                continue;
+            }
+
+            let old = before[index];
+            let new = self.read_watch_value(watchpoint.space, watchpoint.address);
+            let hit = match watchpoint.kind {
+                MCS51_WATCH_KIND::CHANGE => old != new,
+                kind => accesses
+                    .iter()
+                    .any(|access| access.kind == kind && watchpoint.matches(access.space, access.address)),
+            };
+            if !hit || !self.is_condition_true(&watchpoint.condition) {
+                continue;
+            }
+
+            let watchpoint = &mut self.debugger.watchpoints[index];
+            watchpoint.hits += 1;
+            if watchpoint.hits <= watchpoint.ignore_count || self.debugger.event.is_some() {
+                continue;
+            }
+
+            self.debugger.event = Some(MCS51_DEBUG_EVENT::WATCHPOINT {
+                id: watchpoint.id,
+                pc: self.op_pc,
+                space: watchpoint.space,
+                address: watchpoint.address,
+                kind: watchpoint.kind,
+                old,
+                new,
+            });
+        }
+    }
+
+    /*
+    Memory accesses of the instruction at op_pc, decoded before it executes. Read-modify-write
+    operands give a read and a write. Flags are reported as PSW writes for the instructions that
+    compute carries, the parity flag that follows A is left out.
+    */
+    pub fn get_instruction_accesses(&self, opcode: u8) -> Vec<MCS51_Access> {
+        use self::MCS51_WATCH_KIND::*;
+
+        let mut accesses: Vec<MCS51_Access> = Vec::new();
+        let mut add = |space: MCS51_WATCH_SPACE, address: u16, kinds: &[MCS51_WATCH_KIND]| {
+            for kind in kinds {
+                accesses.push(MCS51_Access {
+                    space,
+                    address,
+                    kind: *kind,
+                });
+            }
+        };
+
+        let bank = self.get_current_register_bank_flags() as u16;
+        let sp = self.read_sfr(MCS51_REGISTERS::SP);
+        let direct = |offset: u8| {
+            let address = self.read_operand(offset);
+            if address < 0x80 {
+                return (MCS51_WATCH_SPACE::IRAM, address as u16);
+            }
+            return (MCS51_WATCH_SPACE::SFR, address as u16);
+        };
+        let dptr: [u16; 2] = if self.read_sfr(MCS51_REGISTERS::AUXR1) & MCS51_AUXR1_DPS != 0 {
+            [0x85, 0x84]
+        } else {
+            [0x83, 0x82]
+        };
+
+        const RMW: &[MCS51_WATCH_KIND] = &[READ, WRITE];
+        let iram = MCS51_WATCH_SPACE::IRAM;
+        let sfr = MCS51_WATCH_SPACE::SFR;
+        let bit = MCS51_WATCH_SPACE::BIT;
+        let xdata = MCS51_WATCH_SPACE::XDATA;
+        let acc = 0xE0;
+        let psw = 0xD0;
+
+        // Source or destination of the columns 5 to F: direct, @Ri or Rn
+        let (space, address, register) = match opcode & 0x0F {
+            0x05 => {
+                let (space, address) = direct(1);
+                (space, address, None)
+            }
+            0x06 | 0x07 => {
+                let register = opcode & 0x01;
+                (iram, self.read_register(register) as u16, Some(register))
+            }
+            _ => (iram, bank + (opcode & 0x07) as u16, None),
+        };
+        if let Some(register) = register {
+            add(iram, bank + register as u16, &[READ]);
+        }
+
+        match (opcode >> 4, opcode & 0x0F) {
+            // Columns 0 to 4, one instruction each
+            (_, 0x01) if opcode & 0x10 != 0 => {
+                add(iram, sp.wrapping_add(1) as u16, &[WRITE]);
+                add(iram, sp.wrapping_add(2) as u16, &[WRITE]);
+                add(sfr, 0x81, RMW);
+            }
+            (_, 0x01) => (),
+            (0x0, 0x00) | (0x8, 0x00) | (0x0, 0x02) => (),
+            (0x1, 0x00) => {
+                add(bit, self.read_operand(1) as u16, RMW);
+            }
+            (0x2, 0x00) | (0x3, 0x00) => add(bit, self.read_operand(1) as u16, &[READ]),
+            (0x4, 0x00) | (0x5, 0x00) => add(sfr, psw, &[READ]),
+            (0x6, 0x00) | (0x7, 0x00) => add(sfr, acc, &[READ]),
+            (0x9, 0x00) => {
+                add(sfr, dptr[0], &[WRITE]);
+                add(sfr, dptr[1], &[WRITE]);
+            }
+            (0xA, 0x00) | (0xB, 0x00) | (0x7, 0x02) | (0x8, 0x02) | (0xA, 0x02) => {
+                add(bit, self.read_operand(1) as u16, &[READ]);
+                add(sfr, psw, RMW);
+            }
+            (0xC, 0x00) => {
+                let (space, address) = direct(1);
+                add(space, address, &[READ]);
+                add(iram, sp.wrapping_add(1) as u16, &[WRITE]);
+                add(sfr, 0x81, RMW);
+            }
+            (0xD, 0x00) => {
+                let (space, address) = direct(1);
+                add(iram, sp as u16, &[READ]);
+                add(sfr, 0x81, RMW);
+                add(space, address, &[WRITE]);
+            }
+            (0xE, 0x00) => {
+                add(sfr, dptr[0], &[READ]);
+                add(sfr, dptr[1], &[READ]);
+                add(xdata, self.get_dptr(), &[READ]);
+                add(sfr, acc, &[WRITE]);
+            }
+            (0xF, 0x00) => {
+                add(sfr, dptr[0], &[READ]);
+                add(sfr, dptr[1], &[READ]);
+                add(sfr, acc, &[READ]);
+                add(xdata, self.get_dptr(), &[WRITE]);
+            }
+            (0x1, 0x02) => {
+                add(iram, sp.wrapping_add(1) as u16, &[WRITE]);
+                add(iram, sp.wrapping_add(2) as u16, &[WRITE]);
+                add(sfr, 0x81, RMW);
+            }
+            (0x2, 0x02) | (0x3, 0x02) => {
+                add(iram, sp as u16, &[READ]);
+                add(iram, sp.wrapping_sub(1) as u16, &[READ]);
+                add(sfr, 0x81, RMW);
+            }
+            (0x4, 0x02) | (0x5, 0x02) | (0x6, 0x02) => {
+                let (space, address) = direct(1);
+                add(sfr, acc, &[READ]);
+                add(space, address, RMW);
+            }
+            (0x4, 0x03) | (0x5, 0x03) | (0x6, 0x03) => {
+                let (space, address) = direct(1);
+                add(space, address, RMW);
+            }
+            (0x9, 0x02) => {
+                add(sfr, psw, &[READ]);
+                add(bit, self.read_operand(1) as u16, &[WRITE]);
+            }
+            (0xB, 0x02) => add(bit, self.read_operand(1) as u16, RMW),
+            (0xC, 0x02) | (0xD, 0x02) => add(bit, self.read_operand(1) as u16, &[WRITE]),
+            (0xE, 0x02) | (0xE, 0x03) | (0xF, 0x02) | (0xF, 0x03) => {
+                let register = opcode & 0x01;
+                let address = self.get_xdata_ri_address(register);
+                add(iram, bank + register as u16, &[READ]);
+                add(sfr, 0xA0, &[READ]);
+                if opcode & 0x10 == 0 {
+                    add(xdata, address, &[READ]);
+                    add(sfr, acc, &[WRITE]);
+                } else {
+                    add(sfr, acc, &[READ]);
+                    add(xdata, address, &[WRITE]);
+                }
+            }
+            (0x0, 0x03) | (0x2, 0x03) | (0xC, 0x04) | (0xF, 0x04) | (0x0, 0x04) | (0x1, 0x04) => {
+                add(sfr, acc, RMW)
+            }
+            (0x1, 0x03) | (0x3, 0x03) | (0xD, 0x04) => {
+                add(sfr, acc, RMW);
+                add(sfr, psw, RMW);
+            }
+            (0x7, 0x03) => {
+                add(sfr, acc, &[READ]);
+                add(sfr, dptr[0], &[READ]);
+                add(sfr, dptr[1], &[READ]);
+            }
+            (0x8, 0x03) => add(sfr, acc, RMW),
+            (0x9, 0x03) => {
+                add(sfr, acc, RMW);
+                add(sfr, dptr[0], &[READ]);
+                add(sfr, dptr[1], &[READ]);
+            }
+            (0xA, 0x03) => {
+                add(sfr, dptr[0], RMW);
+                add(sfr, dptr[1], RMW);
+            }
+            (0xB, 0x03) | (0xC, 0x03) | (0xD, 0x03) => add(sfr, psw, RMW),
+            (0x2, 0x04) | (0x3, 0x04) | (0x9, 0x04) => {
+                add(sfr, acc, RMW);
+                add(sfr, psw, RMW);
+            }
+            (0x4, 0x04) | (0x5, 0x04) | (0x6, 0x04) => add(sfr, acc, RMW),
+            (0x7, 0x04) | (0xE, 0x04) => add(sfr, acc, &[WRITE]),
+            (0x8, 0x04) | (0xA, 0x04) => {
+                add(sfr, acc, RMW);
+                add(sfr, 0xF0, RMW);
+                add(sfr, psw, RMW);
+            }
+            (0xB, 0x04) => {
+                add(sfr, acc, &[READ]);
+                add(sfr, psw, RMW);
+            }
+
+            // Columns 5 to F share the source or destination operand
+            (0xA, 0x05) => (),
+            (0x0, _) | (0x1, _) => add(space, address, RMW),
+            (0x2, _) | (0x3, _) | (0x9, _) => {
+                add(space, address, &[READ]);
+                add(sfr, acc, RMW);
+                add(sfr, psw, RMW);
+            }
+            (0x4, _) | (0x5, _) | (0x6, _) => {
+                add(space, address, &[READ]);
+                add(sfr, acc, RMW);
+            }
+            (0x7, _) => add(space, address, &[WRITE]),
+            (0x8, 0x05) => {
+                let (destination_space, destination) = direct(2);
+                add(space, address, &[READ]);
+                add(destination_space, destination, &[WRITE]);
+            }
+            (0x8, _) => {
+                let (destination_space, destination) = direct(1);
+                add(space, address, &[READ]);
+                add(destination_space, destination, &[WRITE]);
+            }
+            (0xA, _) => {
+                let (source_space, source) = direct(1);
+                add(source_space, source, &[READ]);
+                add(space, address, &[WRITE]);
+            }
+            (0xB, 0x05) => {
+                add(space, address, &[READ]);
+                add(sfr, acc, &[READ]);
+                add(sfr, psw, RMW);
+            }
+            (0xB, _) => {
+                add(space, address, &[READ]);
+                add(sfr, psw, RMW);
+            }
+            (0xC, _) | (0xD, 0x06) | (0xD, 0x07) => {
+                add(sfr, acc, RMW);
+                add(space, address, RMW);
+            }
+            (0xD, _) => add(space, address, RMW),
+            (0xE, _) => {
+                add(space, address, &[READ]);
+                add(sfr, acc, &[WRITE]);
+            }
+            (0xF, _) => {
+                add(sfr, acc, &[READ]);
+                add(space, address, &[WRITE]);
+            }
+            _ => (),
+        }
+
+        return accesses;
+    }
+}
@@ -0,0 +1,252 @@
+use super::*;
+
+/*
+Derivatives
+
+The MCS-51 family shares one core but the parts differ in the amount of on-chip program memory,
+internal data RAM, peripherals and implemented Special Function Registers:
+
+8031      : ROMless 8051, program memory is external only
+8051      : 4K ROM, 128 bytes RAM, Timer 0/1, serial port
+8052      : 8K ROM, 256 bytes RAM (upper 128 indirect only), adds Timer 2
+AT89C2051 : 2K Flash, 128 bytes RAM, 20 pin package with P1 and P3 only, no external bus
+AT89S52   : 8K Flash, 256 bytes RAM, Timer 2, watchdog and dual data pointer
+
+Only the AT89S52 leaves power down on an external interrupt, the others need a reset.
+
+Each SFR is listed with its reset value. Unimplemented bits (X in the datasheets) are reset to 0.
+Addresses not listed are not implemented on the part: reads and writes to them are ignored.
+*/
+
+pub struct MCS51_Derivative {
+    pub name: &'static str,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub timer2: bool,
+    pub external_bus: bool,
+    pub power_down_wakeup: bool,
+    pub sfrs: &'static [(u8, u8)],
+}
+
+const MCS51_8051_SFRS: [(u8, u8); 21] = [
+    (0x80, 0xFF), // P0
+    (0x81, 0x07), // SP
+    (0x82, 0x00), // DPL
+    (0x83, 0x00), // DPH
+    (0x87, 0x00), // PCON
+    (0x88, 0x00), // TCON
+    (0x89, 0x00), // TMOD
+    (0x8A, 0x00), // TL0
+    (0x8B, 0x00), // TL1
+    (0x8C, 0x00), // TH0
+    (0x8D, 0x00), // TH1
+    (0x90, 0xFF), // P1
+    (0x98, 0x00), // SCON
+    (0x99, 0x00), // SBUF
+    (0xA0, 0xFF), // P2
+    (0xA8, 0x00), // IE
+    (0xB0, 0xFF), // P3
+    (0xB8, 0x00), // IP
+    (0xD0, 0x00), // PSW
+    (0xE0, 0x00), // ACC
+    (0xF0, 0x00), // B
+];
+
+const MCS51_8052_SFRS: [(u8, u8); 27] = [
+    (0x80, 0xFF), // P0
+    (0x81, 0x07), // SP
+    (0x82, 0x00), // DPL
+    (0x83, 0x00), // DPH
+    (0x87, 0x00), // PCON
+    (0x88, 0x00), // TCON
+    (0x89, 0x00), // TMOD
+    (0x8A, 0x00), // TL0
+    (0x8B, 0x00), // TL1
+    (0x8C, 0x00), // TH0
+    (0x8D, 0x00), // TH1
+    (0x90, 0xFF), // P1
+    (0x98, 0x00), // SCON
+    (0x99, 0x00), // SBUF
+    (0xA0, 0xFF), // P2
+    (0xA8, 0x00), // IE
+    (0xB0, 0xFF), // P3
+    (0xB8, 0x00), // IP
+    (0xC8, 0x00), // T2CON
+    (0xC9, 0x00), // T2MOD
+    (0xCA, 0x00), // RCAP2L
+    (0xCB, 0x00), // RCAP2H
+    (0xCC, 0x00), // TL2
+    (0xCD, 0x00), // TH2
+    (0xD0, 0x00), // PSW
+    (0xE0, 0x00), // ACC
+    (0xF0, 0x00), // B
+];
+
+// No P0/P2 on the 20 pin package
+const MCS51_2051_SFRS: [(u8, u8); 19] = [
+    (0x81, 0x07), // SP
+    (0x82, 0x00), // DPL
+    (0x83, 0x00), // DPH
+    (0x87, 0x00), // PCON
+    (0x88, 0x00), // TCON
+    (0x89, 0x00), // TMOD
+    (0x8A, 0x00), // TL0
+    (0x8B, 0x00), // TL1
+    (0x8C, 0x00), // TH0
+    (0x8D, 0x00), // TH1
+    (0x90, 0xFF), // P1
+    (0x98, 0x00), // SCON
+    (0x99, 0x00), // SBUF
+    (0xA8, 0x00), // IE
+    (0xB0, 0xFF), // P3
+    (0xB8, 0x00), // IP
+    (0xD0, 0x00), // PSW
+    (0xE0, 0x00), // ACC
+    (0xF0, 0x00), // B
+];
+
+// 8052 set plus the second data pointer, AUXR, AUXR1 and the watchdog reset register
+const MCS51_AT89S52_SFRS: [(u8, u8); 32] = [
+    (0x80, 0xFF), // P0
+    (0x81, 0x07), // SP
+    (0x82, 0x00), // DP0L
+    (0x83, 0x00), // DP0H
+    (0x84, 0x00), // DP1L
+    (0x85, 0x00), // DP1H
+    (0x87, 0x00), // PCON
+    (0x88, 0x00), // TCON
+    (0x89, 0x00), // TMOD
+    (0x8A, 0x00), // TL0
+    (0x8B, 0x00), // TL1
+    (0x8C, 0x00), // TH0
+    (0x8D, 0x00), // TH1
+    (0x8E, 0x00), // AUXR
+    (0x90, 0xFF), // P1
+    (0x98, 0x00), // SCON
+    (0x99, 0x00), // SBUF
+    (0xA0, 0xFF), // P2
+    (0xA2, 0x00), // AUXR1
+    (0xA6, 0x00), // WDTRST
+    (0xA8, 0x00), // IE
+    (0xB0, 0xFF), // P3
+    (0xB8, 0x00), // IP
+    (0xC8, 0x00), // T2CON
+    (0xC9, 0x00), // T2MOD
+    (0xCA, 0x00), // RCAP2L
+    (0xCB, 0x00), // RCAP2H
+    (0xCC, 0x00), // TL2
+    (0xCD, 0x00), // TH2
+    (0xD0, 0x00), // PSW
+    (0xE0, 0x00), // ACC
+    (0xF0, 0x00), // B
+];
+
+pub const MCS51_8031: MCS51_Derivative = MCS51_Derivative {
+    name: "8031",
+    rom_size: 0,
+    ram_size: 128,
+    timer2: false,
+    external_bus: true,
+    power_down_wakeup: false,
+    sfrs: &MCS51_8051_SFRS,
+};
+
+pub const MCS51_8051: MCS51_Derivative = MCS51_Derivative {
+    name: "8051",
+    rom_size: 0x1000,
+    ram_size: 128,
+    timer2: false,
+    external_bus: true,
+    power_down_wakeup: false,
+    sfrs: &MCS51_8051_SFRS,
+};
+
+pub const MCS51_8052: MCS51_Derivative = MCS51_Derivative {
+    name: "8052",
+    rom_size: 0x2000,
+    ram_size: 256,
+    timer2: true,
+    external_bus: true,
+    power_down_wakeup: false,
+    sfrs: &MCS51_8052_SFRS,
+};
+
+pub const MCS51_AT89C2051: MCS51_Derivative = MCS51_Derivative {
+    name: "AT89C2051",
+    rom_size: 0x0800,
+    ram_size: 128,
+    timer2: false,
+    external_bus: false,
+    power_down_wakeup: false,
+    sfrs: &MCS51_2051_SFRS,
+};
+
+pub const MCS51_AT89S52: MCS51_Derivative = MCS51_Derivative {
+    name: "AT89S52",
+    rom_size: 0x2000,
+    ram_size: 256,
+    timer2: true,
+    external_bus: true,
+    power_down_wakeup: true,
+    sfrs: &MCS51_AT89S52_SFRS,
+};
+
+pub const MCS51_DERIVATIVES: [&MCS51_Derivative; 5] = [
+    &MCS51_8031,
+    &MCS51_8051,
+    &MCS51_8052,
+    &MCS51_AT89C2051,
+    &MCS51_AT89S52,
+];
+
+impl MCS51_Derivative {
+    // Part names are matched case-insensitively, "80C51" style CMOS names are accepted too
+    pub fn from_name(name: &str) -> Option<&'static MCS51_Derivative> {
+        let name = name.to_uppercase().replacen("80C", "80", 1);
+        return MCS51_DERIVATIVES
+            .iter()
+            .find(|derivative| derivative.name == name)
+            .cloned();
+    }
+
+    // One bit per SFR address 80H-FFH
+    pub fn get_sfr_mask(&self) -> u128 {
+        let mut mask = 0;
+        for (address, _reset) in self.sfrs.iter() {
+            mask |= 1 << (address - 0x80);
+        }
+        return mask;
+    }
+}
+
+impl MCS51 {
+    pub fn new_derivative(derivative: &'static MCS51_Derivative) -> MCS51 {
+        let mut mcs51 = MCS51::new();
+        mcs51.set_derivative(derivative);
+        mcs51
+    }
+
+    pub fn from_part_name(name: &str) -> Option<MCS51> {
+        return MCS51_Derivative::from_name(name).map(MCS51::new_derivative);
+    }
+
+    pub fn set_derivative(&mut self, derivative: &'static MCS51_Derivative) {
+        self.derivative = derivative;
+        self.sfr_mask = derivative.get_sfr_mask();
+        self.code.set_internal_size(derivative.rom_size);
+        self.code.external_bus = derivative.external_bus;
+
+        // Parts without an external bus see no XDATA RAM, the regions mapped by the board stay
+        self.xdata.set_default_ram(derivative.external_bus);
+
+        self.reset_registers();
+    }
+
+    pub fn get_derivative(&self) -> &'static MCS51_Derivative {
+        return self.derivative;
+    }
+
+    pub fn has_sfr(&self, address: u8) -> bool {
+        return address >= 0x80 && self.sfr_mask & (1 << (address - 0x80)) != 0;
+    }
+}
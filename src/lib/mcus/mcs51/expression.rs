@@ -0,0 +1,321 @@
+use super::*;
+use std::fmt;
+
+/*
+Debugger expressions
+
+Conditions of breakpoints and watchpoints are expressions evaluated on the state of the core.
+Values are signed 64-bit integers, comparisons and logical operators give 0 or 1.
+
+Numbers: 123, 0x7F, 7FH (a leading digit is needed for the H suffix)
+Registers: A, ACC, B, R0-R7 (current bank), SP, DPL, DPH, DPTR, PSW, PC and the SFR names
+Flags: C, CY, AC, F0, OV, P
+Bits: SFR.n, e.g. P1.3 or PSW.5
+Memory: D:addr (direct, the SFRs above 7FH), I:addr (indirect internal RAM), X:addr (external
+data), C:addr (code), BIT:addr (bit address)
+Operators, by increasing precedence: ||, &&, |, ^, &, == !=, < <= > >=, + -, unary ! ~ -
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MCS51_MEMORY_SPACE {
+    DIRECT,
+    INDIRECT,
+    XDATA,
+    CODE,
+    BIT,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MCS51_Expression {
+    NUMBER(i64),
+    ACCUMULATOR,
+    REGISTER(u8),
+    DPTR,
+    PC,
+    SFR(u8),
+    BIT(u8),
+    MEMORY(MCS51_MEMORY_SPACE, Box<MCS51_Expression>),
+    UNARY(String, Box<MCS51_Expression>),
+    BINARY(String, Box<MCS51_Expression>, Box<MCS51_Expression>),
+}
+
+const MCS51_BINARY_OPERATORS: [&[&str]; 8] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["+", "-"],
+];
+
+// PSW flags usable by name, as bit addresses
+fn flag_bit(name: &str) -> Option<u8> {
+    return match name {
+        "C" | "CY" => Some(0xD7),
+        "AC" => Some(0xD6),
+        "F0" => Some(0xD5),
+        "OV" => Some(0xD2),
+        "P" => Some(0xD0),
+        _ => None,
+    };
+}
+
+pub fn sfr_address(name: &str) -> Option<u8> {
+    return (0x80..=0xFF).find(|address| mcs51::MCS51_Decompiler::sfr_name(*address) == name);
+}
+
+pub fn parse_number(text: &str) -> Option<i64> {
+    let text = text.to_uppercase();
+    if let Some(hex) = text.strip_prefix("0X") {
+        return i64::from_str_radix(hex, 16).ok();
+    }
+    if let Some(hex) = text.strip_suffix('H') {
+        return i64::from_str_radix(hex, 16).ok();
+    }
+    return text.parse::<i64>().ok();
+}
+
+struct MCS51_Expression_Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> MCS51_Expression_Parser<'a> {
+    fn rest(&self) -> &'a str {
+        return &self.text[self.position..];
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn accept(&mut self, token: &str) -> bool {
+        self.skip_spaces();
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            return true;
+        }
+        return false;
+    }
+
+    fn word(&mut self) -> Option<&'a str> {
+        self.skip_spaces();
+        let rest = self.rest();
+        let length = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        if length == 0 {
+            return None;
+        }
+        self.position += length;
+        return Some(&rest[..length]);
+    }
+
+    fn binary(&mut self, level: usize) -> Result<MCS51_Expression, String> {
+        if level == MCS51_BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+        'operators: loop {
+            for operator in MCS51_BINARY_OPERATORS[level] {
+                // | and & must not take the first half of || and &&
+                let doubled = operator.len() == 1 && self.rest().trim_start().starts_with(&operator.repeat(2));
+                if !doubled && self.accept(operator) {
+                    let right = self.binary(level + 1)?;
+                    left = MCS51_Expression::BINARY(operator.to_string(), Box::new(left), Box::new(right));
+                    continue 'operators;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<MCS51_Expression, String> {
+        for operator in ["!", "~", "-"] {
+            if self.accept(operator) {
+                let operand = self.unary()?;
+                return Ok(MCS51_Expression::UNARY(operator.to_string(), Box::new(operand)));
+            }
+        }
+        return self.primary();
+    }
+
+    fn primary(&mut self) -> Result<MCS51_Expression, String> {
+        if self.accept("(") {
+            let expression = self.binary(0)?;
+            if !self.accept(")") {
+                return Err(format!("Missing ) at {}", self.position));
+            }
+            return Ok(expression);
+        }
+
+        let start = self.position;
+        let word = match self.word() {
+            Some(word) => word.to_uppercase(),
+            None => return Err(format!("Unexpected input at {}", self.position)),
+        };
+
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            return match parse_number(&word) {
+                Some(value) => Ok(MCS51_Expression::NUMBER(value)),
+                None => Err(format!("Invalid number {}", word)),
+            };
+        }
+
+        if self.rest().starts_with(':') {
+            let space = match word.as_str() {
+                "D" => MCS51_MEMORY_SPACE::DIRECT,
+                "I" => MCS51_MEMORY_SPACE::INDIRECT,
+                "X" => MCS51_MEMORY_SPACE::XDATA,
+                "C" => MCS51_MEMORY_SPACE::CODE,
+                "BIT" => MCS51_MEMORY_SPACE::BIT,
+                _ => return Err(format!("Unknown memory space {}", word)),
+            };
+            self.position += 1;
+            let address = self.unary()?;
+            return Ok(MCS51_Expression::MEMORY(space, Box::new(address)));
+        }
+
+        let expression = match word.as_str() {
+            "A" | "ACC" => MCS51_Expression::ACCUMULATOR,
+            "DPTR" => MCS51_Expression::DPTR,
+            "PC" => MCS51_Expression::PC,
+            "R0" | "R1" | "R2" | "R3" | "R4" | "R5" | "R6" | "R7" => {
+                MCS51_Expression::REGISTER(word.as_bytes()[1] - b'0')
+            }
+            _ => match (flag_bit(&word), sfr_address(&word)) {
+                (Some(bit), _) => MCS51_Expression::BIT(bit),
+                (None, Some(address)) => MCS51_Expression::SFR(address),
+                (None, None) => return Err(format!("Unknown name {} at {}", word, start)),
+            },
+        };
+
+        // SFR.n selects a bit of a bit addressable register
+        if self.rest().starts_with('.') {
+            let address = match expression {
+                MCS51_Expression::ACCUMULATOR => 0xE0,
+                MCS51_Expression::SFR(address) if address & 0x07 == 0 => address,
+                _ => return Err(format!("{} is not bit addressable", word)),
+            };
+            self.position += 1;
+            let bit = self.rest().chars().next().and_then(|c| c.to_digit(8));
+            return match bit {
+                Some(bit) => {
+                    self.position += 1;
+                    Ok(MCS51_Expression::BIT(address + bit as u8))
+                }
+                None => Err(format!("Invalid bit number at {}", self.position)),
+            };
+        }
+
+        return Ok(expression);
+    }
+}
+
+impl fmt::Display for MCS51_Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            MCS51_Expression::NUMBER(value) => write!(f, "{:#X}", value),
+            MCS51_Expression::ACCUMULATOR => write!(f, "A"),
+            MCS51_Expression::REGISTER(register) => write!(f, "R{}", register),
+            MCS51_Expression::DPTR => write!(f, "DPTR"),
+            MCS51_Expression::PC => write!(f, "PC"),
+            MCS51_Expression::SFR(address) => {
+                write!(f, "{}", mcs51::MCS51_Decompiler::sfr_name(*address))
+            }
+            MCS51_Expression::BIT(address) => write!(f, "BIT:{:#X}", address),
+            MCS51_Expression::MEMORY(space, address) => {
+                let prefix = match space {
+                    MCS51_MEMORY_SPACE::DIRECT => "D",
+                    MCS51_MEMORY_SPACE::INDIRECT => "I",
+                    MCS51_MEMORY_SPACE::XDATA => "X",
+                    MCS51_MEMORY_SPACE::CODE => "C",
+                    MCS51_MEMORY_SPACE::BIT => "BIT",
+                };
+                write!(f, "{}:({})", prefix, address)
+            }
+            MCS51_Expression::UNARY(operator, operand) => write!(f, "{}{}", operator, operand),
+            MCS51_Expression::BINARY(operator, left, right) => {
+                write!(f, "({} {} {})", left, operator, right)
+            }
+        };
+    }
+}
+
+impl MCS51_Expression {
+    pub fn parse(text: &str) -> Result<MCS51_Expression, String> {
+        let mut parser = MCS51_Expression_Parser { text, position: 0 };
+        let expression = parser.binary(0)?;
+
+        parser.skip_spaces();
+        if !parser.rest().is_empty() {
+            return Err(format!("Unexpected input at {}", parser.position));
+        }
+        return Ok(expression);
+    }
+
+    pub fn evaluate(&self, mcu: &MCS51) -> i64 {
+        return match self {
+            MCS51_Expression::NUMBER(value) => *value,
+            MCS51_Expression::ACCUMULATOR => mcu.get_accumulator() as i64,
+            MCS51_Expression::REGISTER(register) => mcu.read_register(*register) as i64,
+            MCS51_Expression::DPTR => mcu.get_dptr() as i64,
+            MCS51_Expression::PC => mcu.pc as i64,
+            MCS51_Expression::SFR(address) => mcu.read_raw(*address) as i64,
+            MCS51_Expression::BIT(address) => mcu.read_bit(*address) as i64,
+            MCS51_Expression::MEMORY(space, address) => {
+                let address = address.evaluate(mcu);
+                mcu.read_memory(*space, address as u16).unwrap_or(0) as i64
+            }
+            MCS51_Expression::UNARY(operator, operand) => {
+                let value = operand.evaluate(mcu);
+                match operator.as_str() {
+                    "!" => (value == 0) as i64,
+                    "~" => !value,
+                    _ => value.wrapping_neg(),
+                }
+            }
+            MCS51_Expression::BINARY(operator, left, right) => {
+                let left = left.evaluate(mcu);
+                // The right side of && and || is only evaluated when needed
+                match operator.as_str() {
+                    "&&" => return (left != 0 && right.evaluate(mcu) != 0) as i64,
+                    "||" => return (left != 0 || right.evaluate(mcu) != 0) as i64,
+                    _ => (),
+                }
+
+                let right = right.evaluate(mcu);
+                match operator.as_str() {
+                    "|" => left | right,
+                    "^" => left ^ right,
+                    "&" => left & right,
+                    "==" => (left == right) as i64,
+                    "!=" => (left != right) as i64,
+                    "<" => (left < right) as i64,
+                    "<=" => (left <= right) as i64,
+                    ">" => (left > right) as i64,
+                    ">=" => (left >= right) as i64,
+                    "+" => left.wrapping_add(right),
+                    _ => left.wrapping_sub(right),
+                }
+            }
+        };
+    }
+}
+
+impl MCS51 {
+    // Side effect free read for the debugger, None where nothing can be read
+    pub fn read_memory(&self, space: MCS51_MEMORY_SPACE, address: u16) -> Option<u8> {
+        return match space {
+            MCS51_MEMORY_SPACE::DIRECT => Some(self.read_raw(address as u8)),
+            MCS51_MEMORY_SPACE::INDIRECT => self.read_indirect(address as u8).cloned(),
+            MCS51_MEMORY_SPACE::XDATA => self.xdata.peek(address),
+            MCS51_MEMORY_SPACE::CODE => self.fetch_code(address),
+            MCS51_MEMORY_SPACE::BIT => Some(self.read_bit(address as u8) as u8),
+        };
+    }
+}
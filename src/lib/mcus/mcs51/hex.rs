@@ -0,0 +1,109 @@
+use super::*;
+
+/*
+Intel HEX images
+
+Each line is a record: a colon, the byte count, a 16 bit address, the record type, the data bytes
+and a checksum that makes the sum of all the bytes of the record 0 (modulo 256), all written as
+hexadecimal digits.
+
+00 Data
+01 End of file
+02 Extended segment address, the data times 16 is added to the following addresses
+03 Start segment address (CS:IP), ignored, the 8051 always starts at 0000H
+04 Extended linear address, the upper 16 bits of the following addresses
+05 Start linear address, ignored
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MCS51_Hex_Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+fn parse_hex_record(line: &str) -> Result<Vec<u8>, String> {
+    let digits = line
+        .strip_prefix(':')
+        .ok_or_else(|| "Record does not start with :".to_owned())?;
+    if digits.len() % 2 != 0 || digits.len() < 10 {
+        return Err("Record is too short".to_owned());
+    }
+
+    let bytes = (0..digits.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&digits[index..index + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| "Invalid hexadecimal digit".to_owned())?;
+
+    if bytes.len() != bytes[0] as usize + 5 {
+        return Err("Byte count does not match the record length".to_owned());
+    }
+    if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+        return Err("Checksum mismatch".to_owned());
+    }
+    return Ok(bytes);
+}
+
+// The data records, contiguous records are merged into one segment
+pub fn parse_intel_hex(text: &str) -> Result<Vec<MCS51_Hex_Segment>, String> {
+    let mut segments: Vec<MCS51_Hex_Segment> = Vec::new();
+    let mut base: u32 = 0;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let record = parse_hex_record(line).map_err(|err| format!("Line {}: {}", number + 1, err))?;
+        let data = &record[4..record.len() - 1];
+        let offset = ((record[1] as u32) << 8) | record[2] as u32;
+
+        match record[3] {
+            0x00 => {
+                let address = base + offset;
+                match segments.last_mut() {
+                    Some(last) if last.address + last.data.len() as u32 == address => {
+                        last.data.extend_from_slice(data)
+                    }
+                    _ => segments.push(MCS51_Hex_Segment {
+                        address,
+                        data: data.to_vec(),
+                    }),
+                }
+            }
+            0x01 => return Ok(segments),
+            0x02 if data.len() == 2 => base = (((data[0] as u32) << 8) | data[1] as u32) << 4,
+            0x04 if data.len() == 2 => base = (((data[0] as u32) << 8) | data[1] as u32) << 16,
+            0x03 | 0x05 => (),
+            kind => return Err(format!("Line {}: invalid record type {:02X}", number + 1, kind)),
+        }
+    }
+
+    return Err("Missing end of file record".to_owned());
+}
+
+impl MCS51 {
+    /*
+    Replaces the code memory with the data records of an Intel HEX image, the locations it does
+    not list stay unprogrammed. Returns the number of bytes loaded.
+    */
+    pub fn load_intel_hex(&mut self, text: &str) -> Result<usize, String> {
+        let segments = parse_intel_hex(text)?;
+        if let Some(segment) = segments
+            .iter()
+            .find(|segment| segment.address as usize + segment.data.len() > MCS51_CODE_SIZE)
+        {
+            return Err(format!(
+                "Data at {:X} does not fit in code memory",
+                segment.address
+            ));
+        }
+
+        self.code.clear();
+        for segment in &segments {
+            self.code.load(segment.address as u16, &segment.data)?;
+        }
+        return Ok(segments.iter().map(|segment| segment.data.len()).sum());
+    }
+}
@@ -0,0 +1,201 @@
+use super::*;
+
+/*
+Interrupt Structure
+
+The 8051 provides 5 interrupt sources (6 on the 8052): two external interrupts, two timer
+interrupts and the serial port interrupt, plus the Timer 2 interrupt on 8052 parts.
+
+Each source can be individually enabled or disabled by setting or clearing a bit in IE. IE also
+contains a global disable bit, EA, which disables all interrupts at once.
+
+Each source can also be individually programmed to one of two priority levels by setting or
+clearing a bit in IP. A low-priority interrupt can itself be interrupted by a high-priority
+interrupt, but not by another low-priority interrupt. A high-priority interrupt can't be
+interrupted by any other interrupt source. If two requests of different priority levels are
+received simultaneously, the request of higher priority level is serviced. If requests of the
+same priority level are received simultaneously, an internal polling sequence determines which
+request is serviced (IE0, TF0, IE1, TF1, RI + TI, TF2 + EXF2).
+
+The interrupt flags are sampled at each instruction boundary. The hardware generated LCALL is
+blocked when:
+    1. An interrupt of equal or higher priority level is already in progress.
+    2. The instruction in progress is RETI or any write to the IE or IP registers.
+       At least one more instruction is executed before any interrupt is vectored to.
+
+The hardware clears the flags of the timer 0/1 interrupts and of the edge triggered external
+interrupts when vectoring. RI, TI, TF2 and EXF2 have to be cleared by software.
+*/
+
+pub const MCS51_IE_EA: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MCS51_INTERRUPT {
+    EXTERNAL0 = 0,
+    TIMER0,
+    EXTERNAL1,
+    TIMER1,
+    SERIAL,
+    TIMER2,
+}
+
+// Sorted by the internal polling sequence
+pub const MCS51_INTERRUPTS: [MCS51_INTERRUPT; 6] = [
+    MCS51_INTERRUPT::EXTERNAL0,
+    MCS51_INTERRUPT::TIMER0,
+    MCS51_INTERRUPT::EXTERNAL1,
+    MCS51_INTERRUPT::TIMER1,
+    MCS51_INTERRUPT::SERIAL,
+    MCS51_INTERRUPT::TIMER2,
+];
+
+impl MCS51_INTERRUPT {
+    pub fn vector(&self) -> u16 {
+        return 0x03 + (*self as u16) * 0x08;
+    }
+
+    // Bit in both IE and IP
+    pub fn mask(&self) -> u8 {
+        return 1 << (*self as u8);
+    }
+}
+
+impl MCS51 {
+    pub fn get_interrupt_flag(&self, interrupt: MCS51_INTERRUPT) -> bool {
+        let tcon = self.read_sfr(MCS51_REGISTERS::TCON);
+        match interrupt {
+            MCS51_INTERRUPT::EXTERNAL0 => tcon & 0x02 != 0,
+            MCS51_INTERRUPT::TIMER0 => tcon & 0x20 != 0,
+            MCS51_INTERRUPT::EXTERNAL1 => tcon & 0x08 != 0,
+            MCS51_INTERRUPT::TIMER1 => tcon & 0x80 != 0,
+            MCS51_INTERRUPT::SERIAL => self.read_sfr(MCS51_REGISTERS::SCON) & 0x03 != 0,
+            MCS51_INTERRUPT::TIMER2 => self.read_sfr(MCS51_REGISTERS::T2CON) & 0xC0 != 0,
+        }
+    }
+
+    // Flags cleared by the hardware when the interrupt is vectored
+    fn acknowledge_interrupt(&mut self, interrupt: MCS51_INTERRUPT) {
+        let tcon = self.read_sfr(MCS51_REGISTERS::TCON);
+        match interrupt {
+            MCS51_INTERRUPT::EXTERNAL0 if tcon & 0x01 != 0 => {
+                self.write_sfr(MCS51_REGISTERS::TCON, tcon & !0x02)
+            }
+            MCS51_INTERRUPT::TIMER0 => self.write_sfr(MCS51_REGISTERS::TCON, tcon & !0x20),
+            MCS51_INTERRUPT::EXTERNAL1 if tcon & 0x04 != 0 => {
+                self.write_sfr(MCS51_REGISTERS::TCON, tcon & !0x08)
+            }
+            MCS51_INTERRUPT::TIMER1 => self.write_sfr(MCS51_REGISTERS::TCON, tcon & !0x80),
+            _ => (),
+        }
+    }
+
+    pub fn get_int0_pin(&self) -> bool {
+        return self.get_port_pins(3) & 0x04 != 0;
+    }
+
+    pub fn get_int1_pin(&self) -> bool {
+        return self.get_port_pins(3) & 0x08 != 0;
+    }
+
+    /*
+    INT0/INT1 are sampled once per instruction. With ITx set, a high-to-low transition sets IEx,
+    otherwise IEx follows the (inverted) pin level and is not latched.
+    */
+    pub fn sample_external_interrupts(&mut self) {
+        let pins = [self.get_int0_pin(), self.get_int1_pin()];
+        let mut tcon = self.read_sfr(MCS51_REGISTERS::TCON);
+
+        for i in 0..2 {
+            let it_mask = 0x01 << (i * 2);
+            let ie_mask = 0x02 << (i * 2);
+
+            if tcon & it_mask != 0 {
+                if self.interrupt_pins[i] && !pins[i] {
+                    tcon |= ie_mask;
+                }
+            } else if pins[i] {
+                tcon &= !ie_mask;
+            } else {
+                tcon |= ie_mask;
+            }
+
+            self.interrupt_pins[i] = pins[i];
+        }
+
+        self.write_sfr(MCS51_REGISTERS::TCON, tcon);
+    }
+
+    pub fn get_pending_interrupt(&self) -> Option<(MCS51_INTERRUPT, bool)> {
+        let ie = self.read_sfr(MCS51_REGISTERS::IE);
+        if ie & MCS51_IE_EA == 0 || self.interrupt_in_progress[1] {
+            return None;
+        }
+
+        let ip = self.read_sfr(MCS51_REGISTERS::IP);
+        let mut low_priority = None;
+
+        for interrupt in MCS51_INTERRUPTS.iter() {
+            if ie & interrupt.mask() == 0 || !self.get_interrupt_flag(*interrupt) {
+                continue;
+            }
+
+            if ip & interrupt.mask() != 0 {
+                return Some((*interrupt, true));
+            } else if low_priority.is_none() {
+                low_priority = Some((*interrupt, false));
+            }
+        }
+
+        if self.interrupt_in_progress[0] {
+            return None;
+        }
+
+        return low_priority;
+    }
+
+    /*
+    Called at every instruction boundary, performs the hardware LCALL to the interrupt vector if
+    a request is allowed to be serviced. Returns true when the instruction slot was used by the
+    interrupt call.
+    */
+    pub fn poll_interrupts(&mut self) -> bool {
+        self.sample_external_interrupts();
+
+        if self.interrupt_blocked {
+            self.interrupt_blocked = false;
+            return false;
+        }
+
+        let (interrupt, high_priority) = match self.get_pending_interrupt() {
+            Some(pending) => pending,
+            None => return false,
+        };
+
+        self.acknowledge_interrupt(interrupt);
+        self.interrupt_in_progress[high_priority as usize] = true;
+
+        self.push_stack((self.pc & 0xFF) as u8);
+        self.push_stack(((self.pc >> 8) & 0xFF) as u8);
+        self.pc = interrupt.vector();
+        // The hardware generated LCALL takes as long as the instruction
+        self.additional_cycles = MCS51::get_instruction_cycles(0x12) - 1;
+
+        return true;
+    }
+
+    // RETI restores the interrupt logic to accept interrupts at the level of the ISR that ends
+    pub fn end_interrupt(&mut self) {
+        if self.interrupt_in_progress[1] {
+            self.interrupt_in_progress[1] = false;
+        } else {
+            self.interrupt_in_progress[0] = false;
+        }
+        self.interrupt_blocked = true;
+    }
+
+    pub fn reset_interrupts(&mut self) {
+        self.interrupt_in_progress = [false; 2];
+        self.interrupt_blocked = false;
+        self.interrupt_pins = [true; 2];
+    }
+}
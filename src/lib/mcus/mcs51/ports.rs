@@ -0,0 +1,84 @@
+use super::*;
+
+/*
+I/O Ports
+
+Each of the four ports consists of a latch (the SFR P0-P3), an output driver and an input buffer.
+Ports 1, 2 and 3 are quasi-bidirectional: a 1 in the latch only turns on a weak internal pull-up,
+so the pin can be pulled low by an external device and then read as an input. A 0 in the latch
+drives the pin low. The pin level is therefore the wired-AND of the latch and of every external
+driver. Port 0 has open drain outputs and is modeled the same way, as if external pull-ups were
+fitted.
+
+Instructions that read a port read the pins, except the read-modify-write instructions (ANL, ORL,
+XRL, JBC, CPL, INC, DEC, DJNZ, MOV PX.Y,C, CLR PX.Y and SETB PX.Y) which read the latch, so that a
+pin held low externally isn't written back as 0 into its latch.
+
+The external drivers are set through the IOComponent trait, a 0 bit pulls the pin low and a 1 bit
+releases it. Pins are numbered port * 8 + bit, P0.0 being pin 0 and P3.7 pin 31.
+*/
+
+pub const MCS51_PORT_COUNT: usize = 4;
+
+const MCS51_PORT_REGISTERS: [MCS51_REGISTERS; MCS51_PORT_COUNT] = [
+    MCS51_REGISTERS::P0,
+    MCS51_REGISTERS::P1,
+    MCS51_REGISTERS::P2,
+    MCS51_REGISTERS::P3,
+];
+
+impl MCS51 {
+    pub fn get_port_latch(&self, port: usize) -> u8 {
+        return self.read_sfr(MCS51_PORT_REGISTERS[port]);
+    }
+
+    pub fn get_port_pins(&self, port: usize) -> u8 {
+        return self.port_pins[port];
+    }
+
+    // Recomputes the pin levels after a latch or an external driver changed
+    pub fn update_port_pins(&mut self) {
+        for port in 0..MCS51_PORT_COUNT {
+            self.port_pins[port] = self.get_port_latch(port) & self.port_drivers[port];
+        }
+    }
+}
+
+impl IOComponent for MCS51 {
+    fn get_pin(&self, pin: usize) -> bool {
+        return self.get_port_pins(pin / 8) & (1 << (pin % 8)) != 0;
+    }
+
+    fn set_pin(&mut self, pin: usize, val: bool) {
+        let mask = 1 << (pin % 8);
+        let driver = &mut self.port_drivers[pin / 8];
+        if val {
+            *driver |= mask;
+        } else {
+            *driver &= !mask;
+        }
+        self.update_port_pins();
+    }
+
+    fn get_port_u8(&self, port: usize) -> u8 {
+        return self.get_port_pins(port);
+    }
+
+    fn set_port_u8(&mut self, port: usize, val: u8) {
+        self.port_drivers[port] = val;
+        self.update_port_pins();
+    }
+
+    // Port pairs, P0 (low byte) and P1 for port 0, P2 and P3 for port 1
+    fn get_port_u16(&self, port: usize) -> u16 {
+        let low = self.get_port_pins(port * 2) as u16;
+        let high = self.get_port_pins(port * 2 + 1) as u16;
+        return (high << 8) | low;
+    }
+
+    fn set_port_u16(&mut self, port: usize, val: u16) {
+        self.port_drivers[port * 2] = val as u8;
+        self.port_drivers[port * 2 + 1] = (val >> 8) as u8;
+        self.update_port_pins();
+    }
+}
@@ -0,0 +1,116 @@
+use super::*;
+
+/*
+Power Saving Modes
+
+Idle Mode: An instruction that sets PCON.0 causes that to be the last instruction executed before
+going into the Idle mode. In the Idle mode, the internal clock signal is gated off to the CPU, but
+not to the Interrupt, Timer, and Serial Port functions. The CPU status is preserved in its
+entirety: the Stack Pointer, Program Counter, Program Status Word, Accumulator, and all other
+registers maintain their data during Idle.
+
+There are two ways to terminate the Idle. Activation of any enabled interrupt will cause PCON.0 to
+be cleared by hardware, terminating the Idle mode. The interrupt will be serviced, and following
+RETI the next instruction to be executed will be the one following the instruction that put the
+device into Idle. The other way of terminating the Idle mode is with a hardware reset.
+
+Power Down Mode: An instruction that sets PCON.1 causes that to be the last instruction executed
+before going into the Power Down mode. In the Power Down mode, the on-chip oscillator is stopped.
+With the clock frozen, all functions are stopped, but the on-chip RAM and Special Function
+Registers are held. The only exit from Power Down is a hardware reset, except on the parts that
+also wake up on an enabled level activated external interrupt (INT0, INT1), which restarts the
+oscillator and clears PD. If both PD and IDL are set, PD takes precedence.
+
+The machine cycles spent in each state are counted, cycle_count keeps counting the emulated time.
+*/
+
+pub const MCS51_PCON_GF1: u8 = 0x08;
+pub const MCS51_PCON_GF0: u8 = 0x04;
+pub const MCS51_PCON_PD: u8 = 0x02;
+pub const MCS51_PCON_IDL: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MCS51_POWER_STATE {
+    ACTIVE,
+    IDLE,
+    POWER_DOWN,
+}
+
+impl MCS51 {
+    pub fn get_power_state(&self) -> MCS51_POWER_STATE {
+        let pcon = self.read_sfr(MCS51_REGISTERS::PCON);
+        if pcon & MCS51_PCON_PD != 0 {
+            return MCS51_POWER_STATE::POWER_DOWN;
+        }
+        if pcon & MCS51_PCON_IDL != 0 {
+            return MCS51_POWER_STATE::IDLE;
+        }
+        return MCS51_POWER_STATE::ACTIVE;
+    }
+
+    fn clear_pcon_flags(&mut self, flags: u8) {
+        let pcon = self.read_sfr(MCS51_REGISTERS::PCON);
+        self.write_sfr(MCS51_REGISTERS::PCON, pcon & !flags);
+    }
+
+    // An enabled external interrupt in level mode with its pin held low
+    fn power_down_wakeup(&self) -> bool {
+        let ie = self.read_sfr(MCS51_REGISTERS::IE);
+        let tcon = self.read_sfr(MCS51_REGISTERS::TCON);
+        if !self.derivative.power_down_wakeup || ie & MCS51_IE_EA == 0 {
+            return false;
+        }
+
+        let int0 = ie & MCS51_INTERRUPT::EXTERNAL0.mask() != 0
+            && tcon & 0x01 == 0
+            && !self.get_int0_pin();
+        let int1 = ie & MCS51_INTERRUPT::EXTERNAL1.mask() != 0
+            && tcon & 0x04 == 0
+            && !self.get_int1_pin();
+        return int0 || int1;
+    }
+
+    /*
+    Returns true while the oscillator is stopped and nothing else runs this machine cycle, the
+    instruction that set PD completes first
+    */
+    pub fn tick_power_down(&mut self) -> bool {
+        if self.additional_cycles > 0 || self.get_power_state() != MCS51_POWER_STATE::POWER_DOWN {
+            return false;
+        }
+
+        if self.power_down_wakeup() {
+            self.clear_pcon_flags(MCS51_PCON_PD | MCS51_PCON_IDL);
+            return false;
+        }
+
+        self.power_down_cycles += 1;
+        return true;
+    }
+
+    // Instruction slot of an idle CPU, only an interrupt call can end it
+    pub fn tick_idle(&mut self) {
+        self.idle_cycles += 1;
+
+        if self.poll_interrupts() {
+            self.clear_pcon_flags(MCS51_PCON_IDL);
+        }
+    }
+
+    pub fn get_idle_cycles(&self) -> u64 {
+        return self.idle_cycles;
+    }
+
+    pub fn get_power_down_cycles(&self) -> u64 {
+        return self.power_down_cycles;
+    }
+
+    pub fn get_active_cycles(&self) -> u64 {
+        return self.cycle_count - self.idle_cycles - self.power_down_cycles;
+    }
+
+    pub fn reset_power_state(&mut self) {
+        self.idle_cycles = 0;
+        self.power_down_cycles = 0;
+    }
+}
@@ -0,0 +1,105 @@
+use super::*;
+
+/*
+Reset
+
+The reset input is the RST pin, which is the input to a Schmitt Trigger. A reset is accomplished
+by holding the RST pin high for at least two machine cycles (24 oscillator periods), while the
+oscillator is running. The CPU responds by executing an internal reset. It also configures the
+ALE and PSEN pins as inputs. The internal reset is executed during the second cycle in which RST
+is high and is repeated every cycle until RST goes low. The CPU does not run while RST is high.
+
+The internal reset algorithm writes 0s to all the SFRs except the port latches, the Stack
+Pointer, and SBUF. The port latches are initialized to FFH, the Stack Pointer to 07H, and SBUF
+is indeterminate. The internal RAM is not affected by reset. On power up the RAM content is
+indeterminate.
+
+power_on() models the cold start: the internal RAM and the XDATA RAM regions are cleared, or
+filled with pseudo random values when a power-up seed is set, and the cycle counters restart.
+reset() is a warm reset by the RST pin and keeps the memories and the emulated time.
+*/
+
+pub const MCS51_RESET_PIN_CYCLES: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MCS51_RESET_SOURCE {
+    POWER_ON,
+    PIN,
+    WATCHDOG,
+}
+
+// xorshift64*, good enough to make uninitialized memory look uninitialized
+pub struct MCS51_Random {
+    state: u64,
+}
+
+impl MCS51_Random {
+    pub fn new(seed: u64) -> MCS51_Random {
+        MCS51_Random {
+            state: seed ^ 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        return (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8;
+    }
+}
+
+impl MCS51 {
+    // None clears the memories on power up, Some fills them with values derived from the seed
+    pub fn set_power_up_seed(&mut self, seed: Option<u64>) {
+        self.power_up_seed = seed;
+    }
+
+    pub fn power_on(&mut self) {
+        match self.power_up_seed {
+            Some(seed) => {
+                let mut random = MCS51_Random::new(seed);
+                for byte in self.ram.iter_mut() {
+                    *byte = random.next_u8();
+                }
+                self.xdata.fill_ram(|| random.next_u8());
+            }
+            None => {
+                self.ram = [0; 256];
+                self.xdata.fill_ram(|| 0);
+            }
+        }
+
+        self.cycle_count = 0;
+        self.reset_power_state();
+        self.watchdog.timeouts = 0;
+        self.rst_cycles = 0;
+        self.reset();
+        self.reset_source = MCS51_RESET_SOURCE::POWER_ON;
+    }
+
+    pub fn set_rst_pin(&mut self, value: bool) {
+        self.rst_pin = value;
+    }
+
+    pub fn get_rst_pin(&self) -> bool {
+        return self.rst_pin;
+    }
+
+    pub fn get_reset_source(&self) -> MCS51_RESET_SOURCE {
+        return self.reset_source;
+    }
+
+    // Returns true while RST holds the chip in reset
+    pub fn tick_reset_pin(&mut self) -> bool {
+        if !self.rst_pin {
+            self.rst_cycles = 0;
+            return false;
+        }
+
+        self.rst_cycles = self.rst_cycles.saturating_add(1);
+        if self.rst_cycles >= MCS51_RESET_PIN_CYCLES {
+            self.reset();
+        }
+        return true;
+    }
+}
@@ -0,0 +1,104 @@
+use super::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/*
+Run control
+
+run_for and run_until execute whole instructions and give control back with the reason they
+stopped. Before every instruction they look for a stop requested from outside, the PC on an
+unprogrammed location, an unimplemented SFR stop and the reserved opcode A5H. After every
+instruction they look for a breakpoint or watchpoint stop (the breakpoint on the instruction they
+start from is passed, so a run can continue from a breakpoint), a watchdog reset, the CPU
+entering Idle or Power Down, and the budget or the predicate. A run started in a sleep mode keeps clocking until the chip is
+awake again.
+*/
+
+pub const MCS51_RESERVED_OPCODE: u8 = 0xA5;
+
+impl MCS51 {
+    // A handle that other threads can set to stop the running core
+    pub fn get_stop_handle(&self) -> Arc<AtomicBool> {
+        return self.stop_request.clone();
+    }
+
+    fn get_stop_reason_before(&mut self) -> Option<StopReason<u8>> {
+        if self.stop_request.swap(false, Ordering::Relaxed) {
+            return Some(StopReason::EXTERNAL_REQUEST);
+        }
+        if let Some(pc) = self.code_trap {
+            return Some(StopReason::PC_OUT_OF_CODE(pc as u32));
+        }
+        if let Some(error) = self.sfr_error {
+            return Some(StopReason::UNIMPLEMENTED_ACCESS {
+                pc: error.pc as u32,
+                address: error.address as u32,
+            });
+        }
+        if self.get_power_state() != MCS51_POWER_STATE::ACTIVE {
+            return None;
+        }
+
+        let pc = self.pc;
+        let opcode = match self.fetch_code(pc) {
+            Some(opcode) => opcode,
+            None => return Some(StopReason::PC_OUT_OF_CODE(pc as u32)),
+        };
+        if opcode == MCS51_RESERVED_OPCODE {
+            return Some(StopReason::INVALID_OPCODE {
+                pc: pc as u32,
+                opcode,
+            });
+        }
+        return None;
+    }
+
+    pub fn run_control<F: FnMut(&MCS51) -> bool>(
+        &mut self,
+        budget: Option<u64>,
+        mut predicate: F,
+    ) -> StopReason<u8> {
+        let timeouts = self.watchdog.timeouts;
+        let mut sleeping = self.get_power_state() != MCS51_POWER_STATE::ACTIVE;
+        let mut elapsed: u64 = 0;
+        self.debugger.event = None;
+        self.debugger.resume = Some(self.pc);
+
+        loop {
+            if let Some(reason) = self.get_stop_reason_before() {
+                return reason;
+            }
+
+            let cycles = self.cycle_count;
+            self.step();
+            elapsed += self.cycle_count.wrapping_sub(cycles);
+
+            if let Some(event) = self.debugger.event.take() {
+                self.debugger.last_event = Some(event);
+                return match event {
+                    MCS51_DEBUG_EVENT::BREAKPOINT { pc, .. } => StopReason::BREAKPOINT(pc as u32),
+                    MCS51_DEBUG_EVENT::WATCHPOINT { pc, address, .. } => StopReason::WATCHPOINT {
+                        pc: pc as u32,
+                        address: address as u32,
+                    },
+                };
+            }
+            if self.watchdog.timeouts != timeouts {
+                return StopReason::WATCHDOG_RESET;
+            }
+
+            let asleep = self.get_power_state() != MCS51_POWER_STATE::ACTIVE;
+            if asleep && !sleeping {
+                return StopReason::SLEEP;
+            }
+            sleeping = asleep;
+
+            if budget.map_or(false, |budget| elapsed >= budget) {
+                return StopReason::BUDGET;
+            }
+            if predicate(self) {
+                return StopReason::CONDITION;
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use super::*;
+use std::collections::VecDeque;
+
+/*
+Serial Interface
+
+The serial port is full duplex, meaning it can transmit and receive simultaneously. It is also
+receive-buffered, meaning it can commence reception of a second byte before a previously
+received byte has been read from the receive register. The serial port receive and transmit
+registers are both accessed at Special Function Register SBUF. Writing to SBUF loads the
+transmit register, and reading SBUF accesses a physically separate receive register.
+
+Mode 0: Serial data enters and exits through RXD. TXD outputs the shift clock. 8 bits are
+        transmitted/received (LSB first). The baud rate is fixed at 1/12 the oscillator frequency.
+Mode 1: 10 bits are transmitted (through TXD) or received (through RXD): a start bit (0),
+        8 data bits (LSB first), and a stop bit (1). On receive, the stop bit goes into RB8.
+        The baud rate is variable.
+Mode 2: 11 bits are transmitted or received: a start bit (0), 8 data bits (LSB first), a
+        programmable 9th data bit, and a stop bit (1). On Transmit, the 9th data bit (TB8) can
+        be assigned the value of 0 or 1. On receive, the 9th data bit goes into RB8. The baud
+        rate is programmable to either 1/32 or 1/64 the oscillator frequency.
+Mode 3: Same as mode 2 with a variable baud rate.
+
+In modes 1 and 3 the baud rate is (2^SMOD / 32) x Timer 1 overflow rate, or Timer 2 overflow
+rate / 16 when RCLK/TCLK select Timer 2 for the receiver/transmitter.
+
+TI is set at the end of the 8th bit time in mode 0, and at the beginning of the stop bit in
+the other modes. RI is set at the end of the 8th bit time in mode 0, and halfway through the
+stop bit in the other modes, provided that RI is cleared and, in modes 2 and 3, that SM2 is
+cleared or the received 9th bit is set (in mode 1, that SM2 is cleared or a valid stop bit
+was received). Otherwise the received byte is lost.
+
+On the host side, bytes to feed RXD are queued in `input` and the frames shifted out on TXD are
+collected in `output`. In 9-bit modes bit 8 of an entry holds the 9th data bit. The frame whose
+transmission started during the last machine cycle is also kept in `line` for a serial bus.
+Received bytes lost because RI was still set are counted in `overruns`.
+*/
+
+pub const MCS51_SCON_SM0: u8 = 0x80;
+pub const MCS51_SCON_SM1: u8 = 0x40;
+pub const MCS51_SCON_SM2: u8 = 0x20;
+pub const MCS51_SCON_REN: u8 = 0x10;
+pub const MCS51_SCON_TB8: u8 = 0x08;
+pub const MCS51_SCON_RB8: u8 = 0x04;
+pub const MCS51_SCON_TI: u8 = 0x02;
+pub const MCS51_SCON_RI: u8 = 0x01;
+
+pub const MCS51_PCON_SMOD: u8 = 0x80;
+
+pub struct MCS51_Serial {
+    pub input: VecDeque<u16>,
+    pub output: VecDeque<u16>,
+    tx_data: u16,
+    tx_bits: u8,
+    tx_divider: u16,
+    rx_data: u16,
+    rx_bits: u8,
+    rx_divider: u16,
+    pub line: Option<u16>,
+    pub overruns: u64,
+}
+
+impl MCS51_Serial {
+    pub fn new() -> MCS51_Serial {
+        MCS51_Serial {
+            input: VecDeque::new(),
+            output: VecDeque::new(),
+            tx_data: 0,
+            tx_bits: 0,
+            tx_divider: 0,
+            rx_data: 0,
+            rx_bits: 0,
+            rx_divider: 0,
+            line: None,
+            overruns: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.tx_bits = 0;
+        self.tx_divider = 0;
+        self.rx_bits = 0;
+        self.rx_divider = 0;
+        self.line = None;
+    }
+
+    pub fn is_transmitting(&self) -> bool {
+        return self.tx_bits != 0;
+    }
+
+    pub fn is_receiving(&self) -> bool {
+        return self.rx_bits != 0;
+    }
+}
+
+impl MCS51 {
+    pub fn get_serial_mode(&self) -> u8 {
+        return self.read_sfr(MCS51_REGISTERS::SCON) >> 6;
+    }
+
+    // Queues a byte on RXD, the 9th bit is 1 (stop bit or address marker)
+    pub fn serial_input(&mut self, value: u8) {
+        self.serial.input.push_back(0x100 | value as u16);
+    }
+
+    pub fn serial_input_9bit(&mut self, value: u8, bit8: bool) {
+        self.serial.input.push_back(((bit8 as u16) << 8) | value as u16);
+    }
+
+    pub fn serial_output(&mut self) -> Vec<u8> {
+        return self.serial.output.drain(..).map(|v| v as u8).collect();
+    }
+
+    // Writing SBUF starts a transmission
+    pub fn serial_transmit(&mut self, value: u8) {
+        let scon = self.read_sfr(MCS51_REGISTERS::SCON);
+        let tb8 = ((scon & MCS51_SCON_TB8) != 0) as u16;
+
+        self.serial.tx_data = (tb8 << 8) | value as u16;
+        self.serial.tx_divider = 0;
+        self.serial.tx_bits = match scon >> 6 {
+            0 => 8,
+            1 => 9,
+            _ => 10,
+        };
+
+        // Mode 0 is a shift register, the other modes send asynchronous frames on the line
+        self.serial.line = match scon >> 6 {
+            0 => None,
+            1 => Some(0x100 | value as u16),
+            _ => Some(self.serial.tx_data),
+        };
+    }
+
+    // Reception is initiated by REN = 1, and also RI = 0 in mode 0
+    pub fn is_serial_receiver_ready(&self) -> bool {
+        let scon = self.read_sfr(MCS51_REGISTERS::SCON);
+        return scon & MCS51_SCON_REN != 0 && (scon >> 6 != 0 || scon & MCS51_SCON_RI == 0);
+    }
+
+    fn start_reception(&mut self, data: u16) {
+        self.serial.rx_data = data;
+        self.serial.rx_divider = 0;
+        // Half bit times in the asynchronous modes, RI is set halfway through the stop bit
+        self.serial.rx_bits = match self.get_serial_mode() {
+            0 => 8,
+            1 => 20,
+            _ => 22,
+        };
+    }
+
+    /*
+    A frame starting on RXD, the receiver samples it at its own baud rate. Returns false when the
+    frame is lost because the receiver is disabled, in mode 0, or already busy with another frame.
+    */
+    pub fn serial_line_input(&mut self, frame: u16) -> bool {
+        if self.get_serial_mode() == 0 || self.serial.is_receiving() || !self.is_serial_receiver_ready() {
+            return false;
+        }
+
+        self.start_reception(frame);
+        return true;
+    }
+
+    /*
+    Number of bit times elapsed this machine cycle, using the divider of the TX or RX side. The
+    receiver samples in half bit times in modes 1, 2 and 3.
+    */
+    fn serial_bit_clock(&mut self, transmit: bool, timer1_overflow: bool, timer2_overflows: u8) -> u8 {
+        let mode = self.get_serial_mode();
+        let smod = self.read_sfr(MCS51_REGISTERS::PCON) & MCS51_PCON_SMOD != 0;
+        let t2con = self.read_sfr(MCS51_REGISTERS::T2CON);
+        let use_timer2 = if transmit {
+            t2con & MCS51_T2CON_TCLK != 0
+        } else {
+            t2con & MCS51_T2CON_RCLK != 0
+        };
+
+        let (increment, threshold) = match mode {
+            0 => return 1,
+            2 => (self.get_states_per_cycle() as u16, if smod { 16 } else { 32 }),
+            _ if use_timer2 => (timer2_overflows as u16, 16),
+            _ => (timer1_overflow as u16, if smod { 16 } else { 32 }),
+        };
+        let threshold = if transmit { threshold } else { threshold / 2 };
+
+        let divider = if transmit {
+            &mut self.serial.tx_divider
+        } else {
+            &mut self.serial.rx_divider
+        };
+
+        *divider += increment;
+        let mut bits = 0;
+        while *divider >= threshold {
+            *divider -= threshold;
+            bits += 1;
+        }
+
+        return bits;
+    }
+
+    fn serial_receive_complete(&mut self) {
+        let scon = self.read_sfr(MCS51_REGISTERS::SCON);
+        let mode = scon >> 6;
+        let data = self.serial.rx_data;
+        let bit8 = data & 0x100 != 0;
+
+        let accepted = scon & MCS51_SCON_RI == 0
+            && (mode == 0 || scon & MCS51_SCON_SM2 == 0 || bit8);
+
+        if accepted {
+            self.write_sfr(MCS51_REGISTERS::SBUF, data as u8);
+
+            let mut scon = scon | MCS51_SCON_RI;
+            if mode != 0 {
+                scon = (scon & !MCS51_SCON_RB8) | ((bit8 as u8) << 2);
+            }
+            self.write_sfr(MCS51_REGISTERS::SCON, scon);
+        } else if scon & MCS51_SCON_RI != 0 {
+            // The previous byte has not been read yet
+            self.serial.overruns += 1;
+        }
+    }
+
+    /*
+    Advances the serial port by one machine cycle. Timer overflows are the ones that happened
+    during the same machine cycle and clock the port in modes 1 and 3.
+    */
+    pub fn tick_serial(&mut self, timer1_overflow: bool, timer2_overflows: u8) {
+        if self.serial.is_transmitting() {
+            let bits = self.serial_bit_clock(true, timer1_overflow, timer2_overflows);
+            self.serial.tx_bits = self.serial.tx_bits.saturating_sub(bits);
+
+            if self.serial.tx_bits == 0 {
+                let mode = self.get_serial_mode();
+                let data = self.serial.tx_data;
+                let value = if mode >= 2 { data } else { data & 0xFF };
+
+                self.serial.output.push_back(value);
+                let scon = self.read_sfr(MCS51_REGISTERS::SCON);
+                self.write_sfr(MCS51_REGISTERS::SCON, scon | MCS51_SCON_TI);
+            }
+        }
+
+        if !self.serial.is_receiving() {
+            if !self.is_serial_receiver_ready() {
+                return;
+            }
+
+            let data = match self.serial.input.pop_front() {
+                Some(data) => data,
+                None => return,
+            };
+
+            self.start_reception(data);
+        }
+
+        /*
+        The byte is received at the end of the 8th bit in mode 0 and halfway through the stop bit
+        in the other modes. The receiver stays busy until the end of the stop bit.
+        */
+        let remaining = self.serial.rx_bits;
+        let received = if self.get_serial_mode() == 0 { 0 } else { 1 };
+        let bits = self.serial_bit_clock(false, timer1_overflow, timer2_overflows);
+        self.serial.rx_bits = remaining.saturating_sub(bits);
+
+        if remaining > received && self.serial.rx_bits <= received {
+            self.serial_receive_complete();
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use super::*;
+use std::time::Duration;
+
+/*
+Serial Bus
+
+Multiprocessor Communications: Modes 2 and 3 have a special provision for multiprocessor
+communications. In these modes, 9 data bits are received. The 9th one goes into RB8. Then comes
+a stop bit. The port can be programmed such that when the stop bit is received, the serial port
+interrupt will be activated only if RB8 = 1. This feature is enabled by setting bit SM2 in SCON.
+
+When the master processor wants to transmit a block of data to one of several slaves, it first
+sends out an address byte which identifies the target slave. An address byte differs from a data
+byte in that the 9th bit is 1 in an address byte and 0 in a data byte. With SM2 = 1, no slave
+will be interrupted by a data byte. An address byte, however, will interrupt all slaves, so that
+each slave can examine the received byte and see if it is being addressed. The addressed slave
+will clear its SM2 bit and prepare to receive the data bytes that will be coming. The slaves
+that weren't being addressed leave their SM2s set and go on about their business, ignoring the
+coming data bytes.
+
+The bus joins the TXD and RXD lines of several nodes, like an RS-485 network. Every node keeps its
+own oscillator, the bus always clocks the node that is furthest behind in emulated time. A frame
+started by a node is seen by all the other nodes at that moment, each receiver samples it with
+its own baud rate. A receiver that is disabled or busy with another frame ignores it, and SM2
+filters data bytes as usual. `lost_frames` only counts overruns, frames received by a node whose
+RI was still set.
+*/
+
+pub struct MCS51_Serial_Bus {
+    pub nodes: Vec<MCS51>,
+    pub frames: u64,
+    pub lost_frames: u64,
+}
+
+impl MCS51_Serial_Bus {
+    pub fn new() -> MCS51_Serial_Bus {
+        MCS51_Serial_Bus {
+            nodes: Vec::new(),
+            frames: 0,
+            lost_frames: 0,
+        }
+    }
+
+    // Returns the index of the node on the bus
+    pub fn add_node(&mut self, node: MCS51) -> usize {
+        self.nodes.push(node);
+        return self.nodes.len() - 1;
+    }
+
+    pub fn node(&self, index: usize) -> &MCS51 {
+        return &self.nodes[index];
+    }
+
+    pub fn node_mut(&mut self, index: usize) -> &mut MCS51 {
+        return &mut self.nodes[index];
+    }
+
+    // Emulated time of the node that is furthest behind
+    pub fn get_elapsed_time(&self) -> Duration {
+        return self
+            .nodes
+            .iter()
+            .map(|node| node.get_elapsed_time())
+            .min()
+            .unwrap_or_default();
+    }
+
+    // Clocks the node that is furthest behind by one machine cycle and delivers its frame
+    pub fn clock(&mut self) {
+        let sender = match (0..self.nodes.len()).min_by_key(|&i| self.nodes[i].get_elapsed_time()) {
+            Some(sender) => sender,
+            None => return,
+        };
+
+        // Receptions complete while their node is clocked
+        let overruns = self.nodes[sender].serial.overruns;
+        self.nodes[sender].clock();
+        self.lost_frames += self.nodes[sender].serial.overruns - overruns;
+
+        let frame = match self.nodes[sender].serial.line.take() {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        self.frames += 1;
+        for (index, node) in self.nodes.iter_mut().enumerate() {
+            if index != sender {
+                node.serial_line_input(frame);
+            }
+        }
+    }
+
+    // Runs until every node reached the given emulated time
+    pub fn run_for(&mut self, duration: Duration) {
+        let end = self.get_elapsed_time() + duration;
+        while !self.nodes.is_empty() && self.get_elapsed_time() < end {
+            self.clock();
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use super::*;
+use std::fmt;
+
+/*
+Unimplemented SFRs
+
+Only the addresses listed for a derivative hold Special Function Registers. The rest of the SFR
+space is reserved: reads return an undefined value and writes have no effect, the datasheets warn
+that user software should not write 1s to these unlisted locations, since they may be used in
+future products to invoke new features.
+
+The emulator reads them as 0 and discards the writes. The same goes for the upper 128 bytes of
+RAM on the parts with 128 bytes, except that the floating bus reads FFH.
+
+The SFR policy decides what else happens when an instruction uses a direct or bit address that
+the derivative does not implement, or an @Ri address above its RAM: nothing, a record in the log,
+or a stop before the instruction executes with the error in sfr_error. Read-modify-write
+instructions are reported as writes.
+*/
+
+pub const MCS51_UNIMPLEMENTED_RAM_VALUE: u8 = 0xFF;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MCS51_SFR_POLICY {
+    IGNORE,
+    LOG,
+    STOP,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MCS51_SFR_ACCESS {
+    READ,
+    WRITE,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MCS51_SFR_Error {
+    pub pc: u16,
+    pub opcode: u8,
+    pub space: MCS51_MEMORY_SPACE,
+    pub address: u8,
+    pub access: MCS51_SFR_ACCESS,
+}
+
+impl fmt::Display for MCS51_SFR_Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = match self.access {
+            MCS51_SFR_ACCESS::READ => "read from",
+            MCS51_SFR_ACCESS::WRITE => "write to",
+        };
+        let location = match self.space {
+            MCS51_MEMORY_SPACE::INDIRECT => "RAM",
+            _ => "SFR",
+        };
+        return write!(
+            f,
+            "{:04X}: opcode {:02X} {} unimplemented {} {:02X}",
+            self.pc, self.opcode, access, location, self.address
+        );
+    }
+}
+
+// Operand bytes of an instruction that hold a direct address, with the access made through them
+fn get_direct_operands(opcode: u8) -> &'static [(u8, MCS51_SFR_ACCESS)] {
+    use self::MCS51_SFR_ACCESS::*;
+
+    match opcode {
+        0x05 | 0x15 | 0x42 | 0x43 | 0x52 | 0x53 | 0x62 | 0x63 | 0x75 | 0xC5 | 0xD0 | 0xD5
+        | 0xF5 => &[(1, WRITE)],
+        0x86..=0x8F => &[(1, WRITE)],
+        0x85 => &[(1, READ), (2, WRITE)],
+        0x25 | 0x35 | 0x45 | 0x55 | 0x65 | 0x95 | 0xB5 | 0xC0 | 0xE5 => &[(1, READ)],
+        0xA6..=0xAF => &[(1, READ)],
+        _ => &[],
+    }
+}
+
+// Operand byte of an instruction that holds a bit address
+fn get_bit_operand(opcode: u8) -> Option<(u8, MCS51_SFR_ACCESS)> {
+    match opcode {
+        0x10 | 0x92 | 0xB2 | 0xC2 | 0xD2 => Some((1, MCS51_SFR_ACCESS::WRITE)),
+        0x20 | 0x30 | 0x72 | 0x82 | 0xA0 | 0xA2 | 0xB0 => Some((1, MCS51_SFR_ACCESS::READ)),
+        _ => None,
+    }
+}
+
+// Register of the @Ri operand, column 6 uses R0 and column 7 R1, MOVX is in other columns
+fn get_indirect_operand(opcode: u8) -> Option<(u8, MCS51_SFR_ACCESS)> {
+    let register = match opcode & 0x0F {
+        0x06 => 0,
+        0x07 => 1,
+        _ => return None,
+    };
+
+    return match opcode >> 4 {
+        // INC, DEC, MOV @Ri,#data, MOV @Ri,direct, XCH, XCHD, MOV @Ri,A
+        0x0 | 0x1 | 0x7 | 0xA | 0xC | 0xD | 0xF => Some((register, MCS51_SFR_ACCESS::WRITE)),
+        _ => Some((register, MCS51_SFR_ACCESS::READ)),
+    };
+}
+
+impl MCS51 {
+    pub fn set_sfr_policy(&mut self, policy: MCS51_SFR_POLICY) {
+        self.sfr_policy = policy;
+    }
+
+    pub fn get_sfr_policy(&self) -> MCS51_SFR_POLICY {
+        return self.sfr_policy;
+    }
+
+    // The accesses recorded by the LOG policy, oldest first
+    pub fn get_sfr_log(&self) -> &[MCS51_SFR_Error] {
+        return &self.sfr_log;
+    }
+
+    pub fn clear_sfr_log(&mut self) {
+        self.sfr_log.clear();
+    }
+
+    // First access to an unimplemented SFR or upper RAM byte of the instruction at op_pc
+    pub fn check_sfr_access(&self, opcode: u8) -> Option<MCS51_SFR_Error> {
+        let mut operands = get_direct_operands(opcode)
+            .iter()
+            .map(|(offset, access)| (self.read_operand(*offset), *access));
+
+        let bit = get_bit_operand(opcode).map(|(offset, access)| {
+            let (address, _) = MCS51::get_bit_location(self.read_operand(offset));
+            (address, access)
+        });
+
+        let indirect = get_indirect_operand(opcode)
+            .map(|(register, access)| (self.read_register(register), access))
+            .filter(|(address, _)| *address as usize >= self.derivative.ram_size);
+
+        let (space, (address, access)) = match operands
+            .find(|(address, _)| *address >= 0x80 && !self.has_sfr(*address))
+            .or(bit.filter(|(address, _)| *address >= 0x80 && !self.has_sfr(*address)))
+        {
+            Some(direct) => (MCS51_MEMORY_SPACE::DIRECT, direct),
+            None => (MCS51_MEMORY_SPACE::INDIRECT, indirect?),
+        };
+
+        return Some(MCS51_SFR_Error {
+            pc: self.op_pc,
+            opcode,
+            space,
+            address,
+            access,
+        });
+    }
+
+    // Returns false when the instruction must not execute
+    pub fn apply_sfr_policy(&mut self, opcode: u8) -> bool {
+        if self.sfr_policy == MCS51_SFR_POLICY::IGNORE {
+            return true;
+        }
+
+        let error = match self.check_sfr_access(opcode) {
+            Some(error) => error,
+            None => return true,
+        };
+
+        match self.sfr_policy {
+            MCS51_SFR_POLICY::LOG => {
+                self.sfr_log.push(error);
+                return true;
+            }
+            _ => {
+                self.sfr_error = Some(error);
+                return false;
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use super::*;
+
+/*
+Timer 2 (8052)
+
+Timer 2 is a 16-bit Timer/Counter which can operate as either a timer or an event counter,
+selected by C/T2 in T2CON. It has three operating modes: capture, auto-reload (up or down
+counting) and baud rate generator, selected by bits in T2CON.
+
+T2CON:
+    TF2    : Overflow flag, not set when either RCLK or TCLK is set
+    EXF2   : Set on a capture or reload caused by a negative transition on T2EX when EXEN2 is set
+    RCLK   : Timer 2 overflows clock the serial port receiver in modes 1 and 3
+    TCLK   : Timer 2 overflows clock the serial port transmitter in modes 1 and 3
+    EXEN2  : Enables captures or reloads on negative transitions on T2EX
+    TR2    : Start/Stop control
+    C/T2   : Counter mode, counting falling edges on T2 (P1.0)
+    CP/RL2 : Capture on T2EX (P1.1) when set, auto-reload otherwise
+
+Auto-reload with DCEN set in T2MOD lets T2EX select the counting direction. Counting up, the
+timer reloads RCAP2H/RCAP2L on overflow. Counting down, it underflows when TH2 and TL2 equal
+RCAP2H/RCAP2L and reloads 0FFFFH. In both cases EXF2 toggles and acts as a 17th bit.
+
+In baud rate generator mode the timer increments every state time (twice per machine cycle of
+12 oscillator periods, so 6 times per machine cycle) and reloads from RCAP2 without setting TF2.
+*/
+
+pub const MCS51_T2CON_TF2: u8 = 0x80;
+pub const MCS51_T2CON_EXF2: u8 = 0x40;
+pub const MCS51_T2CON_RCLK: u8 = 0x20;
+pub const MCS51_T2CON_TCLK: u8 = 0x10;
+pub const MCS51_T2CON_EXEN2: u8 = 0x08;
+pub const MCS51_T2CON_TR2: u8 = 0x04;
+pub const MCS51_T2CON_CT2: u8 = 0x02;
+pub const MCS51_T2CON_CPRL2: u8 = 0x01;
+
+pub const MCS51_T2MOD_DCEN: u8 = 0x01;
+
+impl MCS51 {
+    pub fn get_timer2(&self) -> u16 {
+        let th2 = self.read_sfr(MCS51_REGISTERS::TH2) as u16;
+        let tl2 = self.read_sfr(MCS51_REGISTERS::TL2) as u16;
+        return (th2 << 8) + tl2;
+    }
+
+    pub fn set_timer2(&mut self, value: u16) {
+        self.write_sfr(MCS51_REGISTERS::TH2, (value >> 8) as u8);
+        self.write_sfr(MCS51_REGISTERS::TL2, value as u8);
+    }
+
+    pub fn get_rcap2(&self) -> u16 {
+        let rcap2h = self.read_sfr(MCS51_REGISTERS::RCAP2H) as u16;
+        let rcap2l = self.read_sfr(MCS51_REGISTERS::RCAP2L) as u16;
+        return (rcap2h << 8) + rcap2l;
+    }
+
+    pub fn set_rcap2(&mut self, value: u16) {
+        self.write_sfr(MCS51_REGISTERS::RCAP2H, (value >> 8) as u8);
+        self.write_sfr(MCS51_REGISTERS::RCAP2L, value as u8);
+    }
+
+    pub fn get_t2_pin(&self) -> bool {
+        return self.get_port_pins(1) & 0x01 != 0;
+    }
+
+    pub fn get_t2ex_pin(&self) -> bool {
+        return self.get_port_pins(1) & 0x02 != 0;
+    }
+
+    pub fn is_timer2_baud_rate_generator(&self) -> bool {
+        return self.read_sfr(MCS51_REGISTERS::T2CON) & (MCS51_T2CON_RCLK | MCS51_T2CON_TCLK) != 0;
+    }
+
+    fn set_t2con_flags(&mut self, set: u8, toggle: u8) {
+        let t2con = self.read_sfr(MCS51_REGISTERS::T2CON);
+        self.write_sfr(MCS51_REGISTERS::T2CON, (t2con | set) ^ toggle);
+    }
+
+    /*
+    Advances Timer 2 by one machine cycle, returns the number of overflows that happened in
+    baud rate generator mode
+    */
+    pub fn tick_timer2(&mut self) -> u8 {
+        let pins = [self.get_t2_pin(), self.get_t2ex_pin()];
+        let t2_edge = self.timer2_pins[0] && !pins[0];
+        let t2ex_edge = self.timer2_pins[1] && !pins[1];
+        self.timer2_pins = pins;
+
+        let t2con = self.read_sfr(MCS51_REGISTERS::T2CON);
+        let baud_rate = t2con & (MCS51_T2CON_RCLK | MCS51_T2CON_TCLK) != 0;
+        let capture = t2con & MCS51_T2CON_CPRL2 != 0 && !baud_rate;
+        let up_down = self.read_sfr(MCS51_REGISTERS::T2MOD) & MCS51_T2MOD_DCEN != 0
+            && !capture
+            && !baud_rate;
+
+        if t2ex_edge && t2con & MCS51_T2CON_EXEN2 != 0 && !up_down {
+            if capture {
+                let timer = self.get_timer2();
+                self.set_rcap2(timer);
+            } else if !baud_rate {
+                let rcap2 = self.get_rcap2();
+                self.set_timer2(rcap2);
+            }
+            self.set_t2con_flags(MCS51_T2CON_EXF2, 0);
+        }
+
+        if t2con & MCS51_T2CON_TR2 == 0 {
+            return 0;
+        }
+
+        let increments = if t2con & MCS51_T2CON_CT2 != 0 {
+            t2_edge as u8
+        } else if baud_rate {
+            self.get_states_per_cycle() as u8
+        } else {
+            1
+        };
+
+        let mut baud_rate_overflows = 0;
+
+        for _i in 0..increments {
+            let timer = self.get_timer2();
+
+            if up_down && !pins[1] {
+                if timer == self.get_rcap2() {
+                    self.set_timer2(0xFFFF);
+                    self.set_t2con_flags(MCS51_T2CON_TF2, MCS51_T2CON_EXF2);
+                } else {
+                    self.set_timer2(timer.wrapping_sub(1));
+                }
+            } else if timer != 0xFFFF {
+                self.set_timer2(timer + 1);
+            } else if capture {
+                self.set_timer2(0);
+                self.set_t2con_flags(MCS51_T2CON_TF2, 0);
+            } else {
+                let rcap2 = self.get_rcap2();
+                self.set_timer2(rcap2);
+
+                if baud_rate {
+                    baud_rate_overflows += 1;
+                } else if up_down {
+                    self.set_t2con_flags(MCS51_T2CON_TF2, MCS51_T2CON_EXF2);
+                } else {
+                    self.set_t2con_flags(MCS51_T2CON_TF2, 0);
+                }
+            }
+        }
+
+        return baud_rate_overflows;
+    }
+}
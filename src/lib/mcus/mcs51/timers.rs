@@ -0,0 +1,152 @@
+use super::*;
+
+/*
+Timer/Counters 0 and 1
+
+In the "Timer" function, the register is incremented every machine cycle. In the "Counter"
+function, the register is incremented in response to a 1-to-0 transition at its corresponding
+external input pin, T0 (P3.4) or T1 (P3.5).
+
+TMOD (one nibble per timer, Timer 1 in the high nibble):
+    GATE : When set, the timer only runs while INTx is high and TRx is set
+    C/T  : Cleared for Timer operation, set for Counter operation
+    M1 M0: Operating mode
+
+Mode 0: 8-bit timer THx with TLx as a 5-bit prescaler (13-bit timer)
+Mode 1: 16-bit timer/counter
+Mode 2: 8-bit auto-reload, THx holds the value reloaded into TLx on each overflow
+Mode 3: Timer 1 stops. Timer 0 becomes two 8-bit counters, TL0 controlled by the Timer 0 bits
+        and TH0 locked into a timer function controlled by TR1, setting TF1 on overflow.
+        Timer 1 can still be used by the serial port, running whenever it is not in mode 3.
+*/
+
+pub const MCS51_TCON_TF1: u8 = 0x80;
+pub const MCS51_TCON_TR1: u8 = 0x40;
+pub const MCS51_TCON_TF0: u8 = 0x20;
+pub const MCS51_TCON_TR0: u8 = 0x10;
+
+const TMOD_GATE: u8 = 0x08;
+const TMOD_CT: u8 = 0x04;
+
+impl MCS51 {
+    pub fn get_timer_mode(&self, timer: u8) -> u8 {
+        return (self.read_sfr(MCS51_REGISTERS::TMOD) >> (timer * 4)) & 0x03;
+    }
+
+    pub fn get_t0_pin(&self) -> bool {
+        return self.get_port_pins(3) & 0x10 != 0;
+    }
+
+    pub fn get_t1_pin(&self) -> bool {
+        return self.get_port_pins(3) & 0x20 != 0;
+    }
+
+    // Returns which of T0 and T1 saw a 1-to-0 transition since the last machine cycle
+    fn sample_timer_pins(&mut self) -> [bool; 2] {
+        let pins = [self.get_t0_pin(), self.get_t1_pin()];
+        let edges = [
+            self.timer_pins[0] && !pins[0],
+            self.timer_pins[1] && !pins[1],
+        ];
+        self.timer_pins = pins;
+        return edges;
+    }
+
+    fn set_tcon_flag(&mut self, flag: u8) {
+        let tcon = self.read_sfr(MCS51_REGISTERS::TCON);
+        self.write_sfr(MCS51_REGISTERS::TCON, tcon | flag);
+    }
+
+    // Increments a timer in mode 0, 1 or 2 and returns true on overflow, mode 3 only counts tl
+    fn increment_timer(&mut self, tl: MCS51_REGISTERS, th: MCS51_REGISTERS, mode: u8) -> bool {
+        let low = self.read_sfr(tl);
+        let high = self.read_sfr(th);
+
+        match mode {
+            0 => {
+                let prescaler = (low.wrapping_add(1)) & 0x1F;
+                self.write_sfr(tl, (low & 0xE0) | prescaler);
+                if prescaler != 0 {
+                    return false;
+                }
+
+                self.write_sfr(th, high.wrapping_add(1));
+                return high == 0xFF;
+            }
+            1 => {
+                self.write_sfr(tl, low.wrapping_add(1));
+                if low != 0xFF {
+                    return false;
+                }
+
+                self.write_sfr(th, high.wrapping_add(1));
+                return high == 0xFF;
+            }
+            2 => {
+                if low == 0xFF {
+                    self.write_sfr(tl, high);
+                    return true;
+                }
+
+                self.write_sfr(tl, low + 1);
+                return false;
+            }
+            _ => {
+                self.write_sfr(tl, low.wrapping_add(1));
+                return low == 0xFF;
+            }
+        }
+    }
+
+    /*
+    Advances Timer 0 and Timer 1 by one machine cycle, returns true when Timer 1 overflowed so
+    the serial port can derive its baud rate from it
+    */
+    pub fn tick_timers(&mut self) -> bool {
+        let edges = self.sample_timer_pins();
+        let tmod = self.read_sfr(MCS51_REGISTERS::TMOD);
+        let tcon = self.read_sfr(MCS51_REGISTERS::TCON);
+
+        let t0_control = tmod & 0x0F;
+        let t0_mode = t0_control & 0x03;
+        let t0_run = tcon & MCS51_TCON_TR0 != 0
+            && (t0_control & TMOD_GATE == 0 || self.get_int0_pin());
+        let t0_count = t0_control & TMOD_CT == 0 || edges[0];
+
+        if t0_run && t0_count && self.increment_timer(MCS51_REGISTERS::TL0, MCS51_REGISTERS::TH0, t0_mode) {
+            self.set_tcon_flag(MCS51_TCON_TF0);
+        }
+
+        if t0_mode == 3
+            && tcon & MCS51_TCON_TR1 != 0
+            && self.increment_timer(MCS51_REGISTERS::TH0, MCS51_REGISTERS::TH0, 3)
+        {
+            self.set_tcon_flag(MCS51_TCON_TF1);
+        }
+
+        let t1_control = tmod >> 4;
+        let t1_mode = t1_control & 0x03;
+        if t1_mode == 3 {
+            return false;
+        }
+
+        // TR1 belongs to TH0 while Timer 0 is in mode 3
+        let t1_enabled = t0_mode == 3 || tcon & MCS51_TCON_TR1 != 0;
+        let t1_run = t1_enabled && (t1_control & TMOD_GATE == 0 || self.get_int1_pin());
+        let t1_count = t1_control & TMOD_CT == 0 || edges[1];
+
+        if t1_run && t1_count && self.increment_timer(MCS51_REGISTERS::TL1, MCS51_REGISTERS::TH1, t1_mode) {
+            if t0_mode != 3 {
+                self.set_tcon_flag(MCS51_TCON_TF1);
+            }
+            return true;
+        }
+
+        return false;
+    }
+
+    pub fn reset_timers(&mut self) {
+        self.timer_pins = [true; 2];
+        self.timer2_pins = [true; 2];
+    }
+}
@@ -0,0 +1,91 @@
+use super::*;
+use std::time::Duration;
+
+/*
+Timing
+
+A machine cycle consists of 6 states (S1-S6), each made of two oscillator periods, so the classic
+MCS-51 needs 12 oscillator periods per machine cycle. Faster derivatives run the same machine
+cycle in 6 (X2 mode) or 4 oscillator periods. Every instruction executes in 1, 2 or 4 machine
+cycles, and the timers, the serial port and the interrupt logic are all clocked per machine cycle.
+*/
+
+pub const MCS51_DEFAULT_OSCILLATOR_FREQUENCY: u32 = 12_000_000;
+pub const MCS51_DEFAULT_CLOCKS_PER_CYCLE: u32 = 12;
+
+// Machine cycles of every opcode, one row per high nibble
+pub const MCS51_INSTRUCTION_CYCLES: [u8; 256] = [
+    1, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0x
+    2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 1x
+    2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 2x
+    2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 3x
+    2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 4x
+    2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 5x
+    2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 6x
+    2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 7x
+    2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, // 8x
+    2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 9x
+    2, 2, 1, 2, 4, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, // Ax
+    2, 2, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, // Bx
+    2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // Cx
+    2, 2, 1, 1, 1, 2, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, // Dx
+    2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // Ex
+    2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // Fx
+];
+
+impl MCS51 {
+    pub fn get_instruction_cycles(opcode: u8) -> u8 {
+        return MCS51_INSTRUCTION_CYCLES[opcode as usize];
+    }
+
+    pub fn set_oscillator_frequency(&mut self, frequency: u32) {
+        self.oscillator_frequency = frequency;
+    }
+
+    pub fn get_oscillator_frequency(&self) -> u32 {
+        return self.oscillator_frequency;
+    }
+
+    // 12 for the classic core, 6 in X2 mode, 4 on some single cycle derivatives
+    pub fn set_clocks_per_cycle(&mut self, clocks: u32) {
+        self.clocks_per_cycle = clocks;
+    }
+
+    pub fn get_clocks_per_cycle(&self) -> u32 {
+        return self.clocks_per_cycle;
+    }
+
+    /*
+    States of two oscillator periods per machine cycle, 6 on the classic core. The timer 2 baud
+    rate generator and the mode 2 serial clock count at half the oscillator frequency, so they
+    advance this many times per machine cycle.
+    */
+    pub fn get_states_per_cycle(&self) -> u32 {
+        return (self.clocks_per_cycle / 2).max(1);
+    }
+
+    // Machine cycles elapsed since reset
+    pub fn get_cycle_count(&self) -> u64 {
+        return self.cycle_count;
+    }
+
+    pub fn get_machine_cycle_frequency(&self) -> f64 {
+        return self.oscillator_frequency as f64 / self.clocks_per_cycle as f64;
+    }
+
+    pub fn cycles_to_duration(&self, cycles: u64) -> Duration {
+        let clocks = cycles as u128 * self.clocks_per_cycle as u128;
+        let nanos = clocks * 1_000_000_000 / self.oscillator_frequency as u128;
+        return Duration::from_nanos(nanos as u64);
+    }
+
+    pub fn duration_to_cycles(&self, duration: Duration) -> u64 {
+        let clocks = duration.as_nanos() * self.oscillator_frequency as u128 / 1_000_000_000;
+        return (clocks / self.clocks_per_cycle as u128) as u64;
+    }
+
+    // Emulated time elapsed since reset
+    pub fn get_elapsed_time(&self) -> Duration {
+        return self.cycles_to_duration(self.cycle_count);
+    }
+}
@@ -0,0 +1,474 @@
+use super::*;
+use std::io;
+use std::io::Write;
+use std::sync::mpsc::Sender;
+
+/*
+Execution trace
+
+A tracer attached to the core records each instruction that starts inside its address range: the
+PC, the opcode bytes, the disassembly, the machine cycles elapsed before it and the cycles it
+takes, the registers and PSW flags it changed and, when enabled, the memory it wrote. Interrupt
+calls are not instructions and are not recorded, the first instruction of the handler is.
+
+Registers are A, B, SP, DPTR (the selected one) and R0-R7 of the bank selected before the
+instruction, the flags are the bits of PSW after the parity update. Memory writes are the write
+accesses decoded by get_instruction_accesses, with the value after the instruction, without the
+locations already reported as registers or flags.
+
+MCS51_Trace_Writer formats the records, one per line for CSV and JSON Lines. The binary format
+starts with the magic "MCS51TR" and a version byte (1), then each record, little endian:
+
+    u16 pc, u64 cycle, u8 cycles, u8 length, length opcode bytes,
+    u8 count, count x (u8 register, u16 old, u16 new)       registers then flags
+    u8 count, count x (u8 space, u16 address, u8 value)     memory writes
+
+Registers are indexes into MCS51_TRACE_REGISTERS, spaces are 0 IRAM, 1 SFR, 2 BIT and 3 XDATA.
+The disassembly is left out, it can be rebuilt from the opcode bytes.
+*/
+
+pub const MCS51_TRACE_REGISTERS: [&str; 20] = [
+    "A", "B", "SP", "DPTR", "R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "CY", "AC", "F0", "RS1",
+    "RS0", "OV", "F1", "P",
+];
+
+const MCS51_TRACE_FIRST_FLAG: u8 = 12;
+const MCS51_TRACE_MAGIC: &[u8; 8] = b"MCS51TR\x01";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MCS51_TRACE_FORMAT {
+    CSV,
+    JSONL,
+    BINARY,
+}
+
+impl MCS51_TRACE_FORMAT {
+    pub fn from_name(name: &str) -> Option<MCS51_TRACE_FORMAT> {
+        return match name.to_lowercase().as_str() {
+            "csv" => Some(MCS51_TRACE_FORMAT::CSV),
+            "jsonl" | "json" => Some(MCS51_TRACE_FORMAT::JSONL),
+            "bin" | "binary" => Some(MCS51_TRACE_FORMAT::BINARY),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MCS51_Trace_Delta {
+    pub register: u8,
+    pub old: u16,
+    pub new: u16,
+}
+
+impl MCS51_Trace_Delta {
+    pub fn get_name(&self) -> &'static str {
+        return MCS51_TRACE_REGISTERS[self.register as usize];
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MCS51_Trace_Write {
+    pub space: MCS51_WATCH_SPACE,
+    pub address: u16,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MCS51_Trace_Record {
+    pub pc: u16,
+    pub bytes: Vec<u8>,
+    pub disassembly: String,
+    pub cycle: u64,
+    pub cycles: u8,
+    pub registers: Vec<MCS51_Trace_Delta>,
+    pub flags: Vec<MCS51_Trace_Delta>,
+    pub writes: Vec<MCS51_Trace_Write>,
+}
+
+pub trait MCS51_Trace_Sink {
+    fn record(&mut self, record: &MCS51_Trace_Record) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+// Hands the records to another thread, or to a test
+impl MCS51_Trace_Sink for Sender<MCS51_Trace_Record> {
+    fn record(&mut self, record: &MCS51_Trace_Record) -> io::Result<()> {
+        return self
+            .send(record.clone())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Trace receiver dropped"));
+    }
+}
+
+fn get_space_prefix(space: MCS51_WATCH_SPACE) -> &'static str {
+    return match space {
+        MCS51_WATCH_SPACE::IRAM => "I",
+        MCS51_WATCH_SPACE::SFR => "D",
+        MCS51_WATCH_SPACE::BIT => "BIT",
+        MCS51_WATCH_SPACE::XDATA => "X",
+    };
+}
+
+fn format_bytes(bytes: &[u8]) -> String {
+    return bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<String>>()
+        .join(" ");
+}
+
+fn format_deltas(deltas: &[MCS51_Trace_Delta]) -> String {
+    return deltas
+        .iter()
+        .map(|delta| match delta.register {
+            0x03 => format!("{}:{:04X}->{:04X}", delta.get_name(), delta.old, delta.new),
+            register if register >= MCS51_TRACE_FIRST_FLAG => {
+                format!("{}:{}->{}", delta.get_name(), delta.old, delta.new)
+            }
+            _ => format!("{}:{:02X}->{:02X}", delta.get_name(), delta.old, delta.new),
+        })
+        .collect::<Vec<String>>()
+        .join(" ");
+}
+
+fn format_writes(writes: &[MCS51_Trace_Write]) -> String {
+    return writes
+        .iter()
+        .map(|write| format!("{}:{:04X}={:02X}", get_space_prefix(write.space), write.address, write.value))
+        .collect::<Vec<String>>()
+        .join(" ");
+}
+
+fn escape_json(text: &str) -> String {
+    return text.replace('\\', "\\\\").replace('"', "\\\"");
+}
+
+pub struct MCS51_Trace_Writer<W: Write> {
+    pub writer: W,
+    pub format: MCS51_TRACE_FORMAT,
+    started: bool,
+}
+
+impl<W: Write> MCS51_Trace_Writer<W> {
+    pub fn new(writer: W, format: MCS51_TRACE_FORMAT) -> MCS51_Trace_Writer<W> {
+        MCS51_Trace_Writer {
+            writer,
+            format,
+            started: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        return self.writer;
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        return match self.format {
+            MCS51_TRACE_FORMAT::CSV => {
+                writeln!(self.writer, "pc,bytes,disassembly,cycle,cycles,registers,flags,writes")
+            }
+            MCS51_TRACE_FORMAT::JSONL => Ok(()),
+            MCS51_TRACE_FORMAT::BINARY => self.writer.write_all(MCS51_TRACE_MAGIC),
+        };
+    }
+
+    fn write_csv(&mut self, record: &MCS51_Trace_Record) -> io::Result<()> {
+        return writeln!(
+            self.writer,
+            "{:04X},{},\"{}\",{},{},{},{},{}",
+            record.pc,
+            format_bytes(&record.bytes),
+            record.disassembly.replace('"', "\"\""),
+            record.cycle,
+            record.cycles,
+            format_deltas(&record.registers),
+            format_deltas(&record.flags),
+            format_writes(&record.writes)
+        );
+    }
+
+    fn write_jsonl(&mut self, record: &MCS51_Trace_Record) -> io::Result<()> {
+        let deltas = |deltas: &[MCS51_Trace_Delta]| {
+            deltas
+                .iter()
+                .map(|delta| format!("\"{}\":[{},{}]", delta.get_name(), delta.old, delta.new))
+                .collect::<Vec<String>>()
+                .join(",")
+        };
+        let writes: Vec<String> = record
+            .writes
+            .iter()
+            .map(|write| format!("[\"{}\",{},{}]", get_space_prefix(write.space), write.address, write.value))
+            .collect();
+
+        return writeln!(
+            self.writer,
+            "{{\"pc\":{},\"bytes\":\"{}\",\"disassembly\":\"{}\",\"cycle\":{},\"cycles\":{},\"registers\":{{{}}},\"flags\":{{{}}},\"writes\":[{}]}}",
+            record.pc,
+            format_bytes(&record.bytes),
+            escape_json(&record.disassembly),
+            record.cycle,
+            record.cycles,
+            deltas(&record.registers),
+            deltas(&record.flags),
+            writes.join(",")
+        );
+    }
+
+    fn write_binary(&mut self, record: &MCS51_Trace_Record) -> io::Result<()> {
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(&record.pc.to_le_bytes());
+        data.extend_from_slice(&record.cycle.to_le_bytes());
+        data.push(record.cycles);
+        data.push(record.bytes.len() as u8);
+        data.extend_from_slice(&record.bytes);
+
+        data.push((record.registers.len() + record.flags.len()) as u8);
+        for delta in record.registers.iter().chain(record.flags.iter()) {
+            data.push(delta.register);
+            data.extend_from_slice(&delta.old.to_le_bytes());
+            data.extend_from_slice(&delta.new.to_le_bytes());
+        }
+
+        data.push(record.writes.len() as u8);
+        for write in &record.writes {
+            data.push(write.space as u8);
+            data.extend_from_slice(&write.address.to_le_bytes());
+            data.push(write.value);
+        }
+        return self.writer.write_all(&data);
+    }
+}
+
+impl<W: Write> MCS51_Trace_Sink for MCS51_Trace_Writer<W> {
+    fn record(&mut self, record: &MCS51_Trace_Record) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            self.write_header()?;
+        }
+
+        return match self.format {
+            MCS51_TRACE_FORMAT::CSV => self.write_csv(record),
+            MCS51_TRACE_FORMAT::JSONL => self.write_jsonl(record),
+            MCS51_TRACE_FORMAT::BINARY => self.write_binary(record),
+        };
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.writer.flush();
+    }
+}
+
+pub struct MCS51_Tracer {
+    pub sink: Box<dyn MCS51_Trace_Sink>,
+    pub start: u16,
+    pub end: u16,
+    pub memory_writes: bool,
+    pub records: u64,
+    pub error: Option<io::Error>,
+    decomp: mcs51::MCS51_Decompiler,
+}
+
+impl MCS51_Tracer {
+    pub fn new(sink: Box<dyn MCS51_Trace_Sink>) -> MCS51_Tracer {
+        let mut decomp = mcs51::MCS51_Decompiler::new();
+        decomp.program = vec![0; MCS51_CODE_SIZE];
+
+        MCS51_Tracer {
+            sink,
+            start: 0x0000,
+            end: 0xFFFF,
+            memory_writes: false,
+            records: 0,
+            error: None,
+            decomp,
+        }
+    }
+
+    // Only the instructions starting from start to end (inclusive) are recorded
+    pub fn set_range(&mut self, start: u16, end: u16) {
+        self.start = start;
+        self.end = end;
+    }
+
+    pub fn set_memory_writes(&mut self, enabled: bool) {
+        self.memory_writes = enabled;
+    }
+
+    // The first error of the sink stops the recording
+    pub fn is_recording(&self, pc: u16) -> bool {
+        return self.error.is_none() && pc >= self.start && pc <= self.end;
+    }
+
+    fn disassemble(&mut self, pc: u16, bytes: &[u8]) -> String {
+        if pc > 0xFFFC || bytes[0] == MCS51_RESERVED_OPCODE {
+            return format!("DB {:02X}H", bytes[0]);
+        }
+
+        // Only the bytes of the instruction are needed, the code may have changed since the last one
+        let start = pc as usize;
+        self.decomp.program[start..start + bytes.len()].copy_from_slice(bytes);
+        return self.decomp.get_instruction(pc).get_code().to_owned();
+    }
+}
+
+// State of the core before a traced instruction
+pub struct MCS51_Trace_State {
+    pc: u16,
+    cycle: u64,
+    bytes: Vec<u8>,
+    registers: [u16; 12],
+    psw: u8,
+    bank: u8,
+    accesses: Vec<MCS51_Access>,
+}
+
+impl MCS51 {
+    pub fn attach_tracer(&mut self, tracer: MCS51_Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    // Flushes the sink and gives the tracer back, with the error that stopped it if any
+    pub fn detach_tracer(&mut self) -> Option<MCS51_Tracer> {
+        let mut tracer = self.tracer.take()?;
+        if let Err(err) = tracer.sink.flush() {
+            tracer.error.get_or_insert(err);
+        }
+        return Some(tracer);
+    }
+
+    fn get_trace_registers(&self, bank: u8) -> [u16; 12] {
+        let mut registers = [
+            self.get_accumulator() as u16,
+            self.read_sfr(MCS51_REGISTERS::B) as u16,
+            self.read_sfr(MCS51_REGISTERS::SP) as u16,
+            self.get_dptr(),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ];
+        for register in 0..8 {
+            registers[4 + register] = self.ram[bank as usize + register] as u16;
+        }
+        return registers;
+    }
+
+    // Locations reported as registers or flags
+    fn is_trace_register(&self, space: MCS51_WATCH_SPACE, address: u16, bank: u8) -> bool {
+        let (space, address) = match space {
+            MCS51_WATCH_SPACE::BIT => {
+                let (byte, _) = MCS51::get_bit_location(address as u8);
+                let space = if byte < 0x80 {
+                    MCS51_WATCH_SPACE::IRAM
+                } else {
+                    MCS51_WATCH_SPACE::SFR
+                };
+                (space, byte as u16)
+            }
+            _ => (space, address),
+        };
+
+        // Only the data pointer selected by AUXR1.DPS is the DPTR register, the other is an SFR
+        let dptr: [u16; 2] = if self.read_sfr(MCS51_REGISTERS::AUXR1) & MCS51_AUXR1_DPS != 0 {
+            [0x85, 0x84]
+        } else {
+            [0x83, 0x82]
+        };
+
+        return match space {
+            MCS51_WATCH_SPACE::IRAM => address >= bank as u16 && address < bank as u16 + 8,
+            MCS51_WATCH_SPACE::SFR => [0xE0, 0xF0, 0x81, 0xD0].contains(&address) || dptr.contains(&address),
+            _ => false,
+        };
+    }
+
+    pub fn begin_trace(&self, opcode: u8) -> Option<MCS51_Trace_State> {
+        let tracer = self.tracer.as_ref()?;
+        let pc = self.op_pc;
+        if !tracer.is_recording(pc) {
+            return None;
+        }
+
+        let bank = self.get_current_register_bank_flags();
+        let length = mcs51::MCS51_Decompiler::instruction_length(opcode);
+        return Some(MCS51_Trace_State {
+            pc,
+            cycle: self.cycle_count.saturating_sub(1),
+            bytes: (0..length).map(|offset| self.read_code_byte(pc.wrapping_add(offset))).collect(),
+            registers: self.get_trace_registers(bank),
+            psw: self.read_sfr(MCS51_REGISTERS::PSW),
+            bank,
+            accesses: if tracer.memory_writes {
+                self.get_instruction_accesses(opcode)
+            } else {
+                Vec::new()
+            },
+        });
+    }
+
+    pub fn end_trace(&mut self, state: MCS51_Trace_State) {
+        let registers = self.get_trace_registers(state.bank);
+        let registers: Vec<MCS51_Trace_Delta> = (0..registers.len())
+            .filter(|index| registers[*index] != state.registers[*index])
+            .map(|index| MCS51_Trace_Delta {
+                register: index as u8,
+                old: state.registers[index],
+                new: registers[index],
+            })
+            .collect();
+
+        let psw = self.read_sfr(MCS51_REGISTERS::PSW);
+        let flags: Vec<MCS51_Trace_Delta> = (0..8)
+            .filter(|bit| (psw ^ state.psw) & (0x80 >> bit) != 0)
+            .map(|bit| MCS51_Trace_Delta {
+                register: MCS51_TRACE_FIRST_FLAG + bit,
+                old: ((state.psw >> (7 - bit)) & 1) as u16,
+                new: ((psw >> (7 - bit)) & 1) as u16,
+            })
+            .collect();
+
+        let mut writes: Vec<MCS51_Trace_Write> = Vec::new();
+        for access in &state.accesses {
+            if access.kind != MCS51_WATCH_KIND::WRITE
+                || self.is_trace_register(access.space, access.address, state.bank)
+                || writes
+                    .iter()
+                    .any(|write| write.space == access.space && write.address == access.address)
+            {
+                continue;
+            }
+            if let Some(value) = self.read_watch_value(access.space, access.address) {
+                writes.push(MCS51_Trace_Write {
+                    space: access.space,
+                    address: access.address,
+                    value,
+                });
+            }
+        }
+
+        let opcode = state.bytes[0];
+        let tracer = self.tracer.as_mut().unwrap();
+        let record = MCS51_Trace_Record {
+            pc: state.pc,
+            disassembly: tracer.disassemble(state.pc, &state.bytes),
+            bytes: state.bytes,
+            cycle: state.cycle,
+            cycles: MCS51::get_instruction_cycles(opcode),
+            registers,
+            flags,
+            writes,
+        };
+
+        match tracer.sink.record(&record) {
+            Ok(()) => tracer.records += 1,
+            Err(err) => tracer.error = Some(err),
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use super::*;
+
+/*
+Watchdog Timer (AT89S52)
+
+The WDT is intended as a recovery method in situations where the CPU may be subjected to software
+upsets. The WDT consists of a 14-bit counter and the Watchdog Timer Reset (WDTRST) SFR. The WDT
+is defaulted to disable from exiting reset. To enable the WDT, a user must write 01EH and 0E1H in
+sequence to the WDTRST register (SFR location 0A6H). When the WDT is enabled, it will increment
+every machine cycle while the oscillator is running. The WDT timeout period is dependent on the
+external clock frequency. There is no way to disable the WDT except through reset (either
+hardware reset or WDT overflow reset). When the WDT overflows, it will drive an output RESET HIGH
+pulse at the RST pin.
+
+To service the WDT, the user should write 01EH and 0E1H to WDTRST. WDTRST is a write-only
+register. The WDT counter cannot be read or written. When the WDT overflows, it will generate an
+output RESET pulse at the RST pin. The WDT stops counting in Power-down, and in Idle when WDIDLE
+(AUXR.4) is set.
+
+AUXR:
+    WDIDLE : Disable the WDT in Idle mode
+    DISRTO : Disable the reset output driven on the RST pin by a WDT overflow
+    DISALE : ALE is active only during a MOVX or MOVC instruction
+AUXR1:
+    DPS    : Data Pointer register select, DP0 when cleared, DP1 when set
+*/
+
+pub const MCS51_AUXR_WDIDLE: u8 = 0x10;
+pub const MCS51_AUXR_DISRTO: u8 = 0x08;
+pub const MCS51_AUXR_DISALE: u8 = 0x01;
+
+pub const MCS51_AUXR1_DPS: u8 = 0x01;
+
+pub const MCS51_WDT_SEQUENCE: [u8; 2] = [0x1E, 0xE1];
+pub const MCS51_WDT_PERIOD: u16 = 0x4000;
+
+pub struct MCS51_Watchdog {
+    pub enabled: bool,
+    pub counter: u16,
+    pub armed: bool,
+    pub timeouts: u64,
+}
+
+impl MCS51_Watchdog {
+    pub fn new() -> MCS51_Watchdog {
+        MCS51_Watchdog {
+            enabled: false,
+            counter: 0,
+            armed: false,
+            timeouts: 0,
+        }
+    }
+
+    // The number of timeouts survives the reset they cause
+    pub fn reset(&mut self) {
+        self.enabled = false;
+        self.counter = 0;
+        self.armed = false;
+    }
+}
+
+impl MCS51 {
+    // 1EH arms the sequence, E1H right after it enables or clears the watchdog
+    pub fn watchdog_write(&mut self, value: u8) {
+        if self.watchdog.armed && value == MCS51_WDT_SEQUENCE[1] {
+            self.watchdog.enabled = true;
+            self.watchdog.counter = 0;
+        }
+        self.watchdog.armed = value == MCS51_WDT_SEQUENCE[0];
+    }
+
+    pub fn is_watchdog_enabled(&self) -> bool {
+        return self.watchdog.enabled;
+    }
+
+    pub fn get_watchdog_timeouts(&self) -> u64 {
+        return self.watchdog.timeouts;
+    }
+
+    /*
+    Advances the watchdog by one machine cycle, returns true when it overflowed and reset the
+    chip
+    */
+    pub fn tick_watchdog(&mut self) -> bool {
+        if !self.watchdog.enabled {
+            return false;
+        }
+
+        let auxr = self.read_sfr(MCS51_REGISTERS::AUXR);
+        if auxr & MCS51_AUXR_WDIDLE != 0 && self.get_power_state() == MCS51_POWER_STATE::IDLE {
+            return false;
+        }
+
+        self.watchdog.counter += 1;
+        if self.watchdog.counter < MCS51_WDT_PERIOD {
+            return false;
+        }
+
+        self.watchdog.timeouts += 1;
+        self.reset();
+        self.reset_source = MCS51_RESET_SOURCE::WATCHDOG;
+        return true;
+    }
+}
@@ -0,0 +1,185 @@
+use crate::lib::traits::component::*;
+
+/*
+External Data Memory
+
+The MCS-51 can address up to 64K bytes of external data memory through the MOVX instructions.
+MOVX @DPTR uses the full 16 bit Data Pointer as the address, MOVX @Ri emits the contents of
+R0 or R1 on P0 and the current P2 latch as the high address byte.
+
+Boards usually decode part of this space to peripherals (latches, UARTs, displays...), so the
+address space is described as a list of regions. When regions overlap, the last one mapped wins.
+*/
+
+pub const MCS51_XDATA_SIZE: usize = 0x10000;
+
+pub enum MCS51_XDATA_REGION {
+    RAM,
+    ROM,
+    DEVICE(Box<dyn BusComponent>),
+}
+
+pub struct MCS51_Xdata_Mapping {
+    pub start: u16,
+    pub end: u16,
+    pub region: MCS51_XDATA_REGION,
+}
+
+pub struct MCS51_Xdata {
+    pub memory: Vec<u8>,
+    pub mappings: Vec<MCS51_Xdata_Mapping>,
+}
+
+impl MCS51_Xdata {
+    pub fn new() -> MCS51_Xdata {
+        let mut xdata = MCS51_Xdata {
+            memory: vec![0; MCS51_XDATA_SIZE],
+            mappings: Vec::new(),
+        };
+
+        xdata.map_ram(0x0000, 0xFFFF);
+        xdata
+    }
+
+    // Removes every region, unmapped addresses read as 0xFF (floating bus) and ignore writes
+    pub fn clear_mappings(&mut self) {
+        self.mappings.clear();
+    }
+
+    /*
+    The RAM across the whole space that a part with an external bus starts with. It sits below
+    every other region, so selecting a part keeps the ROMs and devices already mapped.
+    */
+    pub fn set_default_ram(&mut self, present: bool) {
+        self.mappings.retain(|m| {
+            !(m.start == 0x0000 && m.end == 0xFFFF && matches!(m.region, MCS51_XDATA_REGION::RAM))
+        });
+        if present {
+            self.mappings.insert(
+                0,
+                MCS51_Xdata_Mapping {
+                    start: 0x0000,
+                    end: 0xFFFF,
+                    region: MCS51_XDATA_REGION::RAM,
+                },
+            );
+        }
+    }
+
+    pub fn map_ram(&mut self, start: u16, end: u16) {
+        self.mappings.push(MCS51_Xdata_Mapping {
+            start,
+            end,
+            region: MCS51_XDATA_REGION::RAM,
+        });
+    }
+
+    pub fn map_rom(&mut self, start: u16, data: &[u8]) -> Result<(), String> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        self.load(start, data)?;
+        self.mappings.push(MCS51_Xdata_Mapping {
+            start,
+            end: (start as usize + data.len() - 1) as u16,
+            region: MCS51_XDATA_REGION::ROM,
+        });
+        return Ok(());
+    }
+
+    // Devices receive addresses relative to the start of their region
+    pub fn map_device(&mut self, start: u16, end: u16, device: Box<dyn BusComponent>) {
+        self.mappings.push(MCS51_Xdata_Mapping {
+            start,
+            end,
+            region: MCS51_XDATA_REGION::DEVICE(device),
+        });
+    }
+
+    // Loads the addresses decoded to RAM with the given values, used for the power up contents
+    pub fn fill_ram<F: FnMut() -> u8>(&mut self, mut value: F) {
+        for address in 0..MCS51_XDATA_SIZE {
+            let mapping = self
+                .mappings
+                .iter()
+                .rev()
+                .find(|m| address >= m.start as usize && address <= m.end as usize);
+
+            if let Some(MCS51_Xdata_Mapping {
+                region: MCS51_XDATA_REGION::RAM,
+                ..
+            }) = mapping
+            {
+                self.memory[address] = value();
+            }
+        }
+    }
+
+    // Writes directly to the backing store, regardless of the region type
+    pub fn load(&mut self, start: u16, data: &[u8]) -> Result<(), String> {
+        let start = start as usize;
+        if start + data.len() > MCS51_XDATA_SIZE {
+            return Err(format!(
+                "{} bytes at {:04X} do not fit in external data memory",
+                data.len(),
+                start
+            ));
+        }
+        self.memory[start..start + data.len()].copy_from_slice(data);
+        return Ok(());
+    }
+
+    fn find_mapping(&mut self, address: u16) -> Option<&mut MCS51_Xdata_Mapping> {
+        self.mappings
+            .iter_mut()
+            .rev()
+            .find(|m| address >= m.start && address <= m.end)
+    }
+
+    // Backing store of RAM and ROM regions, devices are not read so that peeking has no side effect
+    pub fn peek(&self, address: u16) -> Option<u8> {
+        let mapping = self
+            .mappings
+            .iter()
+            .rev()
+            .find(|m| address >= m.start && address <= m.end)?;
+
+        match mapping.region {
+            MCS51_XDATA_REGION::RAM | MCS51_XDATA_REGION::ROM => Some(self.memory[address as usize]),
+            MCS51_XDATA_REGION::DEVICE(_) => None,
+        }
+    }
+
+    pub fn read(&mut self, address: u16) -> u8 {
+        let mapping = match self.find_mapping(address) {
+            Some(m) => m,
+            None => return 0xFF,
+        };
+
+        match &mut mapping.region {
+            MCS51_XDATA_REGION::RAM | MCS51_XDATA_REGION::ROM => {}
+            MCS51_XDATA_REGION::DEVICE(device) => return device.read(address - mapping.start),
+        }
+
+        self.memory[address as usize]
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        let mapping = match self.find_mapping(address) {
+            Some(m) => m,
+            None => return,
+        };
+
+        match &mut mapping.region {
+            MCS51_XDATA_REGION::RAM => {}
+            MCS51_XDATA_REGION::ROM => return,
+            MCS51_XDATA_REGION::DEVICE(device) => {
+                device.write(address - mapping.start, value);
+                return;
+            }
+        }
+
+        self.memory[address as usize] = value;
+    }
+}
@@ -9,12 +9,47 @@ pub trait IOComponent {
     fn set_port_u16(&mut self, port: usize, val: u16);
 }
 
+pub trait BusComponent {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+}
+
+/*
+Why run_for / run_until gave control back. Addresses are widened to u32 so every core fits, the
+opcode has the type the core decodes.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason<T> {
+    BUDGET,
+    CONDITION,
+    BREAKPOINT(u32),
+    WATCHPOINT { pc: u32, address: u32 },
+    INVALID_OPCODE { pc: u32, opcode: T },
+    PC_OUT_OF_CODE(u32),
+    UNIMPLEMENTED_ACCESS { pc: u32, address: u32 },
+    SLEEP,
+    WATCHDOG_RESET,
+    EXTERNAL_REQUEST,
+}
+
 pub trait MCU<T> {
     fn clock(&mut self);
     fn next_instruction(&mut self);
     fn run_opcode(&mut self, opcode: T);
-    fn set_program(&mut self, program: Vec<u8>);
+    // Replaces the program, an image that does not fit leaves the code memory as it was
+    fn set_program(&mut self, program: Vec<u8>) -> Result<(), String>;
     fn setup(&mut self);
     fn reset(&mut self);
     fn run(&mut self);
+
+    // Runs whole instructions until at least the given number of cycles elapsed or another stop
+    fn run_for(&mut self, cycles: u64) -> StopReason<T>;
+    // Runs until the predicate, checked after every instruction, holds or another stop
+    fn run_until<F: FnMut(&Self) -> bool>(&mut self, predicate: F) -> StopReason<T>
+    where
+        Self: Sized;
+    // Makes the running run_for / run_until return before the next instruction
+    fn request_stop(&mut self);
+    fn set_breakpoint(&mut self, address: u32);
+    fn clear_breakpoint(&mut self, address: u32);
 }
\ No newline at end of file
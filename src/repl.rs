@@ -0,0 +1,541 @@
+use crate::cli::*;
+use crate::lib::decompiler::mcs51::*;
+use crate::lib::mcus::mcs51::*;
+use crate::lib::traits::component::*;
+use rustyline::Editor;
+
+/*
+MCS51 debugger REPL
+
+Addresses and values are expressions (see MCS51_Expression): 0x100, 100H, DPTR + 2, I:R0...
+Memory locations take a space prefix, D: (direct, the default), I:, X:, C: or BIT:.
+*/
+
+const MCS51_REPL_COMMANDS: &str = "\
+step [N]                 execute N instructions (Enter steps one)
+next                     step over ACALL / LCALL
+finish                   run until the current function returns
+continue [cycles]        run until a breakpoint, a watchpoint or another stop
+break <addr> [if <cond>] set a breakpoint, tbreak sets a temporary one
+watch [r|w|c] <loc>      stop on reads, writes or changes of a location
+delete <id>              remove a breakpoint or watchpoint
+list                     list the breakpoints and watchpoints
+regs                     show the registers
+x/N <loc>                dump N bytes of IRAM, SFR, XDATA or CODE
+set <target> = <value>   write a register, a bit or memory
+disas [start [end]]      disassemble, from the PC by default
+bt                       call stack, from the return addresses on the stack
+load <file>              load a binary or Intel HEX image and reset
+wait <pattern>           run until the next instruction contains the pattern
+help [instruction]       this text, or the description of an instruction
+exit";
+
+const MCS51_SOURCE: &str = include_str!("lib/mcus/mcs51.rs");
+
+/*
+Descriptions of the instructions, taken from the block comments written above the op_*
+functions of the core. The name is the function name without op_, e.g. "CPL A".
+*/
+pub fn get_opcode_help() -> Vec<(String, String)> {
+    let mut help: Vec<(String, String)> = Vec::new();
+    let mut comment: Vec<&str> = Vec::new();
+    let mut in_comment = false;
+    let mut last_comment: Option<String> = None;
+
+    for line in MCS51_SOURCE.lines() {
+        let trimmed = line.trim();
+        if in_comment {
+            if trimmed == "*/" {
+                in_comment = false;
+                last_comment = Some(comment.join("\n").trim().to_owned());
+            } else {
+                comment.push(trimmed);
+            }
+            continue;
+        }
+
+        if trimmed == "/*" {
+            in_comment = true;
+            comment.clear();
+        } else if let Some(name) = trimmed.strip_prefix("pub fn op_") {
+            if let Some(text) = last_comment.take() {
+                let name = name.split('(').next().unwrap_or("");
+                help.push((name.replace('_', " ").to_uppercase(), text));
+            }
+        } else if !trimmed.is_empty() {
+            last_comment = None;
+        }
+    }
+
+    return help;
+}
+
+pub struct MCS51_Repl {
+    pub mcu: MCS51,
+    decomp: MCS51_Decompiler,
+}
+
+impl MCS51_Repl {
+    pub fn new(mcu: MCS51) -> MCS51_Repl {
+        MCS51_Repl {
+            mcu,
+            decomp: MCS51_Decompiler::new(),
+        }
+    }
+
+    pub fn evaluate(&self, text: &str) -> Result<i64, String> {
+        let expression = MCS51_Expression::parse(text)?;
+        return Ok(expression.evaluate(&self.mcu));
+    }
+
+    // A location with an optional space prefix, direct addressing by default
+    pub fn parse_location(&self, text: &str) -> Result<(MCS51_MEMORY_SPACE, u16), String> {
+        let (space, address) = match text.split_once(':') {
+            Some((prefix, address)) => {
+                let space = match prefix.trim().to_uppercase().as_str() {
+                    "D" => MCS51_MEMORY_SPACE::DIRECT,
+                    "I" => MCS51_MEMORY_SPACE::INDIRECT,
+                    "X" => MCS51_MEMORY_SPACE::XDATA,
+                    "C" => MCS51_MEMORY_SPACE::CODE,
+                    "BIT" => MCS51_MEMORY_SPACE::BIT,
+                    _ => return Err(format!("Unknown memory space {}", prefix)),
+                };
+                (space, address)
+            }
+            None => (MCS51_MEMORY_SPACE::DIRECT, text),
+        };
+
+        return Ok((space, self.evaluate(address)? as u16));
+    }
+
+    // The decompiler reads a copy of the code space as the core currently sees it
+    fn refresh_code(&mut self) {
+        self.decomp.program = (0..=0xFFFF).map(|address| self.mcu.read_code_byte(address)).collect();
+    }
+
+    fn disassemble(&mut self, address: u16) -> (String, u16) {
+        let opcode = self.mcu.read_code_byte(address);
+        let length = MCS51_Decompiler::instruction_length(opcode);
+        let bytes: Vec<String> = (0..length)
+            .map(|offset| format!("{:02X}", self.mcu.read_code_byte(address.wrapping_add(offset))))
+            .collect();
+
+        let code = if opcode == MCS51_RESERVED_OPCODE || address > 0xFFFC {
+            format!("DB {:02X}H", opcode)
+        } else {
+            self.decomp.get_instruction(address).get_code().to_owned()
+        };
+
+        let marker = if address == self.mcu.pc { "=>" } else { "  " };
+        let line = format!("{} {:04X}: {:<9} {}", marker, address, bytes.join(" "), code);
+        return (line, length);
+    }
+
+    fn current_instruction(&mut self) -> String {
+        self.refresh_code();
+        return self.disassemble(self.mcu.pc).0;
+    }
+
+    fn describe_stop(&self, reason: StopReason<u8>) -> String {
+        return match (reason, self.mcu.debugger.last_event) {
+            (StopReason::BREAKPOINT(pc), Some(MCS51_DEBUG_EVENT::BREAKPOINT { id, .. })) => {
+                format!("Breakpoint {} at {:04X}", id, pc)
+            }
+            (
+                StopReason::WATCHPOINT { .. },
+                Some(MCS51_DEBUG_EVENT::WATCHPOINT {
+                    id,
+                    pc,
+                    space,
+                    address,
+                    kind,
+                    old,
+                    new,
+                }),
+            ) => format!(
+                "Watchpoint {} {:?} {:?}:{:04X} at {:04X}, {:02X?} -> {:02X?}",
+                id, kind, space, address, pc, old, new
+            ),
+            (reason, _) => format!("Stopped: {:?}", reason),
+        };
+    }
+
+    // The stop reason when it is not the expected one, then the next instruction
+    fn report(&mut self, reason: StopReason<u8>, expected: StopReason<u8>) -> String {
+        let mut output = String::new();
+        if reason != expected {
+            output += &self.describe_stop(reason);
+            output += "\n";
+        }
+        return output + &self.current_instruction();
+    }
+
+    fn step(&mut self, count: u64) -> String {
+        let mut executed: u64 = 0;
+        let reason = self.mcu.run_until(|_| {
+            executed += 1;
+            executed >= count
+        });
+        return self.report(reason, StopReason::CONDITION);
+    }
+
+    fn next(&mut self) -> String {
+        let pc = self.mcu.pc;
+        let opcode = self.mcu.read_code_byte(pc);
+        if opcode != 0x12 && opcode & 0x1F != 0x11 {
+            return self.step(1);
+        }
+
+        let resume = pc.wrapping_add(MCS51_Decompiler::instruction_length(opcode));
+        let sp = self.mcu.read_sfr(MCS51_REGISTERS::SP);
+        let reason = self
+            .mcu
+            .run_until(|mcu| mcu.pc == resume && mcu.read_sfr(MCS51_REGISTERS::SP) == sp);
+        return self.report(reason, StopReason::CONDITION);
+    }
+
+    // RET leaves SP two below its value in the function
+    fn finish(&mut self) -> String {
+        let sp = self.mcu.read_sfr(MCS51_REGISTERS::SP) as i16;
+        let reason = self
+            .mcu
+            .run_until(|mcu| (mcu.read_sfr(MCS51_REGISTERS::SP) as i16) < sp - 1);
+        return self.report(reason, StopReason::CONDITION);
+    }
+
+    fn continue_run(&mut self, arguments: &str) -> Result<String, String> {
+        let reason = if arguments.is_empty() {
+            self.mcu.run_until(|_| false)
+        } else {
+            self.mcu.run_for(self.evaluate(arguments)? as u64)
+        };
+        let output = self.describe_stop(reason);
+        return Ok(output + "\n" + &self.current_instruction());
+    }
+
+    fn set_breakpoint(&mut self, arguments: &str, temporary: bool) -> Result<String, String> {
+        let (address, condition) = match arguments.split_once(" if ") {
+            Some((address, condition)) => (address, condition),
+            None => (arguments, ""),
+        };
+        let address = self.evaluate(address)? as u16;
+        let condition = match condition.trim() {
+            "" => None,
+            condition => Some(MCS51_Expression::parse(condition)?),
+        };
+
+        let id = self.mcu.debugger.add_breakpoint(address, temporary);
+        self.mcu.debugger.set_condition(id, condition);
+        return Ok(format!("Breakpoint {} at {:04X}", id, address));
+    }
+
+    fn set_watchpoint(&mut self, arguments: &str) -> Result<String, String> {
+        let (kind, location) = match arguments.split_once(' ') {
+            Some(("r", location)) => (MCS51_WATCH_KIND::READ, location),
+            Some(("w", location)) => (MCS51_WATCH_KIND::WRITE, location),
+            Some(("c", location)) => (MCS51_WATCH_KIND::CHANGE, location),
+            _ => (MCS51_WATCH_KIND::WRITE, arguments),
+        };
+
+        let space = match self.parse_location(location)? {
+            (MCS51_MEMORY_SPACE::DIRECT, address) if address < 0x80 => (MCS51_WATCH_SPACE::IRAM, address),
+            (MCS51_MEMORY_SPACE::DIRECT, address) => (MCS51_WATCH_SPACE::SFR, address),
+            (MCS51_MEMORY_SPACE::INDIRECT, address) => (MCS51_WATCH_SPACE::IRAM, address),
+            (MCS51_MEMORY_SPACE::XDATA, address) => (MCS51_WATCH_SPACE::XDATA, address),
+            (MCS51_MEMORY_SPACE::BIT, address) => (MCS51_WATCH_SPACE::BIT, address),
+            (MCS51_MEMORY_SPACE::CODE, _) => return Err("Code memory can not be watched".to_owned()),
+        };
+
+        let id = self.mcu.add_watchpoint(space.0, space.1, kind);
+        return Ok(format!("Watchpoint {} {:?} {:?}:{:04X}", id, kind, space.0, space.1));
+    }
+
+    fn list(&self) -> String {
+        let mut lines: Vec<String> = Vec::new();
+        let condition = |condition: &Option<MCS51_Expression>| match condition {
+            Some(condition) => format!(" if {}", condition),
+            None => String::new(),
+        };
+
+        for breakpoint in &self.mcu.debugger.breakpoints {
+            lines.push(format!(
+                "{} break{} {:04X} hits {} ignore {}{}{}",
+                breakpoint.id,
+                if breakpoint.temporary { " (temporary)" } else { "" },
+                breakpoint.address,
+                breakpoint.hits,
+                breakpoint.ignore_count,
+                if breakpoint.enabled { "" } else { " disabled" },
+                condition(&breakpoint.condition),
+            ));
+        }
+        for watchpoint in &self.mcu.debugger.watchpoints {
+            lines.push(format!(
+                "{} watch {:?} {:?}:{:04X} hits {} ignore {}{}{}",
+                watchpoint.id,
+                watchpoint.kind,
+                watchpoint.space,
+                watchpoint.address,
+                watchpoint.hits,
+                watchpoint.ignore_count,
+                if watchpoint.enabled { "" } else { " disabled" },
+                condition(&watchpoint.condition),
+            ));
+        }
+
+        if lines.is_empty() {
+            return "No breakpoints or watchpoints".to_owned();
+        }
+        return lines.join("\n");
+    }
+
+    fn regs(&self) -> String {
+        let mcu = &self.mcu;
+        let psw = mcu.read_sfr(MCS51_REGISTERS::PSW);
+        let flags: String = ["CY", "AC", "F0", "RS1", "RS0", "OV", "-", "P"]
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| psw & (0x80 >> bit) != 0)
+            .map(|(_, name)| *name)
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let registers: Vec<String> = (0..8)
+            .map(|register| format!("R{}={:02X}", register, mcu.read_register(register)))
+            .collect();
+
+        return format!(
+            "PC={:04X} A={:02X} B={:02X} SP={:02X} DPTR={:04X} PSW={:02X} [{}]\n{} (bank {})\ncycles={}",
+            mcu.pc,
+            mcu.get_accumulator(),
+            mcu.read_sfr(MCS51_REGISTERS::B),
+            mcu.read_sfr(MCS51_REGISTERS::SP),
+            mcu.get_dptr(),
+            psw,
+            flags,
+            registers.join(" "),
+            mcu.get_current_register_bank_flags() >> 3,
+            mcu.get_cycle_count(),
+        );
+    }
+
+    fn examine(&self, count: &str, arguments: &str) -> Result<String, String> {
+        let count = if count.is_empty() { 16 } else { self.evaluate(count)? as u32 };
+        let (space, start) = self.parse_location(arguments)?;
+        let prefix = match space {
+            MCS51_MEMORY_SPACE::DIRECT => "D",
+            MCS51_MEMORY_SPACE::INDIRECT => "I",
+            MCS51_MEMORY_SPACE::XDATA => "X",
+            MCS51_MEMORY_SPACE::CODE => "C",
+            MCS51_MEMORY_SPACE::BIT => "BIT",
+        };
+
+        let mut lines: Vec<String> = Vec::new();
+        for row in (0..count).step_by(16) {
+            let address = start.wrapping_add(row as u16);
+            let bytes: Vec<String> = (row..count.min(row + 16))
+                .map(|offset| match self.mcu.read_memory(space, start.wrapping_add(offset as u16)) {
+                    Some(value) => format!("{:02X}", value),
+                    None => "??".to_owned(),
+                })
+                .collect();
+            lines.push(format!("{}:{:04X}: {}", prefix, address, bytes.join(" ")));
+        }
+        return Ok(lines.join("\n"));
+    }
+
+    fn set(&mut self, arguments: &str) -> Result<String, String> {
+        let (target, value) = match arguments.split_once('=') {
+            Some((target, value)) => (target.trim(), value.trim()),
+            None => arguments.split_once(' ').ok_or("Usage: set <target> = <value>")?,
+        };
+        let value = self.evaluate(value)?;
+
+        match MCS51_Expression::parse(target)? {
+            MCS51_Expression::ACCUMULATOR => self.mcu.set_accumulator(value as u8),
+            MCS51_Expression::REGISTER(register) => self.mcu.write_register(register, value as u8),
+            MCS51_Expression::DPTR => self.mcu.set_dptr(value as u16),
+            MCS51_Expression::PC => self.mcu.pc = value as u16,
+            MCS51_Expression::SFR(address) => self.mcu.write(address, value as u8),
+            MCS51_Expression::BIT(address) => self.mcu.write_bit(address, value != 0),
+            MCS51_Expression::MEMORY(space, address) => {
+                let address = address.evaluate(&self.mcu) as u16;
+                match space {
+                    MCS51_MEMORY_SPACE::DIRECT => self.mcu.write(address as u8, value as u8),
+                    MCS51_MEMORY_SPACE::INDIRECT => self.mcu.write_indirect(address as u8, value as u8),
+                    MCS51_MEMORY_SPACE::XDATA => self.mcu.write_xdata(address, value as u8),
+                    MCS51_MEMORY_SPACE::CODE => self.mcu.load_program(address, &[value as u8])?,
+                    MCS51_MEMORY_SPACE::BIT => self.mcu.write_bit(address as u8, value != 0),
+                }
+            }
+            _ => return Err(format!("{} can not be written", target)),
+        }
+        return Ok(String::new());
+    }
+
+    fn disas(&mut self, arguments: &str) -> Result<String, String> {
+        let mut arguments = arguments.split_whitespace();
+        let start = match arguments.next() {
+            Some(start) => self.evaluate(start)? as u16,
+            None => self.mcu.pc,
+        };
+        let end = match arguments.next() {
+            Some(end) => Some(self.evaluate(end)? as u16),
+            None => None,
+        };
+
+        self.refresh_code();
+        let mut lines: Vec<String> = Vec::new();
+        let mut address = start;
+        loop {
+            let (line, length) = self.disassemble(address);
+            lines.push(line);
+
+            let next = address.wrapping_add(length);
+            let done = match end {
+                Some(end) => next > end || next < address,
+                None => lines.len() >= 10,
+            };
+            if done {
+                break;
+            }
+            address = next;
+        }
+        return Ok(lines.join("\n"));
+    }
+
+    /*
+    Without frame information the stack is scanned from SP down to the reset value of SP for
+    pairs of bytes that form the address following an ACALL or LCALL
+    */
+    fn backtrace(&mut self) -> String {
+        let mut lines = vec![format!("#0 {:04X}", self.mcu.pc)];
+        let mut sp = self.mcu.read_sfr(MCS51_REGISTERS::SP);
+
+        while sp > 0x08 {
+            let high = self.mcu.read_indirect(sp).cloned().unwrap_or(0) as u16;
+            let low = self.mcu.read_indirect(sp - 1).cloned().unwrap_or(0) as u16;
+            let address = (high << 8) | low;
+
+            let lcall = address >= 3 && self.mcu.read_code_byte(address - 3) == 0x12;
+            let acall = address >= 2 && self.mcu.read_code_byte(address - 2) & 0x1F == 0x11;
+            if lcall || acall {
+                let call = if lcall { address - 3 } else { address - 2 };
+                lines.push(format!("#{} {:04X} called from {:04X}", lines.len(), address, call));
+                sp -= 2;
+            } else {
+                sp -= 1;
+            }
+        }
+        return lines.join("\n");
+    }
+
+    fn load(&mut self, filename: &str) -> Result<String, String> {
+        let size = load_image(&mut self.mcu, filename, CLI_FORMAT::from_path(filename), 0)?;
+        self.mcu.reset();
+        return Ok(format!("Loaded {} bytes from {}", size, filename));
+    }
+
+    fn wait(&mut self, pattern: &str) -> String {
+        self.refresh_code();
+        let decomp = &mut self.decomp;
+        let reason = self.mcu.run_until(|mcu| {
+            let pc = mcu.pc;
+            mcu.read_code_byte(pc) != MCS51_RESERVED_OPCODE
+                && pc <= 0xFFFC
+                && decomp.get_instruction(pc).get_code().contains(pattern)
+        });
+        return self.report(reason, StopReason::CONDITION);
+    }
+
+    fn help(&self, topic: &str) -> String {
+        if topic.is_empty() {
+            let names: Vec<String> = get_opcode_help().into_iter().map(|(name, _)| name).collect();
+            return format!(
+                "{}\n\nInstructions with a description: {}",
+                MCS51_REPL_COMMANDS,
+                names.join(", ")
+            );
+        }
+
+        let topic = topic.to_uppercase();
+        let texts: Vec<String> = get_opcode_help()
+            .into_iter()
+            .filter(|(name, _)| name.split(' ').next() == Some(topic.as_str()))
+            .map(|(name, text)| format!("{}: {}", name, text))
+            .collect();
+        if texts.is_empty() {
+            return format!("No description of {}", topic);
+        }
+        return texts.join("\n\n");
+    }
+
+    // Runs one command line and returns what it prints
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let line = line.trim();
+        let (command, arguments) = match line.split_once(' ') {
+            Some((command, arguments)) => (command, arguments.trim()),
+            None => (line, ""),
+        };
+        let (command, count) = match command.split_once('/') {
+            Some((command, count)) => (command, count),
+            None => (command, ""),
+        };
+
+        return match command {
+            "" => Ok(self.step(1)),
+            "step" | "s" => {
+                let count = if arguments.is_empty() { 1 } else { self.evaluate(arguments)? };
+                Ok(self.step(count.max(1) as u64))
+            }
+            "next" | "n" => Ok(self.next()),
+            "finish" => Ok(self.finish()),
+            "continue" | "c" => self.continue_run(arguments),
+            "break" | "b" => self.set_breakpoint(arguments, false),
+            "tbreak" => self.set_breakpoint(arguments, true),
+            "watch" => self.set_watchpoint(arguments),
+            "delete" | "d" => {
+                let id = self.evaluate(arguments)? as usize;
+                match self.mcu.debugger.delete(id) {
+                    true => Ok(format!("Deleted {}", id)),
+                    false => Err(format!("No breakpoint or watchpoint {}", id)),
+                }
+            }
+            "list" | "l" => Ok(self.list()),
+            "regs" | "r" => Ok(self.regs()),
+            "x" => self.examine(count, arguments),
+            "set" => self.set(arguments),
+            "disas" => self.disas(arguments),
+            "bt" => Ok(self.backtrace()),
+            "load" => self.load(arguments),
+            "wait" => Ok(self.wait(arguments)),
+            "help" | "h" => Ok(self.help(arguments)),
+            _ => Err(format!("Unknown command {}, try help", command)),
+        };
+    }
+}
+
+pub fn repl_mcs51(mcu: MCS51) {
+    let mut repl = MCS51_Repl::new(mcu);
+    println!("{}", repl.current_instruction());
+
+    let mut rl = Editor::<()>::new();
+    loop {
+        let line = match rl.readline(">> ") {
+            Ok(line) => line,
+            Err(err) => {
+                println!("Error: {:?}", err);
+                break;
+            }
+        };
+        rl.add_history_entry(line.as_str());
+
+        match line.trim() {
+            "exit" | "quit" | "q" => break,
+            line => match repl.execute(line) {
+                Ok(output) if output.is_empty() => (),
+                Ok(output) => println!("{}", output),
+                Err(err) => println!("Error: {}", err),
+            },
+        }
+    }
+}